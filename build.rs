@@ -0,0 +1,49 @@
+use std::process::Command;
+use vergen::{BuildBuilder, Emitter};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Build information served on /version
+    Emitter::default()
+        .add_instructions(&BuildBuilder::default().build_timestamp(true).build()?)?
+        .emit()?;
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha());
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto");
+
+        // Use the vendored protoc so builds don't depend on a system install.
+        // SAFETY: build scripts are single-threaded.
+        unsafe {
+            std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        }
+
+        tonic_prost_build::configure()
+            .build_client(false)
+            // Serve downloads from the stored buffer without copying it
+            .bytes(".emgr.v1.DownloadResponse.body")
+            .compile_protos(&["proto/emgr/v1/images.proto"], &["proto"])?;
+    }
+
+    Ok(())
+}
+
+/// Commit the binary is built from, overridable with `GIT_SHA` where `.git` is not
+/// available (e.g. Docker builds)
+fn git_sha() -> String {
+    if let Ok(sha) = std::env::var("GIT_SHA") {
+        return sha;
+    }
+
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,26 @@
+//! Decoding and resizing of malformed images
+//!
+//! Seeded with `corpus/decode`, which also holds inputs that crashed decoders before.
+#![no_main]
+
+use bytes::Bytes;
+use emgr::{ImageFormat, ImageService, ResizeQuery};
+use libfuzzer_sys::fuzz_target;
+use std::sync::LazyLock;
+use tokio::runtime::{Builder, Runtime};
+
+static RUNTIME: LazyLock<Runtime> =
+    LazyLock::new(|| Builder::new_current_thread().build().unwrap());
+static SERVICE: LazyLock<ImageService> = LazyLock::new(|| ImageService::new().unwrap());
+
+fuzz_target!(|data: &[u8]| {
+    // A small output keeps iterations fast, the decoder still sees the whole source
+    let query = ResizeQuery {
+        url: "https://fuzz.test/source".to_string(),
+        width: Some(32),
+        format: Some(ImageFormat::Png),
+        ..Default::default()
+    };
+    // Errors are expected, only panics and hangs are findings
+    let _ = RUNTIME.block_on(SERVICE.process_image(Bytes::copy_from_slice(data), &query));
+});
@@ -0,0 +1,11 @@
+//! Magic-byte detection on arbitrary input
+#![no_main]
+
+use emgr::ImageService;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if ImageService::detect_format_from_bytes(data).is_some() {
+        assert!(data.len() >= 12);
+    }
+});
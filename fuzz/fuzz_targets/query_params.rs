@@ -0,0 +1,21 @@
+//! Parsing and validation of resize query strings
+//!
+//! Covers the parameters with their own syntax (`ar`, `tint`, `pad`, `pixelate_region`,
+//! `frame`/`time`...), and is where parsers of future parameters should be exercised.
+#![no_main]
+
+use emgr::ResizeQuery;
+use emgr::services::image::pipeline::Pipeline;
+use gen_server::models::ResizeQueryParams;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(params) = serde_urlencoded::from_bytes::<ResizeQueryParams>(data) else {
+        return;
+    };
+    let query = ResizeQuery::from(params);
+
+    let _ = query.validate();
+    let _ = query.frame_selector();
+    let _ = Pipeline::from_query(&query);
+});
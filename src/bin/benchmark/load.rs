@@ -0,0 +1,203 @@
+use crate::BenchmarkConfig;
+use crate::report::{LatencyRecorder, ScenarioResult};
+use crate::workload::Workload;
+use anyhow::Result;
+use futures::future::join_all;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::sleep_until;
+
+/// Outcome of a single request: success, latency and response size
+type RequestOutcome = (bool, Duration, Option<u64>);
+
+/// Server under test and the requests sent to it
+pub struct Target {
+    client: reqwest::Client,
+    config: BenchmarkConfig,
+    test_urls: Vec<String>,
+    resize_params: Vec<(Option<u32>, Option<u32>)>,
+    workload: Option<Workload>,
+}
+
+impl Target {
+    pub fn new(config: BenchmarkConfig, workload: Option<Workload>) -> Result<Arc<Self>> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.request_timeout))
+            .build()?;
+
+        Ok(Arc::new(Self {
+            client,
+            test_urls: config.get_test_urls(),
+            resize_params: config.get_resize_params(),
+            workload,
+            config,
+        }))
+    }
+
+    /// Build the resize URL and expected status of the `index`-th request
+    fn request(&self, index: usize) -> (String, Option<u16>) {
+        match &self.workload {
+            Some(workload) => workload.request(&self.config.get_base_url(), index),
+            None => (self.request_url(index), None),
+        }
+    }
+
+    /// Build the resize URL of the `index`-th request from the configured URLs and sizes
+    fn request_url(&self, index: usize) -> String {
+        let url = &self.test_urls[index % self.test_urls.len()];
+        let (width, height) = self.resize_params[index % self.resize_params.len()];
+
+        // Build query parameters
+        let mut query_params = Vec::new();
+        if let Some(w) = width {
+            query_params.push(format!("width={}", w));
+        }
+        if let Some(h) = height {
+            query_params.push(format!("height={}", h));
+        }
+        query_params.push(format!("format={}", self.config.output_format));
+
+        format!(
+            "{}/api/images/resize?url={}&{}",
+            self.config.get_base_url(),
+            urlencoding::encode(url),
+            query_params.join("&")
+        )
+    }
+
+    async fn send(&self, index: usize) -> RequestOutcome {
+        let (url, expected_status) = self.request(index);
+        let request_start = Instant::now();
+        match self.client.get(url).send().await {
+            Ok(response) => {
+                let status = response.status();
+                let duration = request_start.elapsed();
+                let success = match expected_status {
+                    Some(expected) => status.as_u16() == expected,
+                    None => status.is_success(),
+                };
+                (success, duration, response.content_length())
+            }
+            Err(_) => (false, request_start.elapsed(), None),
+        }
+    }
+
+    /// Spawn the `index`-th request, advancing the progress bar when it completes
+    fn spawn(self: &Arc<Self>, index: usize, progress: &ProgressBar) -> JoinHandle<RequestOutcome> {
+        let target = self.clone();
+        let progress = progress.clone();
+        tokio::spawn(async move {
+            let outcome = target.send(index).await;
+            progress.inc(1);
+            outcome
+        })
+    }
+}
+
+/// Live progress of a scenario
+fn progress_bar(scenario: &str, requests: u64) -> ProgressBar {
+    let progress = ProgressBar::new(requests);
+    progress.set_style(
+        ProgressStyle::with_template("{prefix} [{elapsed_precise}] {wide_bar} {pos}/{len} {msg}")
+            .expect("valid progress template"),
+    );
+    progress.set_prefix(scenario.to_string());
+    progress
+}
+
+/// Fire `concurrency` requests at once
+pub async fn run_burst(target: &Arc<Target>, concurrency: usize) -> ScenarioResult {
+    let scenario = format!("burst-c{}", concurrency);
+    let progress = progress_bar(&scenario, concurrency as u64);
+    let start_time = Instant::now();
+
+    let tasks: Vec<_> = (0..concurrency)
+        .map(|i| target.spawn(i, &progress))
+        .collect();
+    let outcomes = join_all(tasks).await;
+    progress.finish_and_clear();
+
+    summarize(scenario, concurrency as u64, outcomes, start_time.elapsed())
+}
+
+/// Send requests at a fixed rate for a duration, whether or not earlier ones completed
+///
+/// Unlike bursts this is an open-loop load: a slow server builds up requests in flight
+/// instead of slowing the load down, which shows where latency starts to climb.
+pub async fn run_rate(target: &Arc<Target>, rps: f64, duration: Duration) -> ScenarioResult {
+    let scenario = format!("rate-{}rps", rps);
+    let requests = (rps * duration.as_secs_f64()).round().max(1.0) as u64;
+    let interval = Duration::from_secs_f64(1.0 / rps);
+    let progress = progress_bar(&scenario, requests);
+    let start_time = Instant::now();
+
+    let mut tasks = Vec::with_capacity(requests as usize);
+    for i in 0..requests {
+        sleep_until((start_time + interval.mul_f64(i as f64)).into()).await;
+        tasks.push(target.spawn(i as usize, &progress));
+        progress.set_message(format!("{} in flight", i + 1 - progress.position()));
+    }
+    progress.set_message("draining");
+    let outcomes = join_all(tasks).await;
+    progress.finish_and_clear();
+
+    summarize(scenario, requests, outcomes, start_time.elapsed())
+}
+
+/// Rates of a linear ramp split into `steps` fixed-rate steps
+pub fn ramp_rates(from: f64, to: f64, steps: usize) -> Vec<f64> {
+    if steps <= 1 {
+        return vec![to];
+    }
+    (0..steps)
+        .map(|i| from + (to - from) * i as f64 / (steps - 1) as f64)
+        .map(|rps| (rps * 100.0).round() / 100.0)
+        .collect()
+}
+
+fn summarize(
+    scenario: String,
+    requests: u64,
+    outcomes: Vec<Result<RequestOutcome, tokio::task::JoinError>>,
+    total_duration: Duration,
+) -> ScenarioResult {
+    let mut successful_requests = 0u64;
+    let mut latencies = LatencyRecorder::new();
+    let mut total_bytes = 0u64;
+
+    for (success, duration, content_length) in outcomes.into_iter().flatten() {
+        if success {
+            successful_requests += 1;
+            latencies.record(duration);
+            if let Some(bytes) = content_length {
+                total_bytes += bytes;
+            }
+        }
+    }
+
+    ScenarioResult {
+        scenario,
+        requests,
+        successful: successful_requests,
+        duration_secs: total_duration.as_secs_f64(),
+        requests_per_second: successful_requests as f64 / total_duration.as_secs_f64(),
+        throughput_mbps: (total_bytes as f64 / (1024.0 * 1024.0)) / total_duration.as_secs_f64(),
+        latency_ms: latencies.summary(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_rates() {
+        assert_eq!(
+            ramp_rates(10.0, 50.0, 5),
+            vec![10.0, 20.0, 30.0, 40.0, 50.0]
+        );
+        assert_eq!(ramp_rates(10.0, 50.0, 1), vec![50.0]);
+    }
+}
@@ -0,0 +1,429 @@
+mod load;
+mod report;
+mod workload;
+
+use anyhow::{Result, bail};
+use clap::{Parser, ValueEnum};
+use envconfig::Envconfig;
+use load::{Target, ramp_rates, run_burst, run_rate};
+use report::{BenchmarkReport, ScenarioResult, parse_ratio};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use workload::Workload;
+
+/// How the load is generated
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// One burst of simultaneous requests per concurrency level
+    Burst,
+    /// Requests sent at a fixed rate for a duration
+    Rate,
+    /// Fixed-rate steps increasing linearly between two rates
+    Ramp,
+}
+
+/// Benchmark a running EmgR server; targets are configured through BENCHMARK_* variables
+#[derive(Parser, Debug)]
+#[command(name = "benchmark", version, about)]
+struct Cli {
+    /// Write the results as JSON to this file
+    #[arg(long)]
+    json: Option<PathBuf>,
+
+    /// Write the results as CSV to this file
+    #[arg(long)]
+    csv: Option<PathBuf>,
+
+    /// JSON results of a previous run to compare against
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Maximum allowed regression against the baseline (e.g. "10%")
+    #[arg(long, default_value = "10%", value_parser = parse_ratio)]
+    max_regression: f64,
+
+    /// Load generation mode
+    #[arg(long, value_enum, default_value_t = Mode::Burst)]
+    mode: Mode,
+
+    /// Requests per second in rate mode
+    #[arg(long, default_value_t = 10.0)]
+    rps: f64,
+
+    /// Duration in seconds of the rate mode, or of the whole ramp
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+
+    /// Requests per second at the start of the ramp
+    #[arg(long, default_value_t = 1.0)]
+    ramp_from: f64,
+
+    /// Requests per second at the end of the ramp
+    #[arg(long, default_value_t = 50.0)]
+    ramp_to: f64,
+
+    /// Number of fixed-rate steps of the ramp
+    #[arg(long, default_value_t = 10)]
+    ramp_steps: usize,
+
+    /// Seconds of unrecorded load at the starting rate before measuring
+    #[arg(long, default_value_t = 0)]
+    warmup: u64,
+}
+
+impl Cli {
+    fn validate(&self) -> Result<(), String> {
+        match self.mode {
+            Mode::Burst => {}
+            Mode::Rate if self.rps <= 0.0 => {
+                return Err("--rps must be greater than 0".to_string());
+            }
+            Mode::Ramp if self.ramp_from <= 0.0 || self.ramp_to <= 0.0 => {
+                return Err("--ramp-from and --ramp-to must be greater than 0".to_string());
+            }
+            Mode::Ramp if self.ramp_steps == 0 => {
+                return Err("--ramp-steps must be greater than 0".to_string());
+            }
+            _ => {}
+        }
+
+        if self.mode != Mode::Burst && self.duration == 0 {
+            return Err("--duration must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Rate used for the warmup phase in open-loop modes
+    fn starting_rate(&self) -> f64 {
+        match self.mode {
+            Mode::Ramp => self.ramp_from,
+            _ => self.rps,
+        }
+    }
+}
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct BenchmarkConfig {
+    #[envconfig(from = "BENCHMARK_HOST", default = "localhost")]
+    pub host: String,
+
+    #[envconfig(from = "BENCHMARK_PORT", default = "8080")]
+    pub port: u16,
+
+    #[envconfig(from = "BENCHMARK_CONCURRENCY_LEVELS", default = "1,5,10,20,50")]
+    pub concurrency_levels: String,
+
+    #[envconfig(
+        from = "BENCHMARK_TEST_URLS",
+        default = "https://picsum.photos/1920/1080,https://picsum.photos/800/600,https://picsum.photos/400/300"
+    )]
+    pub test_urls: String,
+
+    #[envconfig(
+        from = "BENCHMARK_RESIZE_PARAMS",
+        default = "300x300,800x,x600,1200x800"
+    )]
+    pub resize_params: String,
+
+    #[envconfig(from = "BENCHMARK_WAIT_BETWEEN_TESTS", default = "4")]
+    pub wait_between_tests: u64,
+
+    #[envconfig(from = "BENCHMARK_REQUEST_TIMEOUT", default = "60")]
+    pub request_timeout: u64,
+
+    #[envconfig(from = "BENCHMARK_OUTPUT_FORMAT", default = "jpg")]
+    pub output_format: String,
+
+    /// JSON or CSV file of weighted request templates, replacing the URLs and sizes above
+    #[envconfig(from = "BENCHMARK_WORKLOAD_FILE")]
+    pub workload_file: Option<String>,
+}
+
+impl BenchmarkConfig {
+    /// Parse concurrency levels from comma-separated string
+    pub fn get_concurrency_levels(&self) -> Vec<usize> {
+        self.concurrency_levels
+            .split(',')
+            .filter_map(|s| s.trim().parse().ok())
+            .collect()
+    }
+
+    /// Parse test URLs from comma-separated string
+    pub fn get_test_urls(&self) -> Vec<String> {
+        self.test_urls
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .collect()
+    }
+
+    /// Parse resize parameters from comma-separated string
+    /// Format: "WIDTHxHEIGHT" where WIDTH or HEIGHT can be empty for aspect ratio preservation
+    pub fn get_resize_params(&self) -> Vec<(Option<u32>, Option<u32>)> {
+        self.resize_params
+            .split(',')
+            .filter_map(|s| {
+                let s = s.trim();
+                if let Some((width_str, height_str)) = s.split_once('x') {
+                    let width = if width_str.is_empty() {
+                        None
+                    } else {
+                        width_str.parse().ok()
+                    };
+                    let height = if height_str.is_empty() {
+                        None
+                    } else {
+                        height_str.parse().ok()
+                    };
+                    Some((width, height))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Get the base URL for the benchmark target
+    pub fn get_base_url(&self) -> String {
+        format!("http://{}:{}", self.host, self.port)
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<(), String> {
+        if self.get_concurrency_levels().is_empty() {
+            return Err("No valid concurrency levels configured".to_string());
+        }
+
+        if self.get_test_urls().is_empty() {
+            return Err("No valid test URLs configured".to_string());
+        }
+
+        if self.get_resize_params().is_empty() {
+            return Err("No valid resize parameters configured".to_string());
+        }
+
+        if self.request_timeout == 0 {
+            return Err("Request timeout must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Print the statistics of a scenario
+fn print_result(result: &ScenarioResult) {
+    if result.successful == 0 {
+        println!("❌ All requests failed");
+        return;
+    }
+
+    let latency = &result.latency_ms;
+    println!(
+        "✅ Successful requests: {}/{}",
+        result.successful, result.requests
+    );
+    println!("⏱️  Total time: {:.2}s", result.duration_secs);
+    println!("📈 Requests/sec: {:.2}", result.requests_per_second);
+    println!("🚀 Throughput: {:.2} MB/s", result.throughput_mbps);
+    println!("⚡ Avg response time: {:.2}ms", latency.mean);
+    println!("🔥 Min response time: {:.2}ms", latency.min);
+    println!("🐌 Max response time: {:.2}ms", latency.max);
+    println!(
+        "📊 Percentiles: p50 {:.2}ms | p90 {:.2}ms | p99 {:.2}ms",
+        latency.p50, latency.p90, latency.p99
+    );
+}
+
+/// Load the server without recording results, so caches and pools are warm
+async fn warmup(target: &Arc<Target>, cli: &Cli, concurrency_levels: &[usize]) {
+    let duration = Duration::from_secs(cli.warmup);
+    match cli.mode {
+        Mode::Burst => {
+            let start = Instant::now();
+            while start.elapsed() < duration {
+                run_burst(target, concurrency_levels[0]).await;
+            }
+        }
+        Mode::Rate | Mode::Ramp => {
+            run_rate(target, cli.starting_rate(), duration).await;
+        }
+    }
+}
+
+/// Point out the first ramp step whose p99 latency more than doubled from the first step
+fn print_knee(scenarios: &[ScenarioResult]) {
+    let Some(first) = scenarios.first() else {
+        return;
+    };
+
+    let knee = scenarios
+        .iter()
+        .find(|s| first.latency_ms.p99 > 0.0 && s.latency_ms.p99 > first.latency_ms.p99 * 2.0);
+    match knee {
+        Some(step) => println!(
+            "\n📍 Latency knee around {} (p99 {:.2}ms vs {:.2}ms at {})",
+            step.scenario, step.latency_ms.p99, first.latency_ms.p99, first.scenario
+        ),
+        None => println!("\n📍 No latency knee within the ramp"),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = BenchmarkConfig::init_from_env()?;
+
+    // Validate configuration
+    if let Err(e) = config.validate().and_then(|_| cli.validate()) {
+        eprintln!("❌ Configuration error: {}", e);
+        return Ok(());
+    }
+
+    // Load the workload and baseline first so a bad path fails before the run
+    let workload = config
+        .workload_file
+        .as_deref()
+        .map(|path| Workload::load(path.as_ref()))
+        .transpose()?;
+
+    let baseline = cli
+        .baseline
+        .as_deref()
+        .map(BenchmarkReport::load)
+        .transpose()?;
+
+    println!("🚀 Image Resize Performance Benchmark");
+    println!("=====================================");
+    println!("📋 Configuration:");
+    println!("   Host: {}", config.host);
+    println!("   Port: {}", config.port);
+    println!(
+        "   Concurrency levels: {:?}",
+        config.get_concurrency_levels()
+    );
+    match &workload {
+        Some(workload) => println!(
+            "   Workload: {} request templates from {}",
+            workload.len(),
+            config.workload_file.as_deref().unwrap_or_default()
+        ),
+        None => {
+            println!("   Test URLs count: {}", config.get_test_urls().len());
+            println!(
+                "   Resize params count: {}",
+                config.get_resize_params().len()
+            );
+            println!("   Output format: {}", config.output_format);
+        }
+    }
+    println!("   Request timeout: {}s", config.request_timeout);
+    println!("   Wait between tests: {}s", config.wait_between_tests);
+    println!();
+
+    println!("   Mode: {:?}", cli.mode);
+    println!();
+
+    let concurrency_levels = config.get_concurrency_levels();
+    let target = Target::new(config.clone(), workload)?;
+
+    if cli.warmup > 0 {
+        println!("🔥 Warming up for {}s", cli.warmup);
+        warmup(&target, &cli, &concurrency_levels).await;
+    }
+
+    let mut report = BenchmarkReport::default();
+    match cli.mode {
+        Mode::Burst => {
+            for concurrency in &concurrency_levels {
+                println!("\n📊 Testing with {} concurrent requests", *concurrency);
+                println!("----------------------------------------");
+
+                let result = run_burst(&target, *concurrency).await;
+                print_result(&result);
+                report.scenarios.push(result);
+
+                // Wait between tests
+                sleep(Duration::from_secs(config.wait_between_tests)).await;
+            }
+        }
+        Mode::Rate => {
+            println!(
+                "\n📊 Testing at {} requests/sec for {}s",
+                cli.rps, cli.duration
+            );
+            println!("----------------------------------------");
+
+            let result = run_rate(&target, cli.rps, Duration::from_secs(cli.duration)).await;
+            print_result(&result);
+            report.scenarios.push(result);
+        }
+        Mode::Ramp => {
+            let rates = ramp_rates(cli.ramp_from, cli.ramp_to, cli.ramp_steps);
+            let step_duration = Duration::from_secs(cli.duration).div_f64(rates.len() as f64);
+
+            for rps in rates {
+                println!(
+                    "\n📊 Ramp step at {} requests/sec for {:.1}s",
+                    rps,
+                    step_duration.as_secs_f64()
+                );
+                println!("----------------------------------------");
+
+                let result = run_rate(&target, rps, step_duration).await;
+                print_result(&result);
+                report.scenarios.push(result);
+            }
+
+            print_knee(&report.scenarios);
+        }
+    }
+
+    if let Some(path) = &cli.json {
+        report.write_json(path)?;
+        println!("\n💾 JSON results written to {}", path.display());
+    }
+    if let Some(path) = &cli.csv {
+        report.write_csv(path)?;
+        println!("💾 CSV results written to {}", path.display());
+    }
+
+    if let Some(baseline) = &baseline {
+        let regressions = report.regressions(baseline, cli.max_regression);
+        if !regressions.is_empty() {
+            eprintln!(
+                "\n❌ Performance regressed by more than {:.1}%:",
+                cli.max_regression * 100.0
+            );
+            for regression in &regressions {
+                eprintln!("   {}", regression);
+            }
+            bail!("{} regression(s) against the baseline", regressions.len());
+        }
+        println!(
+            "\n✅ No regression above {:.1}%",
+            cli.max_regression * 100.0
+        );
+    }
+
+    println!("\n🎯 Performance Recommendations:");
+    println!("================================");
+    println!("1. Monitor CPU usage during peak load");
+    println!("2. Check memory consumption patterns");
+    println!("3. Verify network bandwidth utilization");
+    println!("4. Test with different image sizes and formats");
+    println!("5. Profile with tools like `perf` or `flamegraph`");
+    println!("\n💡 Configuration Tips:");
+    println!("- Use BENCHMARK_HOST and BENCHMARK_PORT to target different servers");
+    println!("- Customize BENCHMARK_CONCURRENCY_LEVELS (e.g., '1,10,50,100')");
+    println!("- Add your own test URLs with BENCHMARK_TEST_URLS");
+    println!(
+        "- Configure resize parameters with BENCHMARK_RESIZE_PARAMS (e.g., '100x100,500x,x300')"
+    );
+    println!("- Export results with --json/--csv and gate CI with --baseline results.json");
+    println!("- Replay a traffic mix with BENCHMARK_WORKLOAD_FILE (JSON or CSV templates)");
+    println!("- Find the latency knee with --mode ramp --ramp-from 1 --ramp-to 100");
+
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use anyhow::{Context, Result};
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Duration;
+
+/// Latency percentiles of a scenario, in milliseconds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    /// Summarize a histogram recorded in microseconds
+    pub fn from_histogram(histogram: &Histogram<u64>) -> Self {
+        if histogram.is_empty() {
+            return Self::default();
+        }
+
+        let ms = |us: u64| us as f64 / 1000.0;
+        Self {
+            min: ms(histogram.min()),
+            mean: histogram.mean() / 1000.0,
+            p50: ms(histogram.value_at_quantile(0.50)),
+            p90: ms(histogram.value_at_quantile(0.90)),
+            p99: ms(histogram.value_at_quantile(0.99)),
+            max: ms(histogram.max()),
+        }
+    }
+}
+
+/// Latency recorder for the requests of a scenario
+pub struct LatencyRecorder {
+    histogram: Histogram<u64>,
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self {
+            // 1µs to 1h with 3 significant digits
+            histogram: Histogram::new_with_bounds(1, 3_600_000_000, 3)
+                .expect("valid histogram bounds"),
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.histogram
+            .saturating_record(latency.as_micros().max(1) as u64);
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary::from_histogram(&self.histogram)
+    }
+}
+
+/// Results of a single benchmark scenario
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioResult {
+    /// Stable name used to match scenarios against a baseline
+    pub scenario: String,
+    pub requests: u64,
+    pub successful: u64,
+    pub duration_secs: f64,
+    pub requests_per_second: f64,
+    pub throughput_mbps: f64,
+    pub latency_ms: LatencySummary,
+}
+
+/// Machine-readable results of a benchmark run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub scenarios: Vec<ScenarioResult>,
+}
+
+impl BenchmarkReport {
+    /// Load a report previously written with [`BenchmarkReport::write_json`]
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&data).context(format!("Invalid report: {}", path.display()))
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data).context(format!("Failed to write {}", path.display()))
+    }
+
+    pub fn write_csv(&self, path: &Path) -> Result<()> {
+        let mut csv = String::from(
+            "scenario,requests,successful,duration_secs,requests_per_second,throughput_mbps,\
+             latency_min_ms,latency_mean_ms,latency_p50_ms,latency_p90_ms,latency_p99_ms,latency_max_ms\n",
+        );
+        for s in &self.scenarios {
+            let l = &s.latency_ms;
+            writeln!(
+                csv,
+                "{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+                s.scenario,
+                s.requests,
+                s.successful,
+                s.duration_secs,
+                s.requests_per_second,
+                s.throughput_mbps,
+                l.min,
+                l.mean,
+                l.p50,
+                l.p90,
+                l.p99,
+                l.max
+            )?;
+        }
+        std::fs::write(path, csv).context(format!("Failed to write {}", path.display()))
+    }
+
+    /// List the regressions of this run against a baseline
+    ///
+    /// `max_regression` is a ratio (0.1 for 10%). Latency percentiles may not grow and
+    /// the request rate may not drop by more than that ratio. Scenarios missing from the
+    /// baseline are ignored.
+    pub fn regressions(&self, baseline: &BenchmarkReport, max_regression: f64) -> Vec<String> {
+        let mut regressions = Vec::new();
+
+        for current in &self.scenarios {
+            let Some(base) = baseline
+                .scenarios
+                .iter()
+                .find(|b| b.scenario == current.scenario)
+            else {
+                continue;
+            };
+
+            let latencies = [
+                ("p50", base.latency_ms.p50, current.latency_ms.p50),
+                ("p90", base.latency_ms.p90, current.latency_ms.p90),
+                ("p99", base.latency_ms.p99, current.latency_ms.p99),
+            ];
+            for (name, before, after) in latencies {
+                if before > 0.0 && after > before * (1.0 + max_regression) {
+                    regressions.push(format!(
+                        "{}: {} latency {:.2}ms -> {:.2}ms (+{:.1}%)",
+                        current.scenario,
+                        name,
+                        before,
+                        after,
+                        (after / before - 1.0) * 100.0
+                    ));
+                }
+            }
+
+            let (before, after) = (base.requests_per_second, current.requests_per_second);
+            if before > 0.0 && after < before * (1.0 - max_regression) {
+                regressions.push(format!(
+                    "{}: requests/sec {:.2} -> {:.2} (-{:.1}%)",
+                    current.scenario,
+                    before,
+                    after,
+                    (1.0 - after / before) * 100.0
+                ));
+            }
+        }
+
+        regressions
+    }
+}
+
+/// Parse a regression threshold such as "10%" or "0.1" into a ratio
+pub fn parse_ratio(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let ratio = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+        None => value.parse::<f64>(),
+    }
+    .map_err(|e| format!("invalid threshold '{}': {}", value, e))?;
+
+    if ratio < 0.0 {
+        return Err(format!("threshold must not be negative: {}", value));
+    }
+    Ok(ratio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(p99: f64, rps: f64) -> ScenarioResult {
+        ScenarioResult {
+            scenario: "burst-c10".to_string(),
+            requests: 10,
+            successful: 10,
+            duration_secs: 1.0,
+            requests_per_second: rps,
+            throughput_mbps: 1.0,
+            latency_ms: LatencySummary {
+                p50: 10.0,
+                p90: 20.0,
+                p99,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_ratio() {
+        assert_eq!(parse_ratio("10%"), Ok(0.1));
+        assert_eq!(parse_ratio("0.25"), Ok(0.25));
+        assert!(parse_ratio("-5%").is_err());
+        assert!(parse_ratio("ten").is_err());
+    }
+
+    #[test]
+    fn test_regressions() {
+        let baseline = BenchmarkReport {
+            scenarios: vec![scenario(100.0, 50.0)],
+        };
+
+        let within = BenchmarkReport {
+            scenarios: vec![scenario(105.0, 48.0)],
+        };
+        assert!(within.regressions(&baseline, 0.1).is_empty());
+
+        let slower = BenchmarkReport {
+            scenarios: vec![scenario(150.0, 40.0)],
+        };
+        assert_eq!(slower.regressions(&baseline, 0.1).len(), 2);
+    }
+}
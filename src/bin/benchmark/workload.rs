@@ -0,0 +1,202 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Multiplier used to spread consecutive request indexes over the weights
+const SCRAMBLE: u64 = 2_654_435_761;
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A weighted request of a workload file
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestTemplate {
+    /// Source image URL
+    pub url: String,
+
+    /// Resize query parameters, e.g. "width=300&format=webp"
+    #[serde(default)]
+    pub params: String,
+
+    /// Relative frequency of this request in the mix
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+
+    /// Expected status of the final response (after redirects); any 2xx if omitted
+    #[serde(default)]
+    pub expected_status: Option<u16>,
+
+    /// Make every request unique so it misses the server cache
+    #[serde(default)]
+    pub unique: bool,
+}
+
+/// Weighted mix of requests replayed by the benchmark
+#[derive(Debug)]
+pub struct Workload {
+    templates: Vec<RequestTemplate>,
+    total_weight: u64,
+    unique_counter: AtomicU64,
+}
+
+impl Workload {
+    /// Load a workload from a JSON array or a CSV file with a header row
+    ///
+    /// CSV columns are `url,params,weight,expected_status,unique`; only `url` is required.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .context(format!("Failed to read workload file {}", path.display()))?;
+
+        let templates = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => parse_csv(&data)?,
+            _ => serde_json::from_str(&data)
+                .context(format!("Invalid workload file {}", path.display()))?,
+        };
+        Self::new(templates)
+    }
+
+    pub fn new(templates: Vec<RequestTemplate>) -> Result<Self> {
+        let total_weight = templates.iter().map(|t| t.weight as u64).sum();
+        if total_weight == 0 {
+            bail!("Workload has no request with a positive weight");
+        }
+
+        Ok(Self {
+            templates,
+            total_weight,
+            unique_counter: AtomicU64::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Template of the `index`-th request
+    ///
+    /// Selection is deterministic so runs are reproducible, and over `total_weight`
+    /// consecutive requests every template is picked exactly `weight` times.
+    pub fn pick(&self, index: usize) -> &RequestTemplate {
+        let mut slot = (index as u64).wrapping_mul(SCRAMBLE) % self.total_weight;
+        for template in &self.templates {
+            if slot < template.weight as u64 {
+                return template;
+            }
+            slot -= template.weight as u64;
+        }
+        unreachable!("slot is below the total weight")
+    }
+
+    /// Resize URL and expected status of the `index`-th request
+    pub fn request(&self, base_url: &str, index: usize) -> (String, Option<u16>) {
+        let template = self.pick(index);
+
+        let source = if template.unique {
+            let n = self.unique_counter.fetch_add(1, Ordering::Relaxed);
+            let separator = if template.url.contains('?') { '&' } else { '?' };
+            format!(
+                "{}{}bench={}-{}",
+                template.url,
+                separator,
+                std::process::id(),
+                n
+            )
+        } else {
+            template.url.clone()
+        };
+
+        let mut url = format!(
+            "{}/api/images/resize?url={}",
+            base_url,
+            urlencoding::encode(&source)
+        );
+        let params = template.params.trim_start_matches(['?', '&']);
+        if !params.is_empty() {
+            url.push('&');
+            url.push_str(params);
+        }
+
+        (url, template.expected_status)
+    }
+}
+
+fn parse_csv(data: &str) -> Result<Vec<RequestTemplate>> {
+    let mut lines = data.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<String> = lines
+        .next()
+        .context("Empty workload file")?
+        .split(',')
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let url_column = column("url").context("Workload CSV has no url column")?;
+
+    lines
+        .enumerate()
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|index| fields.get(index).copied())
+                    .filter(|f| !f.is_empty())
+            };
+            let line_number = i + 2;
+
+            Ok(RequestTemplate {
+                url: field(Some(url_column))
+                    .context(format!("Missing url on line {}", line_number))?
+                    .to_string(),
+                params: field(column("params")).unwrap_or_default().to_string(),
+                weight: field(column("weight"))
+                    .map(str::parse)
+                    .transpose()
+                    .context(format!("Invalid weight on line {}", line_number))?
+                    .unwrap_or(1),
+                expected_status: field(column("expected_status"))
+                    .map(str::parse)
+                    .transpose()
+                    .context(format!("Invalid expected_status on line {}", line_number))?,
+                unique: field(column("unique")).is_some_and(|f| matches!(f, "true" | "1" | "yes")),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let templates = parse_csv(
+            "url,params,weight,expected_status,unique\n\
+             https://a.test/1.jpg,width=300&format=webp,3,200,\n\
+             https://a.test/2.jpg,,,,true\n",
+        )
+        .unwrap();
+
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0].params, "width=300&format=webp");
+        assert_eq!(templates[0].weight, 3);
+        assert_eq!(templates[0].expected_status, Some(200));
+        assert_eq!(templates[1].weight, 1);
+        assert!(templates[1].unique);
+    }
+
+    #[test]
+    fn test_pick_follows_weights() {
+        let template = |url: &str, weight| RequestTemplate {
+            url: url.to_string(),
+            params: String::new(),
+            weight,
+            expected_status: None,
+            unique: false,
+        };
+        let workload = Workload::new(vec![template("hit", 3), template("miss", 1)]).unwrap();
+
+        let hits = (0..400).filter(|&i| workload.pick(i).url == "hit").count();
+        assert_eq!(hits, 300);
+    }
+}
@@ -1,4 +1,6 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use emgr::services::health::handler::ReadinessReport;
 use envconfig::Envconfig;
 use std::net::{SocketAddr, TcpStream};
 use std::time::Duration;
@@ -15,22 +17,71 @@ pub struct HealthCheckEnvConfig {
     pub http_timeout: u8,
 }
 
-fn main() -> Result<()> {
+/// Check that the EmgR server is ready to serve requests
+#[derive(Parser, Debug)]
+#[command(name = "healthcheck", version, about)]
+struct Cli {
+    /// Only check that the port accepts TCP connections
+    #[arg(long, conflicts_with = "deep")]
+    tcp: bool,
+
+    /// Also verify that the storage backend is reachable
+    #[arg(long)]
+    deep: bool,
+}
+
+fn tcp_check(address: &str, timeout: Duration) -> Result<()> {
+    let socket_addr: SocketAddr = address.parse()?;
+    TcpStream::connect_timeout(&socket_addr, timeout)?;
+    Ok(())
+}
+
+async fn http_check(address: &str, timeout: Duration, deep: bool) -> Result<()> {
+    let client = reqwest::Client::builder().timeout(timeout).build()?;
+    let response = client
+        .get(format!("http://{}/health/ready", address))
+        .query(&[("deep", deep)])
+        .send()
+        .await?;
+
+    let status = response.status();
+    let report: ReadinessReport = response
+        .json()
+        .await
+        .context(format!("Invalid readiness response (HTTP {})", status))?;
+
+    if !status.is_success() || !report.is_ok() {
+        bail!(
+            "server is not ready (HTTP {}, status: {}, storage: {})",
+            status,
+            report.status,
+            report.storage
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
     let config = HealthCheckEnvConfig::init_from_env()?;
     let address = format!("{}:{}", config.http_host, config.http_port);
+    let timeout = Duration::from_secs(config.http_timeout as u64);
 
-    let socket_addr: SocketAddr = address.parse()?;
+    let result = if cli.tcp {
+        tcp_check(&address, timeout)
+    } else {
+        http_check(&address, timeout, cli.deep).await
+    };
 
-    match TcpStream::connect_timeout(
-        &socket_addr,
-        Duration::from_secs(config.http_timeout as u64),
-    ) {
+    match result {
         Ok(_) => {
             println!("Health check is successful");
             std::process::exit(0);
         }
         Err(e) => {
-            eprintln!("Health check failed: {}", e);
+            eprintln!("Health check failed: {:#}", e);
             std::process::exit(1);
         }
     }
@@ -0,0 +1,487 @@
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use clap::{Args, Parser, Subcommand};
+use emgr::modules::env::env::EnvConfig;
+use emgr::services::signing::handler::{EXPIRES_PARAM, NONCE_PARAM, UrlSigner};
+use emgr::{
+    CacheServiceBuilder, ImageFormat, ImageService, PerformanceConfig, ResizeQuery, ResizeService,
+    StorageConfig, StorageService,
+};
+use envconfig::Envconfig;
+use futures::{StreamExt, stream};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+/// File extensions picked up by the batch mode
+const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// Run the EmgR image pipeline from the command line
+#[derive(Parser, Debug)]
+#[command(name = "resize-cli", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Resize a single image and write the result to a file
+    Oneshot(OneshotArgs),
+    /// Resize every image of a directory and upload the results to the configured storage
+    Batch(BatchArgs),
+    /// Sign a resize or conversion URL with the configured SIGNING_KEY
+    Sign(SignArgs),
+}
+
+#[derive(Args, Debug)]
+struct OneshotArgs {
+    /// Path or http(s) URL of the source image
+    #[arg(short, long)]
+    input: String,
+
+    /// Path of the resized image
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    transform: TransformArgs,
+}
+
+#[derive(Args, Debug)]
+struct BatchArgs {
+    /// Directory to walk recursively
+    #[arg(short, long)]
+    dir: PathBuf,
+
+    /// Public URL the directory is served from, so that storage keys match the ones computed
+    /// by the server for the same images; the relative file path is used if omitted
+    #[arg(long)]
+    source_base_url: Option<String>,
+
+    /// Number of images processed concurrently (defaults to MAX_CONCURRENT_PROCESSING)
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// Preset applied to every image
+    #[command(flatten)]
+    transform: TransformArgs,
+}
+
+#[derive(Args, Debug)]
+struct SignArgs {
+    /// URL to sign, absolute or as a path with its query, e.g. "/api/images/resize?url=...&width=200"
+    url: String,
+
+    /// Refuse the URL after this many seconds
+    #[arg(long)]
+    expires_in: Option<u64>,
+
+    /// Make the URL usable once, with a random nonce; needs --expires-in
+    #[arg(long, requires = "expires_in")]
+    nonce: bool,
+}
+
+/// Transformation parameters, mirroring the resize endpoint query parameters
+#[derive(Args, Debug, Clone)]
+struct TransformArgs {
+    /// The width of the final image
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// The height of the final image
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// The format of the final image (png, webp, jpg, avif, gif, mp4, webm); guessed from the output extension if omitted
+    #[arg(long)]
+    format: Option<ImageFormat>,
+
+    /// How deep the image should be blurred
+    #[arg(long)]
+    blur_sigma: Option<f32>,
+
+    /// Convert the image to grayscale
+    #[arg(long)]
+    grayscale: bool,
+
+    /// Comma separated transform plugins applied after resizing (e.g. sepia,invert)
+    #[arg(long)]
+    plugin: Option<String>,
+
+    /// Aspect ratio (e.g. 16:9) used to derive the missing dimension
+    #[arg(long)]
+    ar: Option<String>,
+
+    /// Scale of the subject within the frame (e.g. 1.2 to crop in, 0.8 to add a matte)
+    #[arg(long)]
+    zoom: Option<f32>,
+
+    /// Output resolution in DPI written into JPEG/PNG metadata
+    #[arg(long)]
+    density: Option<u32>,
+
+    /// Encoder quality of JPEG and AVIF output (1-100), or `auto` to search for the lowest JPEG
+    /// quality within the perceptual budget
+    #[arg(long)]
+    quality: Option<String>,
+
+    /// Largest size of the output in bytes, reducing quality and then dimensions to fit
+    #[arg(long)]
+    max_bytes: Option<u32>,
+
+    /// Noise reduction strength applied before resizing (0-100)
+    #[arg(long)]
+    denoise: Option<f32>,
+
+    /// Darkening towards the corners (0-1)
+    #[arg(long)]
+    vignette: Option<f32>,
+
+    /// Color overlay as hex with optional opacity (e.g. ff8800:0.4)
+    #[arg(long)]
+    tint: Option<String>,
+
+    /// Mosaic block size in pixels, over the whole image or --pixelate-region
+    #[arg(long)]
+    pixelate: Option<u32>,
+
+    /// Area of the resized image to pixelate, as x,y,width,height
+    #[arg(long)]
+    pixelate_region: Option<String>,
+
+    /// Make the background transparent: border or a hex color, with an optional tolerance (e.g. border:40)
+    #[arg(long)]
+    remove_bg: Option<String>,
+
+    /// Reduce the output to a palette of this many colors (2-256)
+    #[arg(long)]
+    colors: Option<u32>,
+
+    /// Dithering of a reduced palette: none, ordered or floyd_steinberg
+    #[arg(long)]
+    dither: Option<String>,
+
+    /// Stretch the histogram: true or luminance keeping the colors, channels also correcting casts
+    #[arg(long)]
+    auto_level: Option<String>,
+
+    /// Interpolation of downscales: nearest, triangle, catmull_rom, gaussian, lanczos3 or mitchell
+    #[arg(long)]
+    filter_down: Option<String>,
+
+    /// Interpolation of upscales: nearest, triangle, catmull_rom, gaussian, lanczos3 or mitchell
+    #[arg(long)]
+    filter_up: Option<String>,
+
+    /// Chroma subsampling of JPEG output: 444, 422, 420 or auto
+    #[arg(long)]
+    subsampling: Option<String>,
+
+    /// Sharpen downscaled images by their reduction
+    #[arg(long)]
+    auto_sharpen: bool,
+
+    /// How the image fits both width and height: cover (default) or liquid
+    #[arg(long)]
+    fit: Option<String>,
+
+    /// Frames of animated sources kept in GIF and WebP output: all (default) or first
+    #[arg(long)]
+    frames: Option<String>,
+
+    /// Gamma correction in linear light (above 1 brightens midtones)
+    #[arg(long)]
+    gamma: Option<f32>,
+
+    /// Exposure adjustment in stops, in linear light
+    #[arg(long)]
+    exposure: Option<f32>,
+
+    /// Margins as top,right,bottom,left in pixels or percent (e.g. 20,5%,20,5%)
+    #[arg(long)]
+    pad: Option<String>,
+
+    /// Focal point kept in frame when cropping, as x,y fractions of the source (e.g. 0.3,0.6)
+    #[arg(long)]
+    focus: Option<String>,
+
+    /// Frame of an animated source to render, starting at 0
+    #[arg(long)]
+    frame: Option<u32>,
+
+    /// Time into an animated source, in seconds, of the frame to render
+    #[arg(long)]
+    time: Option<f32>,
+}
+
+impl TransformArgs {
+    /// Build the resize query used by the pipeline
+    fn to_query(&self, url: &str, output: &Path) -> ResizeQuery {
+        ResizeQuery {
+            url: url.to_string(),
+            width: self.width,
+            height: self.height,
+            format: self.format.or_else(|| format_from_extension(output)),
+            blur_sigma: self.blur_sigma,
+            grayscale: self.grayscale.then_some(true),
+            plugin: self.plugin.clone(),
+            ar: self.ar.clone(),
+            zoom: self.zoom,
+            density: self.density,
+            quality: self.quality.clone(),
+            max_bytes: self.max_bytes,
+            denoise: self.denoise,
+            vignette: self.vignette,
+            tint: self.tint.clone(),
+            pixelate: self.pixelate,
+            pixelate_region: self.pixelate_region.clone(),
+            gamma: self.gamma,
+            exposure: self.exposure,
+            pad: self.pad.clone(),
+            focus: self.focus.clone(),
+            frame: self.frame,
+            time: self.time,
+            // Watermarks live in the library of the server
+            watermark: None,
+            remove_bg: self.remove_bg.clone(),
+            colors: self.colors,
+            dither: self.dither.clone(),
+            auto_level: self.auto_level.clone(),
+            filter_down: self.filter_down.clone(),
+            filter_up: self.filter_up.clone(),
+            subsampling: self.subsampling.clone(),
+            auto_sharpen: self.auto_sharpen.then_some(true),
+            fit: self.fit.clone(),
+            frames: self.frames.clone(),
+        }
+    }
+}
+
+/// Guess the output format from a file extension
+fn format_from_extension(path: &Path) -> Option<ImageFormat> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "png" => Some(ImageFormat::Png),
+        "webp" => Some(ImageFormat::Webp),
+        "jpg" | "jpeg" => Some(ImageFormat::Jpg),
+        "avif" => Some(ImageFormat::Avif),
+        "gif" => Some(ImageFormat::Gif),
+        "mp4" => Some(ImageFormat::Mp4),
+        "webm" => Some(ImageFormat::Webm),
+        _ => None,
+    }
+}
+
+/// Read the source image from a URL or the local file system
+async fn read_source(image_service: &ImageService, input: &str) -> Result<Bytes> {
+    if input.starts_with("http://") || input.starts_with("https://") {
+        image_service.download_image(input).await
+    } else {
+        let data = tokio::fs::read(input)
+            .await
+            .context(format!("Failed to read input file: {}", input))?;
+        Ok(data.into())
+    }
+}
+
+async fn oneshot(image_service: &ImageService, args: OneshotArgs) -> Result<()> {
+    let timer = Instant::now();
+    let query = args.transform.to_query(&args.input, &args.output);
+
+    let source = read_source(image_service, &args.input).await?;
+    let output = image_service.process_image(source.clone(), &query).await?;
+
+    if let Some(parent) = args.output.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("Failed to create the output directory")?;
+    }
+    tokio::fs::write(&args.output, &output.data)
+        .await
+        .context(format!("Failed to write {}", args.output.display()))?;
+
+    println!(
+        "Wrote {} ({}, {}x{}, {} -> {} bytes) in {:.2?}",
+        args.output.display(),
+        output.content_type,
+        output.width,
+        output.height,
+        source.len(),
+        output.data.len(),
+        timer.elapsed()
+    );
+
+    Ok(())
+}
+
+/// Outcome of a batch run
+#[derive(Debug, Default)]
+struct BatchReport {
+    processed: usize,
+    cached: usize,
+    source_bytes: u64,
+    failed: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl BatchReport {
+    fn print(&self, elapsed: Duration) {
+        println!("Batch finished in {:.2?}", elapsed);
+        println!("  processed: {}", self.processed);
+        println!("  already in storage: {}", self.cached);
+        println!("  failed: {}", self.failed.len());
+        println!("  source bytes read: {}", self.source_bytes);
+        for (path, e) in &self.failed {
+            println!("    {}: {:#}", path.display(), e);
+        }
+    }
+}
+
+/// Collect the images below a directory, sorted for a stable processing order
+fn collect_images(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry.context(format!("Failed to walk {}", dir.display()))?;
+        let is_image = entry
+            .path()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+        if entry.file_type().is_file() && is_image {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Source URL of a file, used to derive its storage key
+fn source_url(dir: &Path, path: &Path, base_url: Option<&str>) -> String {
+    let relative = path
+        .strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    match base_url {
+        Some(base_url) => format!("{}/{}", base_url.trim_end_matches('/'), relative),
+        None => relative,
+    }
+}
+
+async fn batch(config: &EnvConfig, args: BatchArgs) -> Result<()> {
+    let timer = Instant::now();
+    let performance_config = PerformanceConfig::from(config);
+    let concurrency = args
+        .concurrency
+        .unwrap_or(performance_config.max_concurrent_processing)
+        .max(1);
+
+    let storage_service = StorageService::new(StorageConfig::from(config))?;
+    let cache_service = CacheServiceBuilder::default()
+        .minio_sub_path(config.sub_path.clone())
+        .content_addressed(config.content_addressed_keys)
+        .build()?;
+    let resize_service =
+        ResizeService::with_config(storage_service, cache_service, performance_config)?;
+
+    let files = collect_images(&args.dir)?;
+    let progress = ProgressBar::new(files.len() as u64);
+    progress.set_style(ProgressStyle::with_template(
+        "[{elapsed_precise}] {wide_bar} {pos}/{len} {msg}",
+    )?);
+
+    let mut results = stream::iter(files)
+        .map(|path| {
+            let resize_service = &resize_service;
+            let query = args.transform.to_query(
+                &source_url(&args.dir, &path, args.source_base_url.as_deref()),
+                &path,
+            );
+            async move {
+                let outcome = async {
+                    let source = tokio::fs::read(&path)
+                        .await
+                        .context("Failed to read input file")?;
+                    let size = source.len() as u64;
+                    let result = resize_service
+                        .resize_source(&query, source.into(), None)
+                        .await?;
+                    Ok::<_, anyhow::Error>((size, result.cache_hit))
+                }
+                .await;
+                (path, outcome)
+            }
+        })
+        .buffer_unordered(concurrency);
+
+    let mut report = BatchReport::default();
+    while let Some((path, outcome)) = results.next().await {
+        progress.set_message(path.display().to_string());
+        match outcome {
+            Ok((_, true)) => report.cached += 1,
+            Ok((bytes, false)) => {
+                report.processed += 1;
+                report.source_bytes += bytes;
+            }
+            Err(e) => report.failed.push((path, e)),
+        }
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+
+    report.print(timer.elapsed());
+    if !report.failed.is_empty() {
+        bail!("{} image(s) failed to process", report.failed.len());
+    }
+
+    Ok(())
+}
+
+/// Print the URL with its signature appended
+fn sign(config: &EnvConfig, args: SignArgs) -> Result<()> {
+    let signer = UrlSigner::from_env(config).context("SIGNING_KEY is not set")?;
+
+    // Only the path and query are signed, absolute URLs keep their origin
+    let path_start = match args.url.find("://") {
+        Some(scheme_end) => args.url[scheme_end + 3..]
+            .find('/')
+            .map_or(args.url.len(), |path| scheme_end + 3 + path),
+        None => 0,
+    };
+    let (origin, path_and_query) = args.url.split_at(path_start);
+    let (path, query) = path_and_query
+        .split_once('?')
+        .unwrap_or((path_and_query, ""));
+
+    let mut query = query.to_string();
+    if let Some(expires_in) = args.expires_in {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        query.push_str(&format!("&{}={}", EXPIRES_PARAM, now + expires_in));
+    }
+    if args.nonce {
+        query.push_str(&format!("&{}={}", NONCE_PARAM, Uuid::new_v4().simple()));
+    }
+    println!("{}{}", origin, signer.signed_url(path, &query));
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Use the same performance tuning and storage as the server
+    let config = EnvConfig::init_from_env()?;
+
+    match cli.command {
+        Command::Oneshot(args) => {
+            let image_service = ImageService::with_config(PerformanceConfig::from(&config))?;
+            oneshot(&image_service, args).await
+        }
+        Command::Batch(args) => batch(&config, args).await,
+        Command::Sign(args) => sign(&config, args),
+    }
+}
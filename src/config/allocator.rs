@@ -0,0 +1,51 @@
+use crate::modules::env::env::EnvConfig;
+use libmimalloc_sys::{mi_collect, mi_option_set, mi_option_t};
+
+/// mimalloc options, not exported as constants by the bindings
+const MI_OPTION_PURGE_DELAY: mi_option_t = 15;
+const MI_OPTION_ARENA_RESERVE: mi_option_t = 23;
+
+/// Tuning of the mimalloc global allocator
+///
+/// Unset options keep the mimalloc defaults, which can also be set through its own
+/// `MIMALLOC_*` environment variables.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorConfig {
+    /// Delay before freed memory is returned to the OS, in milliseconds
+    pub purge_delay_ms: Option<i64>,
+    /// Size of the arenas reserved from the OS, in MB
+    pub arena_reserve_mb: Option<u64>,
+}
+
+impl From<&EnvConfig> for AllocatorConfig {
+    fn from(config: &EnvConfig) -> Self {
+        Self {
+            purge_delay_ms: config.allocator_purge_delay_ms,
+            arena_reserve_mb: config.allocator_arena_reserve_mb,
+        }
+    }
+}
+
+impl AllocatorConfig {
+    /// Set the options, before the bulk of the allocations so new arenas follow them
+    pub fn apply(&self) {
+        // SAFETY: setting options is safe at any time, they are read on use
+        unsafe {
+            if let Some(delay) = self.purge_delay_ms {
+                mi_option_set(MI_OPTION_PURGE_DELAY, delay as _);
+            }
+            if let Some(reserve) = self.arena_reserve_mb {
+                // In KiB
+                mi_option_set(MI_OPTION_ARENA_RESERVE, (reserve * 1024) as _);
+            }
+        }
+    }
+}
+
+/// Return the memory freed by the process to the OS, instead of after the purge delay
+///
+/// No-op for programs using another global allocator.
+pub fn collect() {
+    // SAFETY: collection only touches memory already freed
+    unsafe { mi_collect(true) }
+}
@@ -1,6 +1,76 @@
 use crate::modules::env::env::EnvConfig;
+use crate::services::resize::observer::{Observed, PipelineObserver};
+use crate::services::storage::core::ObjectMetadata;
+use anyhow::{Error, anyhow, bail};
+use gen_server::models::ImageFormat;
+use image::Limits;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::time::Duration;
 
+/// AVIF encodes running at once by default, as a single one can keep several cores busy
+const DEFAULT_AVIF_ENCODES: usize = 2;
+
+/// Most images encoded to each format at once, parsed from `avif=2,png=4`
+///
+/// Formats without a limit are only bounded by the CPU pool.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EncodeLimits(pub HashMap<ImageFormat, usize>);
+
+impl EncodeLimits {
+    /// The limit of `format`, if it has one
+    pub fn get(&self, format: ImageFormat) -> Option<usize> {
+        self.0.get(&format).copied()
+    }
+
+    /// `self` with the limits of `overrides`, a limit of `0` lifting the one of its format
+    fn merge(mut self, overrides: &EncodeLimits) -> Self {
+        for (&format, &limit) in &overrides.0 {
+            self.0.insert(format, limit);
+        }
+        self.0.retain(|_, limit| *limit > 0);
+        self
+    }
+}
+
+impl Default for EncodeLimits {
+    fn default() -> Self {
+        Self(HashMap::from([(ImageFormat::Avif, DEFAULT_AVIF_ENCODES)]))
+    }
+}
+
+impl FromStr for EncodeLimits {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = HashMap::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (format, limit) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid encode limit {:?}, expected format=N", entry))?;
+            let format = format
+                .trim()
+                .to_lowercase()
+                .parse::<ImageFormat>()
+                .map_err(|e| anyhow!("Invalid encode limit {:?}: {}", entry, e))?;
+            if matches!(format, ImageFormat::Mp4 | ImageFormat::Webm) {
+                bail!("Videos are encoded by ffmpeg, not limited by {:?}", entry);
+            }
+            let limit = limit
+                .trim()
+                .parse::<usize>()
+                .map_err(|e| anyhow!("Invalid encode limit {:?}: {}", entry, e))?;
+            limits.insert(format, limit);
+        }
+        Ok(Self(limits))
+    }
+}
+
 /// Performance configuration for the image resize service
 #[derive(Debug, Clone)]
 pub struct PerformanceConfig {
@@ -8,18 +78,32 @@ pub struct PerformanceConfig {
     pub max_concurrent_downloads: usize,
     /// Maximum concurrent image processing tasks
     pub max_concurrent_processing: usize,
-    /// HTTP client timeout
-    pub http_timeout: Duration,
+    /// Longest wait to connect to an origin
+    pub connect_timeout: Duration,
+    /// Longest wait for the response headers of an origin, once the request is sent
+    pub first_byte_timeout: Duration,
+    /// Longest pause of an origin between two chunks of the body
+    pub idle_timeout: Duration,
     /// Maximum image size in bytes (50MB default)
     pub max_image_size: u64,
     /// CPU thread pool size (defaults to CPU count)
     pub cpu_thread_pool_size: Option<usize>,
-    /// Enable HTTP/2 for downloads
+    /// Enable HTTP/2 for downloads, negotiated through ALPN on TLS connections
     pub enable_http2: bool,
+    /// Speak HTTP/2 without negotiation, which only works with HTTP/2 origins
+    pub force_http2_prior_knowledge: bool,
     /// Connection pool size per host
     pub connection_pool_size: usize,
     /// Keep-alive timeout for connections
     pub keep_alive_timeout: Duration,
+    /// Largest image width accepted by decoders, unlimited when unset
+    pub max_decode_width: Option<u32>,
+    /// Largest image height accepted by decoders, unlimited when unset
+    pub max_decode_height: Option<u32>,
+    /// Largest buffer decoders may allocate, in bytes (512MB default)
+    pub max_decode_alloc: u64,
+    /// Most images encoded to each format at once, e.g. AVIF which is slow to encode
+    pub encode_limits: EncodeLimits,
 }
 
 impl Default for PerformanceConfig {
@@ -27,12 +111,19 @@ impl Default for PerformanceConfig {
         Self {
             max_concurrent_downloads: 20,
             max_concurrent_processing: num_cpus::get(),
-            http_timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(5),
+            first_byte_timeout: Duration::from_secs(15),
+            idle_timeout: Duration::from_secs(10),
             max_image_size: 50 * 1024 * 1024, // 50MB
             cpu_thread_pool_size: None,       // Use CPU count
             enable_http2: true,
+            force_http2_prior_knowledge: false,
             connection_pool_size: 50,
             keep_alive_timeout: Duration::from_secs(60),
+            max_decode_width: None,
+            max_decode_height: None,
+            max_decode_alloc: 512 * 1024 * 1024, // 512MB
+            encode_limits: EncodeLimits::default(),
         }
     }
 }
@@ -43,12 +134,19 @@ impl PerformanceConfig {
         Self {
             max_concurrent_downloads: 50,
             max_concurrent_processing: num_cpus::get() * 2,
-            http_timeout: Duration::from_secs(15),
+            connect_timeout: Duration::from_secs(3),
+            first_byte_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(5),
             max_image_size: 100 * 1024 * 1024, // 100MB
             cpu_thread_pool_size: Some(num_cpus::get()),
             enable_http2: true,
+            force_http2_prior_knowledge: false,
             connection_pool_size: 100,
             keep_alive_timeout: Duration::from_secs(120),
+            max_decode_width: None,
+            max_decode_height: None,
+            max_decode_alloc: 1024 * 1024 * 1024, // 1GB
+            encode_limits: EncodeLimits::default(),
         }
     }
 
@@ -57,12 +155,19 @@ impl PerformanceConfig {
         Self {
             max_concurrent_downloads: 10,
             max_concurrent_processing: num_cpus::get(),
-            http_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(2),
+            first_byte_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(3),
             max_image_size: 20 * 1024 * 1024, // 20MB
             cpu_thread_pool_size: Some(num_cpus::get()),
             enable_http2: true,
+            force_http2_prior_knowledge: false,
             connection_pool_size: 25,
             keep_alive_timeout: Duration::from_secs(30),
+            max_decode_width: None,
+            max_decode_height: None,
+            max_decode_alloc: 512 * 1024 * 1024, // 512MB
+            encode_limits: EncodeLimits::default(),
         }
     }
 
@@ -71,12 +176,19 @@ impl PerformanceConfig {
         Self {
             max_concurrent_downloads: 5,
             max_concurrent_processing: num_cpus::get() / 2,
-            http_timeout: Duration::from_secs(45),
+            connect_timeout: Duration::from_secs(10),
+            first_byte_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(15),
             max_image_size: 10 * 1024 * 1024, // 10MB
             cpu_thread_pool_size: Some(num_cpus::get() / 2),
             enable_http2: false, // HTTP/1.1 uses less memory
+            force_http2_prior_knowledge: false,
             connection_pool_size: 10,
             keep_alive_timeout: Duration::from_secs(30),
+            max_decode_width: None,
+            max_decode_height: None,
+            max_decode_alloc: 256 * 1024 * 1024, // 256MB
+            encode_limits: EncodeLimits(HashMap::from([(ImageFormat::Avif, 1)])),
         }
     }
 
@@ -114,9 +226,18 @@ impl PerformanceConfig {
             config.max_concurrent_processing = max_processing;
         }
 
-        if let Some(http_timeout_secs) = env_config.http_timeout_secs {
-            config.http_timeout = Duration::from_secs(http_timeout_secs);
-        }
+        config.connect_timeout = origin_timeout(
+            env_config,
+            env_config.connect_timeout_ms,
+            config.connect_timeout,
+        );
+        config.first_byte_timeout = origin_timeout(
+            env_config,
+            env_config.first_byte_timeout_ms,
+            config.first_byte_timeout,
+        );
+        config.idle_timeout =
+            origin_timeout(env_config, env_config.idle_timeout_ms, config.idle_timeout);
 
         if let Some(max_image_size_mb) = env_config.max_image_size_mb {
             config.max_image_size = max_image_size_mb * 1024 * 1024;
@@ -130,6 +251,10 @@ impl PerformanceConfig {
             config.enable_http2 = enable_http2;
         }
 
+        if let Some(force) = env_config.force_http2_prior_knowledge {
+            config.force_http2_prior_knowledge = force;
+        }
+
         if let Some(connection_pool_size) = env_config.connection_pool_size {
             config.connection_pool_size = connection_pool_size;
         }
@@ -137,12 +262,37 @@ impl PerformanceConfig {
         if let Some(keep_alive_timeout) = env_config.keep_alive_timeout_secs {
             config.keep_alive_timeout = Duration::from_secs(keep_alive_timeout);
         }
+
+        if let Some(max_decode_width) = env_config.max_decode_width {
+            config.max_decode_width = Some(max_decode_width);
+        }
+
+        if let Some(max_decode_height) = env_config.max_decode_height {
+            config.max_decode_height = Some(max_decode_height);
+        }
+
+        if let Some(max_decode_alloc_mb) = env_config.max_decode_alloc_mb {
+            config.max_decode_alloc = max_decode_alloc_mb * 1024 * 1024;
+        }
+
+        if let Some(encode_limits) = &env_config.encode_concurrency {
+            config.encode_limits = config.encode_limits.clone().merge(encode_limits);
+        }
     }
 
     /// Get optimal CPU thread pool size
     pub fn get_cpu_thread_pool_size(&self) -> usize {
         self.cpu_thread_pool_size.unwrap_or_else(num_cpus::get)
     }
+
+    /// Limits enforced by decoders, whatever the image headers claim
+    pub fn decode_limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_image_width = self.max_decode_width;
+        limits.max_image_height = self.max_decode_height;
+        limits.max_alloc = Some(self.max_decode_alloc);
+        limits
+    }
 }
 
 impl From<&EnvConfig> for PerformanceConfig {
@@ -162,18 +312,50 @@ impl From<&EnvConfig> for PerformanceConfig {
             max_concurrent_processing: env_config
                 .max_concurrent_processing
                 .unwrap_or_else(num_cpus::get),
-            http_timeout: Duration::from_secs(env_config.http_timeout_secs.unwrap_or_else(|| 30)),
+            connect_timeout: origin_timeout(
+                env_config,
+                env_config.connect_timeout_ms,
+                Duration::from_secs(5),
+            ),
+            first_byte_timeout: origin_timeout(
+                env_config,
+                env_config.first_byte_timeout_ms,
+                Duration::from_secs(15),
+            ),
+            idle_timeout: origin_timeout(
+                env_config,
+                env_config.idle_timeout_ms,
+                Duration::from_secs(10),
+            ),
             max_image_size: env_config.max_image_size_mb.unwrap_or_else(|| 50) * 1024 * 1024,
             cpu_thread_pool_size: env_config.cpu_thread_pool_size,
             enable_http2: env_config.enable_http2.unwrap_or(false),
+            force_http2_prior_knowledge: env_config.force_http2_prior_knowledge.unwrap_or(false),
             connection_pool_size: env_config.connection_pool_size.unwrap_or(50),
             keep_alive_timeout: Duration::from_secs(
                 env_config.keep_alive_timeout_secs.unwrap_or(60),
             ),
+            max_decode_width: env_config.max_decode_width,
+            max_decode_height: env_config.max_decode_height,
+            max_decode_alloc: env_config.max_decode_alloc_mb.unwrap_or(512) * 1024 * 1024,
+            encode_limits: match &env_config.encode_concurrency {
+                Some(encode_limits) => EncodeLimits::default().merge(encode_limits),
+                None => EncodeLimits::default(),
+            },
         }
     }
 }
 
+/// Timeout of a phase of downloads set in milliseconds, or by `HTTP_TIMEOUT_SECS`
+///
+/// `HTTP_TIMEOUT_SECS` bounded whole downloads before they were timed by phase, and still
+/// bounds each phase not set on its own.
+fn origin_timeout(env_config: &EnvConfig, ms: Option<u64>, default: Duration) -> Duration {
+    ms.map(Duration::from_millis)
+        .or(env_config.http_timeout_secs.map(Duration::from_secs))
+        .unwrap_or(default)
+}
+
 /// Runtime performance metrics
 #[derive(Debug, Default)]
 pub struct PerformanceMetrics {
@@ -183,6 +365,14 @@ pub struct PerformanceMetrics {
     pub avg_download_time_ms: std::sync::atomic::AtomicU64,
     pub avg_processing_time_ms: std::sync::atomic::AtomicU64,
     pub avg_upload_time_ms: std::sync::atomic::AtomicU64,
+    /// Processing locks taken by this replica
+    pub lock_acquired: std::sync::atomic::AtomicU64,
+    /// Requests that found the processing lock held by another replica
+    pub lock_contended: std::sync::atomic::AtomicU64,
+    /// Contended requests that gave up waiting and processed the image themselves
+    pub lock_timeouts: std::sync::atomic::AtomicU64,
+    /// Requests that shared the resize of an identical one in flight on this replica
+    pub coalesced: std::sync::atomic::AtomicU64,
 }
 
 impl PerformanceMetrics {
@@ -205,6 +395,26 @@ impl PerformanceMetrics {
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     }
 
+    pub fn increment_lock_acquired(&self) {
+        self.lock_acquired
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn increment_lock_contended(&self) {
+        self.lock_contended
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn increment_lock_timeouts(&self) {
+        self.lock_timeouts
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn increment_coalesced(&self) {
+        self.coalesced
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
     pub fn get_cache_hit_ratio(&self) -> f64 {
         let hits = self.cache_hits.load(std::sync::atomic::Ordering::Relaxed);
         let misses = self.cache_misses.load(std::sync::atomic::Ordering::Relaxed);
@@ -218,10 +428,22 @@ impl PerformanceMetrics {
     }
 }
 
+impl PipelineObserver for PerformanceMetrics {
+    fn on_lookup(&self, _resize: &Observed, cached: Option<&ObjectMetadata>) {
+        self.increment_requests();
+        if cached.is_some() {
+            self.increment_cache_hits();
+        } else {
+            self.increment_cache_misses();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::modules::env::env::EnvConfig;
+    use crate::modules::env::role::Role;
     use std::time::Duration;
 
     #[test]
@@ -230,8 +452,12 @@ mod tests {
         let env_config = EnvConfig {
             http_host: "0.0.0.0".to_string(),
             http_port: 3000,
+            role: Role::All,
+            #[cfg(feature = "grpc")]
+            grpc_port: 50051,
             storage_type: None,
             sub_path: "".to_string(),
+            content_addressed_keys: false,
             #[cfg(feature = "s3")]
             minio_endpoint_url: "http://localhost:9000".to_string(),
             #[cfg(feature = "s3")]
@@ -244,7 +470,125 @@ mod tests {
             minio_region: "us-east-1".to_string(),
             #[cfg(feature = "local_fs")]
             local_fs_storage_path: "./data/images".to_string(),
+            #[cfg(feature = "redis_storage")]
+            redis_storage_ttl_secs: 0,
+            #[cfg(feature = "wasm_plugins")]
+            wasm_plugin_dir: None,
+            #[cfg(feature = "wasm_plugins")]
+            wasm_plugin_fuel: 1_000_000_000,
+            #[cfg(feature = "wasm_plugins")]
+            wasm_plugin_max_memory_mb: 64,
+            #[cfg(feature = "scripting")]
+            rewrite_script_path: None,
+            admin_token: None,
+            admin_tokens: None,
+            signing_key: None,
+            audit_log_storage: false,
+            public_base_url: None,
+            upload_url_ttl_secs: 900,
+            upload_scanner: None,
+            upload_scan_config: None,
+            upload_scan_timeout_secs: 30,
+            #[cfg(any(
+                feature = "redis_lock",
+                feature = "redis_queue",
+                feature = "redis_tiering",
+                feature = "redis_storage"
+            ))]
+            redis_url: None,
+            #[cfg(feature = "redis_lock")]
+            lock_ttl_secs: 30,
+            #[cfg(feature = "redis_lock")]
+            lock_wait_timeout_secs: 10,
+            job_workers: 2,
+            job_max_attempts: 3,
+            #[cfg(feature = "redis_queue")]
+            job_visibility_timeout_secs: 300,
+            #[cfg(feature = "nats_events")]
+            nats_url: None,
+            #[cfg(feature = "nats_events")]
+            event_subject: "emgr.images.processed".to_string(),
+            peer_urls: None,
+            peer_self_url: None,
+            peer_token: None,
+            peer_cache_max_mb: 256,
+            peer_timeout_ms: 200,
+            auto_quality_max_dssim: 0.01,
+            auto_quality_min: 40,
+            auto_quality_max: 90,
+            auto_quality_max_attempts: 6,
+            max_bytes_min_quality: 20,
+            max_bytes_downscale: true,
+            origin_tls_config: None,
+            object_lock_config: None,
+            tenant_defaults_config: None,
+            allocator_purge_delay_ms: None,
+            allocator_arena_reserve_mb: None,
+            rss_watchdog_threshold_mb: 0,
+            rss_watchdog_interval_secs: 30,
+            rss_watchdog_drop_caches: false,
+            memory_budget_mb: 0,
+            source_probe_kb: 0,
+            source_max_megapixels: 100,
+            complexity_budget: 0.0,
+            complexity_allowlist: None,
+            tiering_cold_after_days: 0,
+            tiering_cold_storage_class: "STANDARD_IA".to_string(),
+            tiering_hot_storage_class: "STANDARD".to_string(),
+            tiering_interval_secs: 3600,
+            deadline_min_cold_miss_ms: 250,
+            moderation_url: None,
+            moderation_action: "flag".to_string(),
+            moderation_threshold: 0.8,
+            moderation_config: None,
+            moderation_timeout_ms: 2000,
+            source_cache_ttl_secs: 0,
+            pregen_manifest: None,
+            pregen_interval_secs: 3600,
+            pregen_concurrency: 2,
+            art_direction_manifest: None,
+            watermark_cache_size: 32,
+            watermark_cache_ttl_secs: 300,
+            matting_url: None,
+            matting_timeout_ms: 10000,
+            metrics_origin_labels: 0,
+            metrics_tenant_labels: 0,
+            report_window_secs: 3600,
+            maintenance_mode: false,
+            mirror_base_url: None,
+            mirror_percent: 10.0,
+            mirror_timeout_ms: 10000,
+            canary_pipeline: None,
+            canary_percent: 0.0,
+            canary_name: "canary".to_string(),
+            download_queue_timeout_ms: 10000,
+            json_inline_max_bytes: 16384,
+            download_max_mb_per_sec: 0.0,
+            download_max_mb_per_sec_per_host: 0.0,
+            #[cfg(feature = "dns_cache")]
+            dns_cache_enabled: true,
+            #[cfg(feature = "dns_cache")]
+            dns_cache_size: 1024,
+            #[cfg(feature = "dns_cache")]
+            dns_min_ttl_secs: 0,
+            #[cfg(feature = "dns_cache")]
+            dns_max_ttl_secs: 300,
+            #[cfg(feature = "dns_cache")]
+            dns_ip_preference: "ipv4".to_string(),
+            default_format: "jpg".to_string(),
+            jpeg_quality: 75,
+            png_compression: "fast".to_string(),
+            auto_sharpen: false,
+            #[cfg(feature = "video")]
+            ffmpeg_path: "ffmpeg".to_string(),
+            #[cfg(feature = "video")]
+            ffmpeg_timeout_secs: 60,
             cdn_base_url: "http://localhost:9000/image-cache".to_string(),
+            cache_control: "public, max-age=31536000, immutable".to_string(),
+            redirect_cache_control: "no-cache".to_string(),
+            cdn_cache_control: None,
+            stale_if_error_secs: 0,
+            vary: None,
             #[cfg(feature = "otel")]
             log_level: "debug".to_string(),
             #[cfg(feature = "otel")]
@@ -253,15 +597,25 @@ mod tests {
             otlp_metric_endpoint: "http://localhost:4318/v1/metrics".to_string(),
             #[cfg(feature = "otel")]
             otlp_service_name: "rust-app-example".to_string(),
+            #[cfg(feature = "otel")]
+            otlp_sample_ratio: 1.0,
             // Performance settings
             max_concurrent_downloads: Some(20),
             max_concurrent_processing: None,
             http_timeout_secs: Some(30),
+            connect_timeout_ms: None,
+            first_byte_timeout_ms: None,
+            idle_timeout_ms: None,
             max_image_size_mb: Some(50),
             cpu_thread_pool_size: None,
             enable_http2: Some(true),
+            force_http2_prior_knowledge: None,
             connection_pool_size: Some(50),
             keep_alive_timeout_secs: Some(60),
+            max_decode_width: None,
+            max_decode_height: None,
+            max_decode_alloc_mb: None,
+            encode_concurrency: None,
             performance_profile: None,
         };
 
@@ -269,12 +623,14 @@ mod tests {
 
         assert_eq!(perf_config.max_concurrent_downloads, 20);
         assert_eq!(perf_config.max_concurrent_processing, num_cpus::get());
-        assert_eq!(perf_config.http_timeout, Duration::from_secs(30));
+        assert_eq!(perf_config.connect_timeout, Duration::from_secs(30));
+        assert_eq!(perf_config.idle_timeout, Duration::from_secs(30));
         assert_eq!(perf_config.max_image_size, 50 * 1024 * 1024);
         assert_eq!(perf_config.cpu_thread_pool_size, None);
         assert_eq!(perf_config.enable_http2, true);
         assert_eq!(perf_config.connection_pool_size, 50);
         assert_eq!(perf_config.keep_alive_timeout, Duration::from_secs(60));
+        assert_eq!(perf_config.encode_limits.get(ImageFormat::Avif), Some(2));
     }
 
     #[test]
@@ -282,8 +638,12 @@ mod tests {
         let env_config = EnvConfig {
             http_host: "0.0.0.0".to_string(),
             http_port: 3000,
+            role: Role::All,
+            #[cfg(feature = "grpc")]
+            grpc_port: 50051,
             storage_type: None,
             sub_path: "".to_string(),
+            content_addressed_keys: false,
             #[cfg(feature = "s3")]
             minio_endpoint_url: "http://localhost:9000".to_string(),
             #[cfg(feature = "s3")]
@@ -296,7 +656,125 @@ mod tests {
             minio_region: "us-east-1".to_string(),
             #[cfg(feature = "local_fs")]
             local_fs_storage_path: "./data/images".to_string(),
+            #[cfg(feature = "redis_storage")]
+            redis_storage_ttl_secs: 0,
+            #[cfg(feature = "wasm_plugins")]
+            wasm_plugin_dir: None,
+            #[cfg(feature = "wasm_plugins")]
+            wasm_plugin_fuel: 1_000_000_000,
+            #[cfg(feature = "wasm_plugins")]
+            wasm_plugin_max_memory_mb: 64,
+            #[cfg(feature = "scripting")]
+            rewrite_script_path: None,
+            admin_token: None,
+            admin_tokens: None,
+            signing_key: None,
+            audit_log_storage: false,
+            public_base_url: None,
+            upload_url_ttl_secs: 900,
+            upload_scanner: None,
+            upload_scan_config: None,
+            upload_scan_timeout_secs: 30,
+            #[cfg(any(
+                feature = "redis_lock",
+                feature = "redis_queue",
+                feature = "redis_tiering",
+                feature = "redis_storage"
+            ))]
+            redis_url: None,
+            #[cfg(feature = "redis_lock")]
+            lock_ttl_secs: 30,
+            #[cfg(feature = "redis_lock")]
+            lock_wait_timeout_secs: 10,
+            job_workers: 2,
+            job_max_attempts: 3,
+            #[cfg(feature = "redis_queue")]
+            job_visibility_timeout_secs: 300,
+            #[cfg(feature = "nats_events")]
+            nats_url: None,
+            #[cfg(feature = "nats_events")]
+            event_subject: "emgr.images.processed".to_string(),
+            peer_urls: None,
+            peer_self_url: None,
+            peer_token: None,
+            peer_cache_max_mb: 256,
+            peer_timeout_ms: 200,
+            auto_quality_max_dssim: 0.01,
+            auto_quality_min: 40,
+            auto_quality_max: 90,
+            auto_quality_max_attempts: 6,
+            max_bytes_min_quality: 20,
+            max_bytes_downscale: true,
+            origin_tls_config: None,
+            object_lock_config: None,
+            tenant_defaults_config: None,
+            allocator_purge_delay_ms: None,
+            allocator_arena_reserve_mb: None,
+            rss_watchdog_threshold_mb: 0,
+            rss_watchdog_interval_secs: 30,
+            rss_watchdog_drop_caches: false,
+            memory_budget_mb: 0,
+            source_probe_kb: 0,
+            source_max_megapixels: 100,
+            complexity_budget: 0.0,
+            complexity_allowlist: None,
+            tiering_cold_after_days: 0,
+            tiering_cold_storage_class: "STANDARD_IA".to_string(),
+            tiering_hot_storage_class: "STANDARD".to_string(),
+            tiering_interval_secs: 3600,
+            deadline_min_cold_miss_ms: 250,
+            moderation_url: None,
+            moderation_action: "flag".to_string(),
+            moderation_threshold: 0.8,
+            moderation_config: None,
+            moderation_timeout_ms: 2000,
+            source_cache_ttl_secs: 0,
+            pregen_manifest: None,
+            pregen_interval_secs: 3600,
+            pregen_concurrency: 2,
+            art_direction_manifest: None,
+            watermark_cache_size: 32,
+            watermark_cache_ttl_secs: 300,
+            matting_url: None,
+            matting_timeout_ms: 10000,
+            metrics_origin_labels: 0,
+            metrics_tenant_labels: 0,
+            report_window_secs: 3600,
+            maintenance_mode: false,
+            mirror_base_url: None,
+            mirror_percent: 10.0,
+            mirror_timeout_ms: 10000,
+            canary_pipeline: None,
+            canary_percent: 0.0,
+            canary_name: "canary".to_string(),
+            download_queue_timeout_ms: 10000,
+            json_inline_max_bytes: 16384,
+            download_max_mb_per_sec: 0.0,
+            download_max_mb_per_sec_per_host: 0.0,
+            #[cfg(feature = "dns_cache")]
+            dns_cache_enabled: true,
+            #[cfg(feature = "dns_cache")]
+            dns_cache_size: 1024,
+            #[cfg(feature = "dns_cache")]
+            dns_min_ttl_secs: 0,
+            #[cfg(feature = "dns_cache")]
+            dns_max_ttl_secs: 300,
+            #[cfg(feature = "dns_cache")]
+            dns_ip_preference: "ipv4".to_string(),
+            default_format: "jpg".to_string(),
+            jpeg_quality: 75,
+            png_compression: "fast".to_string(),
+            auto_sharpen: false,
+            #[cfg(feature = "video")]
+            ffmpeg_path: "ffmpeg".to_string(),
+            #[cfg(feature = "video")]
+            ffmpeg_timeout_secs: 60,
             cdn_base_url: "http://localhost:9000/image-cache".to_string(),
+            cache_control: "public, max-age=31536000, immutable".to_string(),
+            redirect_cache_control: "no-cache".to_string(),
+            cdn_cache_control: None,
+            stale_if_error_secs: 0,
+            vary: None,
             #[cfg(feature = "otel")]
             log_level: "debug".to_string(),
             #[cfg(feature = "otel")]
@@ -305,15 +783,25 @@ mod tests {
             otlp_metric_endpoint: "http://localhost:4318/v1/metrics".to_string(),
             #[cfg(feature = "otel")]
             otlp_service_name: "rust-app-example".to_string(),
+            #[cfg(feature = "otel")]
+            otlp_sample_ratio: 1.0,
             // Custom performance settings
             max_concurrent_downloads: Some(100),
             max_concurrent_processing: Some(8),
             http_timeout_secs: Some(15),
+            connect_timeout_ms: Some(2000),
+            first_byte_timeout_ms: None,
+            idle_timeout_ms: None,
             max_image_size_mb: Some(100),
             cpu_thread_pool_size: Some(4),
             enable_http2: Some(false),
+            force_http2_prior_knowledge: None,
             connection_pool_size: Some(25),
             keep_alive_timeout_secs: Some(120),
+            max_decode_width: Some(8000),
+            max_decode_height: None,
+            max_decode_alloc_mb: Some(256),
+            encode_concurrency: Some("avif=0, png=4".parse().unwrap()),
             performance_profile: None,
         };
 
@@ -321,11 +809,21 @@ mod tests {
 
         assert_eq!(perf_config.max_concurrent_downloads, 100);
         assert_eq!(perf_config.max_concurrent_processing, 8);
-        assert_eq!(perf_config.http_timeout, Duration::from_secs(15));
+        assert_eq!(perf_config.connect_timeout, Duration::from_millis(2000));
+        assert_eq!(perf_config.first_byte_timeout, Duration::from_secs(15));
+        assert_eq!(perf_config.idle_timeout, Duration::from_secs(15));
         assert_eq!(perf_config.max_image_size, 100 * 1024 * 1024);
         assert_eq!(perf_config.cpu_thread_pool_size, Some(4));
         assert_eq!(perf_config.enable_http2, false);
         assert_eq!(perf_config.connection_pool_size, 25);
         assert_eq!(perf_config.keep_alive_timeout, Duration::from_secs(120));
+        let limits = perf_config.decode_limits();
+        assert_eq!(limits.max_image_width, Some(8000));
+        assert_eq!(limits.max_image_height, None);
+        assert_eq!(limits.max_alloc, Some(256 * 1024 * 1024));
+        assert_eq!(perf_config.encode_limits.get(ImageFormat::Avif), None);
+        assert_eq!(perf_config.encode_limits.get(ImageFormat::Png), Some(4));
+        assert!("mp4=1".parse::<EncodeLimits>().is_err());
+        assert!("avif".parse::<EncodeLimits>().is_err());
     }
 }
@@ -0,0 +1,60 @@
+//! EmgR image resizing pipeline as a library.
+//!
+//! The HTTP server in `main.rs` is a thin wrapper around this crate; other Rust
+//! services can embed the same pipeline in-process instead of calling it over HTTP.
+//!
+//! The pipeline is made of four services:
+//!
+//! - [`ImageService`] downloads source images and resizes/encodes them on a dedicated CPU pool.
+//! - [`StorageService`] stores processed images through a [`StorageBackend`]
+//!   (S3, local file system or in-memory, depending on the enabled features).
+//! - [`CacheService`] derives deterministic storage keys from the resize parameters.
+//! - [`ResizeService`] ties them together: cache lookup, download, processing and upload.
+//!
+//! Custom filters can be plugged between resizing and encoding by implementing
+//! [`TransformPlugin`] and registering it in a [`PluginRegistry`] passed to
+//! [`ResizeService::with_plugins`]. Requests select plugins with the `plugin` parameter.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use emgr::{
+//!     CacheServiceBuilder, PerformanceConfig, ResizeQuery, ResizeService, StorageConfig,
+//!     StorageService,
+//! };
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let storage = StorageService::new(StorageConfig::new("https://cdn.example.com".to_string()))?;
+//! let cache = CacheServiceBuilder::default()
+//!     .minio_sub_path(String::new())
+//!     .build()?;
+//! let resizer = ResizeService::with_config(storage, cache, PerformanceConfig::default())?;
+//!
+//! let query = ResizeQuery {
+//!     url: "https://example.com/image.jpg".to_string(),
+//!     width: Some(300),
+//!     format: Some(emgr::ImageFormat::Webp),
+//!     ..Default::default()
+//! };
+//! let result = resizer.resize(&query, None).await?;
+//! println!("Resized image available at {}", result.url);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod config;
+pub mod models;
+pub mod modules;
+pub mod services;
+
+pub use config::performance::PerformanceConfig;
+pub use gen_server::models::ImageFormat;
+pub use models::params::ResizeQuery;
+pub use services::cache::handler::{CacheService, CacheServiceBuilder};
+pub use services::image::handler::ImageService;
+pub use services::plugin::core::TransformPlugin;
+pub use services::plugin::handler::PluginRegistry;
+pub use services::resize::handler::{ResizeResult, ResizeService};
+pub use services::resize::observer::PipelineObserver;
+pub use services::storage::core::{ObjectMetadata, StorageBackend};
+pub use services::storage::handler::{StorageConfig, StorageService};
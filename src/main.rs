@@ -1,17 +1,15 @@
-use crate::modules::api::handler::ApiService;
-use crate::modules::env::env::EnvConfig;
-use crate::modules::router::router::router;
+use emgr::config::allocator::AllocatorConfig;
+use emgr::modules::api::handler::ApiService;
+use emgr::modules::env::env::EnvConfig;
+use emgr::modules::router::router::router;
+use emgr::services::pregen::handler::Pregenerator;
+use emgr::services::watchdog::handler::RssWatchdog;
 
 use envconfig::Envconfig;
 use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
 use tracing::{debug, info};
 
-mod config;
-mod models;
-mod modules;
-mod services;
-
 use mimalloc::MiMalloc;
 
 #[global_allocator]
@@ -20,39 +18,91 @@ static GLOBAL: MiMalloc = MiMalloc;
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = EnvConfig::init_from_env()?;
+    AllocatorConfig::from(&config).apply();
 
     // Initialize tracing and OpenTelemetry
     #[cfg(feature = "otel")]
-    let (metrics, trace_provider, meter_provider) = modules::tracer::init_tracing(config.clone()).await?;
+    let (metrics, trace_provider, meter_provider) =
+        emgr::modules::tracer::init_tracing(config.clone()).await?;
 
     // Get address to listen on
     let addr = format!("{}:{:?}", config.http_host, config.http_port).parse::<SocketAddr>()?;
-    let listener = TcpListener::bind(addr).await?;
-    debug!(config.http_port, config.http_host, "Will start");
+    let role = config.role;
+    debug!(config.http_port, config.http_host, ?role, "Will start");
     debug!(
         config.max_concurrent_downloads,
-        config.max_concurrent_processing, 
-        config.http_timeout_secs, 
-        config.max_image_size_mb, 
-        config.cpu_thread_pool_size, 
-        config.enable_http2, 
-        config.connection_pool_size, 
-        config.keep_alive_timeout_secs, 
-        config.performance_profile, 
+        config.max_concurrent_processing,
+        config.http_timeout_secs,
+        config.connect_timeout_ms,
+        config.first_byte_timeout_ms,
+        config.idle_timeout_ms,
+        config.max_image_size_mb,
+        config.cpu_thread_pool_size,
+        config.enable_http2,
+        config.force_http2_prior_knowledge,
+        config.connection_pool_size,
+        config.keep_alive_timeout_secs,
+        config.performance_profile,
         "Performance configuration"
     );
 
+    #[cfg(feature = "grpc")]
+    let grpc_addr = format!("{}:{}", config.http_host, config.grpc_port).parse::<SocketAddr>()?;
+
+    let watchdog_config = config.clone();
+    let pregenerator = Pregenerator::from_env(&config)?;
     let api_service = Arc::new(ApiService::create(config)?);
 
-    #[cfg(feature = "otel")]
-    let app = router(metrics, api_service).await?;
+    if let Some(watchdog) =
+        RssWatchdog::from_env(&watchdog_config, api_service.resize_service.clone())
+    {
+        tokio::spawn(watchdog.run());
+    }
+
+    // Process the background jobs and tiering sweeps next to the API, or on their own
+    if role.processes_jobs() {
+        let resize_service = api_service.resize_service.clone();
+        if let Some(tiering) = resize_service.tiering().cloned() {
+            tokio::spawn(tiering.run(resize_service.clone()));
+        }
+        if let Some(pregenerator) = pregenerator {
+            tokio::spawn(pregenerator.run(resize_service));
+        }
 
-    #[cfg(not(feature = "otel"))]
-    let app = router(api_service).await?;
+        let api_service = api_service.clone();
+        tokio::spawn(async move {
+            let resize_service = api_service.resize_service.clone();
+            api_service.job_service.run_workers(resize_service).await;
+        });
+    }
+
+    if role.serves_api() {
+        let listener = TcpListener::bind(addr).await?;
+
+        // Serve the gRPC API next to the REST one
+        #[cfg(feature = "grpc")]
+        {
+            let api_service = api_service.clone();
+            tokio::spawn(async move {
+                if let Err(e) = emgr::modules::grpc::server::serve(api_service, grpc_addr).await {
+                    tracing::error!("gRPC server failed: {}", e);
+                }
+            });
+        }
 
-    // Start the server
-    info!("Server running on http://{:?}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+        #[cfg(feature = "otel")]
+        let app = router(metrics, api_service).await?;
+
+        #[cfg(not(feature = "otel"))]
+        let app = router(api_service).await?;
+
+        // Start the server
+        info!("Server running on http://{:?}", listener.local_addr()?);
+        axum::serve(listener, app).await?;
+    } else {
+        info!("Running as a job worker, without listener");
+        tokio::signal::ctrl_c().await?;
+    }
 
     #[cfg(feature = "otel")]
     {
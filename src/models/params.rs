@@ -1,7 +1,197 @@
-use gen_server::models::{ImageFormat, ResizeQueryParams};
+use anyhow::{Result, bail};
+use gen_server::models::{ConvertQueryParams, ImageFormat, ResizeQueryParams};
 use o2o::o2o;
+use serde::{Deserialize, Serialize};
 
-#[derive(o2o, Clone, PartialEq, Debug)]
+/// Output format of queries without one, unless configured otherwise
+pub const DEFAULT_FORMAT: ImageFormat = ImageFormat::Jpg;
+/// Opacity of a `tint` given without one
+const DEFAULT_TINT_OPACITY: f32 = 0.3;
+/// Largest margin in pixels, matching the `Size` limit of the API
+const MAX_PADDING: u32 = 4096;
+/// Largest palette of `colors`
+const MAX_COLORS: u32 = 256;
+/// Longest side of a `fit=liquid` output, as seam carving costs a pass over the image per seam
+const MAX_LIQUID_SIZE: u32 = 1024;
+/// Longest name of a watermark
+const MAX_WATERMARK_NAME: usize = 64;
+/// Tolerance of a `remove_bg` given without one, as the largest difference of a channel
+const DEFAULT_BACKGROUND_TOLERANCE: u8 = 32;
+
+/// Whether images encoded to `format` can be transparent
+pub fn supports_transparency(format: &ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Webp | ImageFormat::Avif | ImageFormat::Gif
+    )
+}
+
+/// Whether images encoded to `format` can be animated
+pub fn supports_animation(format: &ImageFormat) -> bool {
+    matches!(format, ImageFormat::Gif | ImageFormat::Webp)
+}
+
+/// Whether images encoded to `format` honor `quality`, the others being lossless
+pub fn supports_quality(format: &ImageFormat) -> bool {
+    matches!(format, ImageFormat::Jpg | ImageFormat::Avif)
+}
+
+/// Whether `name` can name a watermark: letters, digits, `-` and `_`
+pub fn is_watermark_name(name: &str) -> bool {
+    (1..=MAX_WATERMARK_NAME).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Frame of an animated source to render as a still image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameSelector {
+    /// Frame number, starting at 0
+    Index(u32),
+    /// Time into the animation, in seconds
+    Time(f32),
+}
+
+/// Frames of an animated source kept in the output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeptFrames {
+    /// Every frame, resized one by one, for outputs that can be animated
+    All,
+    /// The first frame, as a still image
+    First,
+}
+
+/// How `remove_bg` tells the background apart
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BackgroundRemoval {
+    /// Pixels close to the color of the corners, connected to the edges of the image
+    Border { tolerance: u8 },
+    /// Pixels close to `color`, anywhere in the image
+    Chroma { color: [u8; 3], tolerance: u8 },
+    /// Mask computed by the external matting service
+    Matting,
+}
+
+/// How a reduced palette spreads the colors it lacks
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dither {
+    /// Nearest color of the palette, leaving flat areas
+    None,
+    /// Regular threshold pattern, compressing better than diffused error
+    Ordered,
+    /// Error diffused to the neighbors, closest to the original
+    FloydSteinberg,
+}
+
+/// Histogram of the image stretched by `auto_level`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AutoLevel {
+    /// Levels of the luminance applied to every channel, keeping the colors
+    Luminance,
+    /// Levels of each channel apart, also correcting color casts
+    Channels,
+}
+
+/// Interpolation of a resize, picked apart for downscales and upscales
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    /// Mitchell-Netravali cubic, free of the ringing of Lanczos on enlarged edges
+    Mitchell,
+}
+
+impl Interpolation {
+    const ALL: [Self; 6] = [
+        Self::Nearest,
+        Self::Triangle,
+        Self::CatmullRom,
+        Self::Gaussian,
+        Self::Lanczos3,
+        Self::Mitchell,
+    ];
+
+    /// Name of the interpolation, as accepted by `filter_down` and `filter_up`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Nearest => "nearest",
+            Self::Triangle => "triangle",
+            Self::CatmullRom => "catmull_rom",
+            Self::Gaussian => "gaussian",
+            Self::Lanczos3 => "lanczos3",
+            Self::Mitchell => "mitchell",
+        }
+    }
+
+    fn parse(param: &str, value: Option<&str>) -> Result<Option<Self>> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        match Self::ALL.into_iter().find(|filter| filter.name() == value) {
+            Some(filter) => Ok(Some(filter)),
+            None => bail!("Invalid {}: {}", param, value),
+        }
+    }
+}
+
+/// Resolution of the color channels of JPEG output, relative to the brightness
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChromaSubsampling {
+    /// Full resolution, keeping text and sharp colored edges crisp
+    Yuv444,
+    /// Half the horizontal resolution
+    Yuv422,
+    /// Half the resolution in both directions, the smallest for photos
+    Yuv420,
+    /// `Yuv444` for screenshots and graphics, `Yuv420` for photos
+    Auto,
+}
+
+impl ChromaSubsampling {
+    /// Name of the subsampling, as accepted by `subsampling`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yuv444 => "444",
+            Self::Yuv422 => "422",
+            Self::Yuv420 => "420",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+/// How an image is fitted to both a width and a height
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fit {
+    /// Scaled to cover them, the overflow cropped around the focal point
+    Cover,
+    /// Scaled to cover them, the overflow removed by seam carving where it has the least
+    /// detail, keeping subjects at both ends in frame
+    Liquid,
+}
+
+/// Margin of one side of the image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Padding {
+    Pixels(u32),
+    /// Percentage of the image width (left and right) or height (top and bottom)
+    Percent(f32),
+}
+
+impl Padding {
+    /// Margin in pixels, for an image side of `length` pixels
+    pub fn resolve(self, length: u32) -> u32 {
+        match self {
+            Self::Pixels(pixels) => pixels,
+            Self::Percent(percent) => (length as f32 * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+#[derive(o2o, Clone, PartialEq, Debug, Serialize, Deserialize)]
 #[from_owned(ResizeQueryParams)]
 pub struct ResizeQuery {
     pub url: String,
@@ -12,10 +202,498 @@ pub struct ResizeQuery {
     #[from(~.map(|x| x as u32))]
     pub height: Option<u32>,
 
-    #[from(~.unwrap_or_else(|| ImageFormat::Jpg))]
-    pub format: ImageFormat,
+    /// Output format, the configured default when unset
+    pub format: Option<ImageFormat>,
 
     pub blur_sigma: Option<f32>,
 
     pub grayscale: Option<bool>,
+
+    pub plugin: Option<String>,
+
+    pub ar: Option<String>,
+
+    pub zoom: Option<f32>,
+
+    #[from(~.map(|x| x as u32))]
+    pub density: Option<u32>,
+
+    pub quality: Option<String>,
+
+    #[from(~.map(|x| x as u32))]
+    pub max_bytes: Option<u32>,
+
+    pub denoise: Option<f32>,
+
+    pub vignette: Option<f32>,
+
+    pub tint: Option<String>,
+
+    #[from(~.map(|x| x as u32))]
+    pub pixelate: Option<u32>,
+
+    pub pixelate_region: Option<String>,
+
+    pub gamma: Option<f32>,
+
+    pub exposure: Option<f32>,
+
+    pub pad: Option<String>,
+
+    /// Focal point kept in frame when cropping, as `x,y` fractions of the source
+    pub focus: Option<String>,
+
+    #[from(~.map(|x| x as u32))]
+    pub frame: Option<u32>,
+
+    pub time: Option<f32>,
+
+    /// Name of the watermark of the tenant, qualified with the tenant once resolved
+    pub watermark: Option<String>,
+
+    pub remove_bg: Option<String>,
+
+    #[from(~.map(|x| x as u32))]
+    pub colors: Option<u32>,
+
+    pub dither: Option<String>,
+
+    pub auto_level: Option<String>,
+
+    pub filter_down: Option<String>,
+
+    pub filter_up: Option<String>,
+
+    pub subsampling: Option<String>,
+
+    pub auto_sharpen: Option<bool>,
+
+    pub fit: Option<String>,
+
+    pub frames: Option<String>,
+}
+
+impl ResizeQuery {
+    /// Names of the requested transform plugins, in the order they should run
+    pub fn plugins(&self) -> impl Iterator<Item = &str> {
+        self.plugin
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Requested aspect ratio (width / height), from `ar` given as `16:9`
+    pub fn aspect_ratio(&self) -> Result<Option<f64>> {
+        let Some(ar) = &self.ar else {
+            return Ok(None);
+        };
+
+        let ratio = ar
+            .split_once(':')
+            .and_then(|(w, h)| Some((w.trim().parse::<f64>().ok()?, h.trim().parse::<f64>().ok()?)))
+            .filter(|(w, h)| w.is_finite() && h.is_finite() && *w > 0.0 && *h > 0.0)
+            .map(|(w, h)| w / h);
+        match ratio {
+            Some(ratio) => Ok(Some(ratio)),
+            None => bail!("Invalid aspect ratio: {}", ar),
+        }
+    }
+
+    /// Requested color overlay as `(rgb, opacity)`, from `tint` given as `ff8800:0.4`
+    pub fn tint(&self) -> Result<Option<([u8; 3], f32)>> {
+        let Some(tint) = &self.tint else {
+            return Ok(None);
+        };
+
+        let (color, opacity) = match tint.split_once(':') {
+            Some((color, opacity)) => (color, opacity.trim().parse::<f32>().ok()),
+            None => (tint.as_str(), Some(DEFAULT_TINT_OPACITY)),
+        };
+        let color = color.trim().trim_start_matches('#');
+        let rgb = (color.len() == 6 && color.is_ascii())
+            .then(|| {
+                let channel = |i: usize| u8::from_str_radix(&color[i..i + 2], 16).ok();
+                Some([channel(0)?, channel(2)?, channel(4)?])
+            })
+            .flatten();
+        match (rgb, opacity) {
+            (Some(rgb), Some(opacity)) if (0.0..=1.0).contains(&opacity) => {
+                Ok(Some((rgb, opacity)))
+            }
+            _ => bail!("Invalid tint: {}", tint),
+        }
+    }
+
+    /// Requested pixelated area as `[x, y, width, height]`, from `pixelate_region`
+    pub fn pixelate_region(&self) -> Result<Option<[u32; 4]>> {
+        let Some(region) = &self.pixelate_region else {
+            return Ok(None);
+        };
+
+        let values: Vec<u32> = region
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .unwrap_or_default();
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Some([x, y, width, height])),
+            _ => bail!("Invalid pixelate region: {}", region),
+        }
+    }
+
+    /// Requested margins as `[top, right, bottom, left]`, from `pad` given as `10,5%,10,5%`
+    ///
+    /// Like CSS, 1 value applies to all sides, 2 to the vertical and horizontal sides and
+    /// 3 to the top, horizontal and bottom sides.
+    pub fn padding(&self) -> Result<Option<[Padding; 4]>> {
+        let Some(pad) = &self.pad else {
+            return Ok(None);
+        };
+
+        let parse = |value: &str| {
+            let value = value.trim();
+            match value.strip_suffix('%') {
+                Some(percent) => percent
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|percent| (0.0..=100.0).contains(percent))
+                    .map(Padding::Percent),
+                None => value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|pixels| *pixels <= MAX_PADDING)
+                    .map(Padding::Pixels),
+            }
+        };
+        let values: Option<Vec<Padding>> = pad.split(',').map(parse).collect();
+        match values.as_deref() {
+            Some(&[all]) => Ok(Some([all; 4])),
+            Some(&[vertical, horizontal]) => Ok(Some([vertical, horizontal, vertical, horizontal])),
+            Some(&[top, horizontal, bottom]) => Ok(Some([top, horizontal, bottom, horizontal])),
+            Some(&[top, right, bottom, left]) => Ok(Some([top, right, bottom, left])),
+            _ => bail!("Invalid padding: {}", pad),
+        }
+    }
+
+    /// Requested focal point as `(x, y)` fractions of the source, from `focus` given as `0.3,0.6`
+    pub fn focal_point(&self) -> Result<Option<(f32, f32)>> {
+        let Some(focus) = &self.focus else {
+            return Ok(None);
+        };
+        let fraction = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|value| (0.0..=1.0).contains(value))
+        };
+        match focus.split_once(',') {
+            Some((x, y)) => match (fraction(x), fraction(y)) {
+                (Some(x), Some(y)) => Ok(Some((x, y))),
+                _ => bail!("Invalid focus: {}", focus),
+            },
+            None => bail!("Invalid focus: {}", focus),
+        }
+    }
+
+    /// Requested background removal, from `remove_bg` given as `border:40`, `ffffff` or `matting`
+    pub fn background_removal(&self) -> Result<Option<BackgroundRemoval>> {
+        let Some(remove_bg) = &self.remove_bg else {
+            return Ok(None);
+        };
+
+        let (mode, tolerance) = match remove_bg.split_once(':') {
+            Some((mode, tolerance)) => (mode.trim(), tolerance.trim().parse::<u8>().ok()),
+            None => (remove_bg.trim(), Some(DEFAULT_BACKGROUND_TOLERANCE)),
+        };
+        let color = mode.trim_start_matches('#');
+        let rgb = (color.len() == 6 && color.is_ascii())
+            .then(|| {
+                let channel = |i: usize| u8::from_str_radix(&color[i..i + 2], 16).ok();
+                Some([channel(0)?, channel(2)?, channel(4)?])
+            })
+            .flatten();
+        match (mode, rgb, tolerance) {
+            ("matting", _, _) if !remove_bg.contains(':') => Ok(Some(BackgroundRemoval::Matting)),
+            ("border", _, Some(tolerance)) => Ok(Some(BackgroundRemoval::Border { tolerance })),
+            (_, Some(color), Some(tolerance)) => {
+                Ok(Some(BackgroundRemoval::Chroma { color, tolerance }))
+            }
+            _ => bail!("Invalid remove_bg: {}", remove_bg),
+        }
+    }
+
+    /// Histogram stretch requested by `auto_level`, `true` standing for the luminance
+    pub fn auto_level(&self) -> Result<Option<AutoLevel>> {
+        match self.auto_level.as_deref() {
+            None | Some("false") => Ok(None),
+            Some("true" | "luminance") => Ok(Some(AutoLevel::Luminance)),
+            Some("channels") => Ok(Some(AutoLevel::Channels)),
+            Some(auto_level) => bail!("Invalid auto_level: {}", auto_level),
+        }
+    }
+
+    /// Interpolation of downscales, from `filter_down`
+    pub fn filter_down(&self) -> Result<Option<Interpolation>> {
+        Interpolation::parse("filter_down", self.filter_down.as_deref())
+    }
+
+    /// Interpolation of upscales, from `filter_up`
+    pub fn filter_up(&self) -> Result<Option<Interpolation>> {
+        Interpolation::parse("filter_up", self.filter_up.as_deref())
+    }
+
+    /// Fitting of the image to both dimensions, from `fit`
+    pub fn fit(&self) -> Result<Fit> {
+        match self.fit.as_deref() {
+            None | Some("cover") => Ok(Fit::Cover),
+            Some("liquid") => Ok(Fit::Liquid),
+            Some(fit) => bail!("Invalid fit: {}", fit),
+        }
+    }
+
+    /// Chroma subsampling of JPEG output, from `subsampling`
+    pub fn subsampling(&self) -> Result<Option<ChromaSubsampling>> {
+        match self.subsampling.as_deref() {
+            None => Ok(None),
+            Some("444") => Ok(Some(ChromaSubsampling::Yuv444)),
+            Some("422") => Ok(Some(ChromaSubsampling::Yuv422)),
+            Some("420") => Ok(Some(ChromaSubsampling::Yuv420)),
+            Some("auto") => Ok(Some(ChromaSubsampling::Auto)),
+            Some(subsampling) => bail!("Invalid subsampling: {}", subsampling),
+        }
+    }
+
+    /// Dithering of the reduced palette, from `dither`
+    pub fn dither(&self) -> Result<Dither> {
+        match self.dither.as_deref() {
+            None | Some("none") => Ok(Dither::None),
+            Some("ordered") => Ok(Dither::Ordered),
+            Some("floyd_steinberg") => Ok(Dither::FloydSteinberg),
+            Some(dither) => bail!("Invalid dither: {}", dither),
+        }
+    }
+
+    /// Frames of an animated source kept in the output, from `frames`
+    pub fn frames(&self) -> Result<KeptFrames> {
+        match self.frames.as_deref() {
+            None | Some("all") => Ok(KeptFrames::All),
+            Some("first") => Ok(KeptFrames::First),
+            Some(frames) => bail!("Invalid frames: {}", frames),
+        }
+    }
+
+    /// Whether animated sources stay animated: only GIF and WebP output can be, and a
+    /// selected frame is a still image
+    pub fn keeps_animation(&self) -> Result<bool> {
+        Ok(supports_animation(&self.output_format())
+            && self.frames()? == KeptFrames::All
+            && self.frame_selector()?.is_none())
+    }
+
+    /// Requested frame of an animated source, from `frame` or `time`
+    pub fn frame_selector(&self) -> Result<Option<FrameSelector>> {
+        match (self.frame, self.time) {
+            (Some(_), Some(_)) => bail!("frame and time can't be combined"),
+            (Some(frame), None) => Ok(Some(FrameSelector::Index(frame))),
+            (None, Some(time)) if time.is_finite() && time >= 0.0 => {
+                Ok(Some(FrameSelector::Time(time)))
+            }
+            (None, Some(time)) => bail!("Invalid time: {}", time),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Whether the encoder quality should be searched against the perceptual budget
+    pub fn auto_quality(&self) -> bool {
+        self.quality.as_deref() == Some("auto")
+    }
+
+    /// Requested encoder quality from 1 to 100, `None` when unset or searched with `auto`
+    pub fn fixed_quality(&self) -> Result<Option<u8>> {
+        match self.quality.as_deref() {
+            None | Some("auto") => Ok(None),
+            Some(quality) => match quality.trim().parse::<u8>() {
+                Ok(value) if (1..=100).contains(&value) => Ok(Some(value)),
+                _ => bail!("Invalid quality: {}", quality),
+            },
+        }
+    }
+
+    /// Format of the output, falling back to JPEG, or PNG for removed backgrounds, for
+    /// queries not resolved against the configured defaults
+    pub fn output_format(&self) -> ImageFormat {
+        match self.format {
+            Some(format) => format,
+            None if self.remove_bg.is_some() => ImageFormat::Png,
+            None => DEFAULT_FORMAT,
+        }
+    }
+
+    /// Whether the output is a video transcoded from an animated source
+    pub fn is_video(&self) -> bool {
+        matches!(self.output_format(), ImageFormat::Mp4 | ImageFormat::Webm)
+    }
+
+    /// Check the parameters that can be rejected before downloading the source
+    pub fn validate(&self) -> Result<()> {
+        self.aspect_ratio()?;
+
+        if let Some(zoom) = self.zoom
+            && !(zoom.is_finite() && zoom > 0.0)
+        {
+            bail!("Invalid zoom: {}", zoom);
+        }
+        // JFIF stores the density in 16 bits
+        if let Some(density) = self.density
+            && !(1..=u16::MAX as u32).contains(&density)
+        {
+            bail!("Invalid density: {}", density);
+        }
+
+        if let Some(denoise) = self.denoise
+            && !(0.0..=100.0).contains(&denoise)
+        {
+            bail!("Invalid denoise strength: {}", denoise);
+        }
+
+        if let Some(vignette) = self.vignette
+            && !(0.0..=1.0).contains(&vignette)
+        {
+            bail!("Invalid vignette strength: {}", vignette);
+        }
+        self.tint()?;
+
+        if let Some(gamma) = self.gamma
+            && !(gamma.is_finite() && gamma > 0.0)
+        {
+            bail!("Invalid gamma: {}", gamma);
+        }
+        if let Some(exposure) = self.exposure
+            && !(-10.0..=10.0).contains(&exposure)
+        {
+            bail!("Invalid exposure: {}", exposure);
+        }
+
+        self.padding()?;
+        self.focal_point()?;
+        if self.frame_selector()?.is_some() && self.frames.as_deref() == Some("all") {
+            bail!("frames=all can't be combined with frame or time");
+        }
+        self.frames()?;
+
+        if self.pixelate == Some(0) {
+            bail!("Invalid pixelate block size: 0");
+        }
+        if self.pixelate_region()?.is_some() && self.pixelate.is_none() {
+            bail!("pixelate_region requires pixelate");
+        }
+
+        if let Some(watermark) = &self.watermark
+            && !is_watermark_name(watermark)
+        {
+            bail!("Invalid watermark: {}", watermark);
+        }
+
+        if self.background_removal()?.is_some() && !supports_transparency(&self.output_format()) {
+            bail!(
+                "remove_bg needs an output format with transparency, not {}",
+                self.output_format()
+            );
+        }
+
+        if let Some(colors) = self.colors
+            && !(2..=MAX_COLORS).contains(&colors)
+        {
+            bail!("Invalid colors: {}", colors);
+        }
+        self.dither()?;
+        if self.dither.is_some() && self.colors.is_none() {
+            bail!("dither requires colors");
+        }
+        self.auto_level()?;
+        self.filter_down()?;
+        self.filter_up()?;
+        self.subsampling()?;
+        if self.fit()? == Fit::Liquid {
+            if !cfg!(feature = "seam_carving") {
+                bail!("fit=liquid requires the seam_carving feature");
+            }
+            let (width, height) = match (self.width, self.height, self.aspect_ratio()?) {
+                (Some(w), Some(h), _) => (w as f64, h as f64),
+                (Some(w), None, Some(ratio)) => (w as f64, w as f64 / ratio),
+                (None, Some(h), Some(ratio)) => (h as f64 * ratio, h as f64),
+                _ => bail!("fit=liquid requires a width and a height, or one of them and ar"),
+            };
+            if width.max(height) > MAX_LIQUID_SIZE as f64 {
+                bail!(
+                    "fit=liquid is limited to {} pixels per side",
+                    MAX_LIQUID_SIZE
+                );
+            }
+        }
+
+        if self.max_bytes == Some(0) {
+            bail!("Invalid max_bytes: 0");
+        }
+
+        self.fixed_quality()?;
+        Ok(())
+    }
+}
+
+impl Default for ResizeQuery {
+    /// An empty query producing the default format without any transformation
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            width: None,
+            height: None,
+            format: None,
+            blur_sigma: None,
+            grayscale: None,
+            plugin: None,
+            ar: None,
+            zoom: None,
+            density: None,
+            quality: None,
+            max_bytes: None,
+            denoise: None,
+            vignette: None,
+            tint: None,
+            pixelate: None,
+            pixelate_region: None,
+            gamma: None,
+            exposure: None,
+            pad: None,
+            focus: None,
+            frame: None,
+            time: None,
+            watermark: None,
+            remove_bg: None,
+            colors: None,
+            dither: None,
+            auto_level: None,
+            filter_down: None,
+            filter_up: None,
+            subsampling: None,
+            auto_sharpen: None,
+            fit: None,
+            frames: None,
+        }
+    }
+}
+
+impl From<ConvertQueryParams> for ResizeQuery {
+    /// A query only changing the format, and the quality, of the source
+    fn from(params: ConvertQueryParams) -> Self {
+        Self {
+            url: params.url,
+            format: params.format,
+            quality: params.quality,
+            ..Self::default()
+        }
+    }
 }
@@ -0,0 +1,29 @@
+use crate::modules::env::env::EnvConfig;
+
+/// Settings of the JSON envelopes answered for `response=json`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JsonEnvelope {
+    /// Largest resized image included as base64 for `inline=true`
+    pub inline_max_bytes: u64,
+}
+
+impl Default for JsonEnvelope {
+    fn default() -> Self {
+        Self {
+            inline_max_bytes: 16 * 1024,
+        }
+    }
+}
+
+impl JsonEnvelope {
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self {
+            inline_max_bytes: config.json_inline_max_bytes,
+        }
+    }
+
+    /// Whether a resized image of `size` bytes is small enough to be inlined
+    pub fn inlines(&self, size: Option<u64>) -> bool {
+        size.is_some_and(|size| size <= self.inline_max_bytes)
+    }
+}
@@ -1,15 +1,83 @@
 use crate::config::performance::PerformanceConfig;
+use crate::models::params::ResizeQuery;
+use crate::modules::api::envelope::JsonEnvelope;
 use crate::modules::env::env::EnvConfig;
+use crate::services::admin::handler::AdminSettings;
+use crate::services::audit::handler::AuditLog;
 use crate::services::cache::handler::CacheServiceBuilder;
-use crate::services::resize::handler::ResizeService;
-use crate::services::storage::handler::StorageService;
-use anyhow::Result;
+use crate::services::cache::source::SourceCache;
+use crate::services::cdn::handler::CacheHeaders;
+#[cfg(feature = "chaos")]
+use crate::services::chaos::handler::ChaosController;
+use crate::services::deadline::handler::{self as deadline, ColdMissEstimate};
+use crate::services::event::handler::EventPublisher;
+use crate::services::image::admission::DownloadAdmission;
+use crate::services::image::bandwidth::BandwidthLimiter;
+use crate::services::image::budget::ByteBudget;
+use crate::services::image::canary::CanaryPipeline;
+use crate::services::image::complexity::ComplexityBudget;
+#[cfg(feature = "dns_cache")]
+use crate::services::image::dns::DnsCache;
+use crate::services::image::encode::EncodingDefaults;
+use crate::services::image::matting::BackgroundMatting;
+use crate::services::image::memory::MemoryGuard;
+use crate::services::image::probe::SourceProbe;
+use crate::services::image::quality::AutoQuality;
+use crate::services::image::tls::OriginTlsConfig;
+#[cfg(feature = "video")]
+use crate::services::image::video::Transcoder;
+use crate::services::job::handler::JobService;
+use crate::services::lock::handler::ProcessingLock;
+use crate::services::mirror::handler::TrafficMirror;
+use crate::services::moderation::handler::Moderation;
+use crate::services::peer::handler::PeerCache;
+use crate::services::picture::handler::ArtDirection;
+use crate::services::plugin::handler::PluginRegistry;
+use crate::services::resize::errors::{ErrorCode, InvalidParams};
+use crate::services::resize::handler::{ResizePlan, ResizeResult, ResizeService};
+use crate::services::resize::metrics::PipelineMetrics;
+use crate::services::resize::report::Accounting;
+use crate::services::script::handler::ScriptHook;
+use crate::services::signing::handler::UrlSigner;
+use crate::services::storage::handler::{StorageConfig, StorageService};
+use crate::services::storage::retention::ObjectLockPolicy;
+use crate::services::tenant::handler::TenantPolicies;
+use crate::services::tiering::handler::StorageTiering;
+use crate::services::upload::handler::UploadService;
+use crate::services::upload::scan::UploadScanner;
+use crate::services::watermark::handler::WatermarkLibrary;
+use anyhow::{Context, Result};
 use derive_builder::Builder;
 use gen_server::apis::ErrorHandler;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 #[derive(Clone, Builder)]
 pub struct ApiService {
     pub resize_service: ResizeService,
+    pub upload_service: UploadService,
+    pub job_service: JobService,
+    #[builder(default)]
+    pub script_hook: ScriptHook,
+    #[builder(default)]
+    pub admin: Option<Arc<AdminSettings>>,
+    #[builder(default)]
+    pub audit_log: AuditLog,
+    #[builder(default)]
+    pub cache_headers: CacheHeaders,
+    #[builder(default)]
+    pub mirror: Option<TrafficMirror>,
+    #[builder(default)]
+    pub json_envelope: JsonEnvelope,
+    #[builder(default)]
+    pub art_direction: Option<ArtDirection>,
+    #[builder(default)]
+    pub url_signer: Option<UrlSigner>,
+    #[cfg(feature = "chaos")]
+    #[builder(default)]
+    pub chaos: ChaosController,
 }
 
 impl ApiService {
@@ -19,52 +87,152 @@ impl ApiService {
 
         // Initialize cache service
         let cache_service = CacheServiceBuilder::default()
-            .minio_sub_path(config.sub_path)
+            .minio_sub_path(config.sub_path.clone())
+            .content_addressed(config.content_addressed_keys)
             .build()?;
 
         // Create storage config
-        let mut storage_config =
-            crate::services::storage::handler::StorageConfig::new(config.cdn_base_url);
-
-        // Add storage type if specified
-        if let Some(storage_type) = config.storage_type {
-            storage_config = storage_config.with_storage_type(storage_type);
-        }
-
-        // Configure S3 storage
-        #[cfg(feature = "s3")]
-        {
-            storage_config = storage_config.with_s3_config(
-                config.minio_endpoint_url,
-                config.minio_access_key_id,
-                config.minio_secret_access_key,
-                config.minio_bucket,
-                config.minio_region,
-            );
-        }
-
-        // Configure local FS storage
-        #[cfg(feature = "local_fs")]
-        {
-            let path = std::path::PathBuf::from(config.local_fs_storage_path);
-
-            storage_config = storage_config.with_local_fs_config(path);
-        }
+        let storage_config = StorageConfig::from(&config);
 
         // Create storage service
         let storage_service = StorageService::new(storage_config)?;
+        #[cfg(feature = "chaos")]
+        let chaos = ChaosController::default();
+        #[cfg(feature = "chaos")]
+        let storage_service = storage_service.with_chaos(chaos.clone());
+        let audit_log = AuditLog::from_env(&config, &storage_service);
+        let upload_service = UploadService::new(
+            storage_service.clone(),
+            cache_service.clone(),
+            config.public_base_url.clone(),
+            Duration::from_secs(config.upload_url_ttl_secs),
+            performance_config.max_image_size as usize,
+        )
+        .with_scanner(UploadScanner::from_env(&config)?);
+        let watermarks = WatermarkLibrary::from_env(&config, &storage_service);
 
         // Initialize resize service with performance configuration
         let resize_service =
-            ResizeService::with_config(storage_service, cache_service, performance_config)?;
+            ResizeService::with_config(storage_service, cache_service, performance_config)?
+                .with_plugins(PluginRegistry::from_env(&config)?)
+                .with_memory_guard(MemoryGuard::from_env(&config))
+                .with_download_admission(DownloadAdmission::from_env(&config))
+                .with_probe(SourceProbe::from_env(&config))
+                .with_bandwidth(BandwidthLimiter::from_env(&config))
+                .with_encoding_defaults(EncodingDefaults::from_env(&config)?)
+                .with_auto_quality(AutoQuality::from_env(&config))
+                .with_byte_budget(ByteBudget::from_env(&config))
+                .with_lock(ProcessingLock::from_env(&config)?)
+                .with_peer_cache(PeerCache::from_env(&config)?)
+                .with_events(EventPublisher::from_env(&config)?)
+                .with_complexity_budget(ComplexityBudget::from_env(&config))
+                .with_tiering(StorageTiering::from_env(&config)?)
+                .with_canary(CanaryPipeline::from_env(&config)?)
+                .with_object_lock(ObjectLockPolicy::from_env(&config)?)
+                .with_tenant_defaults(TenantPolicies::from_env(&config)?)
+                .with_source_cache(SourceCache::from_env(&config))
+                .with_moderation(Moderation::from_env(&config)?)
+                .with_cold_miss_estimate(ColdMissEstimate::from_env(&config))
+                .with_watermarks(Some(watermarks))
+                .with_matting(BackgroundMatting::from_env(&config)?)
+                .with_pipeline_metrics(PipelineMetrics::from_env(&config))
+                .with_accounting(Accounting::from_env(&config));
+        #[cfg(feature = "video")]
+        let resize_service = resize_service.with_transcoder(Transcoder::from_env(&config));
+        #[cfg(feature = "dns_cache")]
+        let resize_service = match DnsCache::from_env(&config)? {
+            Some(dns_cache) => resize_service.with_dns_cache(dns_cache)?,
+            None => resize_service,
+        };
+        let resize_service = match OriginTlsConfig::from_env(&config)? {
+            Some(origin_tls) => resize_service.with_origin_tls(origin_tls)?,
+            None => resize_service,
+        };
+        resize_service.set_maintenance(config.maintenance_mode);
 
         // Create API service
-        let api_service = ApiServiceBuilder::default()
+        let mut builder = ApiServiceBuilder::default();
+        builder
             .resize_service(resize_service)
-            .build()?;
+            .upload_service(upload_service)
+            .job_service(JobService::from_env(&config)?)
+            .script_hook(ScriptHook::from_env(&config)?)
+            .admin(AdminSettings::from_env(&config)?.map(Arc::new))
+            .audit_log(audit_log)
+            .cache_headers(CacheHeaders::from_env(&config)?)
+            .mirror(TrafficMirror::from_env(&config)?)
+            .json_envelope(JsonEnvelope::from_env(&config))
+            .art_direction(ArtDirection::from_env(&config)?)
+            .url_signer(UrlSigner::from_env(&config));
+        #[cfg(feature = "chaos")]
+        builder.chaos(chaos);
+        let api_service = builder.build()?;
 
         Ok(api_service)
     }
+
+    /// Apply the rewrite script and resize, shared by the REST and gRPC APIs
+    ///
+    /// The resize runs in its own task, cancelled when this future is dropped because the
+    /// client disconnected, so it can stop its work and release the processing lock.
+    pub async fn resize_image(
+        &self,
+        query: ResizeQuery,
+        host: Option<&str>,
+    ) -> Result<ResizeResult> {
+        let query = self
+            .script_hook
+            .rewrite(query, host)
+            .map_err(InvalidParams)?;
+
+        let cancel = CancellationToken::new();
+        let _cancel_on_drop = cancel.clone().drop_guard();
+        let resize_service = self.resize_service.clone();
+        let host = host.map(str::to_string);
+        // The spawned task doesn't inherit the deadline of the request
+        let work = async move {
+            resize_service
+                .resize_cancellable(&query, host.as_deref(), &cancel)
+                .await
+        };
+        tokio::spawn(deadline::scope(deadline::current(), work).in_current_span())
+            .await
+            .context("Resize task failed")?
+    }
+
+    /// Change the format of an image, cancelled like [`ApiService::resize_image`]
+    ///
+    /// The rewrite script isn't applied, as it could turn the conversion into a resize.
+    pub async fn convert_image(
+        &self,
+        query: ResizeQuery,
+        host: Option<&str>,
+    ) -> Result<ResizeResult> {
+        let cancel = CancellationToken::new();
+        let _cancel_on_drop = cancel.clone().drop_guard();
+        let resize_service = self.resize_service.clone();
+        let host = host.map(str::to_string);
+        let work = async move {
+            resize_service
+                .convert_cancellable(&query, host.as_deref(), &cancel)
+                .await
+        };
+        tokio::spawn(deadline::scope(deadline::current(), work).in_current_span())
+            .await
+            .context("Conversion task failed")?
+    }
+
+    /// Work out what [`ApiService::resize_image`] would do, for `dry_run=true`
+    pub async fn plan_resize(&self, query: ResizeQuery, host: Option<&str>) -> ResizePlan {
+        match self.script_hook.rewrite(query.clone(), host) {
+            Ok(query) => self.resize_service.plan(&query, host).await,
+            Err(e) => ResizePlan {
+                error: Some(e.to_string()),
+                error_code: Some(ErrorCode::InvalidRequest),
+                ..self.resize_service.plan(&query, host).await
+            },
+        }
+    }
 }
 
 impl ErrorHandler<()> for ApiService {}
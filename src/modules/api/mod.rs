@@ -1,2 +1,3 @@
+pub mod envelope;
 pub mod handler;
 pub mod resize;
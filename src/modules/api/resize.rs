@@ -1,12 +1,27 @@
 use crate::models::params::ResizeQuery;
 use crate::modules::api::handler::ApiService;
+use crate::services::cdn::handler::FALLBACK_CACHE_CONTROL;
+use crate::services::deadline::handler::DeadlineTooShort;
+use crate::services::image::admission::AdmissionTimeout;
+use crate::services::image::complexity::ComplexityExceeded;
+use crate::services::resize::errors::ErrorCode;
+use crate::services::resize::handler::{ResizePlan, ResizeResult};
+use crate::services::storage::core::content_type_from_key;
+use anyhow::Result;
 use async_trait::async_trait;
 use axum::http::Method;
 use axum_extra::extract::{CookieJar, Host};
-use gen_server::apis::images::{DownloadResponse, Images, ResizeResponse};
-use gen_server::models::{DownloadPathParams, ResizeQueryParams};
-use gen_server::types::ByteArray;
-use tracing::log::error;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use gen_server::apis::images::{ConvertResponse, DownloadResponse, Images, ResizeResponse};
+use gen_server::models::{self, ConvertQueryParams, DownloadPathParams, ResizeQueryParams};
+use gen_server::types::{ByteArray, Object};
+use tracing::log::{error, warn};
+
+/// Seconds clients are asked to wait when work is shed
+const RETRY_AFTER_SECS: i32 = 2;
+/// Seconds clients are asked to wait for images not cached during maintenance
+const MAINTENANCE_RETRY_AFTER_SECS: i32 = 30;
 
 #[async_trait]
 impl Images for ApiService {
@@ -20,19 +35,34 @@ impl Images for ApiService {
         let byte_array = self.resize_service.download(path_params).await;
 
         match byte_array {
-            Ok(data) => Ok(DownloadResponse::Status200_OperationPerformedSuccessfully {
-                body: ByteArray(data),
-                cache_control: Some("public, max-age=31536000, immutable".to_string()),
-            }),
-            Err(e) => {
-                // Log the error but return a generic error to the client
-                tracing::error!("Failed to download image: {}", e);
-
-                // Since we don't have a 404 variant, we'll return an empty 200 response
-                // This is better than returning a generic error that causes unhandled errors
+            Ok((data, metadata)) => {
                 Ok(DownloadResponse::Status200_OperationPerformedSuccessfully {
-                    body: ByteArray(Vec::new()),
-                    cache_control: None,
+                    x_image_width: metadata.width.map(|width| width as i32),
+                    x_image_height: metadata.height.map(|height| height as i32),
+                    x_image_bytes: Some(data.len() as i64),
+                    x_image_quality: metadata.quality.map(i32::from),
+                    x_image_content_type: metadata.content_type,
+                    body: ByteArray(data.into()),
+                    cache_control: Some(self.cache_headers.cache_control.clone()),
+                    cdn_cache_control: self.cache_headers.cdn_cache_control.clone(),
+                    vary: self.cache_headers.vary.clone(),
+                    surrogate_key: surrogate_key_header(&metadata.surrogate_keys),
+                    cache_tag: cache_tag_header(&metadata.surrogate_keys),
+                })
+            }
+            Err(e) => {
+                let code = ErrorCode::classify(&e);
+                if code == ErrorCode::NotFound {
+                    warn!("{}", e);
+                    return Ok(DownloadResponse::Status404_ImageNotFound {
+                        body: rejection("image_not_found", code, &e),
+                        x_error_code: Some(code.to_string()),
+                    });
+                }
+                error!("Failed to download image ({}): {}", code, e);
+                Ok(DownloadResponse::Status502_StorageFailedToReadTheImage {
+                    body: rejection("storage_failure", code, &e),
+                    x_error_code: Some(code.to_string()),
                 })
             }
         }
@@ -41,28 +71,342 @@ impl Images for ApiService {
     async fn resize(
         &self,
         _method: &Method,
-        _host: &Host,
+        host: &Host,
         _cookies: &CookieJar,
         query_params: &ResizeQueryParams,
     ) -> Result<ResizeResponse, ()> {
         let query = ResizeQuery::from(query_params.clone());
-        let url = self.resize_service.resize(&query).await;
-
-        match url {
-            Ok(url) => Ok(
-                ResizeResponse::Status301_TheImageWasResizeAndInTheLocationYou {
-                    location: Some(url),
-                },
-            ),
-            Err(e) => {
-                error!("Failed to resize image: {}", e);
+        if query_params.dry_run == Some(true) {
+            let plan = self.plan_resize(query, Some(&host.0)).await;
+            return Ok(ResizeResponse::Status200_PlanOfTheResize(
+                plan_model(plan).into(),
+            ));
+        }
+
+        let result = self.resize_image(query.clone(), Some(&host.0)).await;
+
+        match result {
+            Ok(result) if query_params.response == Some(models::ResponseMode::Json) => {
+                let envelope = self
+                    .envelope(result, query_params.inline == Some(true))
+                    .await;
+                Ok(ResizeResponse::Status200_PlanOfTheResize(envelope.into()))
+            }
+            Ok(result) if query_params.response == Some(models::ResponseMode::Inline) => {
+                Ok(self.inline(result).await)
+            }
+            result => Ok(resize_response(self.redirect(result, &query.url))),
+        }
+    }
 
-                Ok(
-                    ResizeResponse::Status301_TheImageWasResizeAndInTheLocationYou {
-                        location: Some(query.url),
-                    },
-                )
+    async fn convert(
+        &self,
+        _method: &Method,
+        host: &Host,
+        _cookies: &CookieJar,
+        query_params: &ConvertQueryParams,
+    ) -> Result<ConvertResponse, ()> {
+        let query = ResizeQuery::from(query_params.clone());
+        let result = self.convert_image(query.clone(), Some(&host.0)).await;
+        Ok(self.redirect(result, &query.url))
+    }
+}
+
+impl ApiService {
+    /// Redirect to the image, or back to the source `url` when it failed, unless rejected
+    ///
+    /// Conversions and resizes share these responses.
+    fn redirect(&self, result: Result<ResizeResult>, url: &str) -> ConvertResponse {
+        match result {
+            Ok(result) => ConvertResponse::Status301_TheImageWasResizeAndInTheLocationYou {
+                location: Some(result.url),
+                cache_control: self.cache_headers.redirect_cache_control.clone(),
+                etag: Some(etag(&result.cache_key)),
+                cdn_cache_control: self.cache_headers.cdn_cache_control.clone(),
+                vary: self.cache_headers.vary.clone(),
+                surrogate_key: surrogate_key_header(&result.surrogate_keys),
+                cache_tag: cache_tag_header(&result.surrogate_keys),
+                x_image_width: result.width.map(|width| width as i32),
+                x_image_height: result.height.map(|height| height as i32),
+                x_image_bytes: result.size.map(|size| size as i64),
+                x_image_quality: result.quality.map(i32::from),
+                x_error_code: None,
+                server_timing: result.timings.server_timing(),
+                x_debug_cache_key: Some(result.cache_key),
+                x_debug_filter: result.filter.map(str::to_string),
+                x_debug_encoder: result.encoder,
             },
+            Err(e) => {
+                let code = ErrorCode::classify(&e);
+                match code {
+                    ErrorCode::InvalidRequest => {
+                        warn!("Rejecting resize of {}: {}", url, e);
+                        ConvertResponse::Status400_InvalidParameters {
+                            body: rejection("invalid_parameters", code, &e),
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    ErrorCode::OriginClientError
+                    | ErrorCode::OriginServerError
+                    | ErrorCode::OriginTimeout
+                    | ErrorCode::OriginUnreachable => {
+                        warn!("Failed to download {}: {}", url, e);
+                        ConvertResponse::Status502_SourceDownloadFailed {
+                            body: rejection("source_download_failed", code, &e),
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    ErrorCode::TooComplex => {
+                        warn!("Rejecting resize of {}: {}", url, e);
+                        let exceeded = cause::<ComplexityExceeded>(&e);
+                        ConvertResponse::Status413_ResizeTooComplex {
+                            body: models::Rejection {
+                                cost: exceeded.map(|exceeded| exceeded.cost),
+                                budget: exceeded.map(|exceeded| exceeded.budget),
+                                ..rejection("complexity_budget_exceeded", code, &e)
+                            },
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    ErrorCode::ContentBlocked => {
+                        warn!("{}", e);
+                        ConvertResponse::Status451_ImageBlockedByModeration {
+                            body: rejection("content_blocked", code, &e),
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    ErrorCode::Overloaded => {
+                        warn!("Shedding resize of {}: {}", url, e);
+                        let reason = if cause::<AdmissionTimeout>(&e).is_some() {
+                            "download_queue_timeout"
+                        } else {
+                            "memory_budget_exhausted"
+                        };
+                        ConvertResponse::Status503_ServerOverloaded {
+                            body: rejection(reason, code, &e),
+                            retry_after: Some(RETRY_AFTER_SECS),
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    ErrorCode::DeadlineExceeded => {
+                        warn!("{}", e);
+                        let retry_after = cause::<DeadlineTooShort>(&e)
+                            .map_or(RETRY_AFTER_SECS, DeadlineTooShort::retry_after_secs);
+                        ConvertResponse::Status503_ServerOverloaded {
+                            body: rejection("deadline_too_short", code, &e),
+                            retry_after: Some(retry_after),
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    ErrorCode::Maintenance => {
+                        warn!("{}", e);
+                        ConvertResponse::Status503_ServerOverloaded {
+                            body: rejection("maintenance_mode", code, &e),
+                            retry_after: Some(MAINTENANCE_RETRY_AFTER_SECS),
+                            x_error_code: Some(code.to_string()),
+                        }
+                    }
+                    _ => {
+                        error!("Failed to resize image ({}): {}", code, e);
+
+                        ConvertResponse::Status301_TheImageWasResizeAndInTheLocationYou {
+                            location: Some(url.to_string()),
+                            cache_control: Some(FALLBACK_CACHE_CONTROL.to_string()),
+                            etag: None,
+                            cdn_cache_control: None,
+                            vary: self.cache_headers.vary.clone(),
+                            surrogate_key: None,
+                            cache_tag: None,
+                            x_image_width: None,
+                            x_image_height: None,
+                            x_image_bytes: None,
+                            x_image_quality: None,
+                            x_error_code: Some(code.to_string()),
+                            server_timing: None,
+                            x_debug_cache_key: None,
+                            x_debug_filter: None,
+                            x_debug_encoder: None,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answer with the resized image itself, for clients that can't follow the redirect
+    ///
+    /// When the image can't be read back from storage, the client is still redirected to it.
+    async fn inline(&self, result: ResizeResult) -> ResizeResponse {
+        let path_params = DownloadPathParams {
+            key: result.key.clone(),
+        };
+        let (data, metadata) = match self.resize_service.download(&path_params).await {
+            Ok(downloaded) => downloaded,
+            Err(e) => {
+                warn!("Failed to inline resized image {}: {}", result.key, e);
+                let url = result.url.clone();
+                return resize_response(self.redirect(Ok(result), &url));
+            }
+        };
+
+        ResizeResponse::Status200_TheResizedImage {
+            x_image_width: result.width.map(|width| width as i32),
+            x_image_height: result.height.map(|height| height as i32),
+            x_image_bytes: Some(data.len() as i64),
+            x_image_quality: result.quality.map(i32::from),
+            x_image_content_type: metadata.content_type,
+            body: ByteArray(data.into()),
+            cache_control: self.cache_headers.redirect_cache_control.clone(),
+            etag: Some(etag(&result.cache_key)),
+            cdn_cache_control: self.cache_headers.cdn_cache_control.clone(),
+            vary: self.cache_headers.vary.clone(),
+            surrogate_key: surrogate_key_header(&result.surrogate_keys),
+            cache_tag: cache_tag_header(&result.surrogate_keys),
+            server_timing: result.timings.server_timing(),
+        }
+    }
+
+    /// Describe a resized image with the API model, inlining its data when asked and small enough
+    async fn envelope(&self, result: ResizeResult, inline: bool) -> models::ResizeEnvelope {
+        let data_base64 = if inline && self.json_envelope.inlines(result.size) {
+            let path_params = DownloadPathParams {
+                key: result.key.clone(),
+            };
+            match self.resize_service.download(&path_params).await {
+                Ok((data, _)) => Some(STANDARD.encode(data)),
+                Err(e) => {
+                    warn!("Failed to inline resized image {}: {}", result.key, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        models::ResizeEnvelope {
+            content_type: content_type_from_key(&result.key).to_string(),
+            url: result.url,
+            width: result.width.map(|width| width as i32),
+            height: result.height.map(|height| height as i32),
+            bytes: result.size.map(|size| size as i64),
+            data_base64,
         }
     }
 }
+
+/// The resize response of a redirect or rejection shared with conversions
+fn resize_response(response: ConvertResponse) -> ResizeResponse {
+    match response {
+        ConvertResponse::Status301_TheImageWasResizeAndInTheLocationYou {
+            location,
+            cache_control,
+            etag,
+            cdn_cache_control,
+            vary,
+            surrogate_key,
+            cache_tag,
+            x_image_width,
+            x_image_height,
+            x_image_bytes,
+            x_image_quality,
+            x_error_code,
+            server_timing,
+            x_debug_cache_key,
+            x_debug_filter,
+            x_debug_encoder,
+        } => ResizeResponse::Status301_TheImageWasResizeAndInTheLocationYou {
+            location,
+            cache_control,
+            etag,
+            cdn_cache_control,
+            vary,
+            surrogate_key,
+            cache_tag,
+            x_image_width,
+            x_image_height,
+            x_image_bytes,
+            x_image_quality,
+            x_error_code,
+            server_timing,
+            x_debug_cache_key,
+            x_debug_filter,
+            x_debug_encoder,
+        },
+        ConvertResponse::Status400_InvalidParameters { body, x_error_code } => {
+            ResizeResponse::Status400_InvalidParameters { body, x_error_code }
+        }
+        ConvertResponse::Status403_URLNotSignedWithTheConfiguredKey { body, x_error_code } => {
+            ResizeResponse::Status403_URLNotSignedWithTheConfiguredKey { body, x_error_code }
+        }
+        ConvertResponse::Status413_ResizeTooComplex { body, x_error_code } => {
+            ResizeResponse::Status413_ResizeTooComplex { body, x_error_code }
+        }
+        ConvertResponse::Status451_ImageBlockedByModeration { body, x_error_code } => {
+            ResizeResponse::Status451_ImageBlockedByModeration { body, x_error_code }
+        }
+        ConvertResponse::Status502_SourceDownloadFailed { body, x_error_code } => {
+            ResizeResponse::Status502_SourceDownloadFailed { body, x_error_code }
+        }
+        ConvertResponse::Status503_ServerOverloaded {
+            body,
+            retry_after,
+            x_error_code,
+        } => ResizeResponse::Status503_ServerOverloaded {
+            body,
+            retry_after,
+            x_error_code,
+        },
+    }
+}
+
+/// First cause of `error` of type `T`, also found in failures shared by identical resizes
+fn cause<T: std::error::Error + 'static>(error: &anyhow::Error) -> Option<&T> {
+    error.chain().find_map(|cause| cause.downcast_ref::<T>())
+}
+
+/// Describe a rejected resize with the API model
+fn rejection(reason: &str, code: ErrorCode, error: &anyhow::Error) -> models::Rejection {
+    models::Rejection {
+        reason: reason.to_string(),
+        message: error.to_string(),
+        cost: None,
+        budget: None,
+        code: code.to_string(),
+        retriable: code.is_retriable(),
+    }
+}
+
+/// Describe a plan with the API model, leaving unset parameters out
+fn plan_model(plan: ResizePlan) -> models::ResizePlan {
+    let mut params = serde_json::to_value(&plan.params).unwrap_or_default();
+    if let Some(params) = params.as_object_mut() {
+        params.retain(|_, value| !value.is_null());
+    }
+
+    models::ResizePlan {
+        params: Object(params),
+        source_url: plan.source_url,
+        cache_key: plan.cache_key,
+        url: plan.url,
+        cache_hit: plan.cache_hit,
+        width: plan.width.map(|width| width as i32),
+        height: plan.height.map(|height| height as i32),
+        error: plan.error,
+        error_code: plan.error_code.map(|code| code.to_string()),
+    }
+}
+
+/// Strong `ETag` of a redirect, identifying the image it points to by its cache key
+fn etag(cache_key: &str) -> String {
+    format!("\"{}\"", cache_key)
+}
+
+/// Format surrogate keys as a Fastly `Surrogate-Key` header (space separated)
+fn surrogate_key_header(keys: &[String]) -> Option<String> {
+    (!keys.is_empty()).then(|| keys.join(" "))
+}
+
+/// Format surrogate keys as a Cloudflare `Cache-Tag` header (comma separated)
+fn cache_tag_header(keys: &[String]) -> Option<String> {
+    (!keys.is_empty()).then(|| keys.join(","))
+}
@@ -1,6 +1,9 @@
+use crate::config::performance::EncodeLimits;
+use crate::modules::env::role::Role;
 use envconfig::Envconfig;
+use serde::Serialize;
 
-#[derive(Envconfig, Clone)]
+#[derive(Envconfig, Clone, Serialize)]
 pub struct EnvConfig {
     #[envconfig(from = "HOST", default = "0.0.0.0")]
     pub http_host: String,
@@ -8,21 +11,35 @@ pub struct EnvConfig {
     #[envconfig(from = "PORT", default = "3000")]
     pub http_port: u16,
 
+    // `all`, `api` (queue background jobs only) or `worker` (process them only)
+    #[envconfig(from = "ROLE", default = "all")]
+    pub role: Role,
+
+    #[cfg(feature = "grpc")]
+    #[envconfig(from = "GRPC_PORT", default = "50051")]
+    pub grpc_port: u16,
+
     #[envconfig(from = "STORAGE_TYPE")]
     pub storage_type: Option<String>,
 
     #[envconfig(from = "STORAGE_SUB_PATH", default = "")]
     pub sub_path: String,
 
+    // Store processed images under the hash of their content, deduplicating identical outputs
+    #[envconfig(from = "CONTENT_ADDRESSED_KEYS", default = "false")]
+    pub content_addressed_keys: bool,
+
     #[cfg(feature = "s3")]
     #[envconfig(from = "MINIO_ENDPOINT_URL", default = "http://localhost:9000")]
     pub minio_endpoint_url: String,
 
     #[cfg(feature = "s3")]
+    #[serde(skip)]
     #[envconfig(from = "MINIO_ACCESS_KEY_ID", default = "minioadmin")]
     pub minio_access_key_id: String,
 
     #[cfg(feature = "s3")]
+    #[serde(skip)]
     #[envconfig(from = "MINIO_SECRET_ACCESS_KEY", default = "minioadmin")]
     pub minio_secret_access_key: String,
 
@@ -38,9 +55,388 @@ pub struct EnvConfig {
     #[envconfig(from = "LOCAL_FS_STORAGE_PATH", default = "./data/images")]
     pub local_fs_storage_path: String,
 
+    // Images stored in Redis expire after this delay, 0 to keep them until evicted
+    #[cfg(feature = "redis_storage")]
+    #[envconfig(from = "REDIS_STORAGE_TTL_SECS", default = "0")]
+    pub redis_storage_ttl_secs: u64,
+
+    #[cfg(feature = "wasm_plugins")]
+    #[envconfig(from = "WASM_PLUGIN_DIR")]
+    pub wasm_plugin_dir: Option<String>,
+
+    #[cfg(feature = "wasm_plugins")]
+    #[envconfig(from = "WASM_PLUGIN_FUEL", default = "1000000000")]
+    pub wasm_plugin_fuel: u64,
+
+    #[cfg(feature = "wasm_plugins")]
+    #[envconfig(from = "WASM_PLUGIN_MAX_MEMORY_MB", default = "64")]
+    pub wasm_plugin_max_memory_mb: usize,
+
+    #[cfg(feature = "scripting")]
+    #[envconfig(from = "REWRITE_SCRIPT_PATH")]
+    pub rewrite_script_path: Option<String>,
+
+    // The admin API is disabled when unset
+    #[serde(skip)]
+    #[envconfig(from = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    // Named admin tokens, as `actor:token,...`
+    #[serde(skip)]
+    #[envconfig(from = "ADMIN_TOKENS")]
+    pub admin_tokens: Option<String>,
+
+    // Resizes and conversions must carry a valid `sig` when set
+    #[serde(skip)]
+    #[envconfig(from = "SIGNING_KEY")]
+    pub signing_key: Option<String>,
+
+    #[envconfig(from = "AUDIT_LOG_STORAGE", default = "false")]
+    pub audit_log_storage: bool,
+
+    // Public URL of the service, used in direct-upload URLs
+    #[envconfig(from = "PUBLIC_BASE_URL")]
+    pub public_base_url: Option<String>,
+
+    #[envconfig(from = "UPLOAD_URL_TTL_SECS", default = "900")]
+    pub upload_url_ttl_secs: u64,
+
+    // Scanner of uploaded originals, as clamd://host:port or an http(s) callout URL
+    #[envconfig(from = "UPLOAD_SCANNER")]
+    pub upload_scanner: Option<String>,
+
+    // JSON file mapping tenant hosts to their scanner, or to null to leave their uploads unscanned
+    #[envconfig(from = "UPLOAD_SCAN_CONFIG")]
+    pub upload_scan_config: Option<String>,
+
+    #[envconfig(from = "UPLOAD_SCAN_TIMEOUT_SECS", default = "30")]
+    pub upload_scan_timeout_secs: u64,
+
+    #[cfg(any(
+        feature = "redis_lock",
+        feature = "redis_queue",
+        feature = "redis_tiering",
+        feature = "redis_storage"
+    ))]
+    #[serde(skip)]
+    #[envconfig(from = "REDIS_URL")]
+    pub redis_url: Option<String>,
+
+    #[cfg(feature = "redis_lock")]
+    #[envconfig(from = "LOCK_TTL_SECS", default = "30")]
+    pub lock_ttl_secs: u64,
+
+    #[cfg(feature = "redis_lock")]
+    #[envconfig(from = "LOCK_WAIT_TIMEOUT_SECS", default = "10")]
+    pub lock_wait_timeout_secs: u64,
+
+    // Concurrent background jobs processed by this replica
+    #[envconfig(from = "JOB_WORKERS", default = "2")]
+    pub job_workers: usize,
+
+    #[envconfig(from = "JOB_MAX_ATTEMPTS", default = "3")]
+    pub job_max_attempts: u32,
+
+    // Jobs not acknowledged by a worker within this delay are handed to another one
+    #[cfg(feature = "redis_queue")]
+    #[envconfig(from = "JOB_VISIBILITY_TIMEOUT_SECS", default = "300")]
+    pub job_visibility_timeout_secs: u64,
+
+    // Resize events are published when set
+    #[cfg(feature = "nats_events")]
+    #[envconfig(from = "NATS_URL")]
+    pub nats_url: Option<String>,
+
+    #[cfg(feature = "nats_events")]
+    #[envconfig(from = "EVENT_SUBJECT", default = "emgr.images.processed")]
+    pub event_subject: String,
+
+    // Base URLs of all replicas, as `http://host:port,...`; needs PEER_SELF_URL
+    #[envconfig(from = "PEER_URLS")]
+    pub peer_urls: Option<String>,
+
+    // Base URL of this replica, as listed in PEER_URLS
+    #[envconfig(from = "PEER_SELF_URL")]
+    pub peer_self_url: Option<String>,
+
+    // Shared secret required on the internal peer cache routes
+    #[serde(skip)]
+    #[envconfig(from = "PEER_TOKEN")]
+    pub peer_token: Option<String>,
+
+    #[envconfig(from = "PEER_CACHE_MAX_MB", default = "256")]
+    pub peer_cache_max_mb: usize,
+
+    #[envconfig(from = "PEER_TIMEOUT_MS", default = "200")]
+    pub peer_timeout_ms: u64,
+
+    // Largest DSSIM accepted by quality=auto between the resized image and its encoding
+    #[envconfig(from = "AUTO_QUALITY_MAX_DSSIM", default = "0.01")]
+    pub auto_quality_max_dssim: f64,
+
+    #[envconfig(from = "AUTO_QUALITY_MIN", default = "40")]
+    pub auto_quality_min: u8,
+
+    #[envconfig(from = "AUTO_QUALITY_MAX", default = "90")]
+    pub auto_quality_max: u8,
+
+    // Encodes tried per image by quality=auto, bounding the CPU it spends
+    #[envconfig(from = "AUTO_QUALITY_MAX_ATTEMPTS", default = "6")]
+    pub auto_quality_max_attempts: u32,
+
+    // Lowest JPEG quality tried by max_bytes before scaling the image down
+    #[envconfig(from = "MAX_BYTES_MIN_QUALITY", default = "20")]
+    pub max_bytes_min_quality: u8,
+
+    #[envconfig(from = "MAX_BYTES_DOWNSCALE", default = "true")]
+    pub max_bytes_downscale: bool,
+
+    // JSON file of per-origin TLS settings: extra CAs, client certificates, minimum versions
+    #[envconfig(from = "ORIGIN_TLS_CONFIG")]
+    pub origin_tls_config: Option<String>,
+
+    // JSON file of per-tenant S3 Object Lock settings of processed images: mode, retention, legal hold
+    #[envconfig(from = "OBJECT_LOCK_CONFIG")]
+    pub object_lock_config: Option<String>,
+
+    // JSON file of per-tenant transformations: defaults, forced parameters, maximum quality
+    #[envconfig(from = "TENANT_DEFAULTS_CONFIG")]
+    pub tenant_defaults_config: Option<String>,
+
+    // mimalloc purge delay in ms, 0 to return freed memory to the OS at once, -1 never
+    #[envconfig(from = "ALLOCATOR_PURGE_DELAY_MS")]
+    pub allocator_purge_delay_ms: Option<i64>,
+
+    // Size of the memory arenas mimalloc reserves from the OS, in MB
+    #[envconfig(from = "ALLOCATOR_ARENA_RESERVE_MB")]
+    pub allocator_arena_reserve_mb: Option<u64>,
+
+    // Resident memory above which the watchdog logs, in MB, 0 to disable
+    #[envconfig(from = "RSS_WATCHDOG_THRESHOLD_MB", default = "0")]
+    pub rss_watchdog_threshold_mb: u64,
+
+    #[envconfig(from = "RSS_WATCHDOG_INTERVAL_SECS", default = "30")]
+    pub rss_watchdog_interval_secs: u64,
+
+    // Drop the in-memory caches and collect the allocator above the threshold
+    #[envconfig(from = "RSS_WATCHDOG_DROP_CACHES", default = "false")]
+    pub rss_watchdog_drop_caches: bool,
+
+    // Approximate memory of the images processed at once, in MB, 0 for unlimited
+    #[envconfig(from = "MEMORY_BUDGET_MB", default = "0")]
+    pub memory_budget_mb: u64,
+
+    // Leading KB of sources fetched to check them before their full download, 0 to disable
+    #[envconfig(from = "SOURCE_PROBE_KB", default = "0")]
+    pub source_probe_kb: usize,
+
+    // Largest source accepted by the probe, in megapixels
+    #[envconfig(from = "SOURCE_MAX_MEGAPIXELS", default = "100")]
+    pub source_max_megapixels: u64,
+
+    // Highest estimated cost of a resize (source megapixels times operations), 0 for unlimited
+    #[envconfig(from = "COMPLEXITY_BUDGET", default = "0")]
+    pub complexity_budget: f64,
+
+    // Tenant hosts exempt from the complexity budget, comma separated
+    #[envconfig(from = "COMPLEXITY_ALLOWLIST")]
+    pub complexity_allowlist: Option<String>,
+
+    // Days without requests after which processed images move to the cold storage class, 0 to disable
+    #[envconfig(from = "TIERING_COLD_AFTER_DAYS", default = "0")]
+    pub tiering_cold_after_days: u64,
+
+    #[envconfig(from = "TIERING_COLD_STORAGE_CLASS", default = "STANDARD_IA")]
+    pub tiering_cold_storage_class: String,
+
+    // Storage class cold images move back to once requested
+    #[envconfig(from = "TIERING_HOT_STORAGE_CLASS", default = "STANDARD")]
+    pub tiering_hot_storage_class: String,
+
+    #[envconfig(from = "TIERING_INTERVAL_SECS", default = "3600")]
+    pub tiering_interval_secs: u64,
+
+    // Shortest time assumed for a cold miss when checking the deadlines sent by clients
+    #[envconfig(from = "DEADLINE_MIN_COLD_MISS_MS", default = "250")]
+    pub deadline_min_cold_miss_ms: u64,
+
+    // Classifier processed images are sent to for moderation, as an http(s) URL
+    #[envconfig(from = "MODERATION_URL")]
+    pub moderation_url: Option<String>,
+
+    // flag, block or off, for tenants without a policy in MODERATION_CONFIG
+    #[envconfig(from = "MODERATION_ACTION", default = "flag")]
+    pub moderation_action: String,
+
+    #[envconfig(from = "MODERATION_THRESHOLD", default = "0.8")]
+    pub moderation_threshold: f32,
+
+    // JSON file mapping tenant hosts to their moderation action and threshold
+    #[envconfig(from = "MODERATION_CONFIG")]
+    pub moderation_config: Option<String>,
+
+    #[envconfig(from = "MODERATION_TIMEOUT_MS", default = "2000")]
+    pub moderation_timeout_ms: u64,
+
+    // Time downloaded originals are reused from storage before being revalidated, 0 to disable
+    #[envconfig(from = "SOURCE_CACHE_TTL_SECS", default = "0")]
+    pub source_cache_ttl_secs: u64,
+
+    // Manifest of the variants to pre-generate, as an http(s) URL or a file path
+    #[envconfig(from = "PREGEN_MANIFEST")]
+    pub pregen_manifest: Option<String>,
+
+    #[envconfig(from = "PREGEN_INTERVAL_SECS", default = "3600")]
+    pub pregen_interval_secs: u64,
+
+    // Variants pre-generated at once, on top of waiting for idle pools
+    #[envconfig(from = "PREGEN_CONCURRENCY", default = "2")]
+    pub pregen_concurrency: usize,
+
+    // JSON file of the art direction presets and the pictures cropped with them
+    #[envconfig(from = "ART_DIRECTION_MANIFEST")]
+    pub art_direction_manifest: Option<String>,
+
+    // Decoded watermarks kept in memory, and how long before they are read again from storage
+    #[envconfig(from = "WATERMARK_CACHE_SIZE", default = "32")]
+    pub watermark_cache_size: usize,
+
+    #[envconfig(from = "WATERMARK_CACHE_TTL_SECS", default = "300")]
+    pub watermark_cache_ttl_secs: u64,
+
+    // Service cutting out the subject of remove_bg=matting sources, answering with a PNG or WebP
+    #[envconfig(from = "MATTING_URL")]
+    pub matting_url: Option<String>,
+
+    #[envconfig(from = "MATTING_TIMEOUT_MS", default = "10000")]
+    pub matting_timeout_ms: u64,
+
+    // Most frequent origin hosts and tenants labelled in pipeline metrics, 0 to leave the label out
+    #[envconfig(from = "METRICS_ORIGIN_LABELS", default = "0")]
+    pub metrics_origin_labels: usize,
+
+    #[envconfig(from = "METRICS_TENANT_LABELS", default = "0")]
+    pub metrics_tenant_labels: usize,
+
+    // Rolling window summarized by the admin report
+    #[envconfig(from = "REPORT_WINDOW_SECS", default = "3600")]
+    pub report_window_secs: u64,
+
+    // Start in maintenance mode, only serving images already in storage
+    #[envconfig(from = "MAINTENANCE_MODE", default = "false")]
+    pub maintenance_mode: bool,
+
+    // Secondary deployment resize requests are mirrored to, e.g. a canary build
+    #[envconfig(from = "MIRROR_BASE_URL")]
+    pub mirror_base_url: Option<String>,
+
+    // Percentage of the resize requests mirrored
+    #[envconfig(from = "MIRROR_PERCENT", default = "10")]
+    pub mirror_percent: f64,
+
+    #[envconfig(from = "MIRROR_TIMEOUT_MS", default = "10000")]
+    pub mirror_timeout_ms: u64,
+
+    // Alternative pipeline, e.g. resize_filter=catmull_rom,jpeg=mozjpeg
+    #[envconfig(from = "CANARY_PIPELINE")]
+    pub canary_pipeline: Option<String>,
+
+    // Percentage of the images processed by the canary pipeline
+    #[envconfig(from = "CANARY_PERCENT", default = "0")]
+    pub canary_percent: f64,
+
+    // Label of the canary pipeline in cache keys and metrics
+    #[envconfig(from = "CANARY_NAME", default = "canary")]
+    pub canary_name: String,
+
+    // Origin download throughput in MB/s, in total and per host, 0 for unlimited
+    #[envconfig(from = "DOWNLOAD_MAX_MB_PER_SEC", default = "0")]
+    pub download_max_mb_per_sec: f64,
+
+    #[envconfig(from = "DOWNLOAD_MAX_MB_PER_SEC_PER_HOST", default = "0")]
+    pub download_max_mb_per_sec_per_host: f64,
+
+    // Longest wait for a download slot before shedding the request, 0 to wait indefinitely
+    #[envconfig(from = "DOWNLOAD_QUEUE_TIMEOUT_MS", default = "10000")]
+    pub download_queue_timeout_ms: u64,
+
+    // Largest resized image inlined as base64 in JSON responses
+    #[envconfig(from = "JSON_INLINE_MAX_BYTES", default = "16384")]
+    pub json_inline_max_bytes: u64,
+
+    // Caching DNS resolver for origin downloads
+    #[cfg(feature = "dns_cache")]
+    #[envconfig(from = "DNS_CACHE_ENABLED", default = "true")]
+    pub dns_cache_enabled: bool,
+
+    // Records kept in the DNS cache
+    #[cfg(feature = "dns_cache")]
+    #[envconfig(from = "DNS_CACHE_SIZE", default = "1024")]
+    pub dns_cache_size: usize,
+
+    // Bounds applied to the TTL of DNS answers
+    #[cfg(feature = "dns_cache")]
+    #[envconfig(from = "DNS_MIN_TTL_SECS", default = "0")]
+    pub dns_min_ttl_secs: u64,
+
+    #[cfg(feature = "dns_cache")]
+    #[envconfig(from = "DNS_MAX_TTL_SECS", default = "300")]
+    pub dns_max_ttl_secs: u64,
+
+    // Address family tried first: ipv4, ipv6, ipv4_only or ipv6_only
+    #[cfg(feature = "dns_cache")]
+    #[envconfig(from = "DNS_IP_PREFERENCE", default = "ipv4")]
+    pub dns_ip_preference: String,
+
+    // Output format of requests without one
+    #[envconfig(from = "DEFAULT_FORMAT", default = "jpg")]
+    pub default_format: String,
+
+    // JPEG quality of requests without one
+    #[envconfig(from = "JPEG_QUALITY", default = "75")]
+    pub jpeg_quality: u8,
+
+    // PNG compression: fast, default, best, none or a level from 1 to 9
+    #[envconfig(from = "PNG_COMPRESSION", default = "fast")]
+    pub png_compression: String,
+
+    // Sharpen downscaled images of requests without auto_sharpen
+    #[envconfig(from = "AUTO_SHARPEN", default = "false")]
+    pub auto_sharpen: bool,
+
+    // ffmpeg binary transcoding animated GIFs to mp4 and webm
+    #[cfg(feature = "video")]
+    #[envconfig(from = "FFMPEG_PATH", default = "ffmpeg")]
+    pub ffmpeg_path: String,
+
+    #[cfg(feature = "video")]
+    #[envconfig(from = "FFMPEG_TIMEOUT_SECS", default = "60")]
+    pub ffmpeg_timeout_secs: u64,
+
     #[envconfig(from = "CDN_BASE_URL", default = "http://localhost:9000/image-cache")]
     pub cdn_base_url: String,
 
+    #[envconfig(
+        from = "CACHE_CONTROL",
+        default = "public, max-age=31536000, immutable"
+    )]
+    pub cache_control: String,
+
+    // Redirects are revalidated with their ETag by default, left to heuristic caching when empty
+    #[envconfig(from = "REDIRECT_CACHE_CONTROL", default = "no-cache")]
+    pub redirect_cache_control: String,
+
+    // Sent as `CDN-Cache-Control`, so CDNs cache differently from browsers
+    #[envconfig(from = "CDN_CACHE_CONTROL")]
+    pub cdn_cache_control: Option<String>,
+
+    #[envconfig(from = "STALE_IF_ERROR_SECS", default = "0")]
+    pub stale_if_error_secs: u64,
+
+    // Request headers an edge negotiates variants on, e.g. `Accept, DPR`
+    #[envconfig(from = "VARY")]
+    pub vary: Option<String>,
+
     #[cfg(feature = "otel")]
     #[envconfig(from = "LOG_LEVEL", default = "debug")]
     pub log_level: String,
@@ -60,6 +456,11 @@ pub struct EnvConfig {
     #[envconfig(from = "OTLP_SERVICE_NAME", default = "rust-app-example")]
     pub otlp_service_name: String,
 
+    // Share of the traces sampled, from 0 to 1, admin requests with `X-Debug: 1` always are
+    #[cfg(feature = "otel")]
+    #[envconfig(from = "OTLP_SAMPLE_RATIO", default = "1")]
+    pub otlp_sample_ratio: f64,
+
     // Performance configuration
     #[envconfig(from = "MAX_CONCURRENT_DOWNLOADS")]
     pub max_concurrent_downloads: Option<usize>,
@@ -70,6 +471,18 @@ pub struct EnvConfig {
     #[envconfig(from = "HTTP_TIMEOUT_SECS")]
     pub http_timeout_secs: Option<u64>,
 
+    // Longest wait to connect to an origin
+    #[envconfig(from = "CONNECT_TIMEOUT_MS")]
+    pub connect_timeout_ms: Option<u64>,
+
+    // Longest wait for the response headers of an origin, once the request is sent
+    #[envconfig(from = "FIRST_BYTE_TIMEOUT_MS")]
+    pub first_byte_timeout_ms: Option<u64>,
+
+    // Longest pause of an origin between two chunks of the body
+    #[envconfig(from = "IDLE_TIMEOUT_MS")]
+    pub idle_timeout_ms: Option<u64>,
+
     #[envconfig(from = "MAX_IMAGE_SIZE_MB")]
     pub max_image_size_mb: Option<u64>,
 
@@ -79,12 +492,30 @@ pub struct EnvConfig {
     #[envconfig(from = "ENABLE_HTTP2")]
     pub enable_http2: Option<bool>,
 
+    // Speak HTTP/2 to origins without negotiating it, breaking HTTP/1.1 origins
+    #[envconfig(from = "FORCE_HTTP2_PRIOR_KNOWLEDGE")]
+    pub force_http2_prior_knowledge: Option<bool>,
+
     #[envconfig(from = "CONNECTION_POOL_SIZE")]
     pub connection_pool_size: Option<usize>,
 
     #[envconfig(from = "KEEP_ALIVE_TIMEOUT_SECS")]
     pub keep_alive_timeout_secs: Option<u64>,
 
+    // Largest image dimensions and decoder allocation, enforced while decoding
+    #[envconfig(from = "MAX_DECODE_WIDTH")]
+    pub max_decode_width: Option<u32>,
+
+    #[envconfig(from = "MAX_DECODE_HEIGHT")]
+    pub max_decode_height: Option<u32>,
+
+    #[envconfig(from = "MAX_DECODE_ALLOC_MB")]
+    pub max_decode_alloc_mb: Option<u64>,
+
+    // Most images encoded to each format at once, e.g. avif=2,png=4 (0 for unlimited)
+    #[envconfig(from = "ENCODE_CONCURRENCY")]
+    pub encode_concurrency: Option<EncodeLimits>,
+
     #[envconfig(from = "PERFORMANCE_PROFILE")]
     pub performance_profile: Option<String>,
 }
@@ -0,0 +1,38 @@
+use anyhow::{Error, anyhow};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Parts of the service a replica runs, to scale request handling and processing apart
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Serve the API and process background jobs
+    All,
+    /// Serve the API and only queue background jobs
+    Api,
+    /// Only process background jobs, without any listener
+    Worker,
+}
+
+impl Role {
+    pub fn serves_api(self) -> bool {
+        self != Role::Worker
+    }
+
+    pub fn processes_jobs(self) -> bool {
+        self != Role::Api
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(Role::All),
+            "api" => Ok(Role::Api),
+            "worker" => Ok(Role::Worker),
+            _ => Err(anyhow!("Invalid role: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,6 @@
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("emgr.v1");
+}
+
+pub mod server;
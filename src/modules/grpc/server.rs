@@ -0,0 +1,196 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::api::handler::ApiService;
+use crate::modules::grpc::proto;
+use crate::modules::grpc::proto::images_server::{Images, ImagesServer};
+use crate::services::resize::errors::ErrorCode;
+use crate::services::storage::core::content_type_from_key;
+use anyhow::Result;
+use gen_server::models::{DownloadPathParams, ImageFormat};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+/// Metadata key carrying the code of failed resizes, as the REST `X-Error-Code` header
+const ERROR_CODE_METADATA: &str = "x-error-code";
+
+/// gRPC implementation of the Images operations, sharing the REST `ApiService`
+pub struct GrpcImages {
+    api_service: Arc<ApiService>,
+}
+
+#[tonic::async_trait]
+impl Images for GrpcImages {
+    async fn resize(
+        &self,
+        request: Request<proto::ResizeRequest>,
+    ) -> Result<Response<proto::ResizeResponse>, Status> {
+        // gRPC requests can't carry a URL signature, so they would fetch any source
+        if self.api_service.url_signer.is_some() {
+            let mut status = Status::permission_denied(
+                "Resizes are disabled over gRPC while SIGNING_KEY is set",
+            );
+            status.metadata_mut().insert(
+                ERROR_CODE_METADATA,
+                MetadataValue::from_static(ErrorCode::InvalidSignature.as_str()),
+            );
+            return Err(status);
+        }
+        let query = ResizeQuery::from(request.into_inner());
+
+        // gRPC has no Host header, so no tenant tag is emitted
+        let result = self
+            .api_service
+            .resize_image(query, None)
+            .await
+            .map_err(|e| {
+                let code = ErrorCode::classify(&e);
+                let mut status = match code {
+                    ErrorCode::Overloaded
+                    | ErrorCode::Maintenance
+                    | ErrorCode::DeadlineExceeded => Status::unavailable(e.to_string()),
+                    ErrorCode::TooComplex => Status::resource_exhausted(e.to_string()),
+                    ErrorCode::InvalidRequest => Status::invalid_argument(e.to_string()),
+                    ErrorCode::ContentBlocked => Status::permission_denied(e.to_string()),
+                    _ => {
+                        error!("Failed to resize image ({}): {}", code, e);
+                        Status::internal(format!("Failed to resize image: {}", e))
+                    }
+                };
+                status.metadata_mut().insert(
+                    ERROR_CODE_METADATA,
+                    MetadataValue::from_static(code.as_str()),
+                );
+                status
+            })?;
+
+        Ok(Response::new(proto::ResizeResponse {
+            location: result.url,
+            surrogate_keys: result.surrogate_keys,
+        }))
+    }
+
+    async fn download(
+        &self,
+        request: Request<proto::DownloadRequest>,
+    ) -> Result<Response<proto::DownloadResponse>, Status> {
+        let key = request.into_inner().key;
+        let (body, metadata) = self
+            .api_service
+            .resize_service
+            .download(&DownloadPathParams { key: key.clone() })
+            .await
+            .map_err(|e| {
+                error!("Failed to download image: {}", e);
+                Status::not_found(format!("Image not found: {}", key))
+            })?;
+
+        Ok(Response::new(proto::DownloadResponse {
+            body,
+            content_type: metadata
+                .content_type
+                .unwrap_or_else(|| content_type_from_key(&key).to_string()),
+            surrogate_keys: metadata.surrogate_keys,
+        }))
+    }
+}
+
+impl From<proto::ResizeRequest> for ResizeQuery {
+    fn from(request: proto::ResizeRequest) -> Self {
+        let format = match request.format() {
+            proto::ImageFormat::Png => Some(ImageFormat::Png),
+            proto::ImageFormat::Webp => Some(ImageFormat::Webp),
+            proto::ImageFormat::Jpg => Some(ImageFormat::Jpg),
+            proto::ImageFormat::Mp4 => Some(ImageFormat::Mp4),
+            proto::ImageFormat::Webm => Some(ImageFormat::Webm),
+            proto::ImageFormat::Avif => Some(ImageFormat::Avif),
+            proto::ImageFormat::Gif => Some(ImageFormat::Gif),
+            proto::ImageFormat::Unspecified => None,
+        };
+
+        Self {
+            url: request.url,
+            width: request.width,
+            height: request.height,
+            format,
+            blur_sigma: request.blur_sigma,
+            grayscale: request.grayscale,
+            plugin: request.plugin,
+            ar: request.ar,
+            zoom: request.zoom,
+            density: request.density,
+            quality: request.quality,
+            max_bytes: request.max_bytes,
+            denoise: request.denoise,
+            vignette: request.vignette,
+            tint: request.tint,
+            pixelate: request.pixelate,
+            pixelate_region: request.pixelate_region,
+            gamma: request.gamma,
+            exposure: request.exposure,
+            pad: request.pad,
+            focus: request.focus,
+            frame: request.frame,
+            time: request.time,
+            watermark: request.watermark,
+            remove_bg: request.remove_bg,
+            colors: request.colors,
+            dither: request.dither,
+            auto_level: request.auto_level,
+            filter_down: request.filter_down,
+            filter_up: request.filter_up,
+            subsampling: request.subsampling,
+            auto_sharpen: request.auto_sharpen,
+            fit: request.fit,
+            frames: request.frames,
+        }
+    }
+}
+
+/// Serve the gRPC API until the process stops
+///
+/// Deadlines sent by clients in `grpc-timeout` are enforced by tonic: the request is
+/// dropped when it expires, which cancels any pending download or upload.
+pub async fn serve(api_service: Arc<ApiService>, addr: SocketAddr) -> Result<()> {
+    info!("gRPC server running on {}", addr);
+    Server::builder()
+        .add_service(ImagesServer::new(GrpcImages { api_service }))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use super::*;
+    use crate::modules::env::env::EnvConfig;
+    use envconfig::Envconfig;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_refuses_resizes_when_urls_must_be_signed() {
+        let config = EnvConfig::init_from_hashmap(&HashMap::from([
+            ("STORAGE_TYPE".to_string(), "in_memory".to_string()),
+            ("SIGNING_KEY".to_string(), "secret".to_string()),
+        ]))
+        .unwrap();
+        let images = GrpcImages {
+            api_service: Arc::new(ApiService::create(config).unwrap()),
+        };
+
+        let status = images
+            .resize(Request::new(proto::ResizeRequest {
+                url: "https://origin.test/a.png".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(
+            status.metadata().get(ERROR_CODE_METADATA).unwrap(),
+            "invalid_signature"
+        );
+    }
+}
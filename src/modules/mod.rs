@@ -1,5 +1,7 @@
 pub mod api;
 pub mod env;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod router;
 pub mod tracer;
 pub mod utils;
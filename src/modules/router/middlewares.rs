@@ -1,6 +1,9 @@
 use axum::Router;
-use axum::http::Method;
-use tower_http::compression::predicate::{NotForContentType, SizeAbove};
+use axum::extract::Request;
+use axum::http::{Extensions, HeaderMap, HeaderName, Method, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::compression::predicate::NotForContentType;
 use tower_http::compression::{CompressionLayer, DefaultPredicate, Predicate};
 use tower_http::cors::{Any, CorsLayer};
 
@@ -19,9 +22,10 @@ pub fn apply_common_middlewares(router: Router) -> Router {
         // allow requests from any origin
         .allow_origin(Any);
 
+    // JSON and metrics shrink a lot, encoded images and videos don't shrink any further
     let compression_predicate = DefaultPredicate::new()
         .and(NotForContentType::new("application/octet-stream"))
-        .and(SizeAbove::new(0));
+        .and(|_, _, headers: &HeaderMap, _: &Extensions| !is_media(headers));
 
     let compression_layer = CompressionLayer::new()
         .br(true)
@@ -32,3 +36,31 @@ pub fn apply_common_middlewares(router: Router) -> Router {
 
     router.layer(compression_layer).layer(cors)
 }
+
+/// Header carrying the MIME type an image was stored with
+const IMAGE_CONTENT_TYPE: HeaderName = HeaderName::from_static("x-image-content-type");
+
+/// Whether a response holds an image or video, even one stored without a content type
+fn is_media(headers: &HeaderMap) -> bool {
+    headers.contains_key(IMAGE_CONTENT_TYPE)
+        || headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| {
+                content_type.starts_with("image/") || content_type.starts_with("video/")
+            })
+}
+
+/// Serve downloads with the content type they were stored with
+///
+/// The generated handlers can't pick one of several response media types, so the stored
+/// type travels in `X-Image-Content-Type` and replaces whatever `Content-Type` was set.
+pub async fn stored_content_type(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if let Some(content_type) = response.headers().get(IMAGE_CONTENT_TYPE).cloned() {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+}
@@ -1,12 +1,34 @@
 use std::sync::Arc;
 
 use crate::modules::api::handler::ApiService;
-use crate::modules::router::middlewares::apply_common_middlewares;
-use crate::services::health::handler::health;
+use crate::modules::router::middlewares::{apply_common_middlewares, stored_content_type};
+use crate::services::admin::handler::{
+    config, image_info, issue_upload, maintenance, purge, report, require_token, set_maintenance,
+    stats,
+};
+use crate::services::cdn::handler::conditional_redirects;
+use crate::services::deadline::handler::request_deadlines;
+use crate::services::debug::handler::debug_requests;
+use crate::services::docs::handler::{openapi, openapi_json};
+use crate::services::health::handler::{health, ready};
+use crate::services::job::handler::{JOB_ROUTE, job_status, submit_job};
+use crate::services::mirror::handler::mirror_requests;
+use crate::services::peer::handler::{
+    PEER_ROUTE, delete_peer_object, get_peer_object, put_peer_object,
+};
+use crate::services::picture::handler::{PICTURE_ROUTE, picture};
+use crate::services::signing::handler::verify_signatures;
+use crate::services::upload::handler::{UPLOAD_ROUTE, accept_upload};
+use crate::services::version::handler::version;
+use crate::services::watermark::handler::{
+    delete_watermark, get_watermark, list_watermarks, put_watermark,
+};
 use anyhow::Result;
 use axum::Router;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware::{from_fn, from_fn_with_state};
 use axum::response::Redirect;
-use axum::routing::get;
+use axum::routing::{get, post, put};
 use axum_tracing_opentelemetry::middleware::{OtelAxumLayer, OtelInResponseLayer};
 use gen_server::server::new;
 
@@ -16,20 +38,65 @@ pub async fn router(
     api_service: Arc<ApiService>,
 ) -> Result<Router> {
     // Create the main router
-    let app = new(api_service)
+    let app = new(api_service.clone()).layer(from_fn(stored_content_type));
+
+    // Mirror the responses of this replica, before faults are injected
+    let app = match api_service.mirror.clone() {
+        Some(mirror) => app.layer(from_fn_with_state(mirror, mirror_requests)),
+        None => app,
+    };
+
+    // Faults only target the image API, leaving probes and the admin API usable
+    #[cfg(feature = "chaos")]
+    let app = app.layer(from_fn_with_state(
+        api_service.chaos.clone(),
+        crate::services::chaos::handler::inject_faults,
+    ));
+
+    // Unsigned requests are rejected before being mirrored or processed
+    let app = match api_service.url_signer.clone() {
+        Some(signer) => app.layer(from_fn_with_state(signer, verify_signatures)),
+        None => app,
+    };
+
+    let app = app
         .layer(OtelInResponseLayer::default())
         .layer(OtelAxumLayer::default())
+        .layer(from_fn_with_state(api_service.clone(), debug_requests))
+        .layer(from_fn(request_deadlines))
+        .layer(from_fn(conditional_redirects))
         .layer(metrics);
 
     // Add health and metrics endpoints
     let app = app
         .route("/", get(|| async { Redirect::permanent("/health") }))
         .route("/health", get(health))
+        .route("/health/ready", get(ready).with_state(api_service.clone()))
+        .route("/version", get(version))
+        .merge(upload_router(api_service.clone()))
+        .merge(job_router(api_service.clone()))
+        .merge(picture_router(api_service.clone()))
+        .route("/openapi.json", get(openapi).with_state(openapi_json()?))
         .route(
             "/metrics",
             get(crate::services::metrics::handler::metrics_handler),
         );
 
+    #[cfg(feature = "swagger_ui")]
+    let app = app.route("/docs", get(crate::services::docs::handler::swagger_ui));
+
+    let app = if api_service.admin.is_some() {
+        app.nest("/admin", admin_router(api_service.clone()))
+    } else {
+        app
+    };
+
+    let app = if api_service.resize_service.peer_cache().is_some() {
+        app.merge(peer_router(api_service))
+    } else {
+        app
+    };
+
     let router = apply_common_middlewares(app);
     Ok(router)
 }
@@ -37,15 +104,126 @@ pub async fn router(
 #[cfg(not(feature = "otel"))]
 pub async fn router(api_service: Arc<ApiService>) -> Result<Router> {
     // Create the main router
-    let app = new(api_service)
+    let app = new(api_service.clone()).layer(from_fn(stored_content_type));
+
+    // Mirror the responses of this replica, before faults are injected
+    let app = match api_service.mirror.clone() {
+        Some(mirror) => app.layer(from_fn_with_state(mirror, mirror_requests)),
+        None => app,
+    };
+
+    // Faults only target the image API, leaving probes and the admin API usable
+    #[cfg(feature = "chaos")]
+    let app = app.layer(from_fn_with_state(
+        api_service.chaos.clone(),
+        crate::services::chaos::handler::inject_faults,
+    ));
+
+    // Unsigned requests are rejected before being mirrored or processed
+    let app = match api_service.url_signer.clone() {
+        Some(signer) => app.layer(from_fn_with_state(signer, verify_signatures)),
+        None => app,
+    };
+
+    let app = app
         .layer(OtelInResponseLayer::default())
-        .layer(OtelAxumLayer::default());
+        .layer(OtelAxumLayer::default())
+        .layer(from_fn_with_state(api_service.clone(), debug_requests))
+        .layer(from_fn(request_deadlines))
+        .layer(from_fn(conditional_redirects));
 
     // Add health and metrics endpoints
     let app = app
         .route("/", get(|| async { Redirect::permanent("/health") }))
-        .route("/health", get(health));
+        .route("/health", get(health))
+        .route("/health/ready", get(ready).with_state(api_service.clone()))
+        .route("/version", get(version))
+        .merge(upload_router(api_service.clone()))
+        .merge(job_router(api_service.clone()))
+        .merge(picture_router(api_service.clone()))
+        .route("/openapi.json", get(openapi).with_state(openapi_json()?));
+
+    #[cfg(feature = "swagger_ui")]
+    let app = app.route("/docs", get(crate::services::docs::handler::swagger_ui));
+
+    let app = if api_service.admin.is_some() {
+        app.nest("/admin", admin_router(api_service.clone()))
+    } else {
+        app
+    };
+
+    let app = if api_service.resize_service.peer_cache().is_some() {
+        app.merge(peer_router(api_service))
+    } else {
+        app
+    };
 
     let router = apply_common_middlewares(app);
     Ok(router)
 }
+
+/// Authenticated runtime introspection and controls
+fn admin_router(api_service: Arc<ApiService>) -> Router {
+    let router = Router::new()
+        .route("/config", get(config))
+        .route("/stats", get(stats))
+        .route("/report", get(report))
+        .route("/maintenance", get(maintenance).put(set_maintenance))
+        .route("/images/{*key}", get(image_info).delete(purge))
+        .route("/uploads", post(issue_upload))
+        .route("/watermarks/{tenant}", get(list_watermarks))
+        .route(
+            "/watermarks/{tenant}/{name}",
+            get(get_watermark)
+                .put(put_watermark)
+                .delete(delete_watermark),
+        );
+
+    #[cfg(feature = "chaos")]
+    let router = router.route(
+        "/chaos",
+        get(crate::services::chaos::handler::chaos).put(crate::services::chaos::handler::set_chaos),
+    );
+
+    router
+        .route_layer(from_fn_with_state(api_service.clone(), require_token))
+        .with_state(api_service)
+}
+
+/// Direct uploads authorized by one-time tokens
+fn upload_router(api_service: Arc<ApiService>) -> Router {
+    let max_size = api_service.upload_service.max_size();
+    Router::new()
+        .route(&format!("{}/{{token}}", UPLOAD_ROUTE), put(accept_upload))
+        .layer(DefaultBodyLimit::max(max_size))
+        .with_state(api_service)
+}
+
+/// Background resizes and their status
+fn job_router(api_service: Arc<ApiService>) -> Router {
+    Router::new()
+        .route(JOB_ROUTE, post(submit_job))
+        .route(&format!("{}/{{id}}", JOB_ROUTE), get(job_status))
+        .with_state(api_service)
+}
+
+/// Art-directed pictures, cropped per breakpoint
+fn picture_router(api_service: Arc<ApiService>) -> Router {
+    Router::new()
+        .route(&format!("{}/{{id}}", PICTURE_ROUTE), get(picture))
+        .with_state(api_service)
+}
+
+/// Processed images shared between replicas
+fn peer_router(api_service: Arc<ApiService>) -> Router {
+    let max_size = api_service.upload_service.max_size();
+    Router::new()
+        .route(
+            &format!("{}/{{*key}}", PEER_ROUTE),
+            get(get_peer_object)
+                .put(put_peer_object)
+                .delete(delete_peer_object),
+        )
+        .layer(DefaultBodyLimit::max(max_size))
+        .with_state(api_service)
+}
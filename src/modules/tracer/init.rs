@@ -6,6 +6,7 @@ use opentelemetry::trace::TracerProvider;
 use opentelemetry_otlp::{Compression, Protocol, SpanExporter, WithExportConfig, WithTonicConfig};
 use opentelemetry_sdk::Resource;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
 use opentelemetry_sdk::trace::{
     RandomIdGenerator, Sampler, SdkTracerProvider, TracerProviderBuilder,
 };
@@ -17,6 +18,7 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 fn init_tracer_provider(
     otlp_span_endpoint: String,
     otlp_service_name: String,
+    otlp_sample_ratio: f64,
 ) -> Result<SdkTracerProvider> {
     let exporter = SpanExporter::builder()
         .with_tonic()
@@ -31,7 +33,10 @@ fn init_tracer_provider(
 
     let tracer_provider = TracerProviderBuilder::default()
         .with_batch_exporter(exporter)
-        .with_sampler(Sampler::AlwaysOn)
+        // Requests continuing a sampled trace, like debugged ones, are always sampled
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            otlp_sample_ratio.clamp(0.0, 1.0),
+        ))))
         .with_id_generator(RandomIdGenerator::default())
         .with_max_events_per_span(16)
         .with_max_attributes_per_span(16)
@@ -39,6 +44,8 @@ fn init_tracer_provider(
         .build();
 
     global::set_tracer_provider(tracer_provider.clone());
+    // Continue the traces of callers, and name the trace in the `traceparent` response header
+    global::set_text_map_propagator(TraceContextPropagator::new());
 
     Ok(tracer_provider)
 }
@@ -84,11 +91,16 @@ pub async fn init_tracing(
         otlp_metric_endpoint,
         otlp_span_endpoint,
         otlp_service_name,
+        otlp_sample_ratio,
         log_level,
         ..
     }: EnvConfig,
 ) -> Result<(HttpMetricsLayer, SdkTracerProvider, SdkMeterProvider)> {
-    let tracer_provider = init_tracer_provider(otlp_span_endpoint, otlp_service_name.clone())?;
+    let tracer_provider = init_tracer_provider(
+        otlp_span_endpoint,
+        otlp_service_name.clone(),
+        otlp_sample_ratio,
+    )?;
     let meter_provider = init_meter_provider(otlp_metric_endpoint, otlp_service_name)?;
 
     let metrics = HttpMetricsLayerBuilder::default()
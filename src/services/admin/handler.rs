@@ -0,0 +1,323 @@
+use crate::modules::api::handler::ApiService;
+use crate::modules::env::env::EnvConfig;
+use crate::services::audit::handler::AuditEvent;
+use crate::services::cache::source::{SourceCache, SourceCacheStats};
+use crate::services::image::handler::ImageStats;
+use crate::services::mirror::handler::{MirrorStats, TrafficMirror};
+use crate::services::moderation::handler::{Moderation, ModerationStats};
+use crate::services::resize::report::UsageReport;
+use crate::services::storage::retention::ObjectLocked;
+use crate::services::upload::handler::UploadTicket;
+use crate::services::upload::scan::{UploadScanStats, UploadScanner};
+use anyhow::{Context, Result};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tracing::{error, info, warn};
+
+/// Actor of the token set in `ADMIN_TOKEN`
+const DEFAULT_ACTOR: &str = "admin";
+
+/// Identity of an authenticated admin caller, recorded in the audit log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminActor(pub String);
+
+/// Settings of the admin API, only created when an admin token is configured
+#[derive(Debug, Clone)]
+pub struct AdminSettings {
+    /// Bearer tokens and the actor they identify
+    tokens: Vec<(String, String)>,
+    /// Configuration the service was started with, without secrets
+    config: serde_json::Value,
+}
+
+impl AdminSettings {
+    /// Read the tokens from `ADMIN_TOKEN` and `ADMIN_TOKENS` (`actor:token,...`)
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let mut tokens = Vec::new();
+        if let Some(token) = config.admin_token.clone().filter(|t| !t.is_empty()) {
+            tokens.push((DEFAULT_ACTOR.to_string(), token));
+        }
+        for entry in config.admin_tokens.iter().flat_map(|t| t.split(',')) {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let (actor, token) = entry
+                .split_once(':')
+                .filter(|(actor, token)| !actor.is_empty() && !token.is_empty())
+                .context("ADMIN_TOKENS entries must be formatted as actor:token")?;
+            tokens.push((actor.to_string(), token.to_string()));
+        }
+
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            tokens,
+            config: serde_json::to_value(config)?,
+        }))
+    }
+
+    /// Identify the caller from the bearer token of a request
+    pub(crate) fn authorize(&self, headers: &HeaderMap) -> Option<AdminActor> {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))?;
+
+        self.tokens
+            .iter()
+            .find(|(_, expected)| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+            .map(|(actor, _)| AdminActor(actor.clone()))
+    }
+}
+
+/// Compare secrets without leaking the position of the first difference
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Reject requests without an admin bearer token, and identify the caller
+pub async fn require_token(
+    State(api_service): State<Arc<ApiService>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let actor = api_service
+        .admin
+        .as_ref()
+        .and_then(|admin| admin.authorize(request.headers()));
+
+    match actor {
+        Some(actor) => {
+            request.extensions_mut().insert(actor);
+            Ok(next.run(request).await)
+        }
+        None => {
+            warn!("Rejected unauthorized admin request to {}", request.uri());
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// Cache lookups since startup
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub requests: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,
+}
+
+/// Deduplication of processing since startup, across replicas and within this one
+#[derive(Debug, Clone, Serialize)]
+pub struct LockStats {
+    pub acquired: u64,
+    pub contended: u64,
+    pub timeouts: u64,
+    /// Requests that shared the resize of an identical one in flight on this replica
+    pub coalesced: u64,
+}
+
+/// Body of the admin stats endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminStats {
+    pub maintenance: bool,
+    pub image: ImageStats,
+    pub cache: CacheStats,
+    pub lock: LockStats,
+    /// Reuse of downloaded originals, when the source cache is enabled
+    pub source_cache: Option<SourceCacheStats>,
+    /// Outcome of the upload scans, when uploads are scanned
+    pub upload_scans: Option<UploadScanStats>,
+    /// Outcome of the moderation of processed images, when moderated
+    pub moderation: Option<ModerationStats>,
+    /// Outcome of the mirrored requests, when mirroring
+    pub mirror: Option<MirrorStats>,
+}
+
+/// Body of the maintenance endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceState {
+    pub enabled: bool,
+}
+
+pub async fn config(State(api_service): State<Arc<ApiService>>) -> Json<serde_json::Value> {
+    let config = api_service
+        .admin
+        .as_ref()
+        .map(|admin| admin.config.clone())
+        .unwrap_or_default();
+    Json(config)
+}
+
+pub async fn stats(State(api_service): State<Arc<ApiService>>) -> Json<AdminStats> {
+    let resize_service = &api_service.resize_service;
+    let metrics = resize_service.metrics();
+
+    Json(AdminStats {
+        maintenance: resize_service.is_maintenance(),
+        image: resize_service.image_stats(),
+        cache: CacheStats {
+            requests: metrics.total_requests.load(Ordering::Relaxed),
+            hits: metrics.cache_hits.load(Ordering::Relaxed),
+            misses: metrics.cache_misses.load(Ordering::Relaxed),
+            hit_ratio: metrics.get_cache_hit_ratio(),
+        },
+        lock: LockStats {
+            acquired: metrics.lock_acquired.load(Ordering::Relaxed),
+            contended: metrics.lock_contended.load(Ordering::Relaxed),
+            timeouts: metrics.lock_timeouts.load(Ordering::Relaxed),
+            coalesced: metrics.coalesced.load(Ordering::Relaxed),
+        },
+        source_cache: resize_service.source_cache().map(SourceCache::stats),
+        upload_scans: api_service
+            .upload_service
+            .scanner()
+            .map(UploadScanner::stats),
+        moderation: resize_service.moderation().map(Moderation::stats),
+        mirror: api_service.mirror.as_ref().map(TrafficMirror::stats),
+    })
+}
+
+/// Cache efficiency and processing costs over the accounting window
+pub async fn report(State(api_service): State<Arc<ApiService>>) -> Json<UsageReport> {
+    Json(api_service.resize_service.report())
+}
+
+pub async fn maintenance(State(api_service): State<Arc<ApiService>>) -> Json<MaintenanceState> {
+    Json(MaintenanceState {
+        enabled: api_service.resize_service.is_maintenance(),
+    })
+}
+
+pub async fn set_maintenance(
+    State(api_service): State<Arc<ApiService>>,
+    Extension(actor): Extension<AdminActor>,
+    Json(state): Json<MaintenanceState>,
+) -> Json<MaintenanceState> {
+    api_service.resize_service.set_maintenance(state.enabled);
+    info!("Maintenance mode set to {} by {}", state.enabled, actor.0);
+
+    api_service
+        .audit_log
+        .record(
+            AuditEvent::new(actor.0, "maintenance")
+                .details(serde_json::json!({ "enabled": state.enabled })),
+        )
+        .await;
+    Json(state)
+}
+
+/// Delete a processed image from storage, unless it is locked for retention
+pub async fn purge(
+    State(api_service): State<Arc<ApiService>>,
+    Extension(actor): Extension<AdminActor>,
+    Path(key): Path<String>,
+) -> Response {
+    let result = api_service.resize_service.purge(&key).await;
+
+    let event = AuditEvent::new(actor.0, "purge")
+        .target(key.clone())
+        .success(matches!(result, Ok(true)));
+    api_service.audit_log.record(event).await;
+
+    match result {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) if e.is::<ObjectLocked>() => {
+            warn!("Refused to purge {}: {}", key, e);
+            (StatusCode::CONFLICT, e.to_string()).into_response()
+        }
+        Err(e) => {
+            error!("Failed to purge {}: {}", key, e);
+            StatusCode::BAD_REQUEST.into_response()
+        }
+    }
+}
+
+/// Metadata of a stored image, with the verdict of the moderation when classified
+pub async fn image_info(
+    State(api_service): State<Arc<ApiService>>,
+    Path(key): Path<String>,
+) -> Response {
+    match api_service.resize_service.info(&key).await {
+        Ok(Some(metadata)) => Json(metadata).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to read the metadata of {}: {}", key, e);
+            StatusCode::BAD_REQUEST.into_response()
+        }
+    }
+}
+
+/// Query of the upload endpoint
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    /// Tenant host the original is uploaded for, picking its upload scanner
+    pub tenant: Option<String>,
+}
+
+/// Issue a direct-upload URL for an original
+pub async fn issue_upload(
+    State(api_service): State<Arc<ApiService>>,
+    Extension(actor): Extension<AdminActor>,
+    Query(params): Query<UploadParams>,
+) -> Result<Json<UploadTicket>, StatusCode> {
+    let result = api_service
+        .upload_service
+        .issue(params.tenant.as_deref())
+        .await;
+
+    let mut event = AuditEvent::new(actor.0, "upload").success(result.is_ok());
+    if let Ok(ticket) = &result {
+        event = event.target(ticket.source.clone());
+    }
+    api_service.audit_log.record(event).await;
+
+    result.map(Json).map_err(|e| {
+        error!("Failed to issue upload URL: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize_identifies_actor() {
+        let admin = AdminSettings {
+            tokens: vec![
+                ("admin".to_string(), "secret".to_string()),
+                ("ops".to_string(), "other".to_string()),
+            ],
+            config: serde_json::Value::Null,
+        };
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, value.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(
+            admin.authorize(&headers("Bearer secret")),
+            Some(AdminActor("admin".to_string()))
+        );
+        assert_eq!(
+            admin.authorize(&headers("Bearer other")),
+            Some(AdminActor("ops".to_string()))
+        );
+        assert_eq!(admin.authorize(&headers("Bearer secreT")), None);
+        assert_eq!(admin.authorize(&headers("secret")), None);
+        assert_eq!(admin.authorize(&HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::storage::core::ObjectMetadata;
+use crate::services::storage::handler::StorageService;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+/// Storage prefix of the audit events
+const AUDIT_PREFIX: &str = "audit/";
+
+/// An operation recorded in the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Unix time of the operation, in milliseconds
+    pub timestamp_ms: u64,
+    /// Identity of the caller, as established by the auth layer
+    pub actor: String,
+    /// Operation, e.g. `purge` or `maintenance`
+    pub action: String,
+    /// Object of the operation, e.g. the purged key
+    pub target: Option<String>,
+    pub success: bool,
+    #[serde(default)]
+    pub details: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub fn new(actor: impl Into<String>, action: impl Into<String>) -> Self {
+        Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            actor: actor.into(),
+            action: action.into(),
+            target: None,
+            success: true,
+            details: serde_json::Value::Null,
+        }
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn success(mut self, success: bool) -> Self {
+        self.success = success;
+        self
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// Storage key of the event, unique even for events recorded in the same millisecond
+    fn key(&self, sub_path: &str, body: &[u8]) -> String {
+        let hash = format!("{:x}", Sha256::digest(body));
+        format!(
+            "{}{}{}-{}.json",
+            sub_path,
+            AUDIT_PREFIX,
+            self.timestamp_ms,
+            &hash[..16]
+        )
+    }
+}
+
+/// Structured log of administrative operations
+///
+/// Events are always emitted as `audit` tracing events, and are also written to the
+/// storage backend under `audit/` when `AUDIT_LOG_STORAGE` is enabled.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    storage: Option<StorageService>,
+    sub_path: String,
+}
+
+impl AuditLog {
+    pub fn new(storage: Option<StorageService>, sub_path: impl Into<String>) -> Self {
+        Self {
+            storage,
+            sub_path: sub_path.into(),
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig, storage: &StorageService) -> Self {
+        let storage = config.audit_log_storage.then(|| storage.clone());
+        Self::new(storage, config.sub_path.clone())
+    }
+
+    /// Record an event; failing to persist it is logged but doesn't fail the operation
+    pub async fn record(&self, event: AuditEvent) {
+        info!(
+            target: "audit",
+            actor = %event.actor,
+            action = %event.action,
+            object = event.target.as_deref().unwrap_or_default(),
+            success = event.success,
+            details = %event.details,
+            "Audit event"
+        );
+
+        if let Err(e) = self.persist(&event).await {
+            error!("Failed to persist audit event {}: {}", event.action, e);
+        }
+    }
+
+    async fn persist(&self, event: &AuditEvent) -> Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        let body = serde_json::to_vec(event).context("Failed to serialize audit event")?;
+        let key = event.key(&self.sub_path, &body);
+        storage
+            .upload_image(
+                &key,
+                "application/json",
+                body.into(),
+                &ObjectMetadata::default(),
+            )
+            .await
+    }
+}
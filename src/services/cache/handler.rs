@@ -1,57 +1,601 @@
-use crate::models::params::ResizeQuery;
+use crate::models::params::{KeptFrames, ResizeQuery, supports_animation};
+use crate::services::cache::url::normalize_url;
+use crate::services::image::encode::ENCODER_VERSION;
+use anyhow::{Result, bail};
 use derive_builder::Builder;
+use gen_server::models::ImageFormat;
 use sha2::{Digest, Sha256};
 
+/// Scheme of source URLs pointing to originals uploaded to storage
+pub const STORAGE_SCHEME: &str = "storage://";
+/// Storage prefix of uploaded originals
+pub const ORIGINALS_PREFIX: &str = "originals/";
+/// Name prefix of processed images keyed by the hash of their content
+pub const CONTENT_PREFIX: &str = "content-";
+/// Storage prefix of the originals cached by the source cache
+pub const SOURCES_PREFIX: &str = "sources/";
+/// Storage prefix of uploads found infected by the upload scanner
+pub const QUARANTINE_PREFIX: &str = "quarantine/";
+/// Name prefix of sources converted to another format without being resized
+pub const CONVERT_PREFIX: &str = "convert-";
+
 #[derive(Clone, Builder)]
 pub struct CacheService {
     minio_sub_path: String,
+    /// Store processed images under the hash of their content, see [`CacheService::content_key`]
+    #[builder(default)]
+    content_addressed: bool,
 }
 
 impl CacheService {
     /// Generate a deterministic cache key based on resize parameters
+    ///
+    /// Every set parameter is hashed with its name and length, so distinct queries
+    /// can't produce the same input to the hash. Values that mean the same thing
+    /// (an unset and the default format, `0` and `-0`, spacing in plugin lists) share
+    /// their encoding, as do spellings of the same source URL (see [`normalize_url`]).
     pub fn generate_key(&self, params: &ResizeQuery) -> String {
         let mut hasher = Sha256::new();
-        hasher.update(params.url.as_bytes());
-
-        match params.width {
-            Some(width) => {
-                hasher.update(width.to_string().as_bytes());
+        let mut field = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                hasher.update(format!("{}=", name).as_bytes());
+                hasher.update((value.len() as u64).to_le_bytes());
+                hasher.update(value.as_bytes());
             }
-            None => {
-                hasher.update("None".as_bytes());
+        };
+
+        field("encoder", Some(ENCODER_VERSION.to_string()));
+        field("url", Some(normalize_url(&params.url)));
+        field("width", params.width.map(|v| v.to_string()));
+        field("height", params.height.map(|v| v.to_string()));
+        field(
+            "format",
+            Some(params.output_format().to_string().to_lowercase()),
+        );
+        field("blur_sigma", params.blur_sigma.map(float));
+        field("grayscale", params.grayscale.map(|v| v.to_string()));
+        field(
+            "plugin",
+            params
+                .plugin
+                .is_some()
+                .then(|| params.plugins().collect::<Vec<_>>().join(",")),
+        );
+        field("ar", params.ar.clone());
+        field("zoom", params.zoom.map(float));
+        field("density", params.density.map(|v| v.to_string()));
+        field("quality", quality(params));
+        field("max_bytes", params.max_bytes.map(|v| v.to_string()));
+        field("denoise", params.denoise.map(float));
+        field("vignette", params.vignette.map(float));
+        field("tint", params.tint.clone());
+        field("pixelate", params.pixelate.map(|v| v.to_string()));
+        field("pixelate_region", params.pixelate_region.clone());
+        field("gamma", params.gamma.map(float));
+        field("exposure", params.exposure.map(float));
+        field("pad", params.pad.clone());
+        field("focus", params.focus.clone());
+        field("frame", params.frame.map(|v| v.to_string()));
+        field("time", params.time.map(float));
+        field("watermark", params.watermark.clone());
+        field("remove_bg", params.remove_bg.clone());
+        field("colors", params.colors.map(|v| v.to_string()));
+        field("dither", params.dither.clone());
+        field("auto_level", params.auto_level.clone());
+        field("filter_down", params.filter_down.clone());
+        field("filter_up", params.filter_up.clone());
+        field("subsampling", params.subsampling.clone());
+        field("auto_sharpen", params.auto_sharpen.map(|v| v.to_string()));
+        field("fit", params.fit.clone());
+        // Only formats that can be animated depend on `frames`, absent meaning `all`
+        field(
+            "frames",
+            (supports_animation(&params.output_format())
+                && matches!(params.frames(), Ok(KeptFrames::First)))
+            .then(|| "first".to_string()),
+        );
+        field("animation", animated_webp(params));
+
+        let result = hasher.finalize();
+        format!(
+            "{:}{:x}.{}",
+            self.minio_sub_path,
+            result,
+            params.output_format()
+        )
+    }
+
+    /// Cache key of a format conversion, apart from the keys of resizes
+    ///
+    /// Only the source, format and quality are hashed, the conversion ignoring the rest.
+    pub fn convert_key(&self, params: &ResizeQuery) -> String {
+        let format = params.output_format().to_string().to_lowercase();
+        let mut hasher = Sha256::new();
+        for (name, value) in [
+            ("encoder", Some(ENCODER_VERSION.to_string())),
+            ("url", Some(normalize_url(&params.url))),
+            ("format", Some(format)),
+            ("quality", quality(params)),
+            ("animation", animated_webp(params)),
+        ] {
+            if let Some(value) = value {
+                hasher.update(format!("{}=", name).as_bytes());
+                hasher.update((value.len() as u64).to_le_bytes());
+                hasher.update(value.as_bytes());
             }
         }
 
-        match params.height {
-            Some(height) => {
-                hasher.update(height.to_string().as_bytes());
-            }
-            None => {
-                hasher.update("None".as_bytes());
-            }
+        format!(
+            "{}{}{:x}.{}",
+            self.minio_sub_path,
+            CONVERT_PREFIX,
+            hasher.finalize(),
+            params.output_format()
+        )
+    }
+
+    /// Whether `key` is the cache key of a format conversion
+    pub fn is_conversion_key(&self, key: &str) -> bool {
+        key.strip_prefix(&self.minio_sub_path)
+            .is_some_and(|name| name.starts_with(CONVERT_PREFIX))
+    }
+
+    /// Cache key of an image produced by the `pipeline` canary rather than the stable one
+    pub fn pipeline_key(&self, cache_key: &str, pipeline: &str) -> String {
+        let name = cache_key
+            .strip_prefix(&self.minio_sub_path)
+            .unwrap_or(cache_key);
+        format!("{}{}-{}", self.minio_sub_path, pipeline, name)
+    }
+
+    pub fn is_content_addressed(&self) -> bool {
+        self.content_addressed
+    }
+
+    /// Storage key of a processed image in content-addressed mode
+    ///
+    /// Identical outputs of different queries share this key, while the key generated from
+    /// the query only holds a pointer to it.
+    pub fn content_key(&self, data: &[u8], format: &str) -> String {
+        format!(
+            "{}{}{:x}.{}",
+            self.minio_sub_path,
+            CONTENT_PREFIX,
+            Sha256::digest(data),
+            format
+        )
+    }
+
+    /// Storage prefix of processed images
+    pub fn derivatives_prefix(&self) -> &str {
+        &self.minio_sub_path
+    }
+
+    /// Whether `key` is a processed image, rather than an uploaded original or another object
+    pub fn is_derivative_key(&self, key: &str) -> bool {
+        key.strip_prefix(&self.minio_sub_path)
+            .is_some_and(|name| !name.contains('/') && name.contains('.'))
+    }
+
+    /// Storage key of an uploaded original
+    pub fn original_key(&self, id: &str) -> String {
+        format!("{}{}{}", self.minio_sub_path, ORIGINALS_PREFIX, id)
+    }
+
+    /// Storage key of an upload held in quarantine, which can't be used as a source
+    pub fn quarantine_key(&self, id: &str) -> String {
+        format!("{}{}{}", self.minio_sub_path, QUARANTINE_PREFIX, id)
+    }
+
+    /// Storage key of the cached copy of the source at `url`, shared by its spellings
+    pub fn source_cache_key(&self, url: &str) -> String {
+        format!(
+            "{}{}{:x}",
+            self.minio_sub_path,
+            SOURCES_PREFIX,
+            Sha256::digest(normalize_url(url))
+        )
+    }
+
+    /// Storage key of a `storage://originals/<id>` source URL, or `None` for other schemes
+    ///
+    /// Only uploaded originals can be referenced, not processed images or audit events.
+    pub fn storage_source_key(&self, url: &str) -> Result<Option<String>> {
+        let Some(path) = url.strip_prefix(STORAGE_SCHEME) else {
+            return Ok(None);
+        };
+        let Some(id) = path.strip_prefix(ORIGINALS_PREFIX) else {
+            bail!(
+                "Storage sources must be under {}{}",
+                STORAGE_SCHEME,
+                ORIGINALS_PREFIX
+            );
+        };
+        if id.is_empty() || id.split('/').any(|part| part.is_empty() || part == "..") {
+            bail!("Invalid storage source: {}", url);
         }
 
-        hasher.update(params.format.to_string().to_lowercase().as_bytes());
+        Ok(Some(self.original_key(id)))
+    }
 
-        match params.blur_sigma {
-            Some(blur_sigma) => {
-                hasher.update(blur_sigma.to_string().as_bytes());
-            }
-            None => {
-                hasher.update("None".as_bytes());
+    /// Generate the CDN surrogate keys (cache tags) for a request
+    ///
+    /// Every variant of the same source shares the `origin-` tag, so purging it
+    /// at the CDN drops all derivatives. The `tenant-` tag groups everything
+    /// requested through the same host.
+    pub fn generate_surrogate_keys(
+        &self,
+        params: &ResizeQuery,
+        tenant: Option<&str>,
+    ) -> Vec<String> {
+        let origin_hash = format!("{:x}", Sha256::digest(normalize_url(&params.url)));
+        let mut keys = vec![format!("origin-{}", &origin_hash[..16])];
+
+        if let Some(tenant) = tenant {
+            // Drop the port and anything a CDN wouldn't accept in a tag
+            let tenant = tenant
+                .split(':')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase()
+                .replace(
+                    |c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-',
+                    "",
+                );
+
+            if !tenant.is_empty() {
+                keys.push(format!("tenant-{}", tenant));
             }
         }
 
-        match params.grayscale {
-            Some(grayscale) => {
-                hasher.update(grayscale.to_string().as_bytes());
+        keys
+    }
+}
+
+/// Key encoding of the quality, with `080` and `80` sharing theirs
+/// Marker of WebP output keeping animations
+///
+/// WebP output of animated sources used to keep only the first frame, so the animated
+/// variants get keys of their own rather than bumping `ENCODER_VERSION` for every format.
+fn animated_webp(params: &ResizeQuery) -> Option<String> {
+    (params.output_format() == ImageFormat::Webp && params.keeps_animation().unwrap_or(false))
+        .then(|| "webp".to_string())
+}
+
+fn quality(params: &ResizeQuery) -> Option<String> {
+    match params.fixed_quality() {
+        Ok(Some(quality)) => Some(quality.to_string()),
+        _ => params.quality.clone(),
+    }
+}
+
+/// Key encoding of a float parameter, with `-0` and `0` sharing theirs
+fn float(value: f32) -> String {
+    if value == 0.0 { 0.0 } else { value }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gen_server::models::{ImageFormat, ResizeQueryParams};
+    use proptest::prelude::*;
+    use proptest::sample::select;
+    use serde_json::json;
+
+    fn query(url: &str, width: Option<u32>) -> ResizeQuery {
+        let params: ResizeQueryParams =
+            serde_json::from_value(json!({ "url": url, "width": width })).unwrap();
+        ResizeQuery::from(params)
+    }
+
+    #[test]
+    fn test_surrogate_keys_share_origin_tag_across_variants() {
+        let cache_service = CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap();
+
+        let small =
+            cache_service.generate_surrogate_keys(&query("https://a.test/x.jpg", Some(100)), None);
+        let large =
+            cache_service.generate_surrogate_keys(&query("https://a.test/x.jpg", Some(800)), None);
+        let other =
+            cache_service.generate_surrogate_keys(&query("https://a.test/y.jpg", Some(100)), None);
+
+        assert_eq!(small, large);
+        assert_ne!(small, other);
+        assert_eq!(small.len(), 1);
+        assert!(small[0].starts_with("origin-"));
+    }
+
+    #[test]
+    fn test_surrogate_keys_tenant_tag_strips_port() {
+        let cache_service = CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap();
+
+        let keys = cache_service.generate_surrogate_keys(
+            &query("https://a.test/x.jpg", None),
+            Some("Images.Example.com:3000"),
+        );
+
+        assert_eq!(keys[1], "tenant-images.example.com");
+    }
+
+    #[test]
+    fn test_storage_source_key_is_limited_to_originals() {
+        let cache_service = CacheServiceBuilder::default()
+            .minio_sub_path("prod/".to_string())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            cache_service
+                .storage_source_key("storage://originals/abc")
+                .unwrap(),
+            Some("prod/originals/abc".to_string())
+        );
+        assert_eq!(
+            cache_service
+                .storage_source_key("https://a.test/x.jpg")
+                .unwrap(),
+            None
+        );
+        assert!(
+            cache_service
+                .storage_source_key("storage://audit/1.json")
+                .is_err()
+        );
+        assert!(
+            cache_service
+                .storage_source_key("storage://originals/../audit/1.json")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_conversions_have_their_own_keys() {
+        let cache_service = CacheServiceBuilder::default()
+            .minio_sub_path("prod/".to_string())
+            .build()
+            .unwrap();
+
+        let source = query("https://a.test/x.jpg", None);
+        let key = cache_service.convert_key(&source);
+        assert!(key.starts_with("prod/convert-"));
+        assert!(key.ends_with(".jpg"));
+        assert_ne!(key, cache_service.generate_key(&source));
+        assert!(cache_service.is_conversion_key(&key));
+        assert!(cache_service.is_derivative_key(&key));
+        assert!(!cache_service.is_conversion_key(&cache_service.generate_key(&source)));
+
+        // Resize parameters aren't part of a conversion
+        assert_eq!(
+            cache_service.convert_key(&query("https://a.test/x.jpg", Some(100))),
+            key
+        );
+    }
+
+    #[test]
+    fn test_frames_only_fragment_animated_outputs() {
+        let cache_service = cache_service();
+        let key = |format: ImageFormat, frames: Option<&str>| {
+            cache_service.generate_key(&ResizeQuery {
+                format: Some(format),
+                frames: frames.map(str::to_string),
+                ..query("https://a.test/x.gif", None)
+            })
+        };
+
+        for format in [ImageFormat::Gif, ImageFormat::Webp] {
+            assert_eq!(key(format, None), key(format, Some("all")));
+            assert_ne!(key(format, None), key(format, Some("first")));
+        }
+        // Conversions to WebP are animated as well
+        let webp = ResizeQuery {
+            format: Some(ImageFormat::Webp),
+            ..query("https://a.test/x.gif", None)
+        };
+        assert_ne!(
+            cache_service.convert_key(&webp),
+            cache_service.convert_key(&ResizeQuery {
+                frames: Some("first".to_string()),
+                ..webp.clone()
+            })
+        );
+        // Still formats keep the first frame whatever is asked
+        assert_eq!(
+            key(ImageFormat::Png, None),
+            key(ImageFormat::Png, Some("first"))
+        );
+        assert_eq!(
+            key(ImageFormat::Png, None),
+            key(ImageFormat::Png, Some("all"))
+        );
+    }
+
+    fn cache_service() -> CacheService {
+        CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_keys_are_stable() {
+        // Changing any of these invalidates every cached image
+        let cache_service = cache_service();
+        let golden = [
+            (
+                query("https://a.test/x.jpg", None),
+                "8db1b6f6cee4473567e76c430d71d400002be71f7427722c8cd7a78990a9c6c7.jpg",
+            ),
+            (
+                query("https://a.test/x.jpg", Some(300)),
+                "1de1fc23e129bb6ceb30c08ca992dc134630210f45d39fd703460c05105e853a.jpg",
+            ),
+            (
+                ResizeQuery {
+                    url: "https://a.test/x.jpg".to_string(),
+                    width: Some(300),
+                    height: Some(200),
+                    format: Some(ImageFormat::Webp),
+                    blur_sigma: Some(1.5),
+                    plugin: Some("sepia".to_string()),
+                    quality: Some("auto".to_string()),
+                    ..ResizeQuery::default()
+                },
+                "d8148913c86c4fc5167baa16dc0cd4b21fa79bee0238dec41368b1aa62a42460.webp",
+            ),
+        ];
+
+        for (query, key) in golden {
+            assert_eq!(cache_service.generate_key(&query), key, "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn test_keys_separate_adjacent_values() {
+        let cache_service = cache_service();
+        let key = |url: &str, width, height| {
+            cache_service.generate_key(&ResizeQuery {
+                url: url.to_string(),
+                width,
+                height,
+                ..ResizeQuery::default()
+            })
+        };
+
+        assert_ne!(
+            key("https://a.test/x", Some(1), Some(23)),
+            key("https://a.test/x", Some(12), Some(3))
+        );
+        assert_ne!(
+            key("https://a.test/x1", Some(23), None),
+            key("https://a.test/x12", Some(3), None)
+        );
+    }
+
+    // Small domains, so that generated queries often differ by a single parameter
+    prop_compose! {
+        fn arb_geometry()(
+            url in select(vec!["https://a.test/x1", "https://a.test/x12", "https://a.test/x1?2"]),
+            width in proptest::option::of(0u32..16),
+            height in proptest::option::of(0u32..16),
+            format in proptest::option::of(select(vec![
+                ImageFormat::Jpg,
+                ImageFormat::Png,
+                ImageFormat::Webp,
+            ])),
+            ar in proptest::option::of(select(vec!["1:1", "16:9"])),
+            zoom in proptest::option::of(select(vec![0.5f32, 1.0, 1.5])),
+            density in proptest::option::of(select(vec![72u32, 300])),
+            pad in proptest::option::of(select(vec!["1", "1,2", "12"])),
+            focus in proptest::option::of(select(vec!["0,1", "0.1,1", "0,0.1"])),
+            frame in proptest::option::of(0u32..3),
+            time in proptest::option::of(select(vec![0.0f32, 1.0, 1.5])),
+        ) -> ResizeQuery {
+            ResizeQuery {
+                url: url.to_string(),
+                width,
+                height,
+                format,
+                ar: ar.map(str::to_string),
+                zoom,
+                density,
+                pad: pad.map(str::to_string),
+                focus: focus.map(str::to_string),
+                frame,
+                time,
+                ..ResizeQuery::default()
             }
-            None => {
-                hasher.update("None".as_bytes());
+        }
+    }
+
+    prop_compose! {
+        fn arb_query()(
+            geometry in arb_geometry(),
+            blur_sigma in proptest::option::of(select(vec![0.0f32, 1.0, 1.5])),
+            grayscale in proptest::option::of(any::<bool>()),
+            plugin in proptest::option::of(select(vec!["a", "a,b", "ab"])),
+            quality in proptest::option::of(select(vec!["auto", "1", "12"])),
+            max_bytes in proptest::option::of(select(vec![1u32, 12])),
+            (denoise, vignette, gamma, exposure) in (
+                proptest::option::of(select(vec![0.0f32, 1.0])),
+                proptest::option::of(select(vec![0.0f32, 1.0])),
+                proptest::option::of(select(vec![1.0f32, 2.2])),
+                proptest::option::of(select(vec![0.0f32, 1.0])),
+            ),
+            tint in proptest::option::of(select(vec!["ff8800", "ff8800:0.4"])),
+            pixelate in proptest::option::of(1u32..13),
+            pixelate_region in proptest::option::of(select(vec!["0,0,1,1", "0,0,1,12"])),
+            remove_bg in proptest::option::of(select(vec!["border", "border:4"])),
+        ) -> ResizeQuery {
+            ResizeQuery {
+                blur_sigma,
+                grayscale,
+                plugin: plugin.map(str::to_string),
+                quality: quality.map(str::to_string),
+                max_bytes,
+                denoise,
+                vignette,
+                gamma,
+                exposure,
+                tint: tint.map(str::to_string),
+                pixelate,
+                pixelate_region: pixelate_region.map(str::to_string),
+                remove_bg: remove_bg.map(str::to_string),
+                ..geometry
             }
         }
+    }
+
+    /// `query` with the defaults it stands for filled in
+    fn resolved(query: &ResizeQuery) -> ResizeQuery {
+        ResizeQuery {
+            format: Some(query.output_format()),
+            ..query.clone()
+        }
+    }
 
-        let result = hasher.finalize();
-        format!("{:}{:x}.{}", self.minio_sub_path, result, params.format)
+    /// `query` written differently: negative zeros, zero padded qualities, spaced out plugin
+    /// lists and an uppercase source host with its default port
+    fn respelled(query: &ResizeQuery) -> ResizeQuery {
+        let negate_zero = |value: Option<f32>| value.map(|v| if v == 0.0 { -0.0 } else { v });
+        ResizeQuery {
+            url: query.url.replace("https://a.test", "https://A.TEST:443"),
+            blur_sigma: negate_zero(query.blur_sigma),
+            time: negate_zero(query.time),
+            denoise: negate_zero(query.denoise),
+            exposure: negate_zero(query.exposure),
+            plugin: query.plugin.as_ref().map(|p| p.replace(',', " , ")),
+            quality: query.quality.as_ref().map(|q| match q.as_str() {
+                "auto" => q.clone(),
+                _ => format!("0{}", q),
+            }),
+            ..query.clone()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_equivalent_queries_share_keys(query in arb_query()) {
+            let cache_service = cache_service();
+            let key = cache_service.generate_key(&query);
+
+            let round_tripped: ResizeQuery =
+                serde_json::from_value(serde_json::to_value(&query).unwrap()).unwrap();
+            prop_assert_eq!(&cache_service.generate_key(&round_tripped), &key);
+            prop_assert_eq!(&cache_service.generate_key(&resolved(&query)), &key);
+            prop_assert_eq!(&cache_service.generate_key(&respelled(&query)), &key);
+        }
+
+        #[test]
+        fn prop_distinct_queries_have_distinct_keys(a in arb_query(), b in arb_query()) {
+            prop_assume!(resolved(&a) != resolved(&b));
+            let cache_service = cache_service();
+            prop_assert_ne!(cache_service.generate_key(&a), cache_service.generate_key(&b));
+        }
     }
 }
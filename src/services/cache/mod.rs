@@ -1 +1,3 @@
 pub mod handler;
+pub mod source;
+pub mod url;
@@ -0,0 +1,182 @@
+use crate::modules::env::env::EnvConfig;
+use crate::modules::utils::date::unix_now;
+use crate::services::image::handler::ImageService;
+use crate::services::image::validators::{SourceDownload, SourceValidators};
+use crate::services::storage::core::ObjectMetadata;
+use crate::services::storage::handler::StorageService;
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Suffix of the entry recording when a cached source was fetched, and its validators
+const ENTRY_SUFFIX: &str = ".json";
+
+/// Content type cached sources are stored with, whatever their format
+const SOURCE_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Freshness of a cached source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SourceEntry {
+    /// Last download or revalidation, in seconds since the Unix epoch
+    fetched_at: u64,
+    #[serde(default)]
+    validators: SourceValidators,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    revalidated: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Source cache lookups since startup
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceCacheStats {
+    /// Sources read from storage without contacting the origin
+    pub hits: u64,
+    /// Stale sources the origin confirmed unchanged with a 304
+    pub revalidated: u64,
+    /// Sources downloaded in full
+    pub misses: u64,
+}
+
+/// Read-through cache of downloaded originals in storage
+///
+/// Another variant of a source requested within `ttl` of its download is generated from
+/// the copy in storage rather than downloaded again. Past the TTL, the copy is revalidated
+/// with the validators the origin sent, so an unchanged source costs a 304 rather than its
+/// body. Storage failures fall back to the origin, the cache never failing a resize.
+#[derive(Clone)]
+pub struct SourceCache {
+    ttl: Duration,
+    counters: Arc<Counters>,
+}
+
+impl SourceCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            counters: Arc::default(),
+        }
+    }
+
+    /// The source cache configured by the environment, if enabled
+    pub fn from_env(config: &EnvConfig) -> Option<Self> {
+        (config.source_cache_ttl_secs > 0)
+            .then(|| Self::new(Duration::from_secs(config.source_cache_ttl_secs)))
+    }
+
+    pub fn stats(&self) -> SourceCacheStats {
+        let counters = &self.counters;
+        SourceCacheStats {
+            hits: counters.hits.load(Ordering::Relaxed),
+            revalidated: counters.revalidated.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The source at `url`, from its copy under `key` while fresh or still current
+    pub async fn fetch(
+        &self,
+        storage: &StorageService,
+        image: &ImageService,
+        key: &str,
+        url: &str,
+        tenant: Option<&str>,
+    ) -> Result<Bytes> {
+        let now = unix_now();
+        let (validators, cached) = match self.lookup(storage, key).await {
+            Some((entry, data)) if now < entry.fetched_at.saturating_add(self.ttl.as_secs()) => {
+                debug!("Source cache hit for {}", url);
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(data);
+            }
+            Some((entry, data)) => (entry.validators, Some(data)),
+            None => (SourceValidators::default(), None),
+        };
+
+        match image.download_source_for(url, tenant, &validators).await? {
+            SourceDownload::NotModified => {
+                let Some(data) = cached else {
+                    bail!("{} answered an unconditional request with 304", url);
+                };
+                debug!("Source cache revalidated {}", url);
+                self.counters.revalidated.fetch_add(1, Ordering::Relaxed);
+                let entry = SourceEntry {
+                    fetched_at: now,
+                    validators,
+                };
+                if let Err(e) = self.write_entry(storage, key, &entry).await {
+                    warn!("Failed to refresh cached source {}: {}", key, e);
+                }
+                Ok(data)
+            }
+            SourceDownload::Modified { data, validators } => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                let entry = SourceEntry {
+                    fetched_at: now,
+                    validators,
+                };
+                if let Err(e) = self.store(storage, key, data.clone(), &entry).await {
+                    warn!("Failed to cache source {}: {}", key, e);
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    /// Entry and copy of a cached source, treating storage errors as a miss
+    async fn lookup(&self, storage: &StorageService, key: &str) -> Option<(SourceEntry, Bytes)> {
+        let entry_key = format!("{}{}", key, ENTRY_SUFFIX);
+        if !storage.check_cache(&entry_key).await.unwrap_or(false) {
+            return None;
+        }
+        let lookup = async {
+            let entry: SourceEntry = serde_json::from_slice(&storage.get_image(&entry_key).await?)?;
+            let data = storage.get_image(key).await?;
+            anyhow::Ok((entry, data))
+        };
+        match lookup.await {
+            Ok(cached) => Some(cached),
+            Err(e) => {
+                warn!("Failed to read cached source {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Store a downloaded source, then its entry, so that an entry implies its copy
+    async fn store(
+        &self,
+        storage: &StorageService,
+        key: &str,
+        data: Bytes,
+        entry: &SourceEntry,
+    ) -> Result<()> {
+        storage
+            .upload_image(key, SOURCE_CONTENT_TYPE, data, &ObjectMetadata::default())
+            .await?;
+        self.write_entry(storage, key, entry).await
+    }
+
+    async fn write_entry(
+        &self,
+        storage: &StorageService,
+        key: &str,
+        entry: &SourceEntry,
+    ) -> Result<()> {
+        storage
+            .upload_image(
+                &format!("{}{}", key, ENTRY_SUFFIX),
+                "application/json",
+                serde_json::to_vec(entry)?.into(),
+                &ObjectMetadata::default(),
+            )
+            .await
+    }
+}
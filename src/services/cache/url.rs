@@ -0,0 +1,105 @@
+use reqwest::Url;
+
+/// Canonical spelling of a source URL, so that spellings of the same URL share a cache key
+///
+/// Parsing lowercases the scheme and host, drops default ports and resolves `.` and `..`
+/// segments. On top of that, percent-escapes of unreserved characters are decoded and the
+/// others uppercased, query parameters are sorted by name (keeping the order of repeated
+/// ones) and the fragment, which is never sent to the origin, is dropped.
+///
+/// URLs that don't parse, and schemes other than HTTP(S), are returned unchanged.
+pub fn normalize_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return url.to_string();
+    }
+
+    let path = canonical_escapes(parsed.path());
+    parsed.set_path(&path);
+
+    let mut params: Vec<String> = parsed
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(canonical_escapes)
+        .collect();
+    params.sort_by(|a, b| name(a).cmp(name(b)));
+    let query = params.join("&");
+    parsed.set_query((!query.is_empty()).then_some(query.as_str()));
+
+    parsed.set_fragment(None);
+    parsed.into()
+}
+
+fn name(param: &str) -> &str {
+    param.split('=').next().unwrap_or_default()
+}
+
+/// Decode escaped unreserved characters and uppercase the remaining escapes
+fn canonical_escapes(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut canonical = String::with_capacity(value.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], bytes.get(i + 1), bytes.get(i + 2)) {
+            (b'%', Some(&high), Some(&low)) => hex(high).zip(hex(low)).map(|(h, l)| h * 16 + l),
+            _ => None,
+        };
+
+        match escaped {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                canonical.push(char::from(byte));
+                i += 3;
+            }
+            Some(byte) => {
+                canonical.push_str(&format!("%{:02X}", byte));
+                i += 3;
+            }
+            None => {
+                // Parsed URLs are ASCII, anything else is kept as is
+                let c = value[i..].chars().next().unwrap_or_default();
+                canonical.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    canonical
+}
+
+fn hex(digit: u8) -> Option<u8> {
+    char::from(digit).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spellings_of_a_url_share_a_normal_form() {
+        let canonical = "https://images.example.com/a/b%2F~c.jpg?v=2&w=1&w=0";
+        for spelling in [
+            canonical,
+            "HTTPS://Images.Example.COM:443/a/./b%2f%7Ec.jpg?w=1&v=2&w=0",
+            "https://images.example.com/a/x/../b%2F~c.jpg?w=1&&v=2&w=0#top",
+        ] {
+            assert_eq!(normalize_url(spelling), canonical, "{}", spelling);
+        }
+
+        assert_eq!(
+            normalize_url("http://a.test:8080/x"),
+            "http://a.test:8080/x"
+        );
+        assert_ne!(
+            normalize_url("https://a.test/x?w=1&w=0"),
+            normalize_url("https://a.test/x?w=0&w=1")
+        );
+        assert_eq!(
+            normalize_url("storage://originals/AbC"),
+            "storage://originals/AbC"
+        );
+        assert_eq!(normalize_url("not a url"), "not a url");
+    }
+}
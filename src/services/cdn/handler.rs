@@ -0,0 +1,129 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// `Cache-Control` of redirects to the source, which must not outlive the failure
+pub const FALLBACK_CACHE_CONTROL: &str = "no-store";
+
+/// Caching headers of images and resize redirects, for browsers and CDNs
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheHeaders {
+    /// `Cache-Control` of downloaded images
+    pub cache_control: String,
+    /// `Cache-Control` of resize redirects to processed images
+    pub redirect_cache_control: Option<String>,
+    /// `CDN-Cache-Control`, honored by CDNs instead of `Cache-Control`
+    pub cdn_cache_control: Option<String>,
+    /// Request headers the responses vary on
+    pub vary: Option<String>,
+}
+
+impl Default for CacheHeaders {
+    fn default() -> Self {
+        Self {
+            cache_control: "public, max-age=31536000, immutable".to_string(),
+            redirect_cache_control: Some("no-cache".to_string()),
+            cdn_cache_control: None,
+            vary: None,
+        }
+    }
+}
+
+impl CacheHeaders {
+    /// Read the headers, adding `STALE_IF_ERROR_SECS` to every cache policy
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        let header = |name: &str, value: Option<&str>, policy: bool| -> Result<Option<String>> {
+            let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+                return Ok(None);
+            };
+            let value = if policy && config.stale_if_error_secs > 0 {
+                format!("{}, stale-if-error={}", value, config.stale_if_error_secs)
+            } else {
+                value.to_string()
+            };
+            HeaderValue::from_str(&value)
+                .with_context(|| format!("{} is not a valid header value: {}", name, value))?;
+            Ok(Some(value))
+        };
+
+        Ok(Self {
+            cache_control: header("CACHE_CONTROL", Some(&config.cache_control), true)?
+                .context("CACHE_CONTROL can't be empty")?,
+            redirect_cache_control: header(
+                "REDIRECT_CACHE_CONTROL",
+                Some(&config.redirect_cache_control),
+                true,
+            )?,
+            cdn_cache_control: header(
+                "CDN_CACHE_CONTROL",
+                config.cdn_cache_control.as_deref(),
+                true,
+            )?,
+            vary: header("VARY", config.vary.as_deref(), false)?,
+        })
+    }
+}
+
+/// Whether an `If-None-Match` list matches `etag`, comparing weakly as required for it
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Answer resize redirects and inline images the client already holds, by their `ETag`, with
+/// `304 Not Modified`
+///
+/// The resize still runs, so a purged image is generated again before the client is told
+/// to reuse its copy.
+pub async fn conditional_redirects(request: Request, next: Next) -> Response {
+    let if_none_match = request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let response = next.run(request).await;
+    let Some(if_none_match) = if_none_match else {
+        return response;
+    };
+
+    let matches = matches!(
+        response.status(),
+        StatusCode::MOVED_PERMANENTLY | StatusCode::OK
+    ) && response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .is_some_and(|etag| etag_matches(&if_none_match, etag));
+    if !matches {
+        return response;
+    }
+
+    // The caching headers of the redirect are kept, so caches can refresh their copy
+    let (mut parts, _) = response.into_parts();
+    parts.status = StatusCode::NOT_MODIFIED;
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(CONTENT_TYPE);
+    Response::from_parts(parts, Body::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_etags_weakly() {
+        let etag = "\"images/abc.jpg\"";
+        assert!(etag_matches("\"images/abc.jpg\"", etag));
+        assert!(etag_matches("\"other.jpg\", W/\"images/abc.jpg\"", etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches("\"images/abc.png\"", etag));
+    }
+}
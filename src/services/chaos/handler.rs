@@ -0,0 +1,243 @@
+use crate::modules::api::handler::ApiService;
+use crate::services::admin::handler::AdminActor;
+use crate::services::audit::handler::AuditEvent;
+use crate::services::storage::core::{ObjectMetadata, StorageBackend, StoredObject};
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Faults injected into API requests and storage calls
+///
+/// Rates are the share of requests or calls affected, from 0 (never) to 1 (always).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosSettings {
+    /// Share of API requests delayed by `latency_ms`
+    pub latency_rate: f64,
+    pub latency_ms: u64,
+    /// Share of API requests answered with `error_status` instead of being handled
+    pub error_rate: f64,
+    pub error_status: u16,
+    /// Share of storage calls failing before reaching the backend
+    pub storage_failure_rate: f64,
+}
+
+impl Default for ChaosSettings {
+    fn default() -> Self {
+        Self {
+            latency_rate: 0.0,
+            latency_ms: 0,
+            error_rate: 0.0,
+            error_status: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+            storage_failure_rate: 0.0,
+        }
+    }
+}
+
+impl ChaosSettings {
+    fn validate(&self) -> Result<()> {
+        for (name, rate) in [
+            ("latency_rate", self.latency_rate),
+            ("error_rate", self.error_rate),
+            ("storage_failure_rate", self.storage_failure_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("{} must be between 0 and 1, got {}", name, rate);
+            }
+        }
+        if !(400..=599).contains(&self.error_status) {
+            bail!(
+                "error_status must be a 4xx or 5xx status, got {}",
+                self.error_status
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Fault injection for resilience testing, changed at runtime through the admin API
+///
+/// Nothing is injected until rates are set, so builds with the `chaos` feature behave
+/// normally by default.
+#[derive(Debug, Clone, Default)]
+pub struct ChaosController {
+    settings: Arc<RwLock<ChaosSettings>>,
+}
+
+impl ChaosController {
+    pub fn settings(&self) -> ChaosSettings {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Replace the injected faults, rejecting out of range settings
+    pub fn set(&self, settings: ChaosSettings) -> Result<()> {
+        settings.validate()?;
+        *self.settings.write().unwrap() = settings;
+        Ok(())
+    }
+
+    /// Fail a storage call at the configured rate
+    fn storage_fault(&self, operation: &str) -> Result<()> {
+        if roll(self.settings.read().unwrap().storage_failure_rate) {
+            bail!("Injected storage failure on {}", operation);
+        }
+        Ok(())
+    }
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && fastrand::f64() < rate
+}
+
+/// Delay or fail API requests at the configured rates
+pub async fn inject_faults(
+    State(chaos): State<ChaosController>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let settings = chaos.settings();
+    if roll(settings.latency_rate) {
+        tokio::time::sleep(Duration::from_millis(settings.latency_ms)).await;
+    }
+    if roll(settings.error_rate) {
+        warn!(
+            "Injecting a {} into {}",
+            settings.error_status,
+            request.uri()
+        );
+        return StatusCode::from_u16(settings.error_status)
+            .unwrap_or(StatusCode::SERVICE_UNAVAILABLE)
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Storage backend failing a share of the calls before they reach the wrapped one
+pub struct ChaosStorage {
+    inner: Arc<dyn StorageBackend>,
+    chaos: ChaosController,
+}
+
+impl ChaosStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>, chaos: ChaosController) -> Self {
+        Self { inner, chaos }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ChaosStorage {
+    async fn upload_image(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
+        self.chaos.storage_fault("upload")?;
+        self.inner
+            .upload_image(key, content_type, data, metadata)
+            .await
+    }
+
+    async fn check_cache(&self, key: &str) -> Result<bool> {
+        self.chaos.storage_fault("lookup")?;
+        self.inner.check_cache(key).await
+    }
+
+    async fn get_image(&self, key: &str) -> Result<Bytes> {
+        self.chaos.storage_fault("read")?;
+        self.inner.get_image(key).await
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        self.chaos.storage_fault("metadata")?;
+        self.inner.get_metadata(key).await
+    }
+
+    async fn delete_image(&self, key: &str) -> Result<bool> {
+        self.chaos.storage_fault("delete")?;
+        self.inner.delete_image(key).await
+    }
+
+    async fn presign_upload(&self, key: &str, expires_in: Duration) -> Result<Option<String>> {
+        self.chaos.storage_fault("presign")?;
+        self.inner.presign_upload(key, expires_in).await
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        self.chaos.storage_fault("list")?;
+        self.inner.list_objects(prefix).await
+    }
+
+    async fn set_storage_class(&self, key: &str, storage_class: &str) -> Result<bool> {
+        self.chaos.storage_fault("storage class")?;
+        self.inner.set_storage_class(key, storage_class).await
+    }
+}
+
+pub async fn chaos(State(api_service): State<Arc<ApiService>>) -> Json<ChaosSettings> {
+    Json(api_service.chaos.settings())
+}
+
+pub async fn set_chaos(
+    State(api_service): State<Arc<ApiService>>,
+    Extension(actor): Extension<AdminActor>,
+    Json(settings): Json<ChaosSettings>,
+) -> Result<Json<ChaosSettings>, StatusCode> {
+    if let Err(e) = api_service.chaos.set(settings.clone()) {
+        warn!("Rejected chaos settings from {}: {}", actor.0, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    info!("Chaos settings set to {:?} by {}", settings, actor.0);
+
+    api_service
+        .audit_log
+        .record(
+            AuditEvent::new(actor.0, "chaos")
+                .details(serde_json::to_value(&settings).unwrap_or_default()),
+        )
+        .await;
+    Ok(Json(settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_storage_calls_at_the_configured_rate() {
+        let chaos = ChaosController::default();
+        assert!(chaos.storage_fault("read").is_ok());
+
+        chaos
+            .set(ChaosSettings {
+                storage_failure_rate: 1.0,
+                ..ChaosSettings::default()
+            })
+            .unwrap();
+        assert!(chaos.storage_fault("read").is_err());
+
+        for invalid in [
+            ChaosSettings {
+                error_rate: 1.5,
+                ..ChaosSettings::default()
+            },
+            ChaosSettings {
+                error_status: 200,
+                ..ChaosSettings::default()
+            },
+        ] {
+            assert!(chaos.set(invalid).is_err());
+        }
+        assert_eq!(chaos.settings().storage_failure_rate, 1.0);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::modules::env::env::EnvConfig;
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::debug;
+
+/// Request header with the time the client gives up, in milliseconds since the Unix epoch
+const REQUEST_DEADLINE: HeaderName = HeaderName::from_static("x-request-deadline");
+/// Request header with the seconds the client waits for the answer, e.g. `2.5`
+const REQUEST_TIMEOUT: HeaderName = HeaderName::from_static("request-timeout");
+/// Weight of the latest cold miss in the estimate, out of 8
+const ESTIMATE_WEIGHT: u64 = 2;
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// The deadline of the client is too close for a cold miss, so the work wasn't started
+#[derive(Debug, Error)]
+#[error("{remaining:?} left to resize {url}, a cold miss takes about {needed:?}")]
+pub struct DeadlineTooShort {
+    pub url: String,
+    pub remaining: Duration,
+    pub needed: Duration,
+}
+
+impl DeadlineTooShort {
+    /// Seconds a cold miss currently takes, which the client should allow when retrying
+    pub fn retry_after_secs(&self) -> i32 {
+        self.needed.as_secs_f64().ceil().max(1.0) as i32
+    }
+}
+
+/// Deadline of the request, the earlier of its `X-Request-Deadline` and `Request-Timeout`
+fn deadline_of(headers: &HeaderMap) -> Option<Instant> {
+    let now = Instant::now();
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let absolute = header(REQUEST_DEADLINE)
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(|deadline_ms| {
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default();
+            now + Duration::from_millis(deadline_ms.saturating_sub(now_ms))
+        });
+    let relative = header(REQUEST_TIMEOUT)
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .map(|timeout| now + timeout);
+
+    match (absolute, relative) {
+        (Some(absolute), Some(relative)) => Some(absolute.min(relative)),
+        (deadline, None) | (None, deadline) => deadline,
+    }
+}
+
+/// Deadline of the request being handled, if its client set one
+pub fn current() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// Run `work` with `deadline` as the deadline of the request, e.g. in a task it spawned
+pub async fn scope<F: Future>(deadline: Option<Instant>, work: F) -> F::Output {
+    match deadline {
+        Some(deadline) => DEADLINE.scope(deadline, work).await,
+        None => work.await,
+    }
+}
+
+/// Make the deadline sent by the client available to the resize, see [`current`]
+pub async fn request_deadlines(request: Request, next: Next) -> Response {
+    let deadline = deadline_of(request.headers());
+    if let Some(deadline) = deadline {
+        debug!(
+            "Request deadline in {:?}",
+            deadline.saturating_duration_since(Instant::now())
+        );
+    }
+    scope(deadline, next.run(request)).await
+}
+
+/// Moving estimate of the time a cold miss takes, from download to upload
+#[derive(Debug, Clone)]
+pub struct ColdMissEstimate {
+    /// Shortest time assumed for a cold miss, before any was measured and on fast origins
+    floor: Duration,
+    /// Moving average of the measured cold misses, in milliseconds, 0 until the first one
+    average_ms: Arc<AtomicU64>,
+}
+
+impl Default for ColdMissEstimate {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(250))
+    }
+}
+
+impl ColdMissEstimate {
+    pub fn new(floor: Duration) -> Self {
+        Self {
+            floor,
+            average_ms: Arc::default(),
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self::new(Duration::from_millis(config.deadline_min_cold_miss_ms))
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let elapsed_ms = (elapsed.as_millis() as u64).max(1);
+        let _ = self
+            .average_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                Some(match average {
+                    0 => elapsed_ms,
+                    average => (average * (8 - ESTIMATE_WEIGHT) + elapsed_ms * ESTIMATE_WEIGHT) / 8,
+                })
+            });
+    }
+
+    pub fn estimate(&self) -> Duration {
+        Duration::from_millis(self.average_ms.load(Ordering::Relaxed)).max(self.floor)
+    }
+
+    /// Fail with [`DeadlineTooShort`] when `deadline` leaves less than a cold miss takes
+    pub fn check(&self, url: &str, deadline: Option<Instant>) -> Result<(), DeadlineTooShort> {
+        let Some(deadline) = deadline else {
+            return Ok(());
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let needed = self.estimate();
+        if remaining < needed {
+            return Err(DeadlineTooShort {
+                url: url.to_string(),
+                remaining,
+                needed,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_reads_the_earliest_deadline() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(deadline_of(&headers), None);
+
+        headers.insert(REQUEST_TIMEOUT, HeaderValue::from_static("0.5"));
+        let relative = deadline_of(&headers).unwrap();
+        assert!(relative <= Instant::now() + Duration::from_millis(500));
+
+        // Already past
+        headers.insert(REQUEST_DEADLINE, HeaderValue::from_static("1000"));
+        assert!(deadline_of(&headers).unwrap() <= Instant::now());
+
+        headers.insert(REQUEST_TIMEOUT, HeaderValue::from_static("soon"));
+        headers.remove(REQUEST_DEADLINE);
+        assert_eq!(deadline_of(&headers), None);
+    }
+
+    #[test]
+    fn test_refuses_deadlines_shorter_than_a_cold_miss() {
+        let estimate = ColdMissEstimate::new(Duration::from_millis(100));
+        assert!(estimate.check("http://a.test/x.jpg", None).is_ok());
+
+        let in_a_second = Some(Instant::now() + Duration::from_secs(1));
+        assert!(estimate.check("http://a.test/x.jpg", in_a_second).is_ok());
+
+        estimate.record(Duration::from_secs(3));
+        let too_short = estimate
+            .check("http://a.test/x.jpg", in_a_second)
+            .unwrap_err();
+        assert_eq!(too_short.needed, Duration::from_secs(3));
+        assert_eq!(too_short.retry_after_secs(), 3);
+
+        estimate.record(Duration::from_secs(1));
+        assert_eq!(estimate.estimate(), Duration::from_millis(2500));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::modules::api::handler::ApiService;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Request header asking for the debug headers of a resize
+const DEBUG: HeaderName = HeaderName::from_static("x-debug");
+
+/// W3C trace context header, sampling the trace when its flags are `01`
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+/// Response headers only sent to debugging callers
+const DEBUG_HEADERS: [HeaderName; 4] = [
+    HeaderName::from_static("server-timing"),
+    HeaderName::from_static("x-debug-cache-key"),
+    HeaderName::from_static("x-debug-filter"),
+    HeaderName::from_static("x-debug-encoder"),
+];
+
+/// Durations of the stages of a resize, in the order they ran
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StageTimings(Vec<(&'static str, Duration)>);
+
+impl StageTimings {
+    pub fn record(&mut self, stage: &'static str, duration: Duration) {
+        self.0.push((stage, duration));
+    }
+
+    /// Add the stages of `later`, which ran after those already recorded
+    pub fn append(&mut self, later: StageTimings) {
+        self.0.extend(later.0);
+    }
+
+    /// Format as a `Server-Timing` header, in milliseconds
+    pub fn server_timing(&self) -> Option<String> {
+        let mut header = String::new();
+        for (stage, duration) in &self.0 {
+            if !header.is_empty() {
+                header.push_str(", ");
+            }
+            let _ = write!(
+                header,
+                "{};dur={:.1}",
+                stage,
+                duration.as_secs_f64() * 1000.0
+            );
+        }
+        (!header.is_empty()).then_some(header)
+    }
+}
+
+/// Whether the caller asked for debug headers, with the admin token allowing it
+fn is_debugging(api_service: &ApiService, headers: &HeaderMap) -> bool {
+    if headers.get(DEBUG).is_none_or(|value| value != "1") {
+        return false;
+    }
+    let actor = api_service
+        .admin
+        .as_ref()
+        .and_then(|admin| admin.authorize(headers));
+    match actor {
+        Some(actor) => {
+            info!("Debugging request for {}", actor.0);
+            true
+        }
+        None => {
+            warn!("Ignored X-Debug header of an unauthorized request");
+            false
+        }
+    }
+}
+
+/// Trace context of a new, sampled trace
+fn sampled_traceparent() -> HeaderValue {
+    let trace_id = Uuid::new_v4().simple().to_string();
+    let span_id = &Uuid::new_v4().simple().to_string()[..16];
+    HeaderValue::from_str(&format!("00-{}-{}-01", trace_id, span_id))
+        .expect("trace context is a valid header")
+}
+
+/// Answer admin callers sending `X-Debug: 1` with the debug headers of their resize
+///
+/// Their request is traced regardless of the sampling ratio, and the `traceparent`
+/// response header identifies the trace. Everyone else gets the debug headers removed.
+pub async fn debug_requests(
+    State(api_service): State<Arc<ApiService>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let debugging = is_debugging(&api_service, request.headers());
+    if debugging && !request.headers().contains_key(TRACEPARENT) {
+        request
+            .headers_mut()
+            .insert(TRACEPARENT, sampled_traceparent());
+    }
+
+    let mut response = next.run(request).await;
+    if !debugging {
+        for header in DEBUG_HEADERS {
+            response.headers_mut().remove(header);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_server_timing() {
+        let mut timings = StageTimings::default();
+        assert_eq!(timings.server_timing(), None);
+
+        let mut processing = StageTimings::default();
+        processing.record("decode", Duration::from_micros(2_500));
+        processing.record("encode", Duration::from_millis(12));
+        timings.record("download", Duration::from_millis(40));
+        timings.append(processing);
+        assert_eq!(
+            timings.server_timing().unwrap(),
+            "download;dur=40.0, decode;dur=2.5, encode;dur=12.0"
+        );
+        assert!(sampled_traceparent().to_str().unwrap().ends_with("-01"));
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use bytes::Bytes;
+
+/// OpenAPI specification the REST API is generated from
+const OPENAPI_SPEC: &str = include_str!("../../../openapi.yaml");
+
+/// Swagger UI page rendering `/openapi.json`
+#[cfg(feature = "swagger_ui")]
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>EmgR API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+/// The OpenAPI specification, converted to JSON
+pub fn openapi_json() -> Result<Bytes> {
+    let spec: serde_json::Value =
+        serde_yaml::from_str(OPENAPI_SPEC).context("Invalid OpenAPI specification")?;
+    Ok(serde_json::to_vec(&spec)?.into())
+}
+
+pub async fn openapi(State(spec): State<Bytes>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, "application/json")], spec)
+}
+
+#[cfg(feature = "swagger_ui")]
+pub async fn swagger_ui() -> axum::response::Html<&'static str> {
+    axum::response::Html(SWAGGER_UI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_json_documents_the_api() {
+        let spec: serde_json::Value = serde_json::from_slice(&openapi_json().unwrap()).unwrap();
+        assert!(spec["paths"]["/api/images/resize"].is_object());
+    }
+}
@@ -0,0 +1,27 @@
+use crate::models::params::ResizeQuery;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Published after an image was processed and stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResizeEvent {
+    /// Unix time of the event, in milliseconds
+    pub timestamp_ms: u64,
+    /// Storage key of the resized image
+    pub key: String,
+    /// URL of the source image
+    pub origin_url: String,
+    pub params: ResizeQuery,
+    pub tenant: Option<String>,
+    /// Size of the resized image, in bytes
+    pub output_size: usize,
+    /// Time spent downloading, processing and storing the image
+    pub duration_ms: u64,
+}
+
+/// Destination of the resize events, e.g. a message broker
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn publish(&self, event: &ResizeEvent) -> Result<()>;
+}
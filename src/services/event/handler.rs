@@ -0,0 +1,76 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::event::core::{EventSink, ResizeEvent};
+use crate::services::resize::handler::ResizeResult;
+use crate::services::resize::observer::{Observed, PipelineObserver};
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Optional publisher of resize events for downstream consumers
+///
+/// Events are published in the background: a slow or unreachable broker never
+/// delays or fails a resize.
+#[derive(Clone, Default)]
+pub struct EventPublisher {
+    sink: Option<Arc<dyn EventSink>>,
+}
+
+impl EventPublisher {
+    pub fn new(sink: impl EventSink + 'static) -> Self {
+        Self {
+            sink: Some(Arc::new(sink)),
+        }
+    }
+
+    /// Create the publisher configured by the environment
+    #[allow(unused_variables)]
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        #[cfg(feature = "nats_events")]
+        if let Some(url) = &config.nats_url {
+            let sink = crate::services::event::nats_handler::NatsSink::new(
+                url.clone(),
+                config.event_subject.clone(),
+            );
+            return Ok(Self::new(sink));
+        }
+
+        Ok(Self::default())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    pub fn publish(&self, event: ResizeEvent) {
+        let Some(sink) = self.sink.clone() else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            if let Err(e) = sink.publish(&event).await {
+                error!("Failed to publish resize event for {}: {}", event.key, e);
+            }
+        });
+    }
+}
+
+impl PipelineObserver for EventPublisher {
+    fn on_upload(&self, resize: &Observed, result: &ResizeResult, elapsed: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.publish(ResizeEvent {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            key: result.key.clone(),
+            origin_url: resize.params.url.clone(),
+            params: resize.params.clone(),
+            tenant: resize.tenant.map(str::to_string),
+            output_size: result.size.unwrap_or_default() as usize,
+            duration_ms: elapsed.as_millis() as u64,
+        });
+    }
+}
@@ -0,0 +1,5 @@
+pub mod core;
+pub mod handler;
+
+#[cfg(feature = "nats_events")]
+pub mod nats_handler;
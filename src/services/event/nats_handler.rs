@@ -0,0 +1,40 @@
+use crate::services::event::core::{EventSink, ResizeEvent};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::sync::OnceCell;
+
+/// Publishes resize events as JSON messages on a NATS subject
+pub struct NatsSink {
+    url: String,
+    subject: String,
+    /// Connected on first use, as the service is created outside of the runtime
+    client: OnceCell<async_nats::Client>,
+}
+
+impl NatsSink {
+    pub fn new(url: String, subject: String) -> Self {
+        Self {
+            url,
+            subject,
+            client: OnceCell::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsSink {
+    async fn publish(&self, event: &ResizeEvent) -> Result<()> {
+        let client = self
+            .client
+            .get_or_try_init(|| async_nats::connect(self.url.as_str()))
+            .await
+            .context("Failed to connect to NATS")?;
+
+        let payload = serde_json::to_vec(event).context("Failed to serialize resize event")?;
+        client
+            .publish(self.subject.clone(), payload.into())
+            .await
+            .context("Failed to publish resize event")?;
+        Ok(())
+    }
+}
@@ -1,3 +1,72 @@
+use crate::modules::api::handler::ApiService;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::error;
+
+/// Status reported when a check passed
+pub const STATUS_OK: &str = "ok";
+/// Status reported when a check failed
+pub const STATUS_ERROR: &str = "error";
+/// Status reported when a check was not requested
+pub const STATUS_SKIPPED: &str = "skipped";
+
 pub async fn health() -> &'static str {
     "OK"
 }
+
+/// Query parameters of the readiness endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct ReadyParams {
+    /// Also exercise the storage backend
+    #[serde(default)]
+    pub deep: bool,
+}
+
+/// Body returned by the readiness endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadinessReport {
+    pub status: String,
+    pub storage: String,
+    /// Only images already in storage are served, others are refused until it is turned off
+    #[serde(default)]
+    pub maintenance: bool,
+}
+
+impl ReadinessReport {
+    pub fn is_ok(&self) -> bool {
+        self.status == STATUS_OK
+    }
+}
+
+/// Readiness probe, answered from a request handler so a stuck runtime fails it
+pub async fn ready(
+    State(api_service): State<Arc<ApiService>>,
+    Query(params): Query<ReadyParams>,
+) -> (StatusCode, Json<ReadinessReport>) {
+    let storage = if !params.deep {
+        STATUS_SKIPPED
+    } else if let Err(e) = api_service.resize_service.check_storage().await {
+        error!("Storage readiness check failed: {:?}", e);
+        STATUS_ERROR
+    } else {
+        STATUS_OK
+    };
+
+    let (code, status) = if storage == STATUS_ERROR {
+        (StatusCode::SERVICE_UNAVAILABLE, STATUS_ERROR)
+    } else {
+        (StatusCode::OK, STATUS_OK)
+    };
+
+    (
+        code,
+        Json(ReadinessReport {
+            status: status.to_string(),
+            storage: storage.to_string(),
+            maintenance: api_service.resize_service.is_maintenance(),
+        }),
+    )
+}
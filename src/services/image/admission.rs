@@ -0,0 +1,367 @@
+use crate::config::performance::PerformanceConfig;
+use crate::modules::env::env::EnvConfig;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+/// Upper bounds of the wait time buckets, in milliseconds
+const WAIT_BUCKETS_MS: [u64; 5] = [1, 10, 100, 1_000, 10_000];
+
+/// No download slot freed up before the admission deadline, the request should be retried later
+#[derive(Debug, Error)]
+#[error("Timed out after {waited:?} waiting for a download slot, {queued} requests queued")]
+pub struct AdmissionTimeout {
+    pub waited: Duration,
+    pub queued: usize,
+}
+
+/// Count of admissions that waited up to `le_ms`, or longer than the last bound when unset
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WaitBucket {
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+/// Snapshot of the download admission queue
+#[derive(Debug, Clone, Serialize)]
+pub struct AdmissionStats {
+    pub queued: usize,
+    /// Tenants with queued downloads
+    pub tenants_queued: usize,
+    pub admitted: u64,
+    pub timed_out: u64,
+    pub wait_ms: Vec<WaitBucket>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    admitted: AtomicU64,
+    timed_out: AtomicU64,
+    wait_buckets: [AtomicU64; WAIT_BUCKETS_MS.len() + 1],
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    slot: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    available: usize,
+    next_id: u64,
+    queued: usize,
+    /// Tenants with waiters, in the order they are served
+    rotation: VecDeque<String>,
+    waiters: HashMap<String, VecDeque<Waiter>>,
+}
+
+impl Queue {
+    /// Next waiter, taking turns between tenants
+    fn pop(&mut self) -> Option<Waiter> {
+        let tenant = self.rotation.pop_front()?;
+        let waiters = self.waiters.get_mut(&tenant)?;
+        let waiter = waiters.pop_front();
+        if waiters.is_empty() {
+            self.waiters.remove(&tenant);
+        } else {
+            self.rotation.push_back(tenant);
+        }
+        self.queued -= 1;
+        waiter
+    }
+
+    /// Remove the waiter `id` of `tenant`, returning whether it was still queued
+    fn remove(&mut self, tenant: &str, id: u64) -> bool {
+        let Some(waiters) = self.waiters.get_mut(tenant) else {
+            return false;
+        };
+        let Some(position) = waiters.iter().position(|waiter| waiter.id == id) else {
+            return false;
+        };
+        waiters.remove(position);
+        if waiters.is_empty() {
+            self.waiters.remove(tenant);
+            self.rotation.retain(|queued| queued != tenant);
+        }
+        self.queued -= 1;
+        true
+    }
+
+    /// Hand a freed slot to the next waiter still listening, or make it available
+    fn release(&mut self) {
+        while let Some(waiter) = self.pop() {
+            if waiter.slot.send(()).is_ok() {
+                return;
+            }
+        }
+        self.available += 1;
+    }
+}
+
+/// Admission of origin downloads, bounding how many run at once
+///
+/// Downloads beyond the capacity wait in one queue per tenant, served in turns, so a burst
+/// from one tenant only delays its own requests. Waiters give up with [`AdmissionTimeout`]
+/// after the deadline, shedding load instead of piling up requests behind a slow origin.
+#[derive(Debug, Clone)]
+pub struct DownloadAdmission {
+    capacity: usize,
+    /// Longest wait for a slot, unlimited when unset
+    timeout: Option<Duration>,
+    queue: Arc<Mutex<Queue>>,
+    counters: Arc<Counters>,
+    #[cfg(feature = "otel")]
+    wait_time: opentelemetry::metrics::Histogram<f64>,
+}
+
+impl DownloadAdmission {
+    pub fn new(capacity: usize, timeout: Option<Duration>) -> Self {
+        let queue = Arc::new(Mutex::new(Queue {
+            available: capacity,
+            ..Queue::default()
+        }));
+
+        #[cfg(feature = "otel")]
+        {
+            let queue = Arc::clone(&queue);
+            opentelemetry::global::meter("emgr")
+                .u64_observable_gauge("emgr.download.queue.length")
+                .with_description("Downloads waiting for a slot")
+                .with_callback(move |observer| {
+                    let queued = queue.lock().map_or(0, |queue| queue.queued);
+                    observer.observe(queued as u64, &[])
+                })
+                .build();
+        }
+
+        Self {
+            capacity,
+            timeout,
+            queue,
+            counters: Arc::default(),
+            #[cfg(feature = "otel")]
+            wait_time: opentelemetry::global::meter("emgr")
+                .f64_histogram("emgr.download.queue.wait")
+                .with_unit("s")
+                .with_description("Wait for a download slot, by outcome")
+                .build(),
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self::new(
+            PerformanceConfig::from(config).max_concurrent_downloads,
+            (config.download_queue_timeout_ms > 0)
+                .then(|| Duration::from_millis(config.download_queue_timeout_ms)),
+        )
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Downloads currently admitted
+    pub fn in_flight(&self) -> usize {
+        self.capacity.saturating_sub(self.lock().available)
+    }
+
+    /// Wait for a download slot in the queue of `tenant`
+    pub async fn acquire(&self, tenant: Option<&str>) -> Result<AdmissionPermit, AdmissionTimeout> {
+        let started = Instant::now();
+        let tenant = tenant.unwrap_or_default();
+        let (id, slot) = {
+            let mut queue = self.lock();
+            if queue.available > 0 && queue.queued == 0 {
+                queue.available -= 1;
+                drop(queue);
+                return Ok(self.admitted(started));
+            }
+
+            let id = queue.next_id;
+            queue.next_id += 1;
+            let (sender, slot) = oneshot::channel();
+            if !queue.waiters.contains_key(tenant) {
+                queue.rotation.push_back(tenant.to_string());
+            }
+            queue
+                .waiters
+                .entry(tenant.to_string())
+                .or_default()
+                .push_back(Waiter { id, slot: sender });
+            queue.queued += 1;
+            (id, slot)
+        };
+
+        let mut waiting = Waiting {
+            admission: self,
+            tenant,
+            id,
+            slot,
+            settled: false,
+        };
+        let granted = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, &mut waiting.slot)
+                .await
+                .is_ok(),
+            None => (&mut waiting.slot).await.is_ok(),
+        };
+        waiting.settled = true;
+        if granted {
+            return Ok(self.admitted(started));
+        }
+
+        // A slot handed over right as the deadline passed is still taken
+        let mut queue = self.lock();
+        if !queue.remove(tenant, id) && waiting.slot.try_recv().is_ok() {
+            drop(queue);
+            return Ok(self.admitted(started));
+        }
+        let queued = queue.queued;
+        drop(queue);
+
+        let waited = started.elapsed();
+        self.counters.timed_out.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "otel")]
+        self.wait_time.record(
+            waited.as_secs_f64(),
+            &[opentelemetry::KeyValue::new("outcome", "timed_out")],
+        );
+        Err(AdmissionTimeout { waited, queued })
+    }
+
+    pub fn stats(&self) -> AdmissionStats {
+        let (queued, tenants_queued) = {
+            let queue = self.lock();
+            (queue.queued, queue.waiters.len())
+        };
+        let counters = &self.counters;
+        AdmissionStats {
+            queued,
+            tenants_queued,
+            admitted: counters.admitted.load(Ordering::Relaxed),
+            timed_out: counters.timed_out.load(Ordering::Relaxed),
+            wait_ms: counters
+                .wait_buckets
+                .iter()
+                .enumerate()
+                .map(|(index, count)| WaitBucket {
+                    le_ms: WAIT_BUCKETS_MS.get(index).copied(),
+                    count: count.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+
+    fn admitted(&self, started: Instant) -> AdmissionPermit {
+        let waited = started.elapsed();
+        let bucket = WAIT_BUCKETS_MS
+            .iter()
+            .position(|le_ms| waited <= Duration::from_millis(*le_ms))
+            .unwrap_or(WAIT_BUCKETS_MS.len());
+        self.counters.wait_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.counters.admitted.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "otel")]
+        self.wait_time.record(
+            waited.as_secs_f64(),
+            &[opentelemetry::KeyValue::new("outcome", "admitted")],
+        );
+
+        AdmissionPermit {
+            queue: Arc::clone(&self.queue),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().expect("download queue poisoned")
+    }
+}
+
+/// A queued request, leaving the queue if dropped before being admitted
+struct Waiting<'a> {
+    admission: &'a DownloadAdmission,
+    tenant: &'a str,
+    id: u64,
+    slot: oneshot::Receiver<()>,
+    settled: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        // The request was cancelled, give back a slot it may have been handed
+        let mut queue = self.admission.lock();
+        if !queue.remove(self.tenant, self.id) && self.slot.try_recv().is_ok() {
+            queue.release();
+        }
+    }
+}
+
+/// A download slot, freed when dropped
+#[derive(Debug)]
+pub struct AdmissionPermit {
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.queue
+            .lock()
+            .expect("download queue poisoned")
+            .release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_takes_turns_between_tenants() {
+        let admission = DownloadAdmission::new(1, None);
+        let held = admission.acquire(Some("a")).await.unwrap();
+
+        let (order, mut served) = tokio::sync::mpsc::unbounded_channel();
+        for tenant in ["a", "a", "a", "b"] {
+            let admission = admission.clone();
+            let order = order.clone();
+            tokio::spawn(async move {
+                let _permit = admission.acquire(Some(tenant)).await.unwrap();
+                order.send(tenant).unwrap();
+            });
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(admission.stats().queued, 4);
+        assert_eq!(admission.stats().tenants_queued, 2);
+
+        drop(held);
+        let mut tenants = Vec::new();
+        for _ in 0..4 {
+            tenants.push(served.recv().await.unwrap());
+        }
+        // The burst of tenant a doesn't hold back tenant b
+        assert_eq!(tenants, ["a", "b", "a", "a"]);
+        assert_eq!(admission.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_times_out_waiters_and_frees_their_place() {
+        let admission = DownloadAdmission::new(1, Some(Duration::from_millis(20)));
+        let held = admission.acquire(None).await.unwrap();
+
+        let timed_out = admission.acquire(None).await.unwrap_err();
+        assert_eq!(timed_out.queued, 0);
+        assert_eq!(admission.stats().timed_out, 1);
+
+        drop(held);
+        let _permit = admission.acquire(None).await.unwrap();
+        assert_eq!(admission.stats().admitted, 2);
+        assert_eq!(admission.in_flight(), 1);
+    }
+}
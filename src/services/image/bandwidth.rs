@@ -0,0 +1,130 @@
+use crate::modules::env::env::EnvConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BYTES_PER_MB: f64 = 1_000_000.0;
+/// Idle hosts are forgotten once this many are tracked
+const MAX_TRACKED_HOSTS: usize = 1024;
+
+/// Token bucket holding up to one second of bandwidth
+///
+/// Reservations larger than the available tokens put the bucket in debt, so chunks of
+/// any size are paced at the configured rate.
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_sec: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec,
+            state: Mutex::new((bytes_per_sec, Instant::now())),
+        }
+    }
+
+    /// Take `bytes` from the bucket, returning how long to wait before using them
+    fn reserve(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().expect("bandwidth bucket poisoned");
+        let (tokens, refilled_at) = &mut *state;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*refilled_at).as_secs_f64() * self.bytes_per_sec)
+            .min(self.bytes_per_sec);
+        *refilled_at = now;
+        *tokens -= bytes as f64;
+
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / self.bytes_per_sec)
+        }
+    }
+
+    /// Whether the bucket has fully refilled since its last use
+    fn is_idle(&self) -> bool {
+        let (tokens, refilled_at) = *self.state.lock().expect("bandwidth bucket poisoned");
+        tokens + refilled_at.elapsed().as_secs_f64() * self.bytes_per_sec >= self.bytes_per_sec
+    }
+}
+
+/// Caps the throughput of origin downloads, in total and per host
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthLimiter {
+    global: Option<Arc<Bucket>>,
+    per_host_bytes_per_sec: Option<f64>,
+    hosts: Arc<Mutex<HashMap<String, Arc<Bucket>>>>,
+}
+
+impl BandwidthLimiter {
+    /// Limits in MB/s, unlimited when unset or not positive
+    pub fn new(global_mb_per_sec: Option<f64>, per_host_mb_per_sec: Option<f64>) -> Self {
+        let bytes_per_sec = |limit: Option<f64>| {
+            limit
+                .filter(|limit| *limit > 0.0)
+                .map(|limit| limit * BYTES_PER_MB)
+        };
+        Self {
+            global: bytes_per_sec(global_mb_per_sec).map(|rate| Arc::new(Bucket::new(rate))),
+            per_host_bytes_per_sec: bytes_per_sec(per_host_mb_per_sec),
+            hosts: Arc::default(),
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self::new(
+            Some(config.download_max_mb_per_sec),
+            Some(config.download_max_mb_per_sec_per_host),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || self.per_host_bytes_per_sec.is_some()
+    }
+
+    /// Wait until `bytes` downloaded from `host` fit within the limits
+    pub async fn throttle(&self, host: &str, bytes: usize) {
+        let mut wait = self
+            .global
+            .as_ref()
+            .map_or(Duration::ZERO, |bucket| bucket.reserve(bytes));
+        if let Some(bucket) = self.host_bucket(host) {
+            wait = wait.max(bucket.reserve(bytes));
+        }
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn host_bucket(&self, host: &str) -> Option<Arc<Bucket>> {
+        let bytes_per_sec = self.per_host_bytes_per_sec?;
+        let mut hosts = self.hosts.lock().expect("bandwidth hosts poisoned");
+        if !hosts.contains_key(host) && hosts.len() >= MAX_TRACKED_HOSTS {
+            hosts.retain(|_, bucket| !bucket.is_idle());
+        }
+        Some(Arc::clone(
+            hosts
+                .entry(host.to_string())
+                .or_insert_with(|| Arc::new(Bucket::new(bytes_per_sec))),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_reservations_at_the_rate() {
+        let bucket = Bucket::new(1000.0);
+        assert_eq!(bucket.reserve(1000), Duration::ZERO);
+
+        let wait = bucket.reserve(500);
+        assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500));
+        assert!(!bucket.is_idle());
+
+        assert!(!BandwidthLimiter::new(None, Some(0.0)).is_enabled());
+        assert!(BandwidthLimiter::new(None, Some(0.5)).is_enabled());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::image::encode::{DEFAULT_JPEG_QUALITY, Encoding, encode};
+use anyhow::{Result, bail};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::borrow::Cow;
+use std::io::Cursor;
+
+/// Dimensions are scaled by this factor on every step that doesn't fit
+const DOWNSCALE_FACTOR: f32 = 0.75;
+/// Images are not scaled below this many pixels on their shortest side
+const MIN_DOWNSCALED_SIDE: u32 = 16;
+
+/// How far the output may be degraded to fit under `max_bytes`
+#[derive(Debug, Clone, Copy)]
+pub struct ByteBudget {
+    /// Lowest JPEG quality tried before scaling the image down
+    pub min_quality: u8,
+    /// Whether the image may be scaled down when the lowest quality doesn't fit
+    pub downscale: bool,
+}
+
+impl Default for ByteBudget {
+    fn default() -> Self {
+        Self {
+            min_quality: 20,
+            downscale: true,
+        }
+    }
+}
+
+/// Output fitting under the byte budget
+pub struct Fitted {
+    pub data: Vec<u8>,
+    /// JPEG quality of the output
+    pub quality: Option<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ByteBudget {
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self {
+            min_quality: config.max_bytes_min_quality.clamp(1, 100),
+            downscale: config.max_bytes_downscale,
+        }
+    }
+
+    /// Encode `img` in at most `max_bytes`
+    ///
+    /// JPEG output keeps the highest quality up to `encoding.quality` that fits. When even
+    /// the lowest quality (or, for lossless formats, the image itself) is too large, the
+    /// image is scaled down step by step.
+    pub fn fit(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        encoding: Encoding,
+        max_bytes: usize,
+    ) -> Result<Fitted> {
+        let mut current = Cow::Borrowed(img);
+        loop {
+            if let Some((data, quality)) =
+                self.fit_quality(&current, format, encoding, max_bytes)?
+            {
+                let (width, height) = current.dimensions();
+                return Ok(Fitted {
+                    data,
+                    quality,
+                    width,
+                    height,
+                });
+            }
+
+            let (width, height) = current.dimensions();
+            if !self.downscale || width.min(height) <= MIN_DOWNSCALED_SIDE {
+                bail!("Output does not fit in {} bytes", max_bytes);
+            }
+            current = Cow::Owned(current.resize(
+                ((width as f32 * DOWNSCALE_FACTOR) as u32).max(1),
+                ((height as f32 * DOWNSCALE_FACTOR) as u32).max(1),
+                FilterType::Triangle,
+            ));
+        }
+    }
+
+    /// Highest quality encoding of `img` that fits, if any
+    fn fit_quality(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        encoding: Encoding,
+        max_bytes: usize,
+    ) -> Result<Option<(Vec<u8>, Option<u8>)>> {
+        if format != ImageFormat::Jpeg {
+            let data = encode_with(img, format, encoding)?;
+            return Ok((data.len() <= max_bytes).then_some((data, None)));
+        }
+
+        let (mut low, mut high) = (
+            self.min_quality,
+            encoding.quality.unwrap_or(DEFAULT_JPEG_QUALITY).min(100),
+        );
+        let mut best = None;
+        while low <= high {
+            let quality = low + (high - low) / 2;
+            let encoding = Encoding {
+                quality: Some(quality),
+                ..encoding
+            };
+            let data = encode_with(img, format, encoding)?;
+            if data.len() <= max_bytes {
+                best = Some((data, Some(quality)));
+                low = quality + 1;
+            } else if quality == low {
+                break;
+            } else {
+                high = quality - 1;
+            }
+        }
+        Ok(best)
+    }
+}
+
+fn encode_with(img: &DynamicImage, format: ImageFormat, encoding: Encoding) -> Result<Vec<u8>> {
+    let mut output = Cursor::new(Vec::new());
+    encode(img, format, encoding, &mut output)?;
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn fits_by_quality_then_dimensions() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(256, 256, |x, y| {
+            Rgb([(x ^ y) as u8, (x * 7 + y * 3) as u8, (x * y) as u8])
+        }));
+        let encoding = Encoding {
+            quality: Some(90),
+            ..Encoding::default()
+        };
+        let full = encode_with(&img, ImageFormat::Jpeg, encoding)
+            .unwrap()
+            .len();
+
+        let fitted = ByteBudget::default()
+            .fit(&img, ImageFormat::Jpeg, encoding, full / 2)
+            .unwrap();
+        assert!(fitted.data.len() <= full / 2);
+        assert!(fitted.quality.unwrap() < 90);
+        assert_eq!((fitted.width, fitted.height), (256, 256));
+
+        let fitted = ByteBudget::default()
+            .fit(&img, ImageFormat::Png, encoding, 4096)
+            .unwrap();
+        assert!(fitted.data.len() <= 4096);
+        assert!(fitted.width < 256);
+
+        let no_downscale = ByteBudget {
+            downscale: false,
+            ..ByteBudget::default()
+        };
+        assert!(
+            no_downscale
+                .fit(&img, ImageFormat::Png, encoding, 4096)
+                .is_err()
+        );
+    }
+}
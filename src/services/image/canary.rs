@@ -0,0 +1,145 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::cache::handler::CONTENT_PREFIX;
+use crate::services::image::encode::{EncodingDefaults, JpegLibrary};
+use anyhow::{Result, bail};
+use image::imageops::FilterType;
+use sha2::{Digest, Sha256};
+
+/// Resolution of the share of cache keys routed to the canary
+const BUCKETS: u64 = 10_000;
+
+/// Alternative processing pipeline handling a share of the images, to be compared with the
+/// stable one in production before switching over
+///
+/// Images are routed by cache key, so a variant is always produced by the same pipeline.
+/// Canary outputs are stored under keys labelled with the pipeline name, next to the
+/// stable ones, and their metrics carry a `pipeline` label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanaryPipeline {
+    name: String,
+    /// Share of the cache keys handled, from 0 to 1
+    share: f64,
+    resize_filter: Option<FilterType>,
+    jpeg_library: Option<JpegLibrary>,
+}
+
+impl CanaryPipeline {
+    /// Pipeline `name` handling `share` of the images, configured by `spec`
+    ///
+    /// `spec` is a comma separated list of `resize_filter=<filter>` and `jpeg=<encoder>`.
+    pub fn new(name: &str, share: f64, spec: &str) -> Result<Self> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            || CONTENT_PREFIX.trim_end_matches('-') == name
+        {
+            bail!(
+                "Invalid canary pipeline name {:?}, expected lowercase letters, digits and _",
+                name
+            );
+        }
+
+        let mut pipeline = Self {
+            name: name.to_string(),
+            share: share.clamp(0.0, 1.0),
+            resize_filter: None,
+            jpeg_library: None,
+        };
+        for option in spec.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            match option.split_once('=') {
+                Some(("resize_filter", filter)) => {
+                    pipeline.resize_filter = Some(parse_filter(filter)?)
+                }
+                Some(("jpeg", library)) => pipeline.jpeg_library = Some(library.parse()?),
+                _ => bail!(
+                    "Invalid canary pipeline option {:?}, expected resize_filter=<filter> or jpeg=<encoder>",
+                    option
+                ),
+            }
+        }
+        Ok(pipeline)
+    }
+
+    /// The canary pipeline configured by the environment, if any
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let Some(spec) = &config.canary_pipeline else {
+            return Ok(None);
+        };
+        if config.canary_percent <= 0.0 {
+            return Ok(None);
+        }
+        Self::new(&config.canary_name, config.canary_percent / 100.0, spec).map(Some)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the image stored under the stable `cache_key` is produced by this pipeline
+    pub fn handles(&self, cache_key: &str) -> bool {
+        let digest = Sha256::digest(cache_key.as_bytes());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        let bucket = u64::from_le_bytes(bytes) % BUCKETS;
+        (bucket as f64) < self.share * BUCKETS as f64
+    }
+
+    /// The stable `defaults` with the settings of this pipeline
+    pub fn apply(&self, defaults: EncodingDefaults) -> EncodingDefaults {
+        EncodingDefaults {
+            resize_filter: self.resize_filter.or(defaults.resize_filter),
+            jpeg_library: self.jpeg_library.unwrap_or(defaults.jpeg_library),
+            ..defaults
+        }
+    }
+}
+
+fn parse_filter(value: &str) -> Result<FilterType> {
+    Ok(match value.to_lowercase().as_str() {
+        "nearest" => FilterType::Nearest,
+        "triangle" => FilterType::Triangle,
+        "catmull_rom" => FilterType::CatmullRom,
+        "gaussian" => FilterType::Gaussian,
+        "lanczos3" => FilterType::Lanczos3,
+        _ => bail!(
+            "Invalid resize filter {:?}, expected nearest, triangle, catmull_rom, gaussian or lanczos3",
+            value
+        ),
+    })
+}
+
+/// Name of `filter`, as accepted in `resize_filter=<filter>`
+pub fn filter_name(filter: FilterType) -> &'static str {
+    match filter {
+        FilterType::Nearest => "nearest",
+        FilterType::Triangle => "triangle",
+        FilterType::CatmullRom => "catmull_rom",
+        FilterType::Gaussian => "gaussian",
+        FilterType::Lanczos3 => "lanczos3",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_a_stable_share_of_keys() {
+        let canary = CanaryPipeline::new("fast", 0.2, "resize_filter=catmull_rom").unwrap();
+        let keys: Vec<String> = (0..2000).map(|i| format!("{:x}.jpg", i)).collect();
+        let handled = keys.iter().filter(|key| canary.handles(key)).count();
+        assert!((300..500).contains(&handled), "{}", handled);
+
+        let defaults = canary.apply(EncodingDefaults::default());
+        assert_eq!(defaults.resize_filter, Some(FilterType::CatmullRom));
+        assert_eq!(
+            parse_filter(filter_name(FilterType::CatmullRom)).unwrap(),
+            FilterType::CatmullRom
+        );
+        assert_eq!(defaults.jpeg_library, JpegLibrary::Image);
+
+        assert!(CanaryPipeline::new("content", 0.2, "").is_err());
+        assert!(CanaryPipeline::new("fast", 0.2, "resize_filter=bicubic").is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+/// The caller gave up on the request, e.g. because its client disconnected
+#[derive(Debug, Error)]
+#[error("Request cancelled")]
+pub struct Cancelled;
+
+/// Fail with [`Cancelled`] once `cancel` is triggered, between two stages of work
+pub fn check(cancel: &CancellationToken) -> Result<(), Cancelled> {
+    if cancel.is_cancelled() {
+        return Err(Cancelled);
+    }
+    Ok(())
+}
+
+/// Run `work` until it completes or `cancel` is triggered, dropping it in the latter case
+pub async fn or_cancelled<T>(
+    cancel: &CancellationToken,
+    work: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    tokio::select! {
+        result = work => result,
+        _ = cancel.cancelled() => Err(Cancelled.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stops_pending_work_once_cancelled() {
+        let cancel = CancellationToken::new();
+        assert!(check(&cancel).is_ok());
+        assert_eq!(or_cancelled(&cancel, async { Ok(1) }).await.unwrap(), 1);
+
+        cancel.cancel();
+        assert!(check(&cancel).is_err());
+        let result = or_cancelled(&cancel, std::future::pending::<anyhow::Result<()>>()).await;
+        assert!(result.unwrap_err().is::<Cancelled>());
+    }
+}
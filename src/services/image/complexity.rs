@@ -0,0 +1,151 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::env::env::EnvConfig;
+use image::ImageReader;
+use std::io::Cursor;
+use thiserror::Error;
+
+/// The estimated cost of a resize exceeds the complexity budget
+#[derive(Debug, Error)]
+#[error("Estimated cost of {cost:.1} exceeds the complexity budget of {budget:.1}")]
+pub struct ComplexityExceeded {
+    /// Source megapixels times the relative cost of the requested operations
+    pub cost: f64,
+    pub budget: f64,
+}
+
+/// Pre-flight rejection of resizes too costly to run on the shared pool
+///
+/// The cost of a resize is the megapixels of its source, read from its header, times the
+/// relative cost of the requested operations: decoding, resizing and encoding count `1`,
+/// every filter adds to it. Requests through allowlisted tenants are never rejected.
+#[derive(Debug, Clone, Default)]
+pub struct ComplexityBudget {
+    /// Highest accepted cost, 0 for unlimited
+    pub budget: f64,
+    /// Tenant hosts exempt from the budget
+    pub allowlist: Vec<String>,
+}
+
+impl ComplexityBudget {
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self {
+            budget: config.complexity_budget,
+            allowlist: config
+                .complexity_allowlist
+                .iter()
+                .flat_map(|hosts| hosts.split(','))
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Reject `params` on `source` when its estimated cost exceeds the budget
+    ///
+    /// Sources without readable dimensions pass, as they fail to decode anyway.
+    pub fn check(
+        &self,
+        source: &[u8],
+        params: &ResizeQuery,
+        tenant: Option<&str>,
+    ) -> Result<(), ComplexityExceeded> {
+        if self.budget <= 0.0 || tenant.is_some_and(|tenant| self.is_allowlisted(tenant)) {
+            return Ok(());
+        }
+        let Some(cost) = estimate(source, params) else {
+            return Ok(());
+        };
+
+        if cost > self.budget {
+            return Err(ComplexityExceeded {
+                cost,
+                budget: self.budget,
+            });
+        }
+        Ok(())
+    }
+
+    fn is_allowlisted(&self, tenant: &str) -> bool {
+        // Tenants are hosts, compared without their port
+        let host = tenant.split(':').next().unwrap_or_default().to_lowercase();
+        self.allowlist.contains(&host)
+    }
+}
+
+/// Estimated cost of `params` on `source`, `None` without readable dimensions
+pub fn estimate(source: &[u8], params: &ResizeQuery) -> Option<f64> {
+    let (width, height) = ImageReader::new(Cursor::new(source))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    Some(width as f64 * height as f64 / 1_000_000.0 * operations(params))
+}
+
+/// Relative cost of the requested operations, `1` for a plain resize
+fn operations(params: &ResizeQuery) -> f64 {
+    let filter = |set: bool, cost: f64| if set { cost } else { 0.0 };
+    1.0 + filter(params.blur_sigma.is_some_and(|sigma| sigma > 0.0), 1.0)
+        // Non-local means compares every pixel with its neighborhood
+        + filter(params.denoise.is_some_and(|strength| strength > 0.0), 4.0)
+        + filter(params.grayscale == Some(true), 0.25)
+        + filter(params.zoom.is_some(), 0.5)
+        + filter(params.vignette.is_some(), 0.5)
+        + filter(params.tint.is_some(), 0.5)
+        + filter(params.pixelate.is_some(), 0.5)
+        + filter(params.gamma.is_some() || params.exposure.is_some(), 1.0)
+        + filter(params.auto_level.is_some(), 0.5)
+        + filter(params.auto_sharpen == Some(true), 1.0)
+        // Seam carving finds and removes one seam at a time
+        + filter(params.fit.as_deref() == Some("liquid"), 8.0)
+        + filter(params.pad.is_some(), 0.5)
+        // Training the palette samples the image several times
+        + filter(params.colors.is_some(), 1.0)
+        // Fitting the quality or size encodes the image several times
+        + filter(params.auto_quality() || params.max_bytes.is_some(), 3.0)
+        + params.plugins().count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+
+    #[test]
+    fn rejects_costly_resizes_unless_allowlisted() {
+        // 2 megapixels
+        let mut source = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(2000, 1000))
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+        let plain = ResizeQuery {
+            width: Some(100),
+            ..ResizeQuery::default()
+        };
+        let denoised = ResizeQuery {
+            denoise: Some(20.0),
+            ..plain.clone()
+        };
+        assert_eq!(estimate(&source, &plain), Some(2.0));
+        assert_eq!(estimate(&source, &denoised), Some(10.0));
+
+        let budget = ComplexityBudget {
+            budget: 5.0,
+            allowlist: vec!["trusted.example.com".to_string()],
+        };
+        assert!(budget.check(&source, &plain, None).is_ok());
+        let rejected = budget.check(&source, &denoised, None).unwrap_err();
+        assert_eq!((rejected.cost, rejected.budget), (10.0, 5.0));
+        assert!(
+            budget
+                .check(&source, &denoised, Some("Trusted.example.com:443"))
+                .is_ok()
+        );
+        assert!(budget.check(b"not an image", &denoised, None).is_ok());
+        assert!(
+            ComplexityBudget::default()
+                .check(&source, &denoised, None)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use rayon::prelude::*;
+
+/// Neighbors on each side of a pixel taken into account
+const RADIUS: i64 = 2;
+/// Standard deviation of the spatial weights, in pixels
+const SPATIAL_SIGMA: f32 = 1.5;
+
+/// Smooth sensor noise with a bilateral filter, preserving edges
+///
+/// `strength` (0 to 100) is the standard deviation of the color differences still
+/// averaged out, so speckles are removed while contrasted edges are kept. Rows are
+/// filtered in parallel on the calling rayon pool.
+pub fn denoise(img: DynamicImage, strength: f32) -> DynamicImage {
+    if strength <= 0.0 {
+        return img;
+    }
+
+    let has_alpha = img.color().has_alpha();
+    let (width, height) = img.dimensions();
+    let source = img.into_rgba8();
+
+    let size = (2 * RADIUS + 1) as usize;
+    let mut spatial = vec![0.0f32; size * size];
+    for dy in -RADIUS..=RADIUS {
+        for dx in -RADIUS..=RADIUS {
+            let distance = (dx * dx + dy * dy) as f32;
+            spatial[((dy + RADIUS) as usize) * size + (dx + RADIUS) as usize] =
+                (-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA)).exp();
+        }
+    }
+    let range_coefficient = -1.0 / (2.0 * strength * strength);
+
+    let mut output = vec![0u8; source.as_raw().len()];
+    output
+        .par_chunks_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as i64;
+            for x in 0..width as i64 {
+                let center = source.get_pixel(x as u32, y as u32).0;
+                let mut sum = [0.0f32; 3];
+                let mut total_weight = 0.0f32;
+
+                for dy in -RADIUS..=RADIUS {
+                    let ny = (y + dy).clamp(0, height as i64 - 1) as u32;
+                    for dx in -RADIUS..=RADIUS {
+                        let nx = (x + dx).clamp(0, width as i64 - 1) as u32;
+                        let neighbor = source.get_pixel(nx, ny).0;
+                        let color_distance: f32 = (0..3)
+                            .map(|c| {
+                                let d = neighbor[c] as f32 - center[c] as f32;
+                                d * d
+                            })
+                            .sum();
+                        let weight = spatial
+                            [((dy + RADIUS) as usize) * size + (dx + RADIUS) as usize]
+                            * (color_distance * range_coefficient).exp();
+                        for c in 0..3 {
+                            sum[c] += neighbor[c] as f32 * weight;
+                        }
+                        total_weight += weight;
+                    }
+                }
+
+                let pixel = &mut row[x as usize * 4..x as usize * 4 + 4];
+                for c in 0..3 {
+                    pixel[c] = (sum[c] / total_weight).round().clamp(0.0, 255.0) as u8;
+                }
+                pixel[3] = center[3];
+            }
+        });
+
+    let filtered = DynamicImage::ImageRgba8(
+        RgbaImage::from_raw(width, height, output).expect("buffer matches the source size"),
+    );
+    if has_alpha {
+        filtered
+    } else {
+        DynamicImage::ImageRgb8(filtered.into_rgb8())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn smooths_noise_and_keeps_edges() {
+        // Left half dark and right half bright, with alternating speckles
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| {
+            let base = if x < 8 { 40 } else { 210 };
+            let noise = if (x + y) % 2 == 0 { 10 } else { 0 };
+            Rgb([base + noise; 3])
+        }));
+
+        let filtered = denoise(img, 30.0).into_rgb8();
+        let speckle = |x: u32, y: u32| filtered.get_pixel(x, y)[0] as i32;
+        assert!((speckle(3, 4) - speckle(3, 5)).abs() <= 3);
+        assert!(speckle(6, 4) < 60);
+        assert!(speckle(9, 4) > 190);
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{Context, Result, bail};
+use image::codecs::jpeg::PixelDensity;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Signature plus the length, type, 13 data bytes and CRC of the IHDR chunk
+const PNG_IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+const METERS_PER_INCH: f64 = 0.0254;
+
+/// JFIF density header for `dpi`
+pub fn jpeg_density(dpi: u32) -> Result<PixelDensity> {
+    let dpi = u16::try_from(dpi).context("JPEG density must fit in 16 bits")?;
+    Ok(PixelDensity::dpi(dpi))
+}
+
+/// Insert a `pHYs` chunk right after the IHDR of an encoded PNG
+pub fn insert_png_density(png: &mut Vec<u8>, dpi: u32) -> Result<()> {
+    if png.len() < PNG_IHDR_END || !png.starts_with(PNG_SIGNATURE) || &png[12..16] != b"IHDR" {
+        bail!("Encoded PNG does not start with an IHDR chunk");
+    }
+
+    let pixels_per_meter = (dpi as f64 / METERS_PER_INCH).round() as u32;
+    let mut body = Vec::with_capacity(4 + 9);
+    body.extend_from_slice(b"pHYs");
+    body.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    body.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    // Unit specifier 1: pixels per meter
+    body.push(1);
+
+    let mut chunk = Vec::with_capacity(4 + body.len() + 4);
+    chunk.extend_from_slice(&9u32.to_be_bytes());
+    chunk.extend_from_slice(&body);
+    chunk.extend_from_slice(&crc32fast::hash(&body).to_be_bytes());
+
+    png.splice(PNG_IHDR_END..PNG_IHDR_END, chunk);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat};
+    use std::io::Cursor;
+
+    #[test]
+    fn inserts_png_density_after_header() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let mut png = png.into_inner();
+        insert_png_density(&mut png, 300).unwrap();
+
+        assert_eq!(&png[PNG_IHDR_END + 4..PNG_IHDR_END + 8], b"pHYs");
+        // 300 dpi is 11811 pixels per meter
+        assert_eq!(
+            &png[PNG_IHDR_END + 8..PNG_IHDR_END + 12],
+            &11811u32.to_be_bytes()
+        );
+        assert!(image::load_from_memory(&png).is_ok());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Context, Result, bail};
+use hickory_resolver::TokioResolver;
+use hickory_resolver::config::LookupIpStrategy;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Address families tried when connecting to origins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Both families, IPv4 first with a fallback to IPv6 after a short delay
+    Ipv4,
+    /// Both families, IPv6 first with a fallback to IPv4 after a short delay
+    Ipv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpPreference {
+    fn parse(value: &str) -> Result<Self> {
+        Ok(match value.to_lowercase().as_str() {
+            "ipv4" => Self::Ipv4,
+            "ipv6" => Self::Ipv6,
+            "ipv4_only" => Self::Ipv4Only,
+            "ipv6_only" => Self::Ipv6Only,
+            _ => bail!(
+                "Invalid DNS_IP_PREFERENCE {:?}, expected ipv4, ipv6, ipv4_only or ipv6_only",
+                value
+            ),
+        })
+    }
+
+    fn strategy(self) -> LookupIpStrategy {
+        match self {
+            Self::Ipv4 | Self::Ipv6 => LookupIpStrategy::Ipv4AndIpv6,
+            Self::Ipv4Only => LookupIpStrategy::Ipv4Only,
+            Self::Ipv6Only => LookupIpStrategy::Ipv6Only,
+        }
+    }
+
+    /// Order `addresses` so the preferred family comes first
+    ///
+    /// The HTTP connector races the first family against the other one after a short
+    /// delay (happy eyeballs), so the order decides which is attempted first.
+    fn sort(self, addresses: &mut [IpAddr]) {
+        let prefers_v6 = self == Self::Ipv6;
+        addresses.sort_by_key(|address| address.is_ipv6() != prefers_v6);
+    }
+}
+
+/// Lookups made by the resolver, including the ones answered from its cache
+#[derive(Debug, Default)]
+struct Counters {
+    lookups: AtomicU64,
+    failures: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+/// Snapshot of the resolver counters
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsStats {
+    pub lookups: u64,
+    pub failures: u64,
+    pub avg_lookup_ms: f64,
+}
+
+/// Caching DNS resolver used by the HTTP client for origin downloads
+///
+/// Answers are kept for their TTL, clamped to the configured bounds, instead of going
+/// through the system resolver on every new connection.
+#[derive(Clone)]
+pub struct DnsCache {
+    resolver: Arc<TokioResolver>,
+    preference: IpPreference,
+    counters: Arc<Counters>,
+    #[cfg(feature = "otel")]
+    latency: opentelemetry::metrics::Histogram<f64>,
+}
+
+impl DnsCache {
+    /// Resolver reading the name servers from the system configuration
+    pub fn new(
+        cache_size: usize,
+        min_ttl: Duration,
+        max_ttl: Duration,
+        preference: IpPreference,
+    ) -> Result<Self> {
+        let mut builder = TokioResolver::builder_tokio()
+            .context("Failed to read the system DNS configuration")?;
+        let options = builder.options_mut();
+        options.cache_size = cache_size;
+        options.positive_min_ttl = Some(min_ttl);
+        options.positive_max_ttl = Some(max_ttl.max(min_ttl));
+        options.negative_max_ttl = Some(max_ttl.max(min_ttl));
+        options.ip_strategy = preference.strategy();
+
+        Ok(Self {
+            resolver: Arc::new(builder.build()),
+            preference,
+            counters: Arc::default(),
+            #[cfg(feature = "otel")]
+            latency: opentelemetry::global::meter("emgr")
+                .f64_histogram("emgr.dns.lookup.duration")
+                .with_unit("s")
+                .with_description("Resolution time of origin host names")
+                .build(),
+        })
+    }
+
+    /// The resolver configured by the environment, if enabled
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        if !config.dns_cache_enabled {
+            return Ok(None);
+        }
+        Self::new(
+            config.dns_cache_size,
+            Duration::from_secs(config.dns_min_ttl_secs),
+            Duration::from_secs(config.dns_max_ttl_secs),
+            IpPreference::parse(&config.dns_ip_preference)?,
+        )
+        .map(Some)
+    }
+
+    pub fn stats(&self) -> DnsStats {
+        let lookups = self.counters.lookups.load(Ordering::Relaxed);
+        let total_micros = self.counters.total_micros.load(Ordering::Relaxed);
+        DnsStats {
+            lookups,
+            failures: self.counters.failures.load(Ordering::Relaxed),
+            avg_lookup_ms: if lookups == 0 {
+                0.0
+            } else {
+                total_micros as f64 / lookups as f64 / 1000.0
+            },
+        }
+    }
+
+    fn record(&self, elapsed: Duration, failed: bool) {
+        self.counters.lookups.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .total_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if failed {
+            self.counters.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "otel")]
+        self.latency.record(
+            elapsed.as_secs_f64(),
+            &[opentelemetry::KeyValue::new(
+                "outcome",
+                if failed { "error" } else { "ok" },
+            )],
+        );
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.clone();
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = cache.resolver.lookup_ip(name.as_str()).await;
+            cache.record(started.elapsed(), result.is_err());
+
+            let mut addresses: Vec<IpAddr> = result?.iter().collect();
+            cache.preference.sort(&mut addresses);
+            // The port is replaced by the one of the URL
+            let addrs: Addrs = Box::new(
+                addresses
+                    .into_iter()
+                    .map(|address| SocketAddr::new(address, 0)),
+            );
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_preferred_family_first() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let mut addresses = [v6, v4];
+        IpPreference::Ipv4.sort(&mut addresses);
+        assert_eq!(addresses, [v4, v6]);
+        IpPreference::Ipv6.sort(&mut addresses);
+        assert_eq!(addresses, [v6, v4]);
+
+        assert_eq!(
+            IpPreference::parse("IPv6_only").unwrap(),
+            IpPreference::Ipv6Only
+        );
+        assert!(IpPreference::parse("both").is_err());
+    }
+}
@@ -0,0 +1,692 @@
+use crate::models::params::{
+    ChromaSubsampling, DEFAULT_FORMAT, ResizeQuery, supports_transparency,
+};
+use crate::modules::env::env::EnvConfig;
+use crate::services::image::density;
+use anyhow::{Context, Result, anyhow, bail};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, Frame, GenericImageView, ImageFormat, imageops};
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// JPEG quality used when none is requested, the default of the `image` crate
+pub const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+/// Speed of the AVIF encoder, from 1 (smallest output) to 10 (fastest)
+pub const AVIF_SPEED: u8 = 6;
+
+/// Speed of the palette search of animated GIF output, from 1 (best colors) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Version of the encoder output, stored with each image and part of its cache key
+///
+/// Bump it whenever the same request may produce different bytes, e.g. after upgrading
+/// the `image` crate or changing an encoder parameter, so stale variants are replaced
+/// instead of mixing with new ones. The golden tests in `tests/golden.rs` fail until it
+/// is bumped when an output changes.
+pub const ENCODER_VERSION: u32 = 2;
+
+/// Share of the horizontally adjacent pixels of the exact same color above which
+/// `subsampling=auto` takes an image for a screenshot or graphic rather than a photo
+const FLAT_SHARE: f64 = 0.5;
+
+/// Rows sampled by `subsampling=auto`, spread over the height of the image
+const SAMPLED_ROWS: u32 = 256;
+
+/// Library encoding JPEG output
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum JpegLibrary {
+    /// The encoder of the `image` crate
+    #[default]
+    Image,
+    /// mozjpeg, smaller at the same quality but slower
+    #[cfg(feature = "mozjpeg")]
+    Mozjpeg,
+}
+
+impl FromStr for JpegLibrary {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "image" => Ok(Self::Image),
+            #[cfg(feature = "mozjpeg")]
+            "mozjpeg" => Ok(Self::Mozjpeg),
+            #[cfg(not(feature = "mozjpeg"))]
+            "mozjpeg" => bail!("The mozjpeg encoder requires the mozjpeg feature"),
+            _ => bail!(
+                "Unknown JPEG encoder {:?}, expected image or mozjpeg",
+                value
+            ),
+        }
+    }
+}
+
+/// Encoder settings of an output image
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Encoding {
+    /// JPEG and AVIF quality, `DEFAULT_JPEG_QUALITY` when unset
+    pub quality: Option<u8>,
+    /// Resolution written into the metadata, in DPI
+    pub density: Option<u32>,
+    /// Trade-off between PNG encoding time and size
+    pub png_compression: CompressionType,
+    pub jpeg_library: JpegLibrary,
+    /// JPEG chroma subsampling, the default of the encoder when unset
+    pub subsampling: Option<ChromaSubsampling>,
+}
+
+impl Encoding {
+    /// These settings with `subsampling=auto` resolved from the content of `img`
+    pub fn for_image(self, img: &DynamicImage) -> Self {
+        Self {
+            subsampling: self.subsampling.map(|subsampling| match subsampling {
+                ChromaSubsampling::Auto => detect_subsampling(img),
+                subsampling => subsampling,
+            }),
+            ..self
+        }
+    }
+
+    /// Chroma subsampling written by the JPEG encoder
+    ///
+    /// The encoder of the `image` crate always keeps the chroma at full resolution.
+    fn effective_subsampling(&self) -> Option<ChromaSubsampling> {
+        match self.jpeg_library {
+            JpegLibrary::Image => Some(ChromaSubsampling::Yuv444),
+            #[cfg(feature = "mozjpeg")]
+            JpegLibrary::Mozjpeg => self.subsampling,
+        }
+    }
+
+    /// Encoder of `format` with the settings it honors, e.g. `jpeg library=image quality=75`
+    pub fn describe(&self, format: ImageFormat) -> String {
+        let description = match format {
+            ImageFormat::Jpeg => {
+                let description = format!(
+                    "jpeg library={:?} quality={}",
+                    self.jpeg_library,
+                    self.quality.unwrap_or(DEFAULT_JPEG_QUALITY)
+                );
+                match self.effective_subsampling() {
+                    Some(subsampling) => {
+                        format!("{} subsampling={}", description, subsampling.name())
+                    }
+                    None => description,
+                }
+            }
+            ImageFormat::Png => format!("png compression={:?}", self.png_compression),
+            ImageFormat::WebP => return "webp lossless".to_string(),
+            ImageFormat::Avif => format!(
+                "avif speed={} quality={}",
+                AVIF_SPEED,
+                self.quality.unwrap_or(DEFAULT_JPEG_QUALITY)
+            ),
+            format => format!("{:?}", format),
+        }
+        .to_lowercase();
+        match self.density {
+            Some(density) => format!("{} density={}", description, density),
+            None => description,
+        }
+    }
+}
+
+/// Output settings of requests that leave them unset
+#[derive(Debug, Clone, Copy)]
+pub struct EncodingDefaults {
+    /// Format of requests without `format`
+    pub format: gen_server::models::ImageFormat,
+    pub jpeg_quality: u8,
+    pub png_compression: CompressionType,
+    pub jpeg_library: JpegLibrary,
+    /// Resampling filter of every resize, instead of one picked by output size
+    pub resize_filter: Option<imageops::FilterType>,
+    /// Sharpen the downscales of requests without `auto_sharpen`
+    pub auto_sharpen: bool,
+}
+
+impl Default for EncodingDefaults {
+    fn default() -> Self {
+        Self {
+            format: DEFAULT_FORMAT,
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            png_compression: CompressionType::default(),
+            jpeg_library: JpegLibrary::default(),
+            resize_filter: None,
+            auto_sharpen: false,
+        }
+    }
+}
+
+impl EncodingDefaults {
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        let format = config
+            .default_format
+            .to_lowercase()
+            .parse::<gen_server::models::ImageFormat>()
+            .map_err(|e| anyhow!("Invalid DEFAULT_FORMAT: {}", e))?;
+        if matches!(
+            format,
+            gen_server::models::ImageFormat::Mp4 | gen_server::models::ImageFormat::Webm
+        ) {
+            bail!("DEFAULT_FORMAT must be an image format, got {}", format);
+        }
+
+        Ok(Self {
+            format,
+            jpeg_quality: config.jpeg_quality.clamp(1, 100),
+            png_compression: parse_png_compression(&config.png_compression)?,
+            auto_sharpen: config.auto_sharpen,
+            ..Self::default()
+        })
+    }
+
+    /// `params` with the default format and sharpening filled in, borrowed when it
+    /// already has them
+    ///
+    /// Removed backgrounds need transparency, so they default to PNG unless the default
+    /// format has an alpha channel. Sharpening is only filled in when enabled, keeping the
+    /// cache keys of deployments without it. A valid `subsampling` is left out when the
+    /// encoder ignores it, so identical outputs share their cache key.
+    pub fn resolve<'a>(&self, params: &'a ResizeQuery) -> Cow<'a, ResizeQuery> {
+        let auto_sharpen = params.auto_sharpen.or(self.auto_sharpen.then_some(true));
+        let format = params.format.unwrap_or_else(|| {
+            if params.remove_bg.is_some() && !supports_transparency(&self.format) {
+                gen_server::models::ImageFormat::Png
+            } else {
+                self.format
+            }
+        });
+        let subsampling = params
+            .subsampling
+            .clone()
+            .filter(|_| params.subsampling().is_err() || self.subsamples(&format));
+        if params.format.is_some()
+            && params.auto_sharpen == auto_sharpen
+            && params.subsampling == subsampling
+        {
+            return Cow::Borrowed(params);
+        }
+        Cow::Owned(ResizeQuery {
+            format: Some(format),
+            auto_sharpen,
+            subsampling,
+            ..params.clone()
+        })
+    }
+
+    /// Whether output in `format` is subsampled as requested, only mozjpeg doing so
+    ///
+    /// The JPEG encoder of the `image` crate always writes 4:4:4.
+    fn subsamples(&self, format: &gen_server::models::ImageFormat) -> bool {
+        *format == gen_server::models::ImageFormat::Jpg
+            && match self.jpeg_library {
+                JpegLibrary::Image => false,
+                #[cfg(feature = "mozjpeg")]
+                JpegLibrary::Mozjpeg => true,
+            }
+    }
+
+    /// Encoder settings of `params` before any quality search
+    pub fn encoding(&self, params: &ResizeQuery) -> Encoding {
+        Encoding {
+            quality: Some(
+                params
+                    .fixed_quality()
+                    .ok()
+                    .flatten()
+                    .unwrap_or(self.jpeg_quality),
+            ),
+            density: params.density,
+            png_compression: self.png_compression,
+            jpeg_library: self.jpeg_library,
+            subsampling: params.subsampling().ok().flatten(),
+        }
+    }
+}
+
+/// Full resolution chroma for screenshots and graphics, whose text and sharp colored edges
+/// smear when subsampled, and 4:2:0 for photos
+///
+/// Flat areas of the exact same color are common in rendered content but rare in photos,
+/// whose sensor noise varies every pixel.
+fn detect_subsampling(img: &DynamicImage) -> ChromaSubsampling {
+    let (width, height) = img.dimensions();
+    let step = (height / SAMPLED_ROWS).max(1) as usize;
+    let (mut flat, mut total) = (0u64, 0u64);
+    for y in (0..height).step_by(step) {
+        for x in 1..width {
+            total += 1;
+            if img.get_pixel(x, y) == img.get_pixel(x - 1, y) {
+                flat += 1;
+            }
+        }
+    }
+    if total > 0 && flat as f64 / total as f64 > FLAT_SHARE {
+        ChromaSubsampling::Yuv444
+    } else {
+        ChromaSubsampling::Yuv420
+    }
+}
+
+fn parse_png_compression(value: &str) -> Result<CompressionType> {
+    Ok(match value.to_lowercase().as_str() {
+        "fast" => CompressionType::Fast,
+        "default" => CompressionType::Default,
+        "best" => CompressionType::Best,
+        "none" => CompressionType::Uncompressed,
+        level => match level.parse::<u8>() {
+            Ok(level @ 1..=9) => CompressionType::Level(level),
+            _ => bail!(
+                "Invalid PNG_COMPRESSION {:?}, expected fast, default, best, none or 1 to 9",
+                value
+            ),
+        },
+    })
+}
+
+/// Encode `img` as `format` into `output`
+///
+/// WebP is encoded losslessly, so only JPEG and AVIF honor the quality and only PNG the
+/// compression. Only mozjpeg honors the chroma subsampling. JPEG carries the density
+/// in its JFIF header and PNG in a `pHYs` chunk, while WebP and AVIF have no resolution field.
+///
+/// Every encoder parameter is set explicitly rather than left to the `image` crate
+/// defaults, see [`ENCODER_VERSION`].
+pub fn encode(
+    img: &DynamicImage,
+    format: ImageFormat,
+    encoding: Encoding,
+    output: &mut Cursor<Vec<u8>>,
+) -> Result<()> {
+    match format {
+        ImageFormat::Jpeg => {
+            let pixel_density = encoding.density.map(density::jpeg_density).transpose()?;
+            let quality = encoding.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            #[cfg(feature = "mozjpeg")]
+            if encoding.jpeg_library == JpegLibrary::Mozjpeg {
+                return encode_mozjpeg(
+                    img,
+                    quality,
+                    encoding.density,
+                    encoding.for_image(img).subsampling,
+                    output,
+                );
+            }
+            let mut encoder = JpegEncoder::new_with_quality(&mut *output, quality);
+            if let Some(pixel_density) = pixel_density {
+                encoder.set_pixel_density(pixel_density);
+            }
+            img.write_with_encoder(encoder)
+        }
+        ImageFormat::Png => img.write_with_encoder(PngEncoder::new_with_quality(
+            &mut *output,
+            encoding.png_compression,
+            FilterType::Adaptive,
+        )),
+        ImageFormat::WebP => img.write_with_encoder(WebPEncoder::new_lossless(&mut *output)),
+        ImageFormat::Avif => img.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut *output,
+            AVIF_SPEED,
+            encoding.quality.unwrap_or(DEFAULT_JPEG_QUALITY),
+        )),
+        _ => img.write_to(&mut *output, format),
+    }
+    .context(format!("Failed to encode image to {:?}", format))?;
+
+    if format == ImageFormat::Png
+        && let Some(dpi) = encoding.density
+    {
+        density::insert_png_density(output.get_mut(), dpi)?;
+    }
+    Ok(())
+}
+
+/// Encode `frames` as an animation in `format`, GIF or WebP, looping forever with the
+/// delay of each frame
+pub fn encode_animation(
+    frames: Vec<Frame>,
+    format: ImageFormat,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    match format {
+        ImageFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(output, GIF_SPEED);
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .context("Failed to encode animated GIF")?;
+            encoder
+                .encode_frames(frames)
+                .context("Failed to encode animated GIF")
+        }
+        ImageFormat::WebP => encode_animated_webp(frames, output),
+        format => bail!("{:?} can't be animated", format),
+    }
+}
+
+/// Encode `frames` as a lossless animated WebP
+///
+/// The `image` crate only encodes still WebPs, so each frame is encoded as one and its
+/// VP8L bitstream is moved into an ANMF chunk of the animation container.
+fn encode_animated_webp(frames: Vec<Frame>, output: &mut Vec<u8>) -> Result<()> {
+    let Some(first) = frames.first() else {
+        bail!("An animation needs frames");
+    };
+    let (width, height) = first.buffer().dimensions();
+
+    let mut chunks = Vec::new();
+    // Animated, with an alpha channel, on a canvas of the size of the frames
+    let mut vp8x = vec![0x12, 0, 0, 0];
+    vp8x.extend_from_slice(&u24(width - 1)?);
+    vp8x.extend_from_slice(&u24(height - 1)?);
+    push_chunk(&mut chunks, b"VP8X", &vp8x);
+    // Transparent background, looping forever
+    push_chunk(&mut chunks, b"ANIM", &[0; 6]);
+
+    for frame in frames {
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let duration = (numerator / denominator.max(1)).min(0xFF_FFFF);
+        let image = DynamicImage::ImageRgba8(frame.into_buffer());
+        let (frame_width, frame_height) = image.dimensions();
+        let mut still = Vec::new();
+        image
+            .write_with_encoder(WebPEncoder::new_lossless(&mut still))
+            .context("Failed to encode animated WebP")?;
+        let bitstream = find_chunk(&still, b"VP8L").context("Lossless WebP without VP8L chunk")?;
+
+        // Placed at the origin, covering the canvas
+        let mut anmf = vec![0; 6];
+        anmf.extend_from_slice(&u24(frame_width - 1)?);
+        anmf.extend_from_slice(&u24(frame_height - 1)?);
+        anmf.extend_from_slice(&u24(duration)?);
+        // Frames replace the canvas rather than being blended over the previous one
+        anmf.push(0b10);
+        push_chunk(&mut anmf, b"VP8L", bitstream);
+        push_chunk(&mut chunks, b"ANMF", &anmf);
+    }
+
+    let size = u32::try_from(chunks.len() + 4).context("Animated WebP over 4 GiB")?;
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&size.to_le_bytes());
+    output.extend_from_slice(b"WEBP");
+    output.extend_from_slice(&chunks);
+    Ok(())
+}
+
+/// `value` as the 24-bit little-endian integer of WebP headers
+fn u24(value: u32) -> Result<[u8; 3]> {
+    if value > 0xFF_FFFF {
+        bail!("{} doesn't fit in a WebP header", value);
+    }
+    let [a, b, c, _] = value.to_le_bytes();
+    Ok([a, b, c])
+}
+
+/// Append a RIFF chunk, padded to an even size
+fn push_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// Data of the first `fourcc` chunk of a RIFF file
+fn find_chunk<'a>(riff: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut chunks = riff.get(12..)?;
+    while chunks.len() >= 8 {
+        let size = u32::from_le_bytes(chunks[4..8].try_into().ok()?) as usize;
+        let data = chunks.get(8..8 + size)?;
+        if &chunks[..4] == fourcc {
+            return Some(data);
+        }
+        chunks = chunks.get(8 + size + size % 2..)?;
+    }
+    None
+}
+
+/// Encode `img` as JPEG with mozjpeg
+#[cfg(feature = "mozjpeg")]
+fn encode_mozjpeg(
+    img: &DynamicImage,
+    quality: u8,
+    density: Option<u32>,
+    subsampling: Option<ChromaSubsampling>,
+    output: &mut Cursor<Vec<u8>>,
+) -> Result<()> {
+    let rgb = img.to_rgb8();
+    let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+    compress.set_size(rgb.width() as usize, rgb.height() as usize);
+    compress.set_quality(f32::from(quality));
+    // Pixels covered by each chroma sample, mozjpeg defaulting to 4:2:0
+    let chroma = match subsampling {
+        Some(ChromaSubsampling::Yuv444) => Some((1, 1)),
+        Some(ChromaSubsampling::Yuv422) => Some((2, 1)),
+        Some(ChromaSubsampling::Yuv420) => Some((2, 2)),
+        Some(ChromaSubsampling::Auto) | None => None,
+    };
+    if let Some(chroma) = chroma {
+        compress.set_chroma_sampling_pixel_sizes((1, 1), chroma);
+    }
+    if let Some(dpi) = density {
+        compress.set_pixel_density(mozjpeg::PixelDensity {
+            unit: mozjpeg::PixelDensityUnit::Inches,
+            x: u16::try_from(dpi).context("JPEG density must fit in 16 bits")?,
+            y: u16::try_from(dpi).context("JPEG density must fit in 16 bits")?,
+        });
+    }
+
+    let mut started = compress
+        .start_compress(&mut *output)
+        .context("Failed to start the mozjpeg encoder")?;
+    started
+        .write_scanlines(rgb.as_raw())
+        .context("Failed to encode image with mozjpeg")?;
+    started
+        .finish()
+        .context("Failed to finish the mozjpeg output")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn tags_jpeg_density() {
+        let mut jpeg = Cursor::new(Vec::new());
+        let encoding = Encoding {
+            quality: Some(90),
+            density: Some(300),
+            ..Encoding::default()
+        };
+        encode(
+            &DynamicImage::new_rgb8(4, 4),
+            ImageFormat::Jpeg,
+            encoding,
+            &mut jpeg,
+        )
+        .unwrap();
+        let jpeg = jpeg.into_inner();
+
+        // JFIF APP0: units (1 = dots per inch) followed by the X and Y density
+        let jfif = jpeg.windows(5).position(|w| w == b"JFIF\0").unwrap();
+        assert_eq!(&jpeg[jfif + 7..jfif + 12], &[1, 1, 44, 1, 44]);
+    }
+
+    #[test]
+    fn encodes_avif() {
+        let mut avif = Cursor::new(Vec::new());
+        encode(
+            &DynamicImage::new_rgba8(16, 8),
+            ImageFormat::Avif,
+            Encoding::default(),
+            &mut avif,
+        )
+        .unwrap();
+
+        // ISOBMFF file type box with the AVIF brand
+        assert_eq!(&avif.get_ref()[4..12], b"ftypavif");
+        assert_eq!(
+            Encoding::default().describe(ImageFormat::Avif),
+            "avif speed=6 quality=75"
+        );
+    }
+
+    #[test]
+    fn encodes_animated_webp() {
+        use image::codecs::webp::WebPDecoder;
+        use image::{AnimationDecoder, Delay, Rgba, RgbaImage};
+
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 128], [0, 255, 0, 0]];
+        let frames = colors
+            .iter()
+            .map(|&color| {
+                Frame::from_parts(
+                    RgbaImage::from_pixel(5, 3, Rgba(color)),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(120, 1),
+                )
+            })
+            .collect();
+        let mut webp = Vec::new();
+        encode_animation(frames, ImageFormat::WebP, &mut webp).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&webp)).unwrap();
+        assert!(decoder.has_animation());
+        let decoded = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), colors.len());
+        for (frame, color) in decoded.iter().zip(colors) {
+            assert_eq!(frame.buffer().dimensions(), (5, 3));
+            assert_eq!(frame.buffer().get_pixel(2, 1), &Rgba(color));
+            assert_eq!(frame.delay().numer_denom_ms(), (120, 1));
+        }
+
+        assert!(encode_animation(Vec::new(), ImageFormat::WebP, &mut Vec::new()).is_err());
+        assert!(encode_animation(Vec::new(), ImageFormat::Png, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn detects_the_subsampling_from_the_content() {
+        let screenshot = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 32, |x, _| {
+            if x % 16 < 12 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([200, 0, 0])
+            }
+        }));
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 32, |x, y| {
+            Rgb([(x * 3 + y) as u8, (x * y) as u8, (x ^ y) as u8])
+        }));
+        let auto = Encoding {
+            subsampling: Some(ChromaSubsampling::Auto),
+            ..Encoding::default()
+        };
+        assert_eq!(
+            auto.for_image(&screenshot).subsampling,
+            Some(ChromaSubsampling::Yuv444)
+        );
+        assert_eq!(
+            auto.for_image(&photo).subsampling,
+            Some(ChromaSubsampling::Yuv420)
+        );
+
+        // The encoder of the image crate keeps the chroma at full resolution regardless
+        assert_eq!(
+            auto.for_image(&photo).describe(ImageFormat::Jpeg),
+            "jpeg library=image quality=75 subsampling=444"
+        );
+    }
+
+    #[cfg(feature = "mozjpeg")]
+    #[test]
+    fn encodes_jpeg_with_mozjpeg() {
+        let mut jpeg = Cursor::new(Vec::new());
+        let encoding = Encoding {
+            density: Some(300),
+            jpeg_library: "mozjpeg".parse().unwrap(),
+            ..Encoding::default()
+        };
+        encode(
+            &DynamicImage::new_rgba8(16, 8),
+            ImageFormat::Jpeg,
+            encoding,
+            &mut jpeg,
+        )
+        .unwrap();
+
+        let decoded =
+            image::load_from_memory_with_format(jpeg.get_ref(), ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (16, 8));
+    }
+
+    #[test]
+    fn resolves_defaults() {
+        let defaults = EncodingDefaults {
+            format: gen_server::models::ImageFormat::Webp,
+            ..EncodingDefaults::default()
+        };
+        let query = ResizeQuery::default();
+        assert_eq!(
+            defaults.resolve(&query).format,
+            Some(gen_server::models::ImageFormat::Webp)
+        );
+
+        let explicit = ResizeQuery {
+            format: Some(gen_server::models::ImageFormat::Png),
+            ..ResizeQuery::default()
+        };
+        assert!(matches!(defaults.resolve(&explicit), Cow::Borrowed(_)));
+        let sharpened = EncodingDefaults {
+            auto_sharpen: true,
+            ..defaults
+        };
+        assert_eq!(sharpened.resolve(&explicit).auto_sharpen, Some(true));
+        assert_eq!(
+            sharpened.resolve(&explicit).format,
+            Some(gen_server::models::ImageFormat::Png)
+        );
+
+        // The image encoder writes 4:4:4 whatever the subsampling asked
+        let subsampled = |subsampling: &str| ResizeQuery {
+            format: Some(gen_server::models::ImageFormat::Jpg),
+            subsampling: Some(subsampling.to_string()),
+            ..ResizeQuery::default()
+        };
+        assert_eq!(defaults.resolve(&subsampled("420")).subsampling, None);
+        assert_eq!(
+            defaults.resolve(&subsampled("411")).subsampling.as_deref(),
+            Some("411")
+        );
+        #[cfg(feature = "mozjpeg")]
+        {
+            let mozjpeg = EncodingDefaults {
+                jpeg_library: JpegLibrary::Mozjpeg,
+                ..defaults
+            };
+            assert!(matches!(
+                mozjpeg.resolve(&subsampled("420")),
+                Cow::Borrowed(_)
+            ));
+        }
+
+        assert_eq!(
+            parse_png_compression("Best").unwrap(),
+            CompressionType::Best
+        );
+        assert_eq!(
+            parse_png_compression("6").unwrap(),
+            CompressionType::Level(6)
+        );
+        assert!(parse_png_compression("10").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::models::params::FrameSelector;
+use anyhow::{Context, Result, bail};
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{LimitError, LimitErrorKind};
+use image::{
+    AnimationDecoder, DynamicImage, Frame, Frames, ImageDecoder, ImageError, ImageFormat,
+    ImageReader, Limits,
+};
+use std::io::Cursor;
+
+/// Decode the frame of `bytes` picked by `selector`, within `limits`
+///
+/// Still images are a single frame: any time selects it, and only frame 0 exists.
+pub fn decode_frame(
+    bytes: &[u8],
+    format: Option<ImageFormat>,
+    selector: FrameSelector,
+    limits: Limits,
+) -> Result<DynamicImage> {
+    let Some(frames) = animation(bytes, format, limits.clone())? else {
+        if let FrameSelector::Index(index) = selector
+            && index > 0
+        {
+            bail!("Frame {} is out of range, the image is not animated", index);
+        }
+        let mut reader = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .context("Failed to read image")?;
+        reader.limits(limits);
+        return reader.decode().context("Failed to decode image");
+    };
+    select(frames, selector)
+}
+
+/// Decode every frame of an animated GIF or WebP `bytes`, composited on the full canvas
+///
+/// Still images, including single-frame animations, are `None`. `limits` apply to each
+/// frame, and `max_alloc` to all of them together, since they are kept in memory at once.
+pub fn decode_animation(
+    bytes: &[u8],
+    format: Option<ImageFormat>,
+    limits: Limits,
+) -> Result<Option<Vec<Frame>>> {
+    let max_alloc = limits.max_alloc;
+    let Some(frames) = animation(bytes, format, limits)? else {
+        return Ok(None);
+    };
+
+    let mut decoded = Vec::new();
+    let mut allocated = 0u64;
+    for frame in frames {
+        let frame = frame.context("Failed to decode animation frame")?;
+        allocated += frame.buffer().as_raw().len() as u64;
+        if max_alloc.is_some_and(|max_alloc| allocated > max_alloc) {
+            return Err(ImageError::Limits(LimitError::from_kind(
+                LimitErrorKind::InsufficientMemory,
+            )))
+            .context(format!(
+                "Animation is over the decode limit after {} frames",
+                decoded.len() + 1
+            ));
+        }
+        decoded.push(frame);
+    }
+    Ok((decoded.len() > 1).then_some(decoded))
+}
+
+/// Frames of `bytes` when its format can be animated, within `limits`
+fn animation(
+    bytes: &[u8],
+    format: Option<ImageFormat>,
+    limits: Limits,
+) -> Result<Option<Frames<'_>>> {
+    Ok(match format {
+        Some(ImageFormat::Gif) => {
+            let mut decoder =
+                GifDecoder::new(Cursor::new(bytes)).context("Failed to decode GIF")?;
+            decoder.set_limits(limits).context("Failed to decode GIF")?;
+            Some(decoder.into_frames())
+        }
+        Some(ImageFormat::WebP) => {
+            let mut decoder =
+                WebPDecoder::new(Cursor::new(bytes)).context("Failed to decode WebP")?;
+            decoder
+                .set_limits(limits)
+                .context("Failed to decode WebP")?;
+            decoder.has_animation().then(|| decoder.into_frames())
+        }
+        _ => None,
+    })
+}
+
+fn select(frames: Frames<'_>, selector: FrameSelector) -> Result<DynamicImage> {
+    let mut elapsed_ms = 0.0;
+    let mut count = 0;
+
+    for frame in frames {
+        let frame = frame.context("Failed to decode animation frame")?;
+        let picked = match selector {
+            FrameSelector::Index(index) => count == index,
+            FrameSelector::Time(time) => {
+                let (numerator, denominator) = frame.delay().numer_denom_ms();
+                elapsed_ms += numerator as f64 / denominator.max(1) as f64;
+                time as f64 * 1000.0 < elapsed_ms
+            }
+        };
+        if picked {
+            return Ok(DynamicImage::ImageRgba8(frame.into_buffer()));
+        }
+        count += 1;
+    }
+
+    match selector {
+        FrameSelector::Index(index) => {
+            bail!(
+                "Frame {} is out of range, the animation has {} frames",
+                index,
+                count
+            )
+        }
+        FrameSelector::Time(time) => bail!(
+            "Time {}s is out of range, the animation lasts {}s",
+            time,
+            elapsed_ms / 1000.0
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    fn animation() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for shade in [0u8, 128, 255] {
+                let frame = Frame::from_parts(
+                    RgbaImage::from_pixel(4, 4, Rgba([shade, 0, 0, 255])),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100, 1),
+                );
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn decodes_every_frame_of_animations() {
+        let frames = decode_animation(&animation(), Some(ImageFormat::Gif), Limits::default())
+            .unwrap()
+            .unwrap();
+        let shades: Vec<u8> = frames
+            .iter()
+            .map(|frame| frame.buffer().get_pixel(0, 0)[0])
+            .collect();
+        assert_eq!(shades, [0, 128, 255]);
+        assert_eq!(frames[1].delay(), Delay::from_numer_denom_ms(100, 1));
+
+        // Three 4x4 RGBA frames take 192 bytes
+        let mut limits = Limits::default();
+        limits.max_alloc = Some(150);
+        assert!(decode_animation(&animation(), Some(ImageFormat::Gif), limits).is_err());
+        assert!(
+            decode_animation(&animation(), Some(ImageFormat::Png), Limits::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn selects_frames_by_index_and_time() {
+        let gif = animation();
+        let shade = |selector| {
+            decode_frame(&gif, Some(ImageFormat::Gif), selector, Limits::default())
+                .unwrap()
+                .to_rgba8()
+                .get_pixel(0, 0)[0]
+        };
+
+        assert_eq!(shade(FrameSelector::Index(0)), 0);
+        assert_eq!(shade(FrameSelector::Index(2)), 255);
+        assert_eq!(shade(FrameSelector::Time(0.15)), 128);
+        assert!(
+            decode_frame(
+                &gif,
+                Some(ImageFormat::Gif),
+                FrameSelector::Index(3),
+                Limits::default()
+            )
+            .is_err()
+        );
+        assert!(
+            decode_frame(
+                &gif,
+                Some(ImageFormat::Gif),
+                FrameSelector::Time(0.3),
+                Limits::default()
+            )
+            .is_err()
+        );
+
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(2);
+        assert!(
+            decode_frame(
+                &gif,
+                Some(ImageFormat::Gif),
+                FrameSelector::Index(0),
+                limits
+            )
+            .is_err()
+        );
+    }
+}
@@ -1,50 +1,171 @@
 use crate::config::performance::PerformanceConfig;
-use crate::models::params::ResizeQuery;
-use anyhow::{Context, Result};
-use bytes::Bytes;
+#[cfg(feature = "seam_carving")]
+use crate::models::params::Fit;
+use crate::models::params::{BackgroundRemoval, Interpolation, ResizeQuery};
+use crate::services::debug::handler::StageTimings;
+use crate::services::image::admission::{AdmissionStats, DownloadAdmission};
+use crate::services::image::bandwidth::BandwidthLimiter;
+use crate::services::image::budget::ByteBudget;
+use crate::services::image::cancel::{self, or_cancelled};
+use crate::services::image::denoise::denoise;
+#[cfg(feature = "dns_cache")]
+use crate::services::image::dns::{DnsCache, DnsStats};
+use crate::services::image::encode::{Encoding, EncodingDefaults, encode, encode_animation};
+use crate::services::image::frames;
+use crate::services::image::memory::{self, MemoryGuard, MemoryStats};
+use crate::services::image::ops;
+use crate::services::image::ops::background::RemoveBackground;
+use crate::services::image::ops::quantize::Quantize;
+use crate::services::image::ops::sharpen::Sharpen;
+use crate::services::image::ops::watermark::Watermark;
+use crate::services::image::pipeline::{Operation, Pipeline};
+use crate::services::image::probe::{
+    ProbedSource, SourceProbe, SourceTooLarge, content_range_total,
+};
+use crate::services::image::quality::AutoQuality;
+use crate::services::image::resample;
+#[cfg(feature = "seam_carving")]
+use crate::services::image::seams;
+use crate::services::image::slots::{EncodeSlotStats, EncodeSlots};
+use crate::services::image::tls::OriginTlsConfig;
+use crate::services::image::validators::{SourceDownload, SourceValidators};
+#[cfg(feature = "video")]
+use crate::services::image::video::Transcoder;
+use crate::services::plugin::handler::PluginRegistry;
+use anyhow::{Context, Result, bail};
+use bytes::{Bytes, BytesMut};
 use derive_builder::Builder;
-use image::imageops::FilterType;
-use image::{GenericImageView, ImageFormat};
+use futures::StreamExt;
+use image::imageops;
+use image::{DynamicImage, Frame, GenericImageView, ImageFormat, ImageReader, Limits, RgbaImage};
 use reqwest::Client;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
-use tokio::sync::Semaphore;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+/// Largest dimension derived from an aspect ratio, matching the `Size` limit of the API
+const MAX_DERIVED_SIZE: u32 = 4096;
+
+/// The origin answered the download of a source with an error status
+#[derive(Debug, Error)]
+#[error("Failed to download image from {url}: status {status}")]
+pub struct OriginStatus {
+    pub url: String,
+    pub status: u16,
+}
+
+/// The origin didn't send the response headers, or the next chunk of the body, in time
+#[derive(Debug, Error)]
+#[error("Timed out after {timeout:?} waiting for the {phase} of {url}")]
+pub struct OriginTimeout {
+    pub url: String,
+    pub phase: &'static str,
+    pub timeout: Duration,
+}
 
 #[derive(Clone, Builder)]
 pub struct ImageService {
     http_client: Arc<Client>,
-    // Limit concurrent downloads to prevent memory exhaustion
-    download_semaphore: Arc<Semaphore>,
+    // Per-origin TLS settings, and the client built for each entry
+    #[builder(default)]
+    origin_tls: Arc<OriginTlsConfig>,
+    #[builder(default)]
+    origin_clients: Arc<Vec<Client>>,
+    // Limit concurrent downloads to prevent memory exhaustion, queued fairly by tenant
+    download_admission: DownloadAdmission,
     // Custom thread pool for CPU-intensive work
     cpu_pool: Arc<rayon::ThreadPool>,
+    // Caching resolver used by the HTTP client
+    #[cfg(feature = "dns_cache")]
+    #[builder(default)]
+    dns_cache: Option<DnsCache>,
+    // Header check of sources before their full download
+    #[builder(default)]
+    probe: SourceProbe,
+    // Caps on the throughput of origin downloads
+    #[builder(default)]
+    bandwidth: BandwidthLimiter,
+    // Custom transform stages run between resize and encode
+    plugins: Arc<PluginRegistry>,
+    // Format and encoder settings of requests that leave them unset
+    #[builder(default)]
+    encoding_defaults: EncodingDefaults,
+    // Bounds of the quality=auto search
+    #[builder(default)]
+    auto_quality: AutoQuality,
+    // How far outputs may be degraded to fit max_bytes
+    #[builder(default)]
+    byte_budget: ByteBudget,
+    // ffmpeg settings for mp4 and webm outputs
+    #[cfg(feature = "video")]
+    #[builder(default)]
+    transcoder: Transcoder,
+    // Images queued or being processed on the CPU pool
+    #[builder(default)]
+    processing: Arc<AtomicUsize>,
+    // Budget for the memory of images being processed
+    #[builder(default)]
+    memory: MemoryGuard,
+    // Concurrency budgets of the formats slow to encode
+    #[builder(default)]
+    encode_slots: EncodeSlots,
     config: PerformanceConfig,
 }
 
+/// Utilization of the download and processing pools
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageStats {
+    pub downloads_in_flight: usize,
+    pub max_concurrent_downloads: usize,
+    pub download_queue: AdmissionStats,
+    pub processing_in_flight: usize,
+    pub cpu_threads: usize,
+    pub memory: MemoryStats,
+    /// Images being processed to each format with an encode limit
+    pub encode_slots: BTreeMap<String, EncodeSlotStats>,
+    #[cfg(feature = "dns_cache")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsStats>,
+}
+
+/// An encoded output image
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub data: Bytes,
+    pub content_type: String,
+    pub width: u32,
+    pub height: u32,
+    /// Encoder quality picked by `quality=auto` or `max_bytes`
+    pub quality: Option<u8>,
+    /// Time spent decoding, transforming and encoding
+    pub timings: StageTimings,
+    /// Resampling filter of the resize, unset when the image wasn't resized
+    pub filter: Option<&'static str>,
+    /// Encoder and its settings, see [`Encoding::describe`]
+    pub encoder: Option<String>,
+}
+
 impl ImageService {
     pub fn new() -> Result<Self> {
         Self::with_config(PerformanceConfig::default())
     }
 
     pub fn with_config(config: PerformanceConfig) -> Result<Self> {
-        // Configure HTTP client for optimal performance
-        let mut client_builder = Client::builder()
-            .pool_max_idle_per_host(config.connection_pool_size)
-            .pool_idle_timeout(std::time::Duration::from_secs(30))
-            .timeout(config.http_timeout)
-            .tcp_keepalive(config.keep_alive_timeout);
-
-        if config.enable_http2 {
-            client_builder = client_builder.http2_prior_knowledge();
-        }
-
         let http_client = Arc::new(
-            client_builder
+            Self::http_client_builder(&config)
                 .build()
                 .context("Failed to create HTTP client")?,
         );
 
         // Limit concurrent downloads based on configuration
-        let download_semaphore = Arc::new(Semaphore::new(config.max_concurrent_downloads));
+        let download_admission = DownloadAdmission::new(config.max_concurrent_downloads, None);
 
         // Create custom thread pool for CPU work
         let cpu_pool_size = config.get_cpu_thread_pool_size();
@@ -58,117 +179,831 @@ impl ImageService {
 
         Ok(Self {
             http_client,
-            download_semaphore,
+            origin_tls: Arc::default(),
+            origin_clients: Arc::default(),
+            download_admission,
             cpu_pool,
+            #[cfg(feature = "dns_cache")]
+            dns_cache: None,
+            probe: SourceProbe::default(),
+            bandwidth: BandwidthLimiter::default(),
+            plugins: Arc::new(PluginRegistry::with_builtins()),
+            encoding_defaults: EncodingDefaults::default(),
+            auto_quality: AutoQuality::default(),
+            byte_budget: ByteBudget::default(),
+            #[cfg(feature = "video")]
+            transcoder: Transcoder::default(),
+            processing: Arc::default(),
+            memory: MemoryGuard::default(),
+            encode_slots: EncodeSlots::new(&config.encode_limits),
             config,
         })
     }
 
+    /// Configure HTTP client for optimal performance
+    fn http_client_builder(config: &PerformanceConfig) -> reqwest::ClientBuilder {
+        let client_builder = Client::builder()
+            .pool_max_idle_per_host(config.connection_pool_size)
+            .pool_idle_timeout(std::time::Duration::from_secs(30))
+            .connect_timeout(config.connect_timeout)
+            .tcp_keepalive(config.keep_alive_timeout);
+
+        if config.force_http2_prior_knowledge {
+            client_builder.http2_prior_knowledge()
+        } else if config.enable_http2 {
+            // HTTP/2 when the origin offers it through ALPN, HTTP/1.1 otherwise
+            client_builder
+        } else {
+            client_builder.http1_only()
+        }
+    }
+
+    /// Client builder with the configured resolver
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = Self::http_client_builder(&self.config);
+        #[cfg(feature = "dns_cache")]
+        let builder = match &self.dns_cache {
+            Some(dns_cache) => builder.dns_resolver(Arc::new(dns_cache.clone())),
+            None => builder,
+        };
+        builder
+    }
+
+    /// Rebuild the default and per-origin clients after a change of their settings
+    fn rebuild_clients(&mut self) -> Result<()> {
+        let http_client = self
+            .client_builder()
+            .build()
+            .context("Failed to create HTTP client")?;
+        let origin_clients = self
+            .origin_tls
+            .origins
+            .iter()
+            .map(|origin| {
+                origin
+                    .configure(self.client_builder())?
+                    .build()
+                    .context(format!("Failed to create HTTP client for {}", origin.host))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.http_client = Arc::new(http_client);
+        self.origin_clients = Arc::new(origin_clients);
+        Ok(())
+    }
+
+    /// Client used to download `url`, honoring the per-origin TLS settings
+    fn client_for(&self, url: &str) -> &Client {
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| self.origin_tls.position(&url))
+            .map_or(&self.http_client, |index| &self.origin_clients[index])
+    }
+
+    pub fn stats(&self) -> ImageStats {
+        ImageStats {
+            downloads_in_flight: self.download_admission.in_flight(),
+            max_concurrent_downloads: self.download_admission.capacity(),
+            download_queue: self.download_admission.stats(),
+            processing_in_flight: self.processing.load(Ordering::Relaxed),
+            cpu_threads: self.cpu_pool.current_num_threads(),
+            memory: self.memory.stats(),
+            encode_slots: self.encode_slots.stats(),
+            #[cfg(feature = "dns_cache")]
+            dns: self.dns_cache.as_ref().map(DnsCache::stats),
+        }
+    }
+
+    /// Replace the transform plugins available to requests
+    pub fn with_plugins(mut self, plugins: PluginRegistry) -> Self {
+        self.plugins = Arc::new(plugins);
+        self
+    }
+
+    /// Resolve origin host names through `dns_cache`
+    #[cfg(feature = "dns_cache")]
+    pub fn with_dns_cache(mut self, dns_cache: DnsCache) -> Result<Self> {
+        self.dns_cache = Some(dns_cache);
+        self.rebuild_clients()?;
+        Ok(self)
+    }
+
+    /// Use the TLS settings of `origin_tls` for the origins they match
+    pub fn with_origin_tls(mut self, origin_tls: OriginTlsConfig) -> Result<Self> {
+        self.origin_tls = Arc::new(origin_tls);
+        self.rebuild_clients()?;
+        Ok(self)
+    }
+
+    /// Replace the budget for the memory of images being processed
+    pub fn with_memory_guard(mut self, memory: MemoryGuard) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    pub fn memory_guard(&self) -> &MemoryGuard {
+        &self.memory
+    }
+
+    /// Replace the admission of origin downloads
+    pub fn with_download_admission(mut self, download_admission: DownloadAdmission) -> Self {
+        self.download_admission = download_admission;
+        self
+    }
+
+    /// Replace the header check of sources before their full download
+    pub fn with_probe(mut self, probe: SourceProbe) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Replace the caps on origin download throughput
+    pub fn with_bandwidth(mut self, bandwidth: BandwidthLimiter) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Replace the format and encoder settings of requests that leave them unset
+    pub fn with_encoding_defaults(mut self, encoding_defaults: EncodingDefaults) -> Self {
+        self.encoding_defaults = encoding_defaults;
+        self
+    }
+
+    pub fn encoding_defaults(&self) -> &EncodingDefaults {
+        &self.encoding_defaults
+    }
+
+    /// `params` with the default format filled in
+    pub fn resolve_defaults<'a>(&self, params: &'a ResizeQuery) -> Cow<'a, ResizeQuery> {
+        self.encoding_defaults.resolve(params)
+    }
+
+    /// Replace the bounds of the `quality=auto` search
+    pub fn with_auto_quality(mut self, auto_quality: AutoQuality) -> Self {
+        self.auto_quality = auto_quality;
+        self
+    }
+
+    /// Replace the limits of the `max_bytes` search
+    pub fn with_byte_budget(mut self, byte_budget: ByteBudget) -> Self {
+        self.byte_budget = byte_budget;
+        self
+    }
+
+    /// Replace the ffmpeg settings used for video outputs
+    #[cfg(feature = "video")]
+    pub fn with_transcoder(mut self, transcoder: Transcoder) -> Self {
+        self.transcoder = transcoder;
+        self
+    }
+
+    /// Check that the plugins requested by `params` exist, before doing any work
+    pub fn validate_plugins(&self, params: &ResizeQuery) -> Result<()> {
+        self.plugins.validate(params)
+    }
+
     /// Download an image from a URL with optimizations
-    pub async fn download_image(&self, url: &str) -> Result<Vec<u8>> {
-        // Acquire semaphore to limit concurrent downloads
-        let _permit = self
-            .download_semaphore
-            .acquire()
-            .await
-            .context("Failed to acquire download permit")?;
+    pub async fn download_image(&self, url: &str) -> Result<Bytes> {
+        self.download_image_for(url, None).await
+    }
 
+    /// Download an image for `tenant`, queued with its other downloads when all slots are busy
+    pub async fn download_image_for(&self, url: &str, tenant: Option<&str>) -> Result<Bytes> {
+        match self
+            .download_source_for(url, tenant, &SourceValidators::default())
+            .await?
+        {
+            SourceDownload::Modified { data, .. } => Ok(data),
+            SourceDownload::NotModified => {
+                bail!("{} answered an unconditional request with 304", url)
+            }
+        }
+    }
+
+    /// Download an image for `tenant` unless it still matches `validators`
+    ///
+    /// Like [`ImageService::download_image_for`], but new content comes with its validators.
+    /// Only unconditional downloads are probed, revalidations mostly costing a 304.
+    pub async fn download_source_for(
+        &self,
+        url: &str,
+        tenant: Option<&str>,
+        validators: &SourceValidators,
+    ) -> Result<SourceDownload> {
+        let _permit = self.download_admission.acquire(tenant).await?;
+        if self.probe.is_enabled() && validators.is_empty() {
+            self.fetch_probe(url).await?;
+        }
+        self.fetch_if_modified(url, validators).await
+    }
+
+    /// Download an image unless it still matches `validators`
+    ///
+    /// The validators are sent as `If-None-Match` and `If-Modified-Since`, so an unchanged
+    /// source costs a 304 instead of its body. New content comes with its own validators,
+    /// to be stored alongside the cached copy.
+    pub async fn download_if_modified(
+        &self,
+        url: &str,
+        validators: &SourceValidators,
+    ) -> Result<SourceDownload> {
+        let _permit = self.download_admission.acquire(None).await?;
+        self.fetch_if_modified(url, validators).await
+    }
+
+    async fn fetch_if_modified(
+        &self,
+        url: &str,
+        validators: &SourceValidators,
+    ) -> Result<SourceDownload> {
         let response = self
-            .http_client
-            .get(url)
-            .send()
+            .send(url, validators.apply(self.client_for(url).get(url)))
             .await?;
 
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED && !validators.is_empty() {
+            return Ok(SourceDownload::NotModified);
+        }
+
         if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "Failed to download image from {}: status {}",
-                url,
-                response.status()
-            ));
+            return Err(OriginStatus {
+                url: url.to_string(),
+                status: response.status().as_u16(),
+            }
+            .into());
         }
 
         // Check content length to prevent downloading huge files
         if let Some(content_length) = response.content_length() {
             if content_length > self.config.max_image_size {
-                return Err(anyhow::anyhow!(
-                    "Image too large: {} bytes (max: {} bytes)",
-                    content_length,
-                    self.config.max_image_size
-                ));
+                return Err(SourceTooLarge::Bytes {
+                    size: content_length,
+                    max: self.config.max_image_size,
+                }
+                .into());
             }
         }
 
-        // Stream the response body efficiently
-        let bytes = response
-            .bytes()
-            .await
-            .context("Failed to read image bytes")?;
+        let validators = SourceValidators::from_headers(response.headers());
+        let data = self.read_body(url, response).await?;
+
+        Ok(SourceDownload::Modified { data, validators })
+    }
 
-        Ok(bytes.to_vec())
+    /// Fetch the first bytes of a source and check them before downloading it in full
+    ///
+    /// Origins ignoring the `Range` header answer with the whole body, which is only read
+    /// up to the probed length.
+    pub async fn probe_source(&self, url: &str) -> Result<ProbedSource> {
+        let _permit = self.download_admission.acquire(None).await?;
+        self.fetch_probe(url).await
+    }
+
+    async fn fetch_probe(&self, url: &str) -> Result<ProbedSource> {
+        let request = self.client_for(url).get(url).header(
+            reqwest::header::RANGE,
+            format!("bytes=0-{}", self.probe.bytes - 1),
+        );
+        let response = self.send(url, request).await?;
+
+        let size = match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => response
+                .headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(content_range_total),
+            status if status.is_success() => response.content_length(),
+            status => bail!(OriginStatus {
+                url: url.to_string(),
+                status: status.as_u16(),
+            }),
+        };
+
+        let mut head = Vec::with_capacity(self.probe.bytes);
+        let mut chunks = response.bytes_stream();
+        while head.len() < self.probe.bytes
+            && let Some(chunk) = self.next_chunk(url, &mut chunks).await?
+        {
+            head.extend_from_slice(&chunk);
+        }
+        head.truncate(self.probe.bytes);
+
+        let format = Self::detect_format_from_bytes(&head).context("Unsupported image format")?;
+        self.probe
+            .inspect(&head, format, size, self.config.max_image_size)
+    }
+
+    /// Send the request for `url`, waiting for the response headers up to the first-byte
+    /// timeout
+    async fn send(&self, url: &str, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let timeout = self.config.first_byte_timeout;
+        match tokio::time::timeout(timeout, request.send()).await {
+            Ok(response) => Ok(response?),
+            Err(_) => Err(OriginTimeout {
+                url: url.to_string(),
+                phase: "response headers",
+                timeout,
+            }
+            .into()),
+        }
+    }
+
+    /// Next chunk of the body of `url`, waiting for it up to the idle timeout
+    ///
+    /// The timeout restarts with every chunk, so large sources downloading steadily aren't
+    /// cut off, while origins stalling mid-body are given up on.
+    async fn next_chunk(
+        &self,
+        url: &str,
+        chunks: &mut (impl futures::Stream<Item = reqwest::Result<Bytes>> + Unpin),
+    ) -> Result<Option<Bytes>> {
+        let timeout = self.config.idle_timeout;
+        match tokio::time::timeout(timeout, chunks.next()).await {
+            Ok(chunk) => chunk.transpose().context("Failed to read image bytes"),
+            Err(_) => Err(OriginTimeout {
+                url: url.to_string(),
+                phase: "next chunk of the body",
+                timeout,
+            }
+            .into()),
+        }
+    }
+
+    /// Read the body chunk by chunk, pausing whenever the bandwidth limits are reached
+    ///
+    /// Bodies without a `Content-Length`, or lying about it, are cut off once over the
+    /// maximum image size.
+    async fn read_body(&self, url: &str, response: reqwest::Response) -> Result<Bytes> {
+        let host = response.url().host_str().unwrap_or_default().to_string();
+        let capacity = response
+            .content_length()
+            .unwrap_or(0)
+            .min(self.config.max_image_size);
+        let mut body = BytesMut::with_capacity(capacity as usize);
+
+        let mut chunks = response.bytes_stream();
+        while let Some(chunk) = self.next_chunk(url, &mut chunks).await? {
+            let size = (body.len() + chunk.len()) as u64;
+            if size > self.config.max_image_size {
+                return Err(SourceTooLarge::Bytes {
+                    size,
+                    max: self.config.max_image_size,
+                }
+                .into());
+            }
+            if self.bandwidth.is_enabled() {
+                self.bandwidth.throttle(&host, chunk.len()).await;
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(body.freeze())
     }
 
     /// Process image using custom thread pool with CPU affinity
+    ///
+    /// The source is shared with the CPU pool rather than copied.
     pub async fn process_image(
         &self,
-        image_bytes: &[u8],
+        image_bytes: Bytes,
+        params: &ResizeQuery,
+    ) -> Result<ProcessedImage> {
+        self.process_image_cancellable(image_bytes, params, None, &CancellationToken::new())
+            .await
+    }
+
+    /// Process an image, giving up between stages once `cancel` is triggered
+    ///
+    /// Images still queued for the CPU pool are skipped, and ffmpeg is killed for videos.
+    /// The `watermark` of the query, loaded by the caller, is overlaid last.
+    pub async fn process_image_cancellable(
+        &self,
+        image_bytes: Bytes,
+        params: &ResizeQuery,
+        watermark: Option<Arc<DynamicImage>>,
+        cancel: &CancellationToken,
+    ) -> Result<ProcessedImage> {
+        self.run_on_pool(image_bytes, params, watermark, cancel, false)
+            .await
+    }
+
+    /// Only change the format of an image, skipping the resize and every transformation
+    ///
+    /// Only the format and quality of `params` are used. Videos are still transcoded.
+    pub async fn convert_image_cancellable(
+        &self,
+        image_bytes: Bytes,
         params: &ResizeQuery,
-    ) -> Result<(Vec<u8>, String)> {
-        let image_bytes = Bytes::copy_from_slice(image_bytes);
-        let params = params.clone();
+        cancel: &CancellationToken,
+    ) -> Result<ProcessedImage> {
+        self.run_on_pool(image_bytes, params, None, cancel, true)
+            .await
+    }
+
+    /// Decode then process, or only encode for conversions, in the CPU pool
+    async fn run_on_pool(
+        &self,
+        image_bytes: Bytes,
+        params: &ResizeQuery,
+        watermark: Option<Arc<DynamicImage>>,
+        cancel: &CancellationToken,
+        convert_only: bool,
+    ) -> Result<ProcessedImage> {
+        let params = self.resolve_defaults(params).into_owned();
+        if params.is_video() {
+            return or_cancelled(cancel, self.transcode(image_bytes, &params)).await;
+        }
+        // Taken before the turn on the pool, so images waiting for a slot don't hold a thread
+        let encode_slot = or_cancelled(cancel, async {
+            Ok(self.encode_slots.acquire(params.output_format()).await)
+        })
+        .await?;
+        // Released by the CPU pool once done with the image, even if the request is dropped
+        let reservation = self
+            .memory
+            .reserve(memory::estimate(&image_bytes, &params))?;
+
         let cpu_pool = Arc::clone(&self.cpu_pool);
+        let plugins = Arc::clone(&self.plugins);
+        let encoding_defaults = self.encoding_defaults;
+        let auto_quality = self.auto_quality;
+        let byte_budget = self.byte_budget;
+        let processing = Arc::clone(&self.processing);
+        let decode_limits = self.config.decode_limits();
+        let cancel = cancel.clone();
 
         // Use custom thread pool instead of tokio's spawn_blocking
         let (tx, rx) = tokio::sync::oneshot::channel();
 
+        processing.fetch_add(1, Ordering::Relaxed);
         cpu_pool.spawn(move || {
-            let result = Self::process_image_blocking(&image_bytes, &params);
+            // A decoder panicking on a malformed image would otherwise abort the process
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                // The request may have been abandoned while queued
+                cancel::check(&cancel)?;
+                let decode_timer = Instant::now();
+                let mut timings = StageTimings::default();
+                let animation = if params.keeps_animation()? {
+                    let format = Self::detect_format_from_bytes(&image_bytes);
+                    frames::decode_animation(&image_bytes, format, decode_limits.clone())?
+                } else {
+                    None
+                };
+                let mut processed = if let Some(animation) = animation {
+                    timings.record("decode", decode_timer.elapsed());
+                    Self::process_animation_blocking(
+                        animation,
+                        &params,
+                        &plugins,
+                        watermark.as_ref(),
+                        &encoding_defaults,
+                        &cancel,
+                        convert_only,
+                    )?
+                } else {
+                    let img = Self::decode(&image_bytes, &params, decode_limits)?;
+                    timings.record("decode", decode_timer.elapsed());
+                    if convert_only {
+                        Self::convert_blocking(img, &params, &encoding_defaults, &auto_quality)?
+                    } else {
+                        Self::process_image_blocking(
+                            img,
+                            &params,
+                            &plugins,
+                            watermark.as_ref(),
+                            &encoding_defaults,
+                            &auto_quality,
+                            &byte_budget,
+                            &cancel,
+                        )?
+                    }
+                };
+                timings.append(processed.timings);
+                processed.timings = timings;
+                Ok(processed)
+            }))
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "Image processing panicked on a malformed image"
+                ))
+            });
+            processing.fetch_sub(1, Ordering::Relaxed);
+            drop(reservation);
+            drop(encode_slot);
             let _ = tx.send(result);
         });
 
         rx.await.context("Image processing task was cancelled")?
     }
 
+    /// Transcode an animated GIF to a video output, leaving the CPU pool to images
+    #[cfg(feature = "video")]
+    async fn transcode(&self, image_bytes: Bytes, params: &ResizeQuery) -> Result<ProcessedImage> {
+        self.processing.fetch_add(1, Ordering::Relaxed);
+        let result = self.transcoder.transcode(image_bytes, params).await;
+        self.processing.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    #[cfg(not(feature = "video"))]
+    async fn transcode(&self, _image_bytes: Bytes, params: &ResizeQuery) -> Result<ProcessedImage> {
+        bail!(
+            "{} output requires the video feature",
+            params.output_format()
+        )
+    }
+
+    /// Decode the source, or the frame of it requested by `params`, within `limits`
+    ///
+    /// Decoders enforce the limits while allocating, so sources whose headers understate
+    /// their size are still rejected.
+    fn decode(image_bytes: &[u8], params: &ResizeQuery, limits: Limits) -> Result<DynamicImage> {
+        let format = Self::detect_format_from_bytes(image_bytes);
+        if let Some(selector) = params.frame_selector()? {
+            return frames::decode_frame(image_bytes, format, selector, limits);
+        }
+
+        // Use faster image decoding with format hints
+        let mut reader = match format {
+            Some(format) => ImageReader::with_format(Cursor::new(image_bytes), format),
+            None => ImageReader::new(Cursor::new(image_bytes))
+                .with_guessed_format()
+                .context("Failed to read image")?,
+        };
+        reader.limits(limits);
+        reader.decode().context("Failed to decode image")
+    }
+
     /// CPU-intensive image processing with optimizations
+    #[allow(clippy::too_many_arguments)]
     fn process_image_blocking(
-        image_bytes: &[u8],
+        img: DynamicImage,
         params: &ResizeQuery,
-    ) -> Result<(Vec<u8>, String)> {
-        // Use faster image decoding with format hints
-        let img = if let Some(format) = Self::detect_format_from_bytes(image_bytes) {
-            image::load_from_memory_with_format(image_bytes, format)
-                .context("Failed to decode image with format hint")?
+        plugins: &PluginRegistry,
+        watermark: Option<&Arc<DynamicImage>>,
+        encoding_defaults: &EncodingDefaults,
+        auto_quality: &AutoQuality,
+        byte_budget: &ByteBudget,
+        cancel: &CancellationToken,
+    ) -> Result<ProcessedImage> {
+        cancel::check(cancel)?;
+        let transform_timer = Instant::now();
+        let (img, filter) =
+            Self::transform_blocking(img, params, plugins, watermark, encoding_defaults, cancel)?;
+
+        // Optimize encoding based on format
+        let (output_format, content_type) = Self::encoder_format(params)?;
+
+        let mut timings = StageTimings::default();
+        timings.record("transform", transform_timer.elapsed());
+        let encode_timer = Instant::now();
+
+        // Search the JPEG quality against the perceptual budget; lossless formats have none
+        let encoding = encoding_defaults.encoding(params).for_image(&img);
+        let (data, quality) = if params.auto_quality() && output_format == ImageFormat::Jpeg {
+            let (data, quality) = auto_quality.encode_jpeg(&img, params.density)?;
+            (data, Some(quality))
         } else {
-            image::load_from_memory(image_bytes).context("Failed to decode image")?
+            // Pre-allocate buffer based on estimated size
+            let estimated_size = Self::estimate_output_size(&img, &output_format);
+            let mut output_bytes = Cursor::new(Vec::with_capacity(estimated_size));
+            encode(&img, output_format, encoding, &mut output_bytes)?;
+            (output_bytes.into_inner(), None)
         };
 
-        // Use faster resize algorithms for different scenarios
-        let filter = match (params.width, params.height) {
-            // For thumbnails, use faster Triangle filter
-            (Some(w), Some(h)) if w <= 300 && h <= 300 => FilterType::Triangle,
-            // For high quality, use Lanczos3
-            _ => FilterType::Lanczos3,
+        // Trade quality, then dimensions, for size when the output is over budget
+        let (width, height) = img.dimensions();
+        let (data, quality, width, height) = match params.max_bytes {
+            Some(max_bytes) if data.len() > max_bytes as usize => {
+                cancel::check(cancel)?;
+                let encoding = Encoding {
+                    quality: quality.or(encoding.quality),
+                    ..encoding
+                };
+                let fitted = byte_budget.fit(&img, output_format, encoding, max_bytes as usize)?;
+                (fitted.data, fitted.quality, fitted.width, fitted.height)
+            }
+            _ => (data, quality, width, height),
+        };
+        timings.record("encode", encode_timer.elapsed());
+        let encoder = Encoding {
+            quality: quality.or(encoding.quality),
+            ..encoding
+        }
+        .describe(output_format);
+
+        Ok(ProcessedImage {
+            data: data.into(),
+            content_type: content_type.to_string(),
+            width,
+            height,
+            quality,
+            timings,
+            filter,
+            encoder: Some(encoder),
+        })
+    }
+
+    /// Resize every frame of an animation like a still image and encode them as an
+    /// animated GIF or WebP, or only re-encode them when `convert_only`
+    fn process_animation_blocking(
+        animation: Vec<Frame>,
+        params: &ResizeQuery,
+        plugins: &PluginRegistry,
+        watermark: Option<&Arc<DynamicImage>>,
+        encoding_defaults: &EncodingDefaults,
+        cancel: &CancellationToken,
+        convert_only: bool,
+    ) -> Result<ProcessedImage> {
+        let (output_format, content_type) = Self::encoder_format(params)?;
+        let transform_timer = Instant::now();
+        let frame_count = animation.len();
+        let mut transformed = Vec::with_capacity(frame_count);
+        let mut filter = None;
+        for frame in animation {
+            cancel::check(cancel)?;
+            let delay = frame.delay();
+            let img = DynamicImage::ImageRgba8(frame.into_buffer());
+            let img = if convert_only {
+                img
+            } else {
+                let (img, frame_filter) = Self::transform_blocking(
+                    img,
+                    params,
+                    plugins,
+                    watermark,
+                    encoding_defaults,
+                    cancel,
+                )?;
+                filter = frame_filter;
+                img
+            };
+            transformed.push(Frame::from_parts(img.into_rgba8(), 0, 0, delay));
+        }
+        let (width, height) = transformed[0].buffer().dimensions();
+
+        let mut timings = StageTimings::default();
+        timings.record("transform", transform_timer.elapsed());
+        let encode_timer = Instant::now();
+
+        let mut data = Vec::new();
+        encode_animation(transformed, output_format, &mut data)?;
+        // Dropping frames or colors would change the animation, so it is rejected instead
+        if let Some(max_bytes) = params.max_bytes
+            && data.len() > max_bytes as usize
+        {
+            bail!("Animated output does not fit in {} bytes", max_bytes);
+        }
+        timings.record("encode", encode_timer.elapsed());
+
+        Ok(ProcessedImage {
+            data: data.into(),
+            content_type: content_type.to_string(),
+            width,
+            height,
+            quality: None,
+            timings,
+            filter,
+            encoder: Some(format!(
+                "{} frames={}",
+                Encoding::default().describe(output_format),
+                frame_count
+            )),
+        })
+    }
+
+    /// Crop, resize and filter `img` as requested by `params`, along with the name of the
+    /// interpolation when it was resized
+    fn transform_blocking(
+        img: DynamicImage,
+        params: &ResizeQuery,
+        plugins: &PluginRegistry,
+        watermark: Option<&Arc<DynamicImage>>,
+        encoding_defaults: &EncodingDefaults,
+        cancel: &CancellationToken,
+    ) -> Result<(DynamicImage, Option<&'static str>)> {
+        let (mut width, mut height) = Self::target_size(params, img.dimensions())?;
+        let (focus_x, focus_y) = params.focal_point()?.unwrap_or((0.5, 0.5));
+
+        // Zooming in crops the source around its focal point, so the fit frames the subject tighter
+        let img = match params.zoom {
+            Some(zoom) if zoom > 1.0 => {
+                let (source_width, source_height) = img.dimensions();
+                if width.is_none() && height.is_none() {
+                    (width, height) = (Some(source_width), Some(source_height));
+                }
+                let crop_width = ((source_width as f32 / zoom).round() as u32).max(1);
+                let crop_height = ((source_height as f32 / zoom).round() as u32).max(1);
+                img.crop_imm(
+                    focus_offset(source_width, crop_width, focus_x),
+                    focus_offset(source_height, crop_height, focus_y),
+                    crop_width,
+                    crop_height,
+                )
+            }
+            _ => img,
+        };
+
+        // Denoise at full resolution, where the speckles can still be told apart from detail
+        let img = match params.denoise {
+            Some(strength) => denoise(img, strength),
+            None => img,
+        };
+
+        // Key out the background at full resolution too, for smoother edges once resized.
+        // Matted sources were already cut out by the matting service.
+        let img = match params.background_removal()? {
+            Some(BackgroundRemoval::Border { tolerance }) => RemoveBackground {
+                color: None,
+                tolerance,
+                from_border: true,
+            }
+            .apply(img)?,
+            Some(BackgroundRemoval::Chroma { color, tolerance }) => RemoveBackground {
+                color: Some(color),
+                tolerance,
+                from_border: false,
+            }
+            .apply(img)?,
+            Some(BackgroundRemoval::Matting) | None => img,
+        };
+
+        // Pick the interpolation by direction, the request overriding the pipeline defaults
+        let (source_width, source_height) = img.dimensions();
+        let upscale = match (width, height) {
+            (Some(w), None) => w > source_width,
+            (None, Some(h)) => h > source_height,
+            (Some(w), Some(h)) => w > source_width || h > source_height,
+            (None, None) => false,
+        };
+        let filter = if upscale {
+            // Lanczos rings around enlarged edges, the Mitchell cubic stays smooth
+            params
+                .filter_up()?
+                .or(encoding_defaults.resize_filter.map(Interpolation::from))
+                .unwrap_or(Interpolation::Mitchell)
+        } else {
+            match (
+                params.filter_down()?,
+                encoding_defaults.resize_filter,
+                width,
+                height,
+            ) {
+                (Some(filter), _, _, _) => filter,
+                (None, Some(filter), _, _) => filter.into(),
+                // For thumbnails, use faster Triangle filter
+                (None, None, Some(w), Some(h)) if w <= 300 && h <= 300 => Interpolation::Triangle,
+                // For high quality, use Lanczos3
+                _ => Interpolation::Lanczos3,
+            }
         };
 
         // Resize image with optimized logic
-        let img = match (params.width, params.height) {
-            (Some(w), None) => img.resize(w, u32::MAX, filter),
-            (None, Some(h)) => img.resize(u32::MAX, h, filter),
+        let resized = width.is_some() || height.is_some();
+        let img = match (width, height) {
+            (Some(w), None) => resample::resize(&img, w, u32::MAX, filter),
+            (None, Some(h)) => resample::resize(&img, u32::MAX, h, filter),
             (Some(w), Some(h)) => {
                 // Optimize resize-to-fill + crop operation
-                let img = img.resize_to_fill(w, h, filter);
+                let img = match params.fit()? {
+                    #[cfg(feature = "seam_carving")]
+                    Fit::Liquid => seams::carve_to_fill(&img, w, h, filter),
+                    _ => resample::resize_to_fill(&img, w, h, filter),
+                };
                 let (current_width, current_height) = img.dimensions();
 
                 if current_width == w && current_height == h {
                     img // No cropping needed
                 } else {
-                    let crop_x = (current_width.saturating_sub(w)) / 2;
-                    let crop_y = (current_height.saturating_sub(h)) / 2;
+                    let crop_x = focus_offset(current_width, w, focus_x);
+                    let crop_y = focus_offset(current_height, h, focus_y);
                     img.crop_imm(crop_x, crop_y, w.min(current_width), h.min(current_height))
                 }
             }
             (None, None) => img,
         };
 
+        // Large reductions look soft, sharpen them by how much they shrank. Blurred
+        // outputs are meant to be soft.
+        let img = match params.auto_sharpen {
+            Some(true) if params.blur_sigma.is_none_or(|sigma| sigma <= 0.0) => {
+                let reduction = (source_width as f32 / img.width() as f32)
+                    .min(source_height as f32 / img.height() as f32);
+                match Sharpen::for_reduction(reduction) {
+                    Some(sharpen) => sharpen.apply(img)?,
+                    None => img,
+                }
+            }
+            _ => img,
+        };
+
+        cancel::check(cancel)?;
+
+        // Zooming out shrinks the fitted image onto a matte of the same size
+        let img = match params.zoom {
+            Some(zoom) if zoom < 1.0 => Self::add_matte(img, zoom, &params.output_format(), filter),
+            _ => img,
+        };
+
         // Apply filters efficiently
         let img = if let Some(true) = params.grayscale {
             img.grayscale()
@@ -182,25 +1017,171 @@ impl ImageService {
             img
         };
 
-        // Optimize encoding based on format
-        let (output_format, content_type) = match params.format {
+        // Run the requested post-processing steps
+        let img = Pipeline::from_query(params)?.apply(img)?;
+
+        // Run the requested transform plugins
+        let img = plugins.apply(img, params)?;
+
+        // The watermark goes on top of everything else
+        let img = match watermark {
+            Some(watermark) => Watermark {
+                image: Arc::clone(watermark),
+            }
+            .apply(img)?,
+            None => img,
+        };
+
+        // Reduce the palette last, once every color of the output is known
+        let img = match params.colors {
+            Some(colors) => Quantize {
+                colors,
+                dither: params.dither()?,
+            }
+            .apply(img)?,
+            None => img,
+        };
+        cancel::check(cancel)?;
+
+        Ok((img, resized.then(|| filter.name())))
+    }
+
+    /// Encode an image in the output format of `params`, as it is
+    fn convert_blocking(
+        img: DynamicImage,
+        params: &ResizeQuery,
+        encoding_defaults: &EncodingDefaults,
+        auto_quality: &AutoQuality,
+    ) -> Result<ProcessedImage> {
+        let (output_format, content_type) = Self::encoder_format(params)?;
+        let encode_timer = Instant::now();
+
+        let encoding = encoding_defaults.encoding(params).for_image(&img);
+        let (data, quality) = if params.auto_quality() && output_format == ImageFormat::Jpeg {
+            let (data, quality) = auto_quality.encode_jpeg(&img, params.density)?;
+            (data, Some(quality))
+        } else {
+            let estimated_size = Self::estimate_output_size(&img, &output_format);
+            let mut output_bytes = Cursor::new(Vec::with_capacity(estimated_size));
+            encode(&img, output_format, encoding, &mut output_bytes)?;
+            (output_bytes.into_inner(), None)
+        };
+
+        let mut timings = StageTimings::default();
+        timings.record("encode", encode_timer.elapsed());
+        let (width, height) = img.dimensions();
+        let encoder = Encoding {
+            quality: quality.or(encoding.quality),
+            ..encoding
+        }
+        .describe(output_format);
+
+        Ok(ProcessedImage {
+            data: data.into(),
+            content_type: content_type.to_string(),
+            width,
+            height,
+            quality,
+            timings,
+            filter: None,
+            encoder: Some(encoder),
+        })
+    }
+
+    /// Image format and content type of the output of `params`
+    fn encoder_format(params: &ResizeQuery) -> Result<(ImageFormat, &'static str)> {
+        Ok(match params.output_format() {
             gen_server::models::ImageFormat::Jpg => (ImageFormat::Jpeg, "image/jpeg"),
             gen_server::models::ImageFormat::Png => (ImageFormat::Png, "image/png"),
             gen_server::models::ImageFormat::Webp => (ImageFormat::WebP, "image/webp"),
-        };
+            gen_server::models::ImageFormat::Avif => (ImageFormat::Avif, "image/avif"),
+            gen_server::models::ImageFormat::Gif => (ImageFormat::Gif, "image/gif"),
+            gen_server::models::ImageFormat::Mp4 | gen_server::models::ImageFormat::Webm => {
+                bail!("{} is a video format", params.output_format())
+            }
+        })
+    }
+
+    /// Scale an image down by `zoom` and center it on a canvas of its original size
+    fn add_matte(
+        img: DynamicImage,
+        zoom: f32,
+        format: &gen_server::models::ImageFormat,
+        filter: Interpolation,
+    ) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let inner = resample::resize_exact(
+            &img,
+            ((width as f32 * zoom).round() as u32).max(1),
+            ((height as f32 * zoom).round() as u32).max(1),
+            filter,
+        );
 
-        // Pre-allocate buffer based on estimated size
-        let estimated_size = Self::estimate_output_size(&img, &output_format);
-        let mut output_bytes = Cursor::new(Vec::with_capacity(estimated_size));
+        let mut canvas = RgbaImage::from_pixel(width, height, ops::background(format));
+        imageops::overlay(
+            &mut canvas,
+            &inner.to_rgba8(),
+            ((width - inner.width()) / 2) as i64,
+            ((height - inner.height()) / 2) as i64,
+        );
+        DynamicImage::ImageRgba8(canvas)
+    }
+
+    /// Output dimensions set by the query alone, `None` for those depending on the source
+    ///
+    /// Transform plugins may still change them.
+    pub fn predicted_size(params: &ResizeQuery) -> Result<(Option<u32>, Option<u32>)> {
+        if params.width.is_none() && params.height.is_none() {
+            return Ok((None, None));
+        }
+        // The source dimensions only matter when neither dimension is requested
+        let (width, height) = Self::target_size(params, (1, 1))?;
+
+        let Some([top, right, bottom, left]) = params.padding()? else {
+            return Ok((width, height));
+        };
+        Ok((
+            width.map(|w| w + left.resolve(w) + right.resolve(w)),
+            height.map(|h| h + top.resolve(h) + bottom.resolve(h)),
+        ))
+    }
 
-        img.write_to(&mut output_bytes, output_format)
-            .context(format!("Failed to encode image to {:?}", output_format))?;
+    /// Requested output dimensions, deriving the missing one from the aspect ratio
+    ///
+    /// Without any dimension, the source is cropped to the ratio at its own resolution.
+    fn target_size(
+        params: &ResizeQuery,
+        (source_width, source_height): (u32, u32),
+    ) -> Result<(Option<u32>, Option<u32>)> {
+        let Some(ratio) = params.aspect_ratio()? else {
+            return Ok((params.width, params.height));
+        };
+        let derive = |size: f64| -> Result<u32> {
+            let size = (size.round() as u32).max(1);
+            if size > MAX_DERIVED_SIZE {
+                bail!("Aspect ratio {:?} gives a dimension of {}", params.ar, size);
+            }
+            Ok(size)
+        };
 
-        Ok((output_bytes.into_inner(), content_type.to_string()))
+        Ok(match (params.width, params.height) {
+            (Some(w), None) => (Some(w), Some(derive(w as f64 / ratio)?)),
+            (None, Some(h)) => (Some(derive(h as f64 * ratio)?), Some(h)),
+            (None, None) if (source_width as f64 / source_height as f64) > ratio => (
+                Some((source_height as f64 * ratio).round().max(1.0) as u32),
+                Some(source_height),
+            ),
+            (None, None) => (
+                Some(source_width),
+                Some((source_width as f64 / ratio).round().max(1.0) as u32),
+            ),
+            // Both dimensions already define the ratio
+            both => both,
+        })
     }
 
     /// Detect image format from magic bytes for faster decoding
-    fn detect_format_from_bytes(bytes: &[u8]) -> Option<ImageFormat> {
+    pub fn detect_format_from_bytes(bytes: &[u8]) -> Option<ImageFormat> {
         if bytes.len() < 12 {
             return None;
         }
@@ -208,6 +1189,7 @@ impl ImageService {
         match &bytes[0..4] {
             [0xFF, 0xD8, 0xFF, _] => Some(ImageFormat::Jpeg),
             [0x89, 0x50, 0x4E, 0x47] => Some(ImageFormat::Png),
+            [0x47, 0x49, 0x46, 0x38] => Some(ImageFormat::Gif),
             _ => {
                 // Check for WebP
                 if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
@@ -222,7 +1204,7 @@ impl ImageService {
     /// Estimate output buffer size to reduce allocations
     fn estimate_output_size(img: &image::DynamicImage, format: &ImageFormat) -> usize {
         let (width, height) = img.dimensions();
-        let pixels = (width * height) as usize;
+        let pixels = width as usize * height as usize;
 
         match format {
             ImageFormat::Jpeg => pixels / 2, // Rough estimate for JPEG compression
@@ -238,3 +1220,11 @@ impl Default for ImageService {
         Self::new().expect("Failed to create default ImageService")
     }
 }
+
+/// Offset of a `window` within `length` pixels centered on `focus`, a fraction of `length`,
+/// moved back inside the image near its edges
+fn focus_offset(length: u32, window: u32, focus: f32) -> u32 {
+    let max_offset = length.saturating_sub(window);
+    let offset = (focus * length as f32 - window as f32 / 2.0).max(0.0) as u32;
+    offset.min(max_offset)
+}
@@ -0,0 +1,90 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The matting service failed to cut out the subject of a source
+#[derive(Debug, Error)]
+#[error("Background matting failed: {0}")]
+pub struct MattingFailed(pub String);
+
+/// Cuts out the subject of an image, answering with it on a transparent background
+#[async_trait]
+pub trait MattingModel: Send + Sync {
+    async fn matte(&self, content_type: &str, data: Bytes) -> Result<Bytes>;
+}
+
+/// External matting service receiving the source as a `POST` body and answering with the
+/// cut-out as a PNG or WebP image with an alpha channel
+pub struct HttpMatting {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpMatting {
+    pub fn new(url: String, timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            url,
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl MattingModel for HttpMatting {
+    async fn matte(&self, content_type: &str, data: Bytes) -> Result<Bytes> {
+        self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+            .context("Failed to read the answer of the matting service")
+    }
+}
+
+/// Background removal by a matting model, for `remove_bg=matting`
+///
+/// The model runs on the source before it is resized, and its cut-out is then processed
+/// like any other source.
+#[derive(Clone)]
+pub struct BackgroundMatting {
+    model: Arc<dyn MattingModel>,
+}
+
+impl BackgroundMatting {
+    pub fn new(model: Arc<dyn MattingModel>) -> Self {
+        Self { model }
+    }
+
+    /// The matting configured by the environment, if a service is set
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let Some(url) = config.matting_url.clone().filter(|url| !url.is_empty()) else {
+            return Ok(None);
+        };
+        let model = HttpMatting::new(url, Duration::from_millis(config.matting_timeout_ms))?;
+        Ok(Some(Self::new(Arc::new(model))))
+    }
+
+    /// Cut-out of the subject of `source`
+    pub async fn remove_background(&self, source: Bytes) -> Result<Bytes> {
+        let content_type = image::guess_format(&source)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("application/octet-stream");
+        let cutout = self
+            .model
+            .matte(content_type, source)
+            .await
+            .map_err(|e| MattingFailed(e.to_string()))?;
+        match image::guess_format(&cutout) {
+            Ok(image::ImageFormat::Png | image::ImageFormat::WebP) => Ok(cutout),
+            _ => Err(MattingFailed("the cut-out isn't a PNG or WebP image".to_string()).into()),
+        }
+    }
+}
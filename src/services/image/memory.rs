@@ -0,0 +1,196 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::env::env::EnvConfig;
+use image::ImageReader;
+use serde::Serialize;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+/// Bytes per decoded pixel, as processed in RGBA8
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// The memory budget can't fit another image, the request should be retried later
+#[derive(Debug, Error)]
+#[error("Memory budget exhausted: {used} of {budget} bytes in use, {requested} requested")]
+pub struct MemoryExhausted {
+    pub used: u64,
+    pub budget: u64,
+    pub requested: u64,
+}
+
+/// Snapshot of the memory accounted to images being processed
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    pub reserved_bytes: u64,
+    /// 0 when unlimited
+    pub budget_bytes: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    reserved: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Global budget for the approximate memory of images being processed
+///
+/// Each image reserves its source, decoded and output sizes before processing. Work that
+/// doesn't fit is rejected instead of growing the process until it is OOM-killed.
+#[derive(Debug, Clone)]
+pub struct MemoryGuard {
+    budget: u64,
+    usage: Arc<Usage>,
+}
+
+impl Default for MemoryGuard {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl MemoryGuard {
+    /// Guard admitting up to `budget` bytes, unlimited when 0
+    pub fn new(budget: u64) -> Self {
+        let usage = Arc::new(Usage::default());
+
+        #[cfg(feature = "otel")]
+        {
+            let usage = Arc::clone(&usage);
+            opentelemetry::global::meter("emgr")
+                .u64_observable_gauge("emgr.memory.reserved")
+                .with_unit("By")
+                .with_description("Approximate memory of the images being processed")
+                .with_callback(move |observer| {
+                    observer.observe(usage.reserved.load(Ordering::Relaxed), &[])
+                })
+                .build();
+        }
+
+        Self { budget, usage }
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self::new(config.memory_budget_mb * BYTES_PER_MB)
+    }
+
+    /// Whether new work should be shed before downloading its source
+    pub fn is_exhausted(&self) -> bool {
+        self.budget > 0 && self.usage.reserved.load(Ordering::Relaxed) >= self.budget
+    }
+
+    /// Fail with [`MemoryExhausted`] when the budget is already used up
+    pub fn check(&self) -> Result<(), MemoryExhausted> {
+        if self.is_exhausted() {
+            self.usage.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(self.exhausted(0));
+        }
+        Ok(())
+    }
+
+    /// Account `bytes` to an image until the reservation is dropped
+    ///
+    /// An image larger than the whole budget is still admitted when nothing else is in
+    /// progress, as it could never run otherwise.
+    pub fn reserve(&self, bytes: u64) -> Result<MemoryReservation, MemoryExhausted> {
+        let admitted =
+            self.usage
+                .reserved
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |reserved| {
+                    let fits = self.budget == 0 || reserved == 0 || reserved + bytes <= self.budget;
+                    fits.then_some(reserved + bytes)
+                });
+        if admitted.is_err() {
+            self.usage.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(self.exhausted(bytes));
+        }
+
+        Ok(MemoryReservation {
+            usage: Arc::clone(&self.usage),
+            bytes,
+        })
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            reserved_bytes: self.usage.reserved.load(Ordering::Relaxed),
+            budget_bytes: self.budget,
+            rejected: self.usage.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    fn exhausted(&self, requested: u64) -> MemoryExhausted {
+        MemoryExhausted {
+            used: self.usage.reserved.load(Ordering::Relaxed),
+            budget: self.budget,
+            requested,
+        }
+    }
+}
+
+/// Memory accounted to an image, released on drop
+#[derive(Debug)]
+pub struct MemoryReservation {
+    usage: Arc<Usage>,
+    bytes: u64,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.usage.reserved.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Approximate peak memory of processing `source` for `params`
+///
+/// Counts the encoded source, its decoded pixels and the resized output, from the
+/// dimensions in the source header. Sources without readable dimensions only count
+/// their bytes, as they fail to decode anyway.
+pub fn estimate(source: &[u8], params: &ResizeQuery) -> u64 {
+    let dimensions = ImageReader::new(Cursor::new(source))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    let Some((width, height)) = dimensions else {
+        return source.len() as u64;
+    };
+
+    let source_pixels = width as u64 * height as u64;
+    let output_pixels = match (params.width, params.height) {
+        (Some(w), Some(h)) => w as u64 * h as u64,
+        (Some(w), None) => w as u64 * w as u64 * height as u64 / width.max(1) as u64,
+        (None, Some(h)) => h as u64 * h as u64 * width as u64 / height.max(1) as u64,
+        (None, None) => source_pixels,
+    };
+    source.len() as u64 + (source_pixels + output_pixels) * BYTES_PER_PIXEL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_reservations_on_drop() {
+        let guard = MemoryGuard::new(100);
+
+        // Admitted alone even though it exceeds the budget
+        let large = guard.reserve(150).unwrap();
+        assert!(guard.is_exhausted());
+        assert!(guard.reserve(1).is_err());
+        assert!(guard.check().is_err());
+        drop(large);
+
+        let first = guard.reserve(60).unwrap();
+        assert!(guard.reserve(60).is_err());
+        let second = guard.reserve(40).unwrap();
+        assert_eq!(guard.stats().reserved_bytes, 100);
+        drop((first, second));
+
+        let stats = guard.stats();
+        assert_eq!(stats.reserved_bytes, 0);
+        assert_eq!(stats.rejected, 3);
+        assert!(MemoryGuard::default().reserve(u64::MAX / 2).is_ok());
+    }
+}
@@ -1 +1,27 @@
+pub mod admission;
+pub mod bandwidth;
+pub mod budget;
+pub mod canary;
+pub mod cancel;
+pub mod complexity;
+pub mod denoise;
+pub mod density;
+#[cfg(feature = "dns_cache")]
+pub mod dns;
+pub mod encode;
+pub mod frames;
 pub mod handler;
+pub mod matting;
+pub mod memory;
+pub mod ops;
+pub mod pipeline;
+pub mod probe;
+pub mod quality;
+pub mod resample;
+#[cfg(feature = "seam_carving")]
+pub mod seams;
+pub mod slots;
+pub mod tls;
+pub mod validators;
+#[cfg(feature = "video")]
+pub mod video;
@@ -0,0 +1,167 @@
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::collections::VecDeque;
+
+/// Make the background of the image transparent, keyed on its color
+///
+/// Pixels within `tolerance` of the key color become transparent, and those within twice
+/// the tolerance fade out, softening the edges of the subject.
+pub struct RemoveBackground {
+    /// Color of the background, the average of the corners when unset
+    pub color: Option<[u8; 3]>,
+    /// Largest difference of a channel still counted as the background
+    pub tolerance: u8,
+    /// Only remove the background connected to the edges, keeping enclosed areas of its color
+    pub from_border: bool,
+}
+
+impl RemoveBackground {
+    /// Average color of the four corners, the background of product shots
+    fn corner_color(rgba: &RgbaImage) -> [u8; 3] {
+        let (width, height) = rgba.dimensions();
+        let corners = [
+            (0, 0),
+            (width - 1, 0),
+            (0, height - 1),
+            (width - 1, height - 1),
+        ];
+        let mut sum = [0u32; 3];
+        for (x, y) in corners {
+            for (sum, channel) in sum.iter_mut().zip(rgba.get_pixel(x, y).0) {
+                *sum += channel as u32;
+            }
+        }
+        sum.map(|sum| (sum / 4) as u8)
+    }
+
+    /// Opacity kept by `pixel`, from 0 for the background to 1 for the subject
+    fn opacity(&self, pixel: &Rgba<u8>, key: [u8; 3]) -> f32 {
+        let distance = pixel.0[..3]
+            .iter()
+            .zip(key)
+            .map(|(channel, key)| channel.abs_diff(key))
+            .max()
+            .unwrap_or_default() as f32;
+        let tolerance = self.tolerance as f32;
+        if distance <= tolerance {
+            0.0
+        } else {
+            ((distance - tolerance) / tolerance.max(1.0)).min(1.0)
+        }
+    }
+}
+
+impl Operation for RemoveBackground {
+    fn name(&self) -> &'static str {
+        "remove_bg"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let mut rgba = image.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        if width == 0 || height == 0 {
+            return Ok(DynamicImage::ImageRgba8(rgba));
+        }
+        let key = self.color.unwrap_or_else(|| Self::corner_color(&rgba));
+        let fade = |pixel: &mut Rgba<u8>, opacity: f32| {
+            pixel.0[3] = (pixel.0[3] as f32 * opacity).round() as u8;
+        };
+
+        if !self.from_border {
+            for pixel in rgba.pixels_mut() {
+                let opacity = self.opacity(pixel, key);
+                fade(pixel, opacity);
+            }
+            return Ok(DynamicImage::ImageRgba8(rgba));
+        }
+
+        // Flood from the edges through the pixels close enough to the key to fade
+        let mut visited = vec![false; width as usize * height as usize];
+        let mut queue = VecDeque::new();
+        let border = (0..width)
+            .flat_map(|x| [(x, 0), (x, height - 1)])
+            .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+        for (x, y) in border {
+            let index = (y * width + x) as usize;
+            if !visited[index] {
+                visited[index] = true;
+                queue.push_back((x, y));
+            }
+        }
+        while let Some((x, y)) = queue.pop_front() {
+            let pixel = rgba.get_pixel_mut(x, y);
+            let opacity = self.opacity(pixel, key);
+            if opacity >= 1.0 {
+                continue;
+            }
+            fade(pixel, opacity);
+
+            let neighbors = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (x, y) in neighbors {
+                if x < width && y < height && !visited[(y * width + x) as usize] {
+                    visited[(y * width + x) as usize] = true;
+                    queue.push_back((x, y));
+                }
+            }
+        }
+        Ok(DynamicImage::ImageRgba8(rgba))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// White image with a black ring around a white center
+    fn product() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(9, 9, |x, y| {
+            let ring = (2..=6).contains(&x)
+                && (2..=6).contains(&y)
+                && (x == 2 || x == 6 || y == 2 || y == 6);
+            if ring {
+                image::Rgb([0, 0, 0])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_removes_the_background_connected_to_the_border() {
+        let removed = RemoveBackground {
+            color: None,
+            tolerance: 32,
+            from_border: true,
+        }
+        .apply(product())
+        .unwrap()
+        .into_rgba8();
+
+        assert_eq!(removed.get_pixel(0, 0).0[3], 0);
+        assert_eq!(removed.get_pixel(2, 4).0[3], 255);
+        // The white inside the ring isn't connected to the border
+        assert_eq!(removed.get_pixel(4, 4).0[3], 255);
+    }
+
+    #[test]
+    fn test_keys_out_a_color_everywhere() {
+        let removed = RemoveBackground {
+            color: Some([255, 255, 255]),
+            tolerance: 32,
+            from_border: false,
+        }
+        .apply(product())
+        .unwrap()
+        .into_rgba8();
+
+        assert_eq!(removed.get_pixel(0, 0).0[3], 0);
+        assert_eq!(removed.get_pixel(4, 4).0[3], 0);
+        assert_eq!(removed.get_pixel(6, 6).0[3], 255);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::models::params::AutoLevel;
+use crate::services::image::ops::edit_rgba;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::{DynamicImage, RgbaImage};
+
+/// Share of the darkest and brightest pixels clipped, so stray specks don't pin the levels
+const CLIP: f64 = 0.005;
+
+/// Stretch the histogram of the image to the full range, brightening dull scans
+///
+/// The levels are read from the histogram with a small share of outliers clipped, then
+/// every channel is remapped through a lookup table.
+pub struct AutoLevels {
+    pub mode: AutoLevel,
+}
+
+impl AutoLevels {
+    /// Darkest and brightest values of `histogram`, past the clipped outliers
+    fn levels(histogram: &[u64; 256]) -> (u8, u8) {
+        let total: u64 = histogram.iter().sum();
+        let clipped = (total as f64 * CLIP) as u64;
+        (
+            Self::bound(histogram, clipped, 0..256),
+            Self::bound(histogram, clipped, (0..256).rev()),
+        )
+    }
+
+    /// First of `values` past the `clipped` pixels of `histogram`
+    fn bound(histogram: &[u64; 256], clipped: u64, mut values: impl Iterator<Item = usize>) -> u8 {
+        let mut seen = 0;
+        values
+            .find(|&value| {
+                seen += histogram[value];
+                seen > clipped
+            })
+            .unwrap_or_default() as u8
+    }
+
+    /// Lookup table stretching `low..=high` to `0..=255`, unchanged for flat images
+    fn stretch((low, high): (u8, u8)) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (value, mapped) in lut.iter_mut().enumerate() {
+            *mapped = if high <= low {
+                value as u8
+            } else {
+                let scaled = (value as f32 - low as f32) * 255.0 / (high - low) as f32;
+                scaled.round().clamp(0.0, 255.0) as u8
+            };
+        }
+        lut
+    }
+
+    /// One table per channel, shared by all three for the luminance
+    fn tables(&self, rgba: &RgbaImage) -> [[u8; 256]; 3] {
+        let mut histograms = [[0u64; 256]; 3];
+        let opaque = rgba.pixels().filter(|pixel| pixel.0[3] > 0);
+        match self.mode {
+            AutoLevel::Luminance => {
+                for pixel in opaque {
+                    let [r, g, b, _] = pixel.0;
+                    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+                    histograms[0][luma as usize] += 1;
+                }
+                [Self::stretch(Self::levels(&histograms[0])); 3]
+            }
+            AutoLevel::Channels => {
+                for pixel in opaque {
+                    for (histogram, &value) in histograms.iter_mut().zip(&pixel.0[..3]) {
+                        histogram[value as usize] += 1;
+                    }
+                }
+                histograms.map(|histogram| Self::stretch(Self::levels(&histogram)))
+            }
+        }
+    }
+}
+
+impl Operation for AutoLevels {
+    fn name(&self) -> &'static str {
+        "auto_level"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        Ok(edit_rgba(image, |rgba| {
+            let tables = self.tables(rgba);
+            for pixel in rgba.pixels_mut() {
+                for (channel, table) in pixel.0[..3].iter_mut().zip(&tables) {
+                    *channel = table[*channel as usize];
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Washed out gradient with a blue cast, from 64 to 191
+    fn dull() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(128, 1, |x, _| {
+            let value = 64 + x as u8;
+            Rgb([value, value, value.saturating_add(40)])
+        }))
+    }
+
+    #[test]
+    fn test_stretches_the_histogram() {
+        let stretched = |mode| {
+            let image = AutoLevels { mode }.apply(dull()).unwrap().into_rgb8();
+            (image.get_pixel(0, 0).0, image.get_pixel(127, 0).0)
+        };
+
+        let (dark, bright) = stretched(AutoLevel::Channels);
+        assert!(dark.iter().all(|&channel| channel <= 2), "{:?}", dark);
+        assert!(bright.iter().all(|&channel| channel >= 253), "{:?}", bright);
+
+        // The luminance keeps the cast, stretching every channel alike
+        let (dark, bright) = stretched(AutoLevel::Luminance);
+        assert!(dark[0] < 64 && dark[2] > dark[0]);
+        assert!(bright[0] > 191);
+    }
+}
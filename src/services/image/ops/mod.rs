@@ -0,0 +1,35 @@
+use gen_server::models::ImageFormat;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+pub mod background;
+pub mod levels;
+pub mod pad;
+pub mod pixelate;
+pub mod quantize;
+pub mod sharpen;
+pub mod tint;
+pub mod tone;
+pub mod vignette;
+pub mod watermark;
+
+/// Color of added canvas areas: transparent, except for JPEG which has no alpha channel
+pub fn background(format: &ImageFormat) -> Rgba<u8> {
+    match format {
+        ImageFormat::Jpg => Rgba([255, 255, 255, 255]),
+        _ => Rgba([0, 0, 0, 0]),
+    }
+}
+
+/// Edit `image` as RGBA, converting it back to RGB when it had no alpha channel
+fn edit_rgba(image: DynamicImage, edit: impl FnOnce(&mut RgbaImage)) -> DynamicImage {
+    let has_alpha = image.color().has_alpha();
+    let mut rgba = image.into_rgba8();
+    edit(&mut rgba);
+
+    let image = DynamicImage::ImageRgba8(rgba);
+    if has_alpha {
+        image
+    } else {
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    }
+}
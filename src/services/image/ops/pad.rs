@@ -0,0 +1,54 @@
+use crate::models::params::Padding;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage, imageops};
+
+/// Extend the canvas around the image
+pub struct Pad {
+    /// Margins as `[top, right, bottom, left]`
+    pub padding: [Padding; 4],
+    pub background: Rgba<u8>,
+}
+
+impl Operation for Pad {
+    fn name(&self) -> &'static str {
+        "pad"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let (width, height) = image.dimensions();
+        let [top, right, bottom, left] = self.padding;
+        let (top, bottom) = (top.resolve(height), bottom.resolve(height));
+        let (right, left) = (right.resolve(width), left.resolve(width));
+
+        let mut canvas =
+            RgbaImage::from_pixel(width + left + right, height + top + bottom, self.background);
+        imageops::overlay(&mut canvas, &image.to_rgba8(), left as i64, top as i64);
+        Ok(DynamicImage::ImageRgba8(canvas))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn pads_each_side() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(100, 50, Rgb([255, 0, 0])));
+        let pad = Pad {
+            padding: [
+                Padding::Pixels(10),
+                Padding::Percent(5.0),
+                Padding::Pixels(0),
+                Padding::Percent(20.0),
+            ],
+            background: Rgba([255, 255, 255, 255]),
+        };
+
+        let output = pad.apply(image).unwrap().into_rgba8();
+        assert_eq!(output.dimensions(), (100 + 5 + 20, 50 + 10));
+        assert_eq!(output.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(output.get_pixel(20, 10), &Rgba([255, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::services::image::pipeline::Operation;
+use anyhow::{Result, bail};
+use image::{DynamicImage, GenericImage, GenericImageView, imageops};
+
+/// Replace blocks of pixels by their average color, e.g. to redact faces or plates
+pub struct Pixelate {
+    /// Side of the blocks, in pixels
+    pub block: u32,
+    /// Area to pixelate as `[x, y, width, height]`, the whole image when unset
+    pub region: Option<[u32; 4]>,
+}
+
+impl Operation for Pixelate {
+    fn name(&self) -> &'static str {
+        "pixelate"
+    }
+
+    fn apply(&self, mut image: DynamicImage) -> Result<DynamicImage> {
+        let (width, height) = image.dimensions();
+        let [x, y, region_width, region_height] = self.region.unwrap_or([0, 0, width, height]);
+        if x.checked_add(region_width)
+            .is_none_or(|right| right > width)
+            || y.checked_add(region_height)
+                .is_none_or(|bottom| bottom > height)
+        {
+            bail!(
+                "Pixelate region {},{},{},{} is outside the {}x{} image",
+                x,
+                y,
+                region_width,
+                region_height,
+                width,
+                height
+            );
+        }
+
+        // Averaging every block is a box downscale; scaling back up with nearest neighbor
+        // turns each averaged pixel into a flat block
+        let area = image.crop_imm(x, y, region_width, region_height);
+        let blocks = area.resize_exact(
+            region_width.div_ceil(self.block),
+            region_height.div_ceil(self.block),
+            imageops::FilterType::Triangle,
+        );
+        let mosaic =
+            blocks.resize_exact(region_width, region_height, imageops::FilterType::Nearest);
+        image.copy_from(&mosaic, x, y)?;
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn pixelates_region_only() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 8) as u8, (y * 8) as u8, 0])
+        }));
+        let pixelate = Pixelate {
+            block: 8,
+            region: Some([8, 8, 16, 16]),
+        };
+
+        let output = pixelate.apply(image.clone()).unwrap().into_rgb8();
+        // Pixels of a block share one color, pixels outside the region are untouched
+        assert_eq!(output.get_pixel(8, 8), output.get_pixel(15, 15));
+        assert_ne!(output.get_pixel(8, 8), output.get_pixel(16, 16));
+        assert_eq!(output.get_pixel(4, 4), image.to_rgb8().get_pixel(4, 4));
+
+        let outside = Pixelate {
+            block: 8,
+            region: Some([24, 24, 16, 16]),
+        };
+        assert!(outside.apply(image).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::models::params::Dither;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use color_quant::NeuQuant;
+use image::DynamicImage;
+
+/// Sampling factor of the palette search, 1 being the slowest and best, 30 the fastest
+const SAMPLE_FACTOR: i32 = 10;
+
+/// 4x4 Bayer matrix of the ordered dithering, in sixteenths
+const BAYER: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+/// Reduce the image to a palette of `colors`, posterizing it
+pub struct Quantize {
+    pub colors: u32,
+    pub dither: Dither,
+}
+
+impl Operation for Quantize {
+    fn name(&self) -> &'static str {
+        "quantize"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let has_alpha = image.color().has_alpha();
+        let mut rgba = image.into_rgba8();
+        let (width, height) = (rgba.width() as usize, rgba.height() as usize);
+        let palette = NeuQuant::new(SAMPLE_FACTOR, self.colors as usize, rgba.as_raw());
+
+        match self.dither {
+            Dither::None => {
+                for pixel in rgba.chunks_exact_mut(4) {
+                    palette.map_pixel(pixel);
+                }
+            }
+            Dither::Ordered => {
+                // Nudge pixels by their threshold so neighbors round to different colors
+                let spread = 255.0 / (self.colors as f32).cbrt();
+                for (index, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+                    let (x, y) = (index % width, index / width);
+                    let offset = (BAYER[y % 4][x % 4] / 16.0 - 0.5) * spread;
+                    for channel in &mut pixel[..3] {
+                        *channel = (*channel as f32 + offset).round().clamp(0.0, 255.0) as u8;
+                    }
+                    palette.map_pixel(pixel);
+                }
+            }
+            Dither::FloydSteinberg => {
+                // Spread the error of each pixel over its unvisited neighbors
+                let mut values: Vec<f32> = rgba.as_raw().iter().map(|&v| v as f32).collect();
+                for y in 0..height {
+                    for x in 0..width {
+                        let at = (y * width + x) * 4;
+                        let mut pixel = [0u8; 4];
+                        for (channel, value) in pixel.iter_mut().zip(&values[at..at + 4]) {
+                            *channel = value.round().clamp(0.0, 255.0) as u8;
+                        }
+                        let original = pixel;
+                        palette.map_pixel(&mut pixel);
+                        rgba.as_mut()[at..at + 4].copy_from_slice(&pixel);
+
+                        let neighbors = [
+                            (x + 1 < width, 1, 0, 7.0),
+                            (x > 0 && y + 1 < height, -1, 1, 3.0),
+                            (y + 1 < height, 0, 1, 5.0),
+                            (x + 1 < width && y + 1 < height, 1, 1, 1.0),
+                        ];
+                        for (inside, dx, dy, weight) in neighbors {
+                            if !inside {
+                                continue;
+                            }
+                            let neighbor =
+                                (((y + dy) * width) as isize + x as isize + dx) as usize * 4;
+                            for channel in 0..4 {
+                                let error = original[channel] as f32 - pixel[channel] as f32;
+                                values[neighbor + channel] += error * weight / 16.0;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let image = DynamicImage::ImageRgba8(rgba);
+        Ok(if has_alpha {
+            image
+        } else {
+            DynamicImage::ImageRgb8(image.into_rgb8())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, 128])
+        }))
+    }
+
+    #[test]
+    fn test_reduces_the_colors_to_the_palette() {
+        for dither in [Dither::None, Dither::Ordered, Dither::FloydSteinberg] {
+            let quantized = Quantize { colors: 8, dither }
+                .apply(gradient())
+                .unwrap()
+                .into_rgb8();
+            let colors: HashSet<[u8; 3]> = quantized.pixels().map(|pixel| pixel.0).collect();
+            assert!(
+                colors.len() <= 8,
+                "{:?} left {} colors",
+                dither,
+                colors.len()
+            );
+            assert!(colors.len() > 1);
+        }
+    }
+}
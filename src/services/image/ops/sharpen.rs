@@ -0,0 +1,78 @@
+use crate::services::image::ops::edit_rgba;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Strength added by each halving of the dimensions
+const AMOUNT_PER_HALVING: f32 = 0.25;
+
+/// Strongest sharpening, reached past a 16x reduction
+const MAX_AMOUNT: f32 = 1.0;
+
+/// Radius of the blur the mask is computed from, in output pixels
+const SIGMA: f32 = 0.6;
+
+/// Differences from the blurred image ignored, so flat areas don't grow grainy
+const THRESHOLD: i32 = 2;
+
+/// Mild unsharp mask restoring the crispness lost by large reductions
+///
+/// The image is compared with a blurred copy and its differences amplified by `amount`.
+pub struct Sharpen {
+    pub amount: f32,
+}
+
+impl Sharpen {
+    /// Sharpening of an image reduced `reduction` times, none for upscales
+    pub fn for_reduction(reduction: f32) -> Option<Self> {
+        let amount = (reduction.log2() * AMOUNT_PER_HALVING).min(MAX_AMOUNT);
+        (amount > 0.0).then_some(Self { amount })
+    }
+}
+
+impl Operation for Sharpen {
+    fn name(&self) -> &'static str {
+        "auto_sharpen"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let blurred = image.blur(SIGMA).into_rgba8();
+        Ok(edit_rgba(image, |rgba| {
+            for (pixel, blurred) in rgba.pixels_mut().zip(blurred.pixels()) {
+                for (channel, &smooth) in pixel.0[..3].iter_mut().zip(&blurred.0[..3]) {
+                    let difference = *channel as i32 - smooth as i32;
+                    if difference.abs() > THRESHOLD {
+                        let sharpened = *channel as f32 + difference as f32 * self.amount;
+                        *channel = sharpened.round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_sharpens_edges_by_the_reduction() {
+        assert!(Sharpen::for_reduction(1.0).is_none());
+        assert!(Sharpen::for_reduction(0.5).is_none());
+        assert_eq!(Sharpen::for_reduction(4.0).unwrap().amount, 0.5);
+        assert_eq!(Sharpen::for_reduction(1000.0).unwrap().amount, MAX_AMOUNT);
+
+        // A soft edge from 100 to 150 grows steeper, flat areas stay as they are
+        let edge = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 4, |x, _| match x {
+            0..8 => Rgb([100, 100, 100]),
+            8 => Rgb([125, 125, 125]),
+            _ => Rgb([150, 150, 150]),
+        }));
+        let sharpened = Sharpen { amount: 1.0 }.apply(edge).unwrap().into_rgb8();
+        assert!(sharpened.get_pixel(7, 0).0[0] < 100);
+        assert!(sharpened.get_pixel(9, 0).0[0] > 150);
+        assert_eq!(sharpened.get_pixel(2, 0).0[0], 100);
+        assert_eq!(sharpened.get_pixel(14, 0).0[0], 150);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::services::image::ops::edit_rgba;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Overlay a flat color on the image
+pub struct Tint {
+    pub color: [u8; 3],
+    /// Opacity of the overlay, from 0 (invisible) to 1 (flat color)
+    pub opacity: f32,
+}
+
+impl Operation for Tint {
+    fn name(&self) -> &'static str {
+        "tint"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        Ok(edit_rgba(image, |rgba| {
+            for pixel in rgba.pixels_mut() {
+                for (channel, tint) in pixel.0[..3].iter_mut().zip(self.color) {
+                    *channel = (*channel as f32 * (1.0 - self.opacity) + tint as f32 * self.opacity)
+                        .round() as u8;
+                }
+            }
+        }))
+    }
+}
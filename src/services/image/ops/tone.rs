@@ -0,0 +1,81 @@
+use crate::services::image::ops::edit_rgba;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Exposure and gamma adjustments, computed in linear light
+///
+/// Scaling sRGB values directly brightens shadows and highlights unevenly and shifts
+/// hues, so channels are decoded to linear light, adjusted, and encoded back.
+pub struct Tone {
+    /// Exposure in stops, each one doubling the light
+    pub exposure: f32,
+    /// Gamma, above 1 to brighten the midtones
+    pub gamma: f32,
+}
+
+impl Operation for Tone {
+    fn name(&self) -> &'static str {
+        "tone"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let gain = self.exposure.exp2();
+        let lut: Vec<u8> = (0..=255u8)
+            .map(|value| {
+                let linear = (srgb_to_linear(value as f32 / 255.0) * gain).clamp(0.0, 1.0);
+                let adjusted = linear.powf(1.0 / self.gamma);
+                (linear_to_srgb(adjusted) * 255.0).round() as u8
+            })
+            .collect();
+
+        Ok(edit_rgba(image, |rgba| {
+            for pixel in rgba.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = lut[*channel as usize];
+                }
+            }
+        }))
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn adjusts_in_linear_light() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([0, 118, 255])));
+        let tone = |exposure, gamma| {
+            Tone { exposure, gamma }
+                .apply(image.clone())
+                .unwrap()
+                .into_rgb8()
+                .get_pixel(0, 0)
+                .0
+        };
+
+        assert_eq!(tone(0.0, 1.0), [0, 118, 255]);
+        // sRGB 118 is 18% grey; one stop doubles it to 36%, i.e. sRGB 162
+        assert_eq!(tone(1.0, 1.0), [0, 162, 255]);
+        assert_eq!(tone(-1.0, 1.0)[1], 85);
+        assert!(tone(0.0, 2.0)[1] > 118);
+    }
+}
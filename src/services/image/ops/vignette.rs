@@ -0,0 +1,38 @@
+use crate::services::image::ops::edit_rgba;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Distance from the center, relative to the corners, where the darkening starts
+const INNER_RADIUS: f32 = 0.4;
+
+/// Darken the image towards its corners
+pub struct Vignette {
+    /// Darkening in the corners, from 0 (none) to 1 (black)
+    pub strength: f32,
+}
+
+impl Operation for Vignette {
+    fn name(&self) -> &'static str {
+        "vignette"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        Ok(edit_rgba(image, |rgba| {
+            let (width, height) = rgba.dimensions();
+            let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+            let corner = (center_x * center_x + center_y * center_y).sqrt().max(1.0);
+
+            for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+                let (dx, dy) = (x as f32 + 0.5 - center_x, y as f32 + 0.5 - center_y);
+                let distance = (dx * dx + dy * dy).sqrt() / corner;
+                // Smoothstep from the inner radius to the corners
+                let t = ((distance - INNER_RADIUS) / (1.0 - INNER_RADIUS)).clamp(0.0, 1.0);
+                let factor = 1.0 - self.strength * t * t * (3.0 - 2.0 * t);
+                for channel in &mut pixel.0[..3] {
+                    *channel = (*channel as f32 * factor).round() as u8;
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,72 @@
+use crate::services::image::ops::edit_rgba;
+use crate::services::image::pipeline::Operation;
+use anyhow::Result;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView};
+use std::sync::Arc;
+
+/// Largest share of the image width and height covered by the watermark
+const MAX_SHARE: f32 = 0.25;
+/// Margin between the watermark and the corner, as a share of the shorter side of the image
+const MARGIN_SHARE: f32 = 0.02;
+
+/// Overlay a watermark in the bottom right corner, scaled down to fit
+pub struct Watermark {
+    pub image: Arc<DynamicImage>,
+}
+
+impl Operation for Watermark {
+    fn name(&self) -> &'static str {
+        "watermark"
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let (width, height) = image.dimensions();
+        let (mark_width, mark_height) = self.image.dimensions();
+        let scale = (width as f32 * MAX_SHARE / mark_width as f32)
+            .min(height as f32 * MAX_SHARE / mark_height as f32)
+            .min(1.0);
+        let mark = if scale < 1.0 {
+            let mark_width = ((mark_width as f32 * scale).round() as u32).max(1);
+            let mark_height = ((mark_height as f32 * scale).round() as u32).max(1);
+            self.image
+                .resize_exact(mark_width, mark_height, FilterType::Triangle)
+                .into_rgba8()
+        } else {
+            self.image.to_rgba8()
+        };
+
+        let margin = (width.min(height) as f32 * MARGIN_SHARE).round() as i64;
+        let x = width as i64 - mark.width() as i64 - margin;
+        let y = height as i64 - mark.height() as i64 - margin;
+        Ok(edit_rgba(image, |rgba| {
+            imageops::overlay(rgba, &mark, x, y)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    #[test]
+    fn overlays_the_bottom_right_corner() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(200, 100, Rgb([255, 255, 255])));
+        let watermark = Watermark {
+            image: Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                100,
+                100,
+                Rgba([255, 0, 0, 255]),
+            ))),
+        };
+
+        let marked = watermark.apply(image).unwrap().into_rgb8();
+        assert_eq!(marked.dimensions(), (200, 100));
+        // Scaled down to a quarter of the height, 2 pixels from the corner
+        assert_eq!(marked.get_pixel(197, 97), &Rgb([255, 0, 0]));
+        assert_eq!(marked.get_pixel(173, 73), &Rgb([255, 0, 0]));
+        assert_eq!(marked.get_pixel(172, 72), &Rgb([255, 255, 255]));
+        assert_eq!(marked.get_pixel(198, 98), &Rgb([255, 255, 255]));
+    }
+}
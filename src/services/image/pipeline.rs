@@ -0,0 +1,111 @@
+use crate::models::params::ResizeQuery;
+use crate::services::image::ops;
+use crate::services::image::ops::levels::AutoLevels;
+use crate::services::image::ops::pad::Pad;
+use crate::services::image::ops::pixelate::Pixelate;
+use crate::services::image::ops::tint::Tint;
+use crate::services::image::ops::tone::Tone;
+use crate::services::image::ops::vignette::Vignette;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// A post-processing step run on the resized image
+pub trait Operation: Send + Sync {
+    /// Name of the step, for logs and errors
+    fn name(&self) -> &'static str;
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage>;
+}
+
+/// Post-processing steps requested by a query, run in order after resizing
+#[derive(Default)]
+pub struct Pipeline {
+    operations: Vec<Box<dyn Operation>>,
+}
+
+impl Pipeline {
+    /// Steps requested by `params`
+    pub fn from_query(params: &ResizeQuery) -> Result<Self> {
+        let mut pipeline = Self::default();
+        // Redaction runs first, on the resized pixels its region refers to
+        if let Some(block) = params.pixelate
+            && block > 1
+        {
+            pipeline = pipeline.then(Pixelate {
+                block,
+                region: params.pixelate_region()?,
+            });
+        }
+        // Levels are set before the tone adjustments, which then start from the full range
+        if let Some(mode) = params.auto_level()? {
+            pipeline = pipeline.then(AutoLevels { mode });
+        }
+        if params.exposure.is_some() || params.gamma.is_some() {
+            pipeline = pipeline.then(Tone {
+                exposure: params.exposure.unwrap_or(0.0),
+                gamma: params.gamma.unwrap_or(1.0),
+            });
+        }
+        if let Some(strength) = params.vignette
+            && strength > 0.0
+        {
+            pipeline = pipeline.then(Vignette { strength });
+        }
+        if let Some((color, opacity)) = params.tint()? {
+            pipeline = pipeline.then(Tint { color, opacity });
+        }
+        // Margins come last so that they keep a uniform background
+        if let Some(padding) = params.padding()? {
+            pipeline = pipeline.then(Pad {
+                padding,
+                background: ops::background(&params.output_format()),
+            });
+        }
+        Ok(pipeline)
+    }
+
+    /// Append a step
+    pub fn then(mut self, operation: impl Operation + 'static) -> Self {
+        self.operations.push(Box::new(operation));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Run all steps on `image`
+    pub fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        self.operations
+            .iter()
+            .try_fold(image, |image, operation| operation.apply(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_requested_steps_in_order() {
+        let params = ResizeQuery {
+            vignette: Some(0.5),
+            tint: Some("#ff8800".to_string()),
+            ..ResizeQuery::default()
+        };
+        let pipeline = Pipeline::from_query(&params).unwrap();
+        let names: Vec<_> = pipeline.operations.iter().map(|op| op.name()).collect();
+        assert_eq!(names, ["vignette", "tint"]);
+
+        assert!(
+            Pipeline::from_query(&ResizeQuery::default())
+                .unwrap()
+                .is_empty()
+        );
+        let invalid = ResizeQuery {
+            tint: Some("orange".to_string()),
+            ..ResizeQuery::default()
+        };
+        assert!(Pipeline::from_query(&invalid).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Result, bail};
+use image::{ImageFormat, ImageReader};
+use std::io::Cursor;
+use thiserror::Error;
+
+/// A source is larger than accepted, in bytes or pixels
+#[derive(Debug, Error)]
+pub enum SourceTooLarge {
+    #[error("Image too large: {size} bytes (max: {max} bytes)")]
+    Bytes { size: u64, max: u64 },
+    #[error("Image too large: {width}x{height} pixels (max: {max} pixels)")]
+    Pixels { width: u32, height: u32, max: u64 },
+}
+
+/// Range request reading the header of sources before their full download
+///
+/// Sources in an unsupported format, or too large in bytes or pixels, are rejected
+/// after a few kilobytes instead of being downloaded in full.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceProbe {
+    /// Leading bytes fetched, 0 to download sources without probing
+    pub bytes: usize,
+    /// Largest accepted source, in pixels
+    pub max_pixels: u64,
+}
+
+impl Default for SourceProbe {
+    fn default() -> Self {
+        Self {
+            bytes: 0,
+            max_pixels: 100_000_000,
+        }
+    }
+}
+
+/// What the leading bytes of a source tell about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbedSource {
+    pub format: ImageFormat,
+    /// Unknown when the header doesn't fit in the probed bytes
+    pub dimensions: Option<(u32, u32)>,
+    /// Full size of the source, when announced by the origin
+    pub size: Option<u64>,
+}
+
+impl SourceProbe {
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self {
+            bytes: config.source_probe_kb * 1024,
+            max_pixels: config.source_max_megapixels * 1_000_000,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.bytes > 0
+    }
+
+    /// Check the leading bytes `head` of a source in `format`, `size` bytes long
+    pub fn inspect(
+        &self,
+        head: &[u8],
+        format: ImageFormat,
+        size: Option<u64>,
+        max_size: u64,
+    ) -> Result<ProbedSource> {
+        if let Some(size) = size
+            && size > max_size
+        {
+            bail!(SourceTooLarge::Bytes {
+                size,
+                max: max_size
+            });
+        }
+
+        let dimensions = ImageReader::with_format(Cursor::new(head), format)
+            .into_dimensions()
+            .ok();
+        if let Some((width, height)) = dimensions
+            && width as u64 * height as u64 > self.max_pixels
+        {
+            bail!(SourceTooLarge::Pixels {
+                width,
+                height,
+                max: self.max_pixels
+            });
+        }
+
+        Ok(ProbedSource {
+            format,
+            dimensions,
+            size,
+        })
+    }
+}
+
+/// Full length announced by a `Content-Range: bytes 0-1023/4096` header
+pub fn content_range_total(value: &str) -> Option<u64> {
+    value
+        .strip_prefix("bytes ")?
+        .split_once('/')?
+        .1
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    #[test]
+    fn rejects_oversized_sources_from_their_header() {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(400, 300))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let probe = SourceProbe {
+            bytes: 64,
+            max_pixels: 120_000,
+        };
+
+        let probed = probe
+            .inspect(
+                &png[..64],
+                ImageFormat::Png,
+                Some(png.len() as u64),
+                1 << 20,
+            )
+            .unwrap();
+        assert_eq!(probed.dimensions, Some((400, 300)));
+        assert!(
+            probe
+                .inspect(&png[..64], ImageFormat::Png, Some(2 << 20), 1 << 20)
+                .is_err()
+        );
+
+        let strict = SourceProbe {
+            max_pixels: 120_000 - 1,
+            ..probe
+        };
+        assert!(
+            strict
+                .inspect(&png[..64], ImageFormat::Png, None, 1 << 20)
+                .is_err()
+        );
+
+        assert_eq!(content_range_total("bytes 0-1023/4096"), Some(4096));
+        assert_eq!(content_range_total("bytes 0-1023/*"), None);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::image::encode::{Encoding, encode};
+use anyhow::{Context, Result};
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat};
+use std::io::Cursor;
+
+/// Longest side the images are compared at, keeping each attempt cheap
+const COMPARE_SIZE: u32 = 512;
+/// Side of the square windows SSIM is computed over
+const WINDOW: u32 = 8;
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Bounds of the `quality=auto` search
+#[derive(Debug, Clone, Copy)]
+pub struct AutoQuality {
+    /// Largest DSSIM accepted between the resized image and its encoded output
+    pub max_dssim: f64,
+    pub min_quality: u8,
+    pub max_quality: u8,
+    /// Encodes tried per image, bounding the CPU spent on the search
+    pub max_attempts: u32,
+}
+
+impl Default for AutoQuality {
+    fn default() -> Self {
+        Self {
+            max_dssim: 0.01,
+            min_quality: 40,
+            max_quality: 90,
+            max_attempts: 6,
+        }
+    }
+}
+
+impl AutoQuality {
+    pub fn from_env(config: &EnvConfig) -> Self {
+        let min_quality = config.auto_quality_min.clamp(1, 100);
+        Self {
+            max_dssim: config.auto_quality_max_dssim,
+            min_quality,
+            max_quality: config.auto_quality_max.clamp(min_quality, 100),
+            max_attempts: config.auto_quality_max_attempts.max(1),
+        }
+    }
+
+    /// Encode `img` as JPEG with the lowest quality whose output stays within the budget
+    ///
+    /// Binary searches between the quality bounds, falling back to the maximum quality
+    /// when no attempt meets the budget. Returns the output and the quality used.
+    pub fn encode_jpeg(&self, img: &DynamicImage, density: Option<u32>) -> Result<(Vec<u8>, u8)> {
+        let reference = comparable_luma(img);
+        let (mut low, mut high) = (self.min_quality, self.max_quality);
+        let mut best = None;
+
+        for _ in 0..self.max_attempts {
+            if low > high {
+                break;
+            }
+            let quality = low + (high - low) / 2;
+            let output = encode_at(img, quality, density)?;
+            let decoded = image::load_from_memory_with_format(&output, ImageFormat::Jpeg)
+                .context("Failed to decode JPEG attempt")?;
+
+            if dssim(&reference, &comparable_luma(&decoded)) <= self.max_dssim {
+                best = Some((output, quality));
+                if quality == low {
+                    break;
+                }
+                high = quality - 1;
+            } else {
+                low = quality + 1;
+            }
+        }
+
+        match best {
+            Some(best) => Ok(best),
+            None => Ok((encode_at(img, self.max_quality, density)?, self.max_quality)),
+        }
+    }
+}
+
+fn encode_at(img: &DynamicImage, quality: u8, density: Option<u32>) -> Result<Vec<u8>> {
+    let mut output = Cursor::new(Vec::new());
+    let encoding = Encoding {
+        quality: Some(quality),
+        density,
+        ..Encoding::default()
+    };
+    encode(img, ImageFormat::Jpeg, encoding, &mut output)?;
+    Ok(output.into_inner())
+}
+
+/// Luma of `img`, scaled down so that its longest side is at most `COMPARE_SIZE`
+fn comparable_luma(img: &DynamicImage) -> GrayImage {
+    let (width, height) = img.dimensions();
+    if width.max(height) > COMPARE_SIZE {
+        img.thumbnail(COMPARE_SIZE, COMPARE_SIZE).to_luma8()
+    } else {
+        img.to_luma8()
+    }
+}
+
+/// Structural dissimilarity (`1 / SSIM - 1`) of two images of the same size
+///
+/// SSIM is averaged over non-overlapping windows, which is coarser than the gaussian
+/// windows of the reference implementation but cheap enough to run on every attempt.
+fn dssim(reference: &GrayImage, candidate: &GrayImage) -> f64 {
+    let (width, height) = reference.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for y in (0..height).step_by(WINDOW as usize) {
+        for x in (0..width).step_by(WINDOW as usize) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            let (window_width, window_height) = (WINDOW.min(width - x), WINDOW.min(height - y));
+            for dy in 0..window_height {
+                for dx in 0..window_width {
+                    let a = reference.get_pixel(x + dx, y + dy)[0] as f64;
+                    let b = candidate.get_pixel(x + dx, y + dy)[0] as f64;
+                    sum_a += a;
+                    sum_b += b;
+                    sum_aa += a * a;
+                    sum_bb += b * b;
+                    sum_ab += a * b;
+                }
+            }
+
+            let n = (window_width * window_height) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        return 0.0;
+    }
+    1.0 / (total / windows as f64).max(f64::EPSILON) - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn searches_lowest_quality_within_budget() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(128, 128, |x, y| {
+            Rgb([(x * 2) as u8, (y * 2) as u8, ((x ^ y) * 4) as u8])
+        }));
+        let luma = comparable_luma(&img);
+        assert_eq!(dssim(&luma, &luma), 0.0);
+
+        let loose = AutoQuality {
+            max_dssim: 0.05,
+            ..AutoQuality::default()
+        };
+        let strict = AutoQuality {
+            max_dssim: 0.0001,
+            ..AutoQuality::default()
+        };
+        let (loose_output, loose_quality) = loose.encode_jpeg(&img, None).unwrap();
+        let (strict_output, strict_quality) = strict.encode_jpeg(&img, None).unwrap();
+
+        assert!((40..=90).contains(&loose_quality));
+        assert!(loose_quality <= strict_quality);
+        assert!(loose_output.len() <= strict_output.len());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::models::params::Interpolation;
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, GenericImageView, Rgba32FImage};
+use rayon::prelude::*;
+
+impl From<FilterType> for Interpolation {
+    fn from(filter: FilterType) -> Self {
+        match filter {
+            FilterType::Nearest => Self::Nearest,
+            FilterType::Triangle => Self::Triangle,
+            FilterType::CatmullRom => Self::CatmullRom,
+            FilterType::Gaussian => Self::Gaussian,
+            FilterType::Lanczos3 => Self::Lanczos3,
+        }
+    }
+}
+
+/// Scale `image` to fit within `width` and `height`, keeping its aspect ratio
+pub fn resize(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: Interpolation,
+) -> DynamicImage {
+    if image.dimensions() == (width, height) {
+        return image.clone();
+    }
+    let (width, height) = fitted(image.dimensions(), (width, height), false);
+    resize_exact(image, width, height, filter)
+}
+
+/// Scale `image` to cover `width` and `height`, keeping its aspect ratio and overflow
+pub fn resize_to_cover(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: Interpolation,
+) -> DynamicImage {
+    let (cover_width, cover_height) = fitted(image.dimensions(), (width, height), true);
+    resize_exact(image, cover_width, cover_height, filter)
+}
+
+/// Scale `image` to cover `width` and `height`, cropping the overflow around its center
+pub fn resize_to_fill(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: Interpolation,
+) -> DynamicImage {
+    let filled = resize_to_cover(image, width, height, filter);
+    let (fill_width, fill_height) = filled.dimensions();
+    filled.crop_imm(
+        (fill_width - width.min(fill_width)) / 2,
+        (fill_height - height.min(fill_height)) / 2,
+        width,
+        height,
+    )
+}
+
+/// Scale `image` to exactly `width` by `height`
+pub fn resize_exact(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: Interpolation,
+) -> DynamicImage {
+    let filter = match filter {
+        Interpolation::Nearest => FilterType::Nearest,
+        Interpolation::Triangle => FilterType::Triangle,
+        Interpolation::CatmullRom => FilterType::CatmullRom,
+        Interpolation::Gaussian => FilterType::Gaussian,
+        Interpolation::Lanczos3 => FilterType::Lanczos3,
+        Interpolation::Mitchell => return mitchell_resize(image, width, height),
+    };
+    image.resize_exact(width, height, filter)
+}
+
+/// Dimensions of `source` scaled to fit within, or to cover, `target`
+fn fitted(source: (u32, u32), target: (u32, u32), cover: bool) -> (u32, u32) {
+    let width_ratio = target.0 as f64 / source.0 as f64;
+    let height_ratio = target.1 as f64 / source.1 as f64;
+    let ratio = if cover {
+        width_ratio.max(height_ratio)
+    } else {
+        width_ratio.min(height_ratio)
+    };
+    let scale = |length: u32| ((length as f64 * ratio).round() as u64).clamp(1, u32::MAX as u64);
+    (scale(source.0) as u32, scale(source.1) as u32)
+}
+
+/// Mitchell-Netravali cubic with B = C = 1/3
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x.powi(3)
+            + (-18.0 + 12.0 * B + 6.0 * C) * x.powi(2)
+            + (6.0 - 2.0 * B))
+            / 6.0
+    } else if x < 2.0 {
+        ((-B - 6.0 * C) * x.powi(3)
+            + (6.0 * B + 30.0 * C) * x.powi(2)
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+/// Normalized weights of the source samples of each destination sample, with the first
+/// source sample they start at
+fn weights(source: u32, destination: u32) -> Vec<(usize, Vec<f32>)> {
+    let scale = source as f32 / destination as f32;
+    // Downscales widen the kernel so every source sample contributes
+    let stretch = scale.max(1.0);
+    let support = 2.0 * stretch;
+    (0..destination)
+        .map(|index| {
+            let center = (index as f32 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(source as usize);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|sample| mitchell((sample as f32 + 0.5 - center) / stretch))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 {
+                weights.iter_mut().for_each(|weight| *weight /= sum);
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Resample `image` with the Mitchell filter, one axis at a time
+fn mitchell_resize(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let source = image.to_rgba32f();
+    let (source_width, source_height) = source.dimensions();
+
+    let columns = weights(source_width, width);
+    let mut horizontal = vec![0f32; width as usize * source_height as usize * 4];
+    horizontal
+        .par_chunks_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let line =
+                &source.as_raw()[y * source_width as usize * 4..][..source_width as usize * 4];
+            for (pixel, (start, weights)) in row.chunks_exact_mut(4).zip(&columns) {
+                for (offset, weight) in weights.iter().enumerate() {
+                    let sample = &line[(start + offset) * 4..][..4];
+                    for (channel, value) in pixel.iter_mut().zip(sample) {
+                        *channel += value * weight;
+                    }
+                }
+            }
+        });
+
+    let rows = weights(source_height, height);
+    let mut resampled = vec![0f32; width as usize * height as usize * 4];
+    resampled
+        .par_chunks_mut(width as usize * 4)
+        .zip(&rows)
+        .for_each(|(row, (start, weights))| {
+            for (offset, weight) in weights.iter().enumerate() {
+                let line =
+                    &horizontal[(start + offset) * width as usize * 4..][..width as usize * 4];
+                for (channel, value) in row.iter_mut().zip(line) {
+                    *channel += value * weight;
+                }
+            }
+            // The negative lobes overshoot around edges
+            row.iter_mut()
+                .for_each(|channel| *channel = channel.clamp(0.0, 1.0));
+        });
+
+    let resampled = DynamicImage::ImageRgba32F(
+        Rgba32FImage::from_raw(width, height, resampled)
+            .expect("the buffer holds every resampled pixel"),
+    );
+    match image.color() {
+        ColorType::L8 => resampled.into_luma8().into(),
+        ColorType::La8 => resampled.into_luma_alpha8().into(),
+        ColorType::Rgb8 => resampled.into_rgb8().into(),
+        ColorType::L16 => resampled.into_luma16().into(),
+        ColorType::La16 => resampled.into_luma_alpha16().into(),
+        ColorType::Rgb16 => resampled.into_rgb16().into(),
+        ColorType::Rgba16 => resampled.into_rgba16().into(),
+        ColorType::Rgb32F => resampled.into_rgb32f().into(),
+        ColorType::Rgba32F => resampled,
+        _ => resampled.into_rgba8().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_mitchell_keeps_flat_areas_and_dimensions() {
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 6, Rgb([200, 100, 50])));
+        for (width, height) in [(25, 15), (4, 3)] {
+            let resized = resize_exact(&flat, width, height, Interpolation::Mitchell);
+            assert_eq!(resized.dimensions(), (width, height));
+            assert_eq!(resized.color(), ColorType::Rgb8);
+            assert!(
+                resized
+                    .to_rgb8()
+                    .pixels()
+                    .all(|pixel| pixel.0 == [200, 100, 50])
+            );
+        }
+
+        assert_eq!(
+            resize_to_fill(&flat, 30, 30, Interpolation::Mitchell).dimensions(),
+            (30, 30)
+        );
+        assert_eq!(
+            resize(&flat, 30, 30, Interpolation::Mitchell).dimensions(),
+            (30, 18)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::models::params::Interpolation;
+use crate::services::image::resample;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage, imageops};
+
+/// Largest share of the covering dimension removed as seams, as carving deeper distorts
+/// the subjects. The rest of the overflow is cropped.
+const MAX_CARVED_SHARE: f32 = 0.5;
+
+/// Scale `image` to cover `width` and `height`, then remove the seams of the overflow
+/// crossing the least detail instead of cropping it
+///
+/// The result still overflows when the aspect ratio changes too much to carve, for the
+/// caller to crop what is left.
+pub fn carve_to_fill(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: Interpolation,
+) -> DynamicImage {
+    let covered = resample::resize_to_cover(image, width, height, filter);
+    let (cover_width, cover_height) = covered.dimensions();
+    let carved = |length: u32, target: u32| {
+        (length - target.min(length)).min((length as f32 * MAX_CARVED_SHARE) as u32)
+    };
+
+    let has_alpha = covered.color().has_alpha();
+    let mut rgba = covered.into_rgba8();
+    let columns = carved(cover_width, width);
+    if columns > 0 {
+        rgba = remove_seams(&rgba, columns);
+    }
+    let rows = carved(cover_height, height);
+    if rows > 0 {
+        // Horizontal seams are the vertical seams of the rotated image
+        rgba = imageops::rotate270(&remove_seams(&imageops::rotate90(&rgba), rows));
+    }
+
+    let carved = DynamicImage::ImageRgba8(rgba);
+    if has_alpha {
+        carved
+    } else {
+        DynamicImage::ImageRgb8(carved.into_rgb8())
+    }
+}
+
+/// Brightness of `pixel`, transparent pixels counting as black
+fn luma(pixel: &Rgba<u8>) -> i32 {
+    let [r, g, b, a] = pixel.0.map(i32::from);
+    (r * 299 + g * 587 + b * 114) / 1000 * a / 255
+}
+
+/// Contrast of the pixel at `x`, `y` with its neighbors
+fn gradient(luma: &[Vec<i32>], x: usize, y: usize) -> u32 {
+    let row = &luma[y];
+    let horizontal = row[x.saturating_sub(1)] - row[(x + 1).min(row.len() - 1)];
+    let vertical = luma[y.saturating_sub(1)][x] - luma[(y + 1).min(luma.len() - 1)][x];
+    horizontal.unsigned_abs() + vertical.unsigned_abs()
+}
+
+/// Column of each row of the connected top to bottom path of least total `energy`
+fn lowest_seam(energy: &[Vec<u32>]) -> Vec<usize> {
+    let width = energy[0].len();
+    let neighbors = |x: usize| x.saturating_sub(1)..(x + 2).min(width);
+
+    let mut cost: Vec<Vec<u32>> = Vec::with_capacity(energy.len());
+    cost.push(energy[0].clone());
+    for row in &energy[1..] {
+        let above = &cost[cost.len() - 1];
+        let row = row
+            .iter()
+            .enumerate()
+            .map(|(x, &energy)| energy + neighbors(x).map(|x| above[x]).min().unwrap_or(0))
+            .collect();
+        cost.push(row);
+    }
+
+    // Walk back up from the cheapest end of the path
+    let mut seam = vec![0; energy.len()];
+    let last = &cost[cost.len() - 1];
+    let mut x = (0..width).min_by_key(|&x| last[x]).unwrap_or(0);
+    for (y, column) in seam.iter_mut().enumerate().rev() {
+        if y + 1 < energy.len() {
+            x = neighbors(x).min_by_key(|&x| cost[y][x]).unwrap_or(x);
+        }
+        *column = x;
+    }
+    seam
+}
+
+/// Remove `count` vertical seams from `image`, one at a time
+fn remove_seams(image: &RgbaImage, count: u32) -> RgbaImage {
+    let height = image.height();
+    let mut pixels: Vec<Vec<Rgba<u8>>> = image.rows().map(|row| row.copied().collect()).collect();
+    let mut luma: Vec<Vec<i32>> = pixels
+        .iter()
+        .map(|row| row.iter().map(luma).collect())
+        .collect();
+    let mut energy: Vec<Vec<u32>> = (0..luma.len())
+        .map(|y| (0..luma[y].len()).map(|x| gradient(&luma, x, y)).collect())
+        .collect();
+
+    for _ in 0..count {
+        let seam = lowest_seam(&energy);
+        for (y, &x) in seam.iter().enumerate() {
+            pixels[y].remove(x);
+            luma[y].remove(x);
+            energy[y].remove(x);
+        }
+        // Seams move by at most one column per row, so only the contrast of the pixels
+        // next to the removed ones changed
+        let width = luma[0].len();
+        for (y, &x) in seam.iter().enumerate() {
+            let start = x.saturating_sub(2);
+            let end = (x + 2).min(width);
+            for (x, energy) in (start..end).zip(&mut energy[y][start..end]) {
+                *energy = gradient(&luma, x, y);
+            }
+        }
+    }
+
+    let width = pixels[0].len() as u32;
+    RgbaImage::from_fn(width, height, |x, y| pixels[y as usize][x as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_carves_the_flat_area_between_subjects() {
+        // Two dark squares on a flat background, too far apart to fit a centered crop
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(120, 40, |x, y| {
+            let subject = (10..30).contains(&y) && ((5..25).contains(&x) || (95..115).contains(&x));
+            if subject {
+                Rgb([20, 20, 20])
+            } else {
+                Rgb([230, 230, 230])
+            }
+        }));
+
+        let carved = carve_to_fill(&image, 80, 40, Interpolation::Triangle).into_rgb8();
+        assert_eq!(carved.dimensions(), (80, 40));
+        let dark = |range: std::ops::Range<u32>| {
+            range
+                .filter(|&x| carved.get_pixel(x, 20).0[0] < 100)
+                .count()
+        };
+        // Both subjects are kept whole
+        assert_eq!(dark(0..40), 20);
+        assert_eq!(dark(40..80), 20);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::performance::EncodeLimits;
+use gen_server::models::ImageFormat;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Use of the budget of a format
+#[derive(Debug, Clone, Serialize)]
+pub struct EncodeSlotStats {
+    pub limit: usize,
+    pub in_flight: usize,
+}
+
+/// Concurrency budgets of the formats slow to encode, so they can't take the whole CPU pool
+///
+/// A slot is taken before the image gets its turn on the pool and held until it is encoded,
+/// so images waiting for a slot don't block a pool thread.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeSlots {
+    /// Limit and semaphore of each limited format
+    slots: Arc<HashMap<ImageFormat, (usize, Arc<Semaphore>)>>,
+}
+
+impl EncodeSlots {
+    pub fn new(limits: &EncodeLimits) -> Self {
+        Self {
+            slots: Arc::new(
+                limits
+                    .0
+                    .iter()
+                    .map(|(&format, &limit)| (format, (limit, Arc::new(Semaphore::new(limit)))))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Wait for a slot to encode to `format`, `None` when the format isn't limited
+    pub async fn acquire(&self, format: ImageFormat) -> Option<OwnedSemaphorePermit> {
+        let (_, semaphore) = self.slots.get(&format)?;
+        // The semaphores are never closed
+        Arc::clone(semaphore).acquire_owned().await.ok()
+    }
+
+    /// Use of the budget of each limited format
+    pub fn stats(&self) -> BTreeMap<String, EncodeSlotStats> {
+        self.slots
+            .iter()
+            .map(|(format, (limit, semaphore))| {
+                let stats = EncodeSlotStats {
+                    limit: *limit,
+                    in_flight: limit - semaphore.available_permits(),
+                };
+                (format.to_string(), stats)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_limits_concurrent_encodes_per_format() {
+        let slots = EncodeSlots::new(&"avif=1".parse().unwrap());
+
+        let held = slots.acquire(ImageFormat::Avif).await.unwrap();
+        assert_eq!(slots.stats()["avif"].in_flight, 1);
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(50), slots.acquire(ImageFormat::Avif));
+        assert!(waiting.await.is_err());
+        assert!(slots.acquire(ImageFormat::Jpg).await.is_none());
+
+        drop(held);
+        assert!(slots.acquire(ImageFormat::Avif).await.is_some());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Context, Result, bail};
+use reqwest::tls::{Certificate, Identity, Version};
+use reqwest::{ClientBuilder, Url};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// TLS settings of the origins matching `host`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OriginTls {
+    /// Host name, or `*.example.com` for any subdomain of `example.com`
+    pub host: String,
+    /// PEM bundle of root certificates trusted in addition to the system ones
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate chain presented for mutual TLS
+    pub client_cert_file: Option<PathBuf>,
+    /// PKCS#8 PEM private key of the client certificate
+    pub client_key_file: Option<PathBuf>,
+    /// Lowest accepted protocol version: `1.0`, `1.1`, `1.2` or `1.3`
+    pub min_version: Option<String>,
+}
+
+impl OriginTls {
+    /// Whether `host` is covered by this entry
+    pub fn matches(&self, host: &str) -> bool {
+        let pattern = self.host.to_ascii_lowercase();
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.ends_with('.') && subdomain.len() > 1),
+            None => host == pattern,
+        }
+    }
+
+    /// Apply the settings to a client used for the matching origins
+    pub fn configure(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        if let Some(ca_file) = &self.ca_file {
+            let certificates = Certificate::from_pem_bundle(&read(ca_file)?)
+                .context(format!("Invalid CA bundle {}", ca_file.display()))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        match (&self.client_cert_file, &self.client_key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                let identity = Identity::from_pkcs8_pem(&read(cert_file)?, &read(key_file)?)
+                    .context(format!(
+                        "Invalid client certificate {} for {}",
+                        cert_file.display(),
+                        self.host
+                    ))?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => bail!(
+                "Origin TLS for {} needs both client_cert_file and client_key_file",
+                self.host
+            ),
+        }
+
+        if let Some(min_version) = &self.min_version {
+            builder = builder.min_tls_version(parse_version(min_version)?);
+        }
+        Ok(builder)
+    }
+}
+
+/// Per-origin TLS settings, the first matching entry applying
+#[derive(Debug, Clone, Default)]
+pub struct OriginTlsConfig {
+    pub origins: Vec<OriginTls>,
+}
+
+impl OriginTlsConfig {
+    /// Read the JSON array of `OriginTls` entries at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let origins = serde_json::from_slice(&read(path)?)
+            .context(format!("Invalid origin TLS config {}", path.display()))?;
+        Ok(Self { origins })
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        config
+            .origin_tls_config
+            .as_deref()
+            .filter(|path| !path.is_empty())
+            .map(|path| Self::load(Path::new(path)))
+            .transpose()
+    }
+
+    /// Index of the entry applying to `url`, if any
+    pub fn position(&self, url: &Url) -> Option<usize> {
+        let host = url.host_str()?;
+        self.origins.iter().position(|origin| origin.matches(host))
+    }
+}
+
+fn parse_version(version: &str) -> Result<Version> {
+    Ok(match version {
+        "1.0" => Version::TLS_1_0,
+        "1.1" => Version::TLS_1_1,
+        "1.2" => Version::TLS_1_2,
+        "1.3" => Version::TLS_1_3,
+        _ => bail!(
+            "Invalid TLS version {:?}, expected 1.0, 1.1, 1.2 or 1.3",
+            version
+        ),
+    })
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).context(format!("Failed to read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_hosts_and_subdomains() {
+        let config = OriginTlsConfig {
+            origins: serde_json::from_str(
+                r#"[
+                    {"host": "*.internal.example.com", "min_version": "1.3"},
+                    {"host": "Assets.Example.com"}
+                ]"#,
+            )
+            .unwrap(),
+        };
+        let position = |url: &str| config.position(&Url::parse(url).unwrap());
+
+        assert_eq!(position("https://cdn.internal.example.com/a.jpg"), Some(0));
+        assert_eq!(position("https://a.b.internal.example.com/a.jpg"), Some(0));
+        assert_eq!(position("https://internal.example.com/a.jpg"), None);
+        assert_eq!(position("https://evilinternal.example.com/a.jpg"), None);
+        assert_eq!(position("https://assets.example.com/a.jpg"), Some(1));
+        assert!(parse_version("1.4").is_err());
+    }
+}
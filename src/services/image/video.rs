@@ -0,0 +1,182 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::env::env::EnvConfig;
+use crate::services::debug::handler::StageTimings;
+use crate::services::image::handler::ProcessedImage;
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use gen_server::models::ImageFormat;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Transcodes animated GIFs to MP4 or WebM with an `ffmpeg` binary
+#[derive(Debug, Clone)]
+pub struct Transcoder {
+    ffmpeg: PathBuf,
+    timeout: Duration,
+}
+
+impl Default for Transcoder {
+    fn default() -> Self {
+        Self {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Transcoder {
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self {
+            ffmpeg: PathBuf::from(&config.ffmpeg_path),
+            timeout: Duration::from_secs(config.ffmpeg_timeout_secs),
+        }
+    }
+
+    /// Transcode the animated GIF `gif`, scaled like an image to `params`
+    ///
+    /// The output goes through a temporary file rather than a pipe, so that MP4 can be
+    /// written with its index up front and start playing before it is fully downloaded.
+    pub async fn transcode(&self, gif: Bytes, params: &ResizeQuery) -> Result<ProcessedImage> {
+        if !gif.starts_with(b"GIF8") {
+            bail!(
+                "{} output needs an animated GIF source",
+                params.output_format()
+            );
+        }
+        let source = image::ImageReader::with_format(Cursor::new(&gif), image::ImageFormat::Gif)
+            .into_dimensions()
+            .context("Failed to read GIF dimensions")?;
+        let (width, height) = output_size(source, params.width, params.height);
+
+        let (codec_args, content_type): (&[&str], _) = match params.output_format() {
+            ImageFormat::Mp4 => (
+                &[
+                    "-c:v",
+                    "libx264",
+                    "-pix_fmt",
+                    "yuv420p",
+                    "-movflags",
+                    "+faststart",
+                ],
+                "video/mp4",
+            ),
+            ImageFormat::Webm => (
+                &[
+                    "-c:v",
+                    "libvpx-vp9",
+                    "-pix_fmt",
+                    "yuv420p",
+                    "-b:v",
+                    "0",
+                    "-crf",
+                    "35",
+                ],
+                "video/webm",
+            ),
+            _ => bail!("{} is not a video format", params.output_format()),
+        };
+
+        let output = std::env::temp_dir().join(format!(
+            "emgr-{}.{}",
+            uuid::Uuid::new_v4(),
+            params.output_format()
+        ));
+        // Cover the requested box and crop the overflow, like resized images
+        let filter = format!(
+            "scale={width}:{height}:force_original_aspect_ratio=increase:flags=lanczos,crop={width}:{height}"
+        );
+        let transcode_timer = Instant::now();
+        let mut child = Command::new(&self.ffmpeg)
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-f",
+                "gif",
+                "-i",
+                "pipe:0",
+            ])
+            .args(["-vf", &filter, "-an"])
+            .args(codec_args)
+            .arg("-y")
+            .arg(&output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context(format!("Failed to run {}", self.ffmpeg.display()))?;
+
+        let mut stdin = child.stdin.take().context("ffmpeg stdin is not piped")?;
+        let writer = tokio::spawn(async move {
+            // ffmpeg closing its input early is reported through its exit status
+            let _ = stdin.write_all(&gif).await;
+        });
+
+        let result = tokio::time::timeout(self.timeout, child.wait_with_output()).await;
+        writer.abort();
+        let data = match result {
+            Ok(Ok(status)) if status.status.success() => tokio::fs::read(&output)
+                .await
+                .context("Failed to read transcoded video"),
+            Ok(Ok(status)) => Err(anyhow::anyhow!(
+                "ffmpeg failed with {}: {}",
+                status.status,
+                String::from_utf8_lossy(&status.stderr).trim()
+            )),
+            Ok(Err(e)) => Err(e).context("Failed to wait for ffmpeg"),
+            Err(_) => Err(anyhow::anyhow!("ffmpeg timed out after {:?}", self.timeout)),
+        };
+        let _ = tokio::fs::remove_file(&output).await;
+        let mut timings = StageTimings::default();
+        timings.record("transcode", transcode_timer.elapsed());
+
+        Ok(ProcessedImage {
+            data: data?.into(),
+            content_type: content_type.to_string(),
+            width,
+            height,
+            quality: None,
+            timings,
+            filter: Some("lanczos3"),
+            encoder: Some(format!("ffmpeg {}", codec_args.join(" "))),
+        })
+    }
+}
+
+/// Dimensions of the video for a source of `source` pixels, rounded down to even
+/// numbers as required by 4:2:0 chroma subsampling
+fn output_size(source: (u32, u32), width: Option<u32>, height: Option<u32>) -> (u32, u32) {
+    let (source_width, source_height) = (source.0.max(1) as f64, source.1.max(1) as f64);
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (
+            width,
+            (source_height * width as f64 / source_width).round() as u32,
+        ),
+        (None, Some(height)) => (
+            (source_width * height as f64 / source_height).round() as u32,
+            height,
+        ),
+        (None, None) => source,
+    };
+    let even = |value: u32| (value & !1).max(2);
+    (even(width), even(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_output_size_to_even() {
+        assert_eq!(output_size((481, 271), None, None), (480, 270));
+        assert_eq!(output_size((400, 300), Some(201), None), (200, 150));
+        assert_eq!(output_size((400, 300), None, Some(100)), (132, 100));
+        assert_eq!(output_size((400, 300), Some(64), Some(64)), (64, 64));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::models::params::ResizeQuery;
+use crate::services::resize::errors::ErrorCode;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A resize to run in the background
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub query: ResizeQuery,
+    /// Tenant the surrogate keys are generated for
+    pub tenant: Option<String>,
+    /// Number of failed runs so far
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Processing,
+    Completed,
+    /// All attempts failed and the job was moved to the dead-letter queue
+    Failed,
+}
+
+/// Progress of a job, as reported by the job API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub id: String,
+    pub state: JobState,
+    pub attempts: u32,
+    /// CDN URL of the resized image, once completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Error of the last failed attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Stable code of the error of the last failed attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// A job taken from the queue, to acknowledge once handled
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub job: Job,
+    /// Backend-specific handle of the queue entry
+    pub receipt: String,
+}
+
+/// Queue of resize jobs shared by the API and the workers
+///
+/// Durable backends redeliver jobs that are taken but never acknowledged, e.g.
+/// because the worker was restarted.
+#[async_trait]
+pub trait JobQueue: Send + Sync {
+    async fn push(&self, job: &Job) -> Result<()>;
+
+    /// Take the next job, waiting up to `timeout` for one
+    async fn pop(&self, timeout: Duration) -> Result<Option<Delivery>>;
+
+    /// Remove a handled job from the queue
+    async fn ack(&self, delivery: &Delivery) -> Result<()>;
+
+    /// Move a job that failed all its attempts out of the queue
+    async fn dead_letter(&self, delivery: &Delivery, error: &str) -> Result<()>;
+
+    async fn set_status(&self, status: &JobStatus) -> Result<()>;
+
+    async fn status(&self, id: &str) -> Result<Option<JobStatus>>;
+}
@@ -0,0 +1,281 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::api::handler::ApiService;
+use crate::modules::env::env::EnvConfig;
+use crate::modules::env::role::Role;
+use crate::services::job::core::{Job, JobQueue, JobState, JobStatus};
+use crate::services::job::memory_handler::MemoryQueue;
+use crate::services::resize::errors::ErrorCode;
+use crate::services::resize::handler::ResizeService;
+use crate::services::signing::handler::rejection;
+use anyhow::Result;
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header::LOCATION;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::Host;
+use gen_server::models::ResizeQueryParams;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Route of the async job API
+pub const JOB_ROUTE: &str = "/api/jobs";
+
+/// How long a worker waits for a job before polling again
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause after a queue error, so an unreachable queue isn't hammered
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Runs resizes in the background through a [`JobQueue`]
+///
+/// Failed jobs are retried up to `max_attempts` times, then moved to the
+/// dead-letter queue of the backend.
+#[derive(Clone)]
+pub struct JobService {
+    queue: Arc<dyn JobQueue>,
+    max_attempts: u32,
+    /// Number of concurrent workers of this replica
+    workers: usize,
+}
+
+impl JobService {
+    pub fn new(queue: Arc<dyn JobQueue>, max_attempts: u32, workers: usize) -> Self {
+        Self {
+            queue,
+            max_attempts: max_attempts.max(1),
+            workers,
+        }
+    }
+
+    /// Create the job queue configured by the environment
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        #[cfg(feature = "redis_queue")]
+        if let Some(url) = &config.redis_url {
+            let queue = crate::services::job::redis_handler::RedisQueue::new(
+                url,
+                Duration::from_secs(config.job_visibility_timeout_secs),
+            )?;
+            return Ok(Self::new(
+                Arc::new(queue),
+                config.job_max_attempts,
+                config.job_workers,
+            ));
+        }
+
+        if config.role != Role::All {
+            warn!(
+                "ROLE={:?} needs a shared job queue (redis_queue feature and REDIS_URL), \
+                 the in-memory queue only serves this replica",
+                config.role
+            );
+        }
+        Ok(Self::new(
+            Arc::new(MemoryQueue::new()),
+            config.job_max_attempts,
+            config.job_workers,
+        ))
+    }
+
+    /// Queue a resize, returning the status to poll
+    pub async fn submit(&self, query: ResizeQuery, tenant: Option<&str>) -> Result<JobStatus> {
+        let job = Job {
+            id: Uuid::new_v4().simple().to_string(),
+            query,
+            tenant: tenant.map(str::to_string),
+            attempts: 0,
+        };
+        let status = JobStatus {
+            id: job.id.clone(),
+            state: JobState::Queued,
+            attempts: 0,
+            url: None,
+            error: None,
+            error_code: None,
+        };
+
+        self.queue.set_status(&status).await?;
+        self.queue.push(&job).await?;
+        info!("Queued job {} for {}", job.id, job.query.url);
+        Ok(status)
+    }
+
+    pub async fn status(&self, id: &str) -> Result<Option<JobStatus>> {
+        self.queue.status(id).await
+    }
+
+    /// Run the next job with `process`, returning whether there was one
+    pub async fn process_next<F, Fut>(&self, timeout: Duration, process: F) -> Result<bool>
+    where
+        F: FnOnce(Job) -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let Some(delivery) = self.queue.pop(timeout).await? else {
+            return Ok(false);
+        };
+        let mut job = delivery.job.clone();
+        let mut status = JobStatus {
+            id: job.id.clone(),
+            state: JobState::Processing,
+            attempts: job.attempts,
+            url: None,
+            error: None,
+            error_code: None,
+        };
+        self.queue.set_status(&status).await?;
+
+        match process(job.clone()).await {
+            Ok(url) => {
+                info!("Completed job {}", job.id);
+                status.state = JobState::Completed;
+                status.url = Some(url);
+                self.queue.ack(&delivery).await?;
+            }
+            Err(e) => {
+                job.attempts += 1;
+                status.attempts = job.attempts;
+                status.error = Some(e.to_string());
+                status.error_code = Some(ErrorCode::classify(&e));
+
+                if job.attempts >= self.max_attempts {
+                    warn!("Job {} failed {} times: {}", job.id, job.attempts, e);
+                    status.state = JobState::Failed;
+                    self.queue.dead_letter(&delivery, &e.to_string()).await?;
+                } else {
+                    info!("Retrying job {} after failure: {}", job.id, e);
+                    status.state = JobState::Queued;
+                    // Requeue before acknowledging, so a crash can't lose the job
+                    self.queue.push(&job).await?;
+                    self.queue.ack(&delivery).await?;
+                }
+            }
+        }
+
+        self.queue.set_status(&status).await?;
+        Ok(true)
+    }
+
+    /// Consume the queue with `resize_service` on every worker, until the process exits
+    pub async fn run_workers(&self, resize_service: ResizeService) {
+        info!("Starting {} job workers", self.workers);
+        let workers = (0..self.workers).map(|_| self.run_worker(&resize_service));
+        futures::future::join_all(workers).await;
+    }
+
+    async fn run_worker(&self, resize_service: &ResizeService) {
+        loop {
+            let processed = self
+                .process_next(POLL_TIMEOUT, |job| async move {
+                    let result = resize_service
+                        .resize(&job.query, job.tenant.as_deref())
+                        .await?;
+                    Ok(result.url)
+                })
+                .await;
+
+            if let Err(e) = processed {
+                error!("Job worker failed: {}", e);
+                tokio::time::sleep(ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Queue a resize and answer with the job to poll
+pub async fn submit_job(
+    State(api_service): State<Arc<ApiService>>,
+    host: Host,
+    Json(params): Json<ResizeQueryParams>,
+) -> Response {
+    // Job bodies can't carry a URL signature, so they would fetch any source
+    if api_service.url_signer.is_some() {
+        warn!("Rejected a job while SIGNING_KEY is set");
+        return rejection(
+            "invalid_signature",
+            "Jobs are disabled while SIGNING_KEY is set",
+        );
+    }
+
+    let query = match api_service
+        .script_hook
+        .rewrite(ResizeQuery::from(params), Some(&host.0))
+    {
+        Ok(query) => query,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    match api_service.job_service.submit(query, Some(&host.0)).await {
+        Ok(status) => (
+            StatusCode::ACCEPTED,
+            [(LOCATION, format!("{}/{}", JOB_ROUTE, status.id))],
+            Json(status),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to queue job: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn job_status(
+    State(api_service): State<Arc<ApiService>>,
+    Path(id): Path<String>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    match api_service.job_service.status(&id).await {
+        Ok(Some(status)) => Ok(Json(status)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to read job status: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[tokio::test]
+    async fn test_failed_jobs_are_retried_then_dead_lettered() {
+        let queue = Arc::new(MemoryQueue::new());
+        let jobs = JobService::new(queue.clone(), 2, 1);
+        let timeout = Duration::from_millis(10);
+
+        let ok = jobs.submit(ResizeQuery::default(), None).await.unwrap();
+        assert!(
+            jobs.process_next(timeout, |_| async { Ok("cdn/ok.jpg".to_string()) })
+                .await
+                .unwrap()
+        );
+        let status = jobs.status(&ok.id).await.unwrap().unwrap();
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(status.url.as_deref(), Some("cdn/ok.jpg"));
+
+        let failing = jobs.submit(ResizeQuery::default(), None).await.unwrap();
+        for state in [JobState::Queued, JobState::Failed] {
+            jobs.process_next(timeout, |_| async { bail!("broken source") })
+                .await
+                .unwrap();
+            assert_eq!(
+                jobs.status(&failing.id).await.unwrap().unwrap().state,
+                state
+            );
+        }
+
+        assert!(
+            !jobs
+                .process_next(timeout, |_| async { Ok(String::new()) })
+                .await
+                .unwrap()
+        );
+        let dead = queue.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].0.id, failing.id);
+        assert_eq!(dead[0].1, "broken source");
+    }
+}
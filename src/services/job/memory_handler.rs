@@ -0,0 +1,78 @@
+use crate::services::job::core::{Delivery, Job, JobQueue, JobStatus};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Process-local job queue, losing pending jobs on restart
+#[derive(Default)]
+pub struct MemoryQueue {
+    jobs: Mutex<VecDeque<Job>>,
+    statuses: Mutex<HashMap<String, JobStatus>>,
+    dead: Mutex<Vec<(Job, String)>>,
+    notify: Notify,
+}
+
+impl MemoryQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Jobs moved to the dead-letter queue, with their last error
+    pub fn dead_letters(&self) -> Vec<(Job, String)> {
+        self.dead.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl JobQueue for MemoryQueue {
+    async fn push(&self, job: &Job) -> Result<()> {
+        self.jobs.lock().unwrap().push_back(job.clone());
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn pop(&self, timeout: Duration) -> Result<Option<Delivery>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(job) = self.jobs.lock().unwrap().pop_front() {
+                return Ok(Some(Delivery {
+                    receipt: job.id.clone(),
+                    job,
+                }));
+            }
+            if tokio::time::timeout_at(deadline, self.notify.notified())
+                .await
+                .is_err()
+            {
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn ack(&self, _delivery: &Delivery) -> Result<()> {
+        Ok(())
+    }
+
+    async fn dead_letter(&self, delivery: &Delivery, error: &str) -> Result<()> {
+        self.dead
+            .lock()
+            .unwrap()
+            .push((delivery.job.clone(), error.to_string()));
+        Ok(())
+    }
+
+    async fn set_status(&self, status: &JobStatus) -> Result<()> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .insert(status.id.clone(), status.clone());
+        Ok(())
+    }
+
+    async fn status(&self, id: &str) -> Result<Option<JobStatus>> {
+        Ok(self.statuses.lock().unwrap().get(id).cloned())
+    }
+}
@@ -0,0 +1,6 @@
+pub mod core;
+pub mod handler;
+pub mod memory_handler;
+
+#[cfg(feature = "redis_queue")]
+pub mod redis_handler;
@@ -0,0 +1,184 @@
+use crate::services::job::core::{Delivery, Job, JobQueue, JobStatus};
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use redis::streams::{
+    StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamReadOptions, StreamReadReply,
+};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// Stream holding the pending jobs
+const STREAM_KEY: &str = "emgr:jobs";
+
+/// Stream holding the jobs that failed all their attempts
+const DEAD_LETTER_KEY: &str = "emgr:jobs:dead";
+
+/// Consumer group shared by all workers
+const GROUP: &str = "emgr-workers";
+
+/// Prefix of the job status keys
+const STATUS_PREFIX: &str = "emgr:job:";
+
+/// How long job statuses are kept after their last update
+const STATUS_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Durable job queue backed by a Redis stream and consumer group
+///
+/// Jobs taken by a worker that doesn't acknowledge them within the visibility
+/// timeout, e.g. because its pod was restarted, are claimed by another worker.
+pub struct RedisQueue {
+    client: redis::Client,
+    /// Connected on first use, as the service is created outside of the runtime
+    connection: OnceCell<ConnectionManager>,
+    /// Name of this replica in the consumer group
+    consumer: String,
+    visibility_timeout: Duration,
+}
+
+impl RedisQueue {
+    pub fn new(url: &str, visibility_timeout: Duration) -> Result<Self> {
+        let client = redis::Client::open(url).context("Invalid Redis URL")?;
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+            consumer: Uuid::new_v4().simple().to_string(),
+            visibility_timeout,
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| async {
+                let mut connection = ConnectionManager::new(self.client.clone()).await?;
+                // The group already exists unless this is the first replica
+                let created: redis::RedisResult<()> = connection
+                    .xgroup_create_mkstream(STREAM_KEY, GROUP, "0")
+                    .await;
+                match created {
+                    Err(e) if e.code() != Some("BUSYGROUP") => Err(e),
+                    _ => Ok(connection),
+                }
+            })
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(connection.clone())
+    }
+}
+
+fn delivery(entry: &StreamId) -> Result<Delivery> {
+    let payload: String = entry
+        .get("job")
+        .ok_or_else(|| anyhow!("Job entry {} has no payload", entry.id))?;
+    Ok(Delivery {
+        job: serde_json::from_str(&payload).context("Failed to parse job")?,
+        receipt: entry.id.clone(),
+    })
+}
+
+#[async_trait]
+impl JobQueue for RedisQueue {
+    async fn push(&self, job: &Job) -> Result<()> {
+        let payload = serde_json::to_string(job).context("Failed to serialize job")?;
+        let _: Option<String> = self
+            .connection()
+            .await?
+            .xadd(STREAM_KEY, "*", &[("job", payload)])
+            .await
+            .context("Failed to enqueue job")?;
+        Ok(())
+    }
+
+    async fn pop(&self, timeout: Duration) -> Result<Option<Delivery>> {
+        let mut connection = self.connection().await?;
+
+        // Jobs abandoned by other workers come first
+        let claimed: StreamAutoClaimReply = connection
+            .xautoclaim_options(
+                STREAM_KEY,
+                GROUP,
+                &self.consumer,
+                self.visibility_timeout.as_millis() as u64,
+                "0-0",
+                StreamAutoClaimOptions::default().count(1),
+            )
+            .await
+            .context("Failed to claim abandoned jobs")?;
+        if let Some(entry) = claimed.claimed.first() {
+            return delivery(entry).map(Some);
+        }
+
+        let options = StreamReadOptions::default()
+            .group(GROUP, &self.consumer)
+            .count(1)
+            .block(timeout.as_millis() as usize);
+        let reply: Option<StreamReadReply> = connection
+            .xread_options(&[STREAM_KEY], &[">"], &options)
+            .await
+            .context("Failed to read jobs")?;
+
+        reply
+            .and_then(|reply| reply.keys.into_iter().next())
+            .and_then(|key| key.ids.into_iter().next())
+            .map(|entry| delivery(&entry))
+            .transpose()
+    }
+
+    async fn ack(&self, delivery: &Delivery) -> Result<()> {
+        let mut connection = self.connection().await?;
+        let _: usize = connection
+            .xack(STREAM_KEY, GROUP, &[&delivery.receipt])
+            .await
+            .context("Failed to acknowledge job")?;
+        let _: usize = connection
+            .xdel(STREAM_KEY, &[&delivery.receipt])
+            .await
+            .context("Failed to delete job")?;
+        Ok(())
+    }
+
+    async fn dead_letter(&self, delivery: &Delivery, error: &str) -> Result<()> {
+        let payload = serde_json::to_string(&delivery.job).context("Failed to serialize job")?;
+        let _: Option<String> = self
+            .connection()
+            .await?
+            .xadd(
+                DEAD_LETTER_KEY,
+                "*",
+                &[("job", payload.as_str()), ("error", error)],
+            )
+            .await
+            .context("Failed to dead-letter job")?;
+        self.ack(delivery).await
+    }
+
+    async fn set_status(&self, status: &JobStatus) -> Result<()> {
+        let payload = serde_json::to_string(status).context("Failed to serialize job status")?;
+        let _: () = self
+            .connection()
+            .await?
+            .set_ex(
+                format!("{}{}", STATUS_PREFIX, status.id),
+                payload,
+                STATUS_TTL_SECS,
+            )
+            .await
+            .context("Failed to store job status")?;
+        Ok(())
+    }
+
+    async fn status(&self, id: &str) -> Result<Option<JobStatus>> {
+        let payload: Option<String> = self
+            .connection()
+            .await?
+            .get(format!("{}{}", STATUS_PREFIX, id))
+            .await
+            .context("Failed to read job status")?;
+        payload
+            .map(|payload| serde_json::from_str(&payload).context("Failed to parse job status"))
+            .transpose()
+    }
+}
@@ -0,0 +1,13 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Lease-based lock shared by all replicas
+#[async_trait]
+pub trait DistributedLock: Send + Sync {
+    /// Take the lock for `ttl` unless another holder has it; returns the lease token
+    async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<Option<String>>;
+
+    /// Release the lock if it is still held with `token`
+    async fn release(&self, key: &str, token: &str) -> Result<()>;
+}
@@ -0,0 +1,164 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::lock::core::DistributedLock;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Timing of the processing lock
+#[derive(Debug, Clone)]
+pub struct LockSettings {
+    /// Lease duration, after which a crashed holder no longer blocks others
+    pub ttl: Duration,
+    /// How long to wait for another replica's result before processing anyway
+    pub wait_timeout: Duration,
+    /// How often to look for the other replica's result while waiting
+    pub poll_interval: Duration,
+}
+
+impl Default for LockSettings {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(30),
+            wait_timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Result of trying to take the processing lock of a cache key
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockOutcome {
+    /// No lock is configured, or it is unreachable: process without coordination
+    Disabled,
+    /// This replica processes the image and must release the lease
+    Acquired(String),
+    /// Another replica is processing the image
+    Contended,
+}
+
+/// Optional lock deduplicating the processing of cold images across replicas
+#[derive(Clone, Default)]
+pub struct ProcessingLock {
+    lock: Option<Arc<dyn DistributedLock>>,
+    settings: LockSettings,
+}
+
+impl ProcessingLock {
+    pub fn new(lock: impl DistributedLock + 'static, settings: LockSettings) -> Self {
+        Self {
+            lock: Some(Arc::new(lock)),
+            settings,
+        }
+    }
+
+    /// Create the lock configured by the environment
+    #[allow(unused_variables)]
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        #[cfg(feature = "redis_lock")]
+        if let Some(url) = &config.redis_url {
+            let lock = crate::services::lock::redis_handler::RedisLock::new(url)?;
+            let settings = LockSettings {
+                ttl: Duration::from_secs(config.lock_ttl_secs),
+                wait_timeout: Duration::from_secs(config.lock_wait_timeout_secs),
+                ..Default::default()
+            };
+            return Ok(Self::new(lock, settings));
+        }
+
+        Ok(Self::default())
+    }
+
+    pub fn settings(&self) -> &LockSettings {
+        &self.settings
+    }
+
+    /// Try to take the lock of a cache key; lock errors fall back to no coordination
+    pub async fn acquire(&self, key: &str) -> LockOutcome {
+        let Some(lock) = &self.lock else {
+            return LockOutcome::Disabled;
+        };
+
+        match lock.try_acquire(key, self.settings.ttl).await {
+            Ok(Some(token)) => LockOutcome::Acquired(token),
+            Ok(None) => {
+                debug!("Processing lock for {} is held by another replica", key);
+                LockOutcome::Contended
+            }
+            Err(e) => {
+                error!("Failed to acquire processing lock for {}: {}", key, e);
+                LockOutcome::Disabled
+            }
+        }
+    }
+
+    /// Release a lease taken by [`ProcessingLock::acquire`]
+    pub async fn release(&self, key: &str, token: &str) {
+        if let Some(lock) = &self.lock
+            && let Err(e) = lock.release(key, token).await
+        {
+            error!("Failed to release processing lock for {}: {}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use async_trait::async_trait;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct LocalLock(Mutex<HashSet<String>>);
+
+    #[async_trait]
+    impl DistributedLock for LocalLock {
+        async fn try_acquire(&self, key: &str, _ttl: Duration) -> Result<Option<String>> {
+            let acquired = self.0.lock().unwrap().insert(key.to_string());
+            Ok(acquired.then(|| key.to_string()))
+        }
+
+        async fn release(&self, key: &str, _token: &str) -> Result<()> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    struct BrokenLock;
+
+    #[async_trait]
+    impl DistributedLock for BrokenLock {
+        async fn try_acquire(&self, _key: &str, _ttl: Duration) -> Result<Option<String>> {
+            bail!("unreachable")
+        }
+
+        async fn release(&self, _key: &str, _token: &str) -> Result<()> {
+            bail!("unreachable")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processing_lock_outcomes() {
+        let lock = ProcessingLock::new(LocalLock::default(), LockSettings::default());
+
+        let LockOutcome::Acquired(token) = lock.acquire("key").await else {
+            panic!("first acquire should succeed");
+        };
+        assert_eq!(lock.acquire("key").await, LockOutcome::Contended);
+        lock.release("key", &token).await;
+        assert!(matches!(
+            lock.acquire("key").await,
+            LockOutcome::Acquired(_)
+        ));
+
+        // Without a reachable lock, replicas process independently
+        let broken = ProcessingLock::new(BrokenLock, LockSettings::default());
+        assert_eq!(broken.acquire("key").await, LockOutcome::Disabled);
+        assert_eq!(
+            ProcessingLock::default().acquire("key").await,
+            LockOutcome::Disabled
+        );
+    }
+}
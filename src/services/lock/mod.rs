@@ -0,0 +1,5 @@
+pub mod core;
+pub mod handler;
+
+#[cfg(feature = "redis_lock")]
+pub mod redis_handler;
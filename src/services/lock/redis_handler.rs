@@ -0,0 +1,75 @@
+use crate::services::lock::core::DistributedLock;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::Script;
+use redis::aio::ConnectionManager;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// Prefix of the lock keys in Redis
+const KEY_PREFIX: &str = "emgr:lock:";
+
+/// Deletes the lock only if it still holds our token, so an expired lease can't
+/// release the lock of the replica that took it over
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+else
+    return 0
+end
+"#;
+
+/// Distributed lock backed by Redis `SET NX PX`
+pub struct RedisLock {
+    client: redis::Client,
+    /// Connected on first use, as the service is created outside of the runtime
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl RedisLock {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).context("Invalid Redis URL")?;
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(connection.clone())
+    }
+}
+
+#[async_trait]
+impl DistributedLock for RedisLock {
+    async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<Option<String>> {
+        let token = Uuid::new_v4().simple().to_string();
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(format!("{}{}", KEY_PREFIX, key))
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to acquire Redis lock")?;
+
+        Ok(acquired.map(|_| token))
+    }
+
+    async fn release(&self, key: &str, token: &str) -> Result<()> {
+        let _: i64 = Script::new(RELEASE_SCRIPT)
+            .key(format!("{}{}", KEY_PREFIX, key))
+            .arg(token)
+            .invoke_async(&mut self.connection().await?)
+            .await
+            .context("Failed to release Redis lock")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Context, Result};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use reqwest::Client;
+use reqwest::redirect::Policy;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+/// Path of the requests mirrored, downloads being keyed by the storage of each deployment
+const MIRRORED_PATH: &str = "/api/images/resize";
+
+/// Header with the size of the resized image, compared between both deployments
+const IMAGE_BYTES: &str = "x-image-bytes";
+
+/// Mirrored requests in flight at once, others being skipped so a slow target can't pile up
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Outcome of mirrored requests, compared with the responses of this replica
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MirrorStats {
+    pub mirrored: u64,
+    /// Same status and image size
+    pub matched: u64,
+    pub status_mismatches: u64,
+    pub size_mismatches: u64,
+    /// The mirror target failed to answer
+    pub failed: u64,
+    /// Sampled but dropped, too many mirrored requests being in flight
+    pub skipped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    sampled: AtomicU64,
+    mirrored: AtomicU64,
+    matched: AtomicU64,
+    status_mismatches: AtomicU64,
+    size_mismatches: AtomicU64,
+    failed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// What both deployments answered to a resize
+#[derive(Debug, Clone, PartialEq)]
+struct Answer {
+    status: StatusCode,
+    size: Option<u64>,
+}
+
+impl Answer {
+    fn new(status: StatusCode, headers: &HeaderMap) -> Self {
+        Self {
+            status,
+            size: headers
+                .get(IMAGE_BYTES)
+                .and_then(|size| size.to_str().ok())
+                .and_then(|size| size.parse().ok()),
+        }
+    }
+}
+
+/// Shadow traffic sent to a secondary deployment, such as a canary build
+///
+/// A share of the resize requests is replayed against `base_url` once answered, and the
+/// status and image size of both answers are compared. Mirroring never delays nor alters
+/// the responses of this replica.
+#[derive(Clone)]
+pub struct TrafficMirror {
+    base_url: String,
+    /// Share of the requests mirrored, from 0 to 1
+    rate: f64,
+    client: Client,
+    in_flight: Arc<Semaphore>,
+    counters: Arc<Counters>,
+    #[cfg(feature = "otel")]
+    requests: opentelemetry::metrics::Counter<u64>,
+}
+
+impl TrafficMirror {
+    pub fn new(base_url: &str, rate: f64, timeout: Duration) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(timeout)
+            // Resizes answer with redirects, compared as such
+            .redirect(Policy::none())
+            .build()
+            .context("Failed to create mirror HTTP client")?;
+
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            rate: rate.clamp(0.0, 1.0),
+            client,
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+            counters: Arc::default(),
+            #[cfg(feature = "otel")]
+            requests: opentelemetry::global::meter("emgr")
+                .u64_counter("emgr.mirror.requests")
+                .with_description("Mirrored requests, by outcome")
+                .build(),
+        })
+    }
+
+    /// Create the mirror configured by the environment, if any
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let Some(base_url) = &config.mirror_base_url else {
+            return Ok(None);
+        };
+        Ok(Some(Self::new(
+            base_url,
+            config.mirror_percent / 100.0,
+            Duration::from_millis(config.mirror_timeout_ms),
+        )?))
+    }
+
+    pub fn stats(&self) -> MirrorStats {
+        let counters = &self.counters;
+        MirrorStats {
+            mirrored: counters.mirrored.load(Ordering::Relaxed),
+            matched: counters.matched.load(Ordering::Relaxed),
+            status_mismatches: counters.status_mismatches.load(Ordering::Relaxed),
+            size_mismatches: counters.size_mismatches.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether to mirror the next request, evenly spreading the sampled ones
+    fn sample(&self) -> bool {
+        let n = self.counters.sampled.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.rate).floor() > (n * self.rate).floor()
+    }
+
+    /// Replay `path_and_query` against the mirror target and compare with `expected`
+    async fn mirror(&self, path_and_query: &str, host: Option<String>, expected: Answer) {
+        let mut request = self
+            .client
+            .get(format!("{}{}", self.base_url, path_and_query));
+        // Tenants are told apart by host
+        if let Some(host) = host {
+            request = request.header(header::HOST, host);
+        }
+
+        let outcome = match request.send().await {
+            Ok(response) => {
+                let answer = Answer::new(response.status(), response.headers());
+                if answer.status != expected.status {
+                    warn!(
+                        "Mirrored {} answered {} instead of {}",
+                        path_and_query, answer.status, expected.status
+                    );
+                    "status_mismatch"
+                } else if answer.size != expected.size {
+                    debug!(
+                        "Mirrored {} answered {:?} bytes instead of {:?}",
+                        path_and_query, answer.size, expected.size
+                    );
+                    "size_mismatch"
+                } else {
+                    "match"
+                }
+            }
+            Err(e) => {
+                debug!("Failed to mirror {}: {}", path_and_query, e);
+                "error"
+            }
+        };
+        self.record(outcome);
+    }
+
+    fn record(&self, outcome: &'static str) {
+        let counters = &self.counters;
+        if outcome != "skipped" {
+            counters.mirrored.fetch_add(1, Ordering::Relaxed);
+        }
+        let counter = match outcome {
+            "match" => &counters.matched,
+            "status_mismatch" => &counters.status_mismatches,
+            "size_mismatch" => &counters.size_mismatches,
+            "error" => &counters.failed,
+            _ => &counters.skipped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "otel")]
+        self.requests
+            .add(1, &[opentelemetry::KeyValue::new("outcome", outcome)]);
+    }
+}
+
+/// Mirror a share of the resize requests once answered
+pub async fn mirror_requests(
+    State(mirror): State<TrafficMirror>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::GET || request.uri().path() != MIRRORED_PATH || !mirror.sample()
+    {
+        return next.run(request).await;
+    }
+
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.to_string())
+        .unwrap_or_default();
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+
+    let Ok(permit) = mirror.in_flight.clone().try_acquire_owned() else {
+        mirror.record("skipped");
+        return response;
+    };
+    let expected = Answer::new(response.status(), response.headers());
+    tokio::spawn(async move {
+        mirror.mirror(&path_and_query, host, expected).await;
+        drop(permit);
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_the_configured_share() {
+        let mirror =
+            TrafficMirror::new("http://canary.test/", 0.25, Duration::from_secs(1)).unwrap();
+        assert_eq!(mirror.base_url, "http://canary.test");
+        let sampled = (0..100).filter(|_| mirror.sample()).count();
+        assert_eq!(sampled, 25);
+
+        let mirror = TrafficMirror::new("http://canary.test", 0.0, Duration::from_secs(1)).unwrap();
+        assert!(!(0..100).any(|_| mirror.sample()));
+    }
+}
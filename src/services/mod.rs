@@ -1,8 +1,33 @@
+pub mod admin;
+pub mod audit;
 pub mod cache;
+pub mod cdn;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod deadline;
+pub mod debug;
+pub mod docs;
+pub mod event;
 pub mod health;
 pub mod image;
+pub mod job;
+pub mod lock;
+pub mod mirror;
+pub mod moderation;
+pub mod peer;
+pub mod picture;
+pub mod plugin;
+pub mod pregen;
 pub mod resize;
+pub mod script;
+pub mod signing;
 pub mod storage;
+pub mod tenant;
+pub mod tiering;
+pub mod upload;
+pub mod version;
+pub mod watchdog;
+pub mod watermark;
 
 #[cfg(feature = "otel")]
 pub mod metrics;
@@ -0,0 +1,317 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::storage::core::ModerationVerdict;
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::warn;
+
+/// A processed image was blocked by the moderation policy of its tenant
+#[derive(Debug, Error)]
+#[error("Image of {url} blocked by moderation: {label} scored {score}")]
+pub struct ContentBlocked {
+    pub url: String,
+    pub label: String,
+    pub score: f32,
+}
+
+/// What happens to the images of a tenant scoring at least the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// Store the image, with the verdict flagging it
+    Flag,
+    /// Refuse to serve the image
+    Block,
+    /// Don't classify the images of the tenant
+    Off,
+}
+
+/// Moderation policy of one tenant
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct TenantModeration {
+    pub action: ModerationAction,
+    /// Score from which images are flagged or blocked, the default threshold when unset
+    #[serde(default)]
+    pub threshold: Option<f32>,
+}
+
+/// Classifies images as safe or not
+#[async_trait]
+pub trait Classifier: Send + Sync {
+    async fn classify(&self, content_type: &str, data: &[u8]) -> Result<ModerationVerdict>;
+}
+
+/// Score of an HTTP classifier
+#[derive(Debug, Deserialize)]
+struct ClassifierScore {
+    score: f32,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+/// External classifier receiving the image as a `POST` body and answering with
+/// `{"score": 0.93, "label": "explicit"}`
+pub struct HttpClassifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpClassifier {
+    pub fn new(url: String, timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            url,
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Classifier for HttpClassifier {
+    async fn classify(&self, content_type: &str, data: &[u8]) -> Result<ModerationVerdict> {
+        let score: ClassifierScore = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(data.to_vec())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid answer of the moderation classifier")?;
+        if !(0.0..=1.0).contains(&score.score) {
+            bail!("Moderation score out of range: {}", score.score);
+        }
+
+        Ok(ModerationVerdict {
+            score: score.score,
+            label: score.label,
+            flagged: false,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    classified: AtomicU64,
+    flagged: AtomicU64,
+    blocked: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Moderation outcomes since startup
+#[derive(Debug, Clone, Serialize)]
+pub struct ModerationStats {
+    pub classified: u64,
+    pub flagged: u64,
+    pub blocked: u64,
+    /// Images stored unclassified as the classifier failed
+    pub failed: u64,
+}
+
+/// Moderation of processed images, before they are stored
+///
+/// Images scoring at least the threshold of their tenant are flagged in their metadata, or
+/// blocked. The classifier failing doesn't fail the resize: the image is stored without a
+/// verdict, as an outage of the classifier shouldn't take images down.
+#[derive(Clone)]
+pub struct Moderation {
+    classifier: Arc<dyn Classifier>,
+    default: TenantModeration,
+    /// Policy of each tenant host
+    tenants: HashMap<String, TenantModeration>,
+    counters: Arc<Counters>,
+}
+
+impl Moderation {
+    pub fn new(
+        classifier: Arc<dyn Classifier>,
+        default: TenantModeration,
+        tenants: HashMap<String, TenantModeration>,
+    ) -> Self {
+        Self {
+            classifier,
+            default,
+            tenants: tenants
+                .into_iter()
+                .map(|(tenant, policy)| (tenant.to_lowercase(), policy))
+                .collect(),
+            counters: Arc::default(),
+        }
+    }
+
+    /// The moderation configured by the environment, if a classifier is set
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let Some(url) = config.moderation_url.clone().filter(|url| !url.is_empty()) else {
+            return Ok(None);
+        };
+        let default = TenantModeration {
+            action: match config.moderation_action.as_str() {
+                "flag" => ModerationAction::Flag,
+                "block" => ModerationAction::Block,
+                "off" => ModerationAction::Off,
+                action => bail!(
+                    "Invalid MODERATION_ACTION {:?}, expected flag, block or off",
+                    action
+                ),
+            },
+            threshold: Some(config.moderation_threshold),
+        };
+        let tenants = match config.moderation_config.as_deref() {
+            Some(path) if !path.is_empty() => Self::load_tenants(Path::new(path))?,
+            _ => HashMap::new(),
+        };
+
+        let classifier =
+            HttpClassifier::new(url, Duration::from_millis(config.moderation_timeout_ms))?;
+        Ok(Some(Self::new(Arc::new(classifier), default, tenants)))
+    }
+
+    /// Read the JSON object of `TenantModeration` entries by tenant host at `path`
+    fn load_tenants(path: &Path) -> Result<HashMap<String, TenantModeration>> {
+        let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .context(format!("Invalid moderation config {}", path.display()))
+    }
+
+    fn policy_for(&self, tenant: Option<&str>) -> TenantModeration {
+        // Tenants are hosts, compared without their port
+        let host = tenant.map(|tenant| tenant.split(':').next().unwrap_or_default().to_lowercase());
+        let policy = host
+            .and_then(|host| self.tenants.get(&host))
+            .copied()
+            .unwrap_or(self.default);
+        TenantModeration {
+            threshold: policy.threshold.or(self.default.threshold),
+            ..policy
+        }
+    }
+
+    /// Verdict on an image of `url` processed for `tenant`, `None` when not classified
+    ///
+    /// Fails with [`ContentBlocked`] when the policy of the tenant blocks the image.
+    pub async fn moderate(
+        &self,
+        url: &str,
+        tenant: Option<&str>,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<Option<ModerationVerdict>> {
+        let policy = self.policy_for(tenant);
+        if policy.action == ModerationAction::Off {
+            return Ok(None);
+        }
+
+        let mut verdict = match self.classifier.classify(content_type, data).await {
+            Ok(verdict) => verdict,
+            Err(e) => {
+                warn!("Failed to classify image of {}: {:#}", url, e);
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+        };
+        self.counters.classified.fetch_add(1, Ordering::Relaxed);
+        verdict.flagged = verdict.score >= policy.threshold.unwrap_or(1.0);
+        if !verdict.flagged {
+            return Ok(Some(verdict));
+        }
+
+        if policy.action == ModerationAction::Block {
+            self.counters.blocked.fetch_add(1, Ordering::Relaxed);
+            return Err(ContentBlocked {
+                url: url.to_string(),
+                label: verdict.label.unwrap_or_else(|| "unsafe".to_string()),
+                score: verdict.score,
+            }
+            .into());
+        }
+        warn!("Flagged image of {}: scored {}", url, verdict.score);
+        self.counters.flagged.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(verdict))
+    }
+
+    pub fn stats(&self) -> ModerationStats {
+        let counters = &self.counters;
+        ModerationStats {
+            classified: counters.classified.load(Ordering::Relaxed),
+            flagged: counters.flagged.load(Ordering::Relaxed),
+            blocked: counters.blocked.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Classifier giving every image the same score
+    struct FixedScore(f32);
+
+    #[async_trait]
+    impl Classifier for FixedScore {
+        async fn classify(&self, _content_type: &str, _data: &[u8]) -> Result<ModerationVerdict> {
+            Ok(ModerationVerdict {
+                score: self.0,
+                label: Some("explicit".to_string()),
+                flagged: false,
+            })
+        }
+    }
+
+    fn scoring(score: f32) -> Moderation {
+        Moderation::new(
+            Arc::new(FixedScore(score)),
+            TenantModeration {
+                action: ModerationAction::Flag,
+                threshold: Some(0.8),
+            },
+            HashMap::from([
+                (
+                    "Kids.example.com".to_string(),
+                    TenantModeration {
+                        action: ModerationAction::Block,
+                        threshold: Some(0.5),
+                    },
+                ),
+                (
+                    "art.example.com".to_string(),
+                    TenantModeration {
+                        action: ModerationAction::Off,
+                        threshold: None,
+                    },
+                ),
+            ]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_applies_the_policy_of_the_tenant() {
+        let moderation = scoring(0.6);
+        let moderate =
+            |tenant| moderation.moderate("http://a.test/x.jpg", tenant, "image/jpeg", b"");
+
+        // Under the default threshold
+        let verdict = moderate(Some("other.example.com")).await.unwrap().unwrap();
+        assert!(!verdict.flagged);
+
+        let blocked = moderate(Some("kids.example.com:443")).await.unwrap_err();
+        assert!(blocked.is::<ContentBlocked>());
+
+        assert_eq!(moderate(Some("art.example.com")).await.unwrap(), None);
+
+        let flagged = scoring(0.9)
+            .moderate("http://a.test/x.jpg", None, "image/jpeg", b"")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(flagged.flagged);
+        assert_eq!(moderation.stats().blocked, 1);
+    }
+}
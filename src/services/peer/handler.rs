@@ -0,0 +1,397 @@
+use crate::modules::api::handler::ApiService;
+use crate::modules::env::env::EnvConfig;
+use crate::services::admin::handler::constant_time_eq;
+use crate::services::peer::ring::HashRing;
+use crate::services::storage::core::ObjectMetadata;
+use anyhow::{Context, Result, bail};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use reqwest::Client;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Route serving the variants held in memory to sibling replicas
+pub const PEER_ROUTE: &str = "/internal/peer-cache";
+
+/// Header carrying the JSON encoded metadata of a variant
+const METADATA_HEADER: &str = "x-object-metadata";
+
+/// Header carrying the shared secret between replicas
+const PEER_TOKEN_HEADER: &str = "x-peer-token";
+
+/// A processed image held in memory
+#[derive(Debug, Clone)]
+pub struct CachedObject {
+    pub data: Bytes,
+    pub metadata: ObjectMetadata,
+}
+
+/// Size-bounded in-memory store, evicting the oldest entries first
+#[derive(Debug, Default)]
+struct MemoryStore {
+    entries: HashMap<String, CachedObject>,
+    order: VecDeque<String>,
+    size: usize,
+    max_size: usize,
+}
+
+impl MemoryStore {
+    fn get(&self, key: &str) -> Option<CachedObject> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: String, object: CachedObject) {
+        if object.data.len() > self.max_size || self.entries.contains_key(&key) {
+            return;
+        }
+
+        self.size += object.data.len();
+        self.order.push_back(key.clone());
+        self.entries.insert(key, object);
+
+        while self.size > self.max_size {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.size -= evicted.data.len();
+            }
+        }
+    }
+
+    /// Remove an entry, returning whether it was held
+    fn remove(&mut self, key: &str) -> bool {
+        let Some(removed) = self.entries.remove(key) else {
+            return false;
+        };
+        self.size -= removed.data.len();
+        self.order.retain(|held| held != key);
+        true
+    }
+
+    /// Remove every entry, returning the bytes released
+    fn clear(&mut self) -> usize {
+        self.entries.clear();
+        self.order.clear();
+        std::mem::take(&mut self.size)
+    }
+}
+
+/// Groupcache-style cache of recently processed variants shared between replicas
+///
+/// Every key is owned by one replica of the peer list, chosen by consistent hashing.
+/// Replicas keep the variants they produce in memory and hand them to the owner, so
+/// siblings can fetch a fresh variant from the owner's memory instead of storage.
+#[derive(Clone)]
+pub struct PeerCache {
+    self_url: String,
+    ring: Arc<HashRing>,
+    store: Arc<Mutex<MemoryStore>>,
+    client: Client,
+    token: String,
+}
+
+impl PeerCache {
+    pub fn new(
+        self_url: String,
+        peers: Vec<String>,
+        max_size: usize,
+        timeout: Duration,
+        token: String,
+    ) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to create peer cache HTTP client")?;
+
+        Ok(Self {
+            self_url: normalize(&self_url),
+            ring: Arc::new(HashRing::new(peers.iter().map(|p| normalize(p)).collect())),
+            store: Arc::new(Mutex::new(MemoryStore {
+                max_size,
+                ..Default::default()
+            })),
+            client,
+            token,
+        })
+    }
+
+    /// Create the peer cache configured by the environment, if any
+    ///
+    /// The internal routes are served with the public ones, so a shared secret is required.
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let (Some(peers), Some(self_url)) = (&config.peer_urls, &config.peer_self_url) else {
+            return Ok(None);
+        };
+        let Some(token) = config.peer_token.clone().filter(|token| !token.is_empty()) else {
+            bail!("PEER_TOKEN must be set along with PEER_URLS");
+        };
+
+        let peers = peers
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Some(Self::new(
+            self_url.clone(),
+            peers,
+            config.peer_cache_max_mb * 1024 * 1024,
+            Duration::from_millis(config.peer_timeout_ms),
+            token,
+        )?))
+    }
+
+    /// Whether a request to the internal routes carries the shared secret
+    pub fn authorize(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(PEER_TOKEN_HEADER)
+            .is_some_and(|value| constant_time_eq(value.as_bytes(), self.token.as_bytes()))
+    }
+
+    fn request(&self, method: reqwest::Method, peer: &str, key: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}/{}", peer, PEER_ROUTE, key))
+            .header(PEER_TOKEN_HEADER, &self.token)
+    }
+
+    fn owner(&self, key: &str) -> Option<&str> {
+        self.ring.owner(key).filter(|owner| *owner != self.self_url)
+    }
+
+    /// Variant held in this replica's memory
+    pub fn get_local(&self, key: &str) -> Option<CachedObject> {
+        self.store.lock().unwrap().get(key)
+    }
+
+    pub fn put_local(&self, key: &str, object: CachedObject) {
+        self.store.lock().unwrap().insert(key.to_string(), object);
+    }
+
+    /// Drop a variant from this replica's memory, returning whether it was held
+    pub fn remove_local(&self, key: &str) -> bool {
+        self.store.lock().unwrap().remove(key)
+    }
+
+    /// Drop every variant held in this replica's memory, returning the bytes released
+    pub fn clear_local(&self) -> usize {
+        self.store.lock().unwrap().clear()
+    }
+
+    /// Look a variant up in memory, then in the memory of its owner
+    pub async fn get(&self, key: &str) -> Option<CachedObject> {
+        if let Some(object) = self.get_local(key) {
+            return Some(object);
+        }
+
+        let owner = self.owner(key)?;
+        match self.fetch(owner, key).await {
+            Ok(object) => object,
+            Err(e) => {
+                debug!("Peer cache lookup of {} on {} failed: {}", key, owner, e);
+                None
+            }
+        }
+    }
+
+    /// Keep a freshly produced variant, and hand it to its owner in the background
+    pub fn put(&self, key: &str, object: CachedObject) {
+        self.put_local(key, object.clone());
+
+        let Some(owner) = self.owner(key).map(str::to_string) else {
+            return;
+        };
+        let cache = self.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = cache.push(&owner, &key, object).await {
+                error!("Failed to push {} to peer {}: {}", key, owner, e);
+            }
+        });
+    }
+
+    /// Drop a variant from memory, here and on every sibling, so it is no longer served
+    ///
+    /// Besides the owner, any replica that produced the variant keeps a copy. Siblings
+    /// that can't be reached are logged, as their copy is only served until evicted by size.
+    pub async fn evict(&self, key: &str) {
+        self.remove_local(key);
+
+        let siblings = self.ring.peers().filter(|peer| *peer != self.self_url);
+        futures::future::join_all(siblings.map(|peer| async move {
+            let evicted = self
+                .request(reqwest::Method::DELETE, peer, key)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = evicted {
+                error!("Failed to evict {} from peer {}: {}", key, peer, e);
+            }
+        }))
+        .await;
+    }
+
+    async fn fetch(&self, peer: &str, key: &str) -> Result<Option<CachedObject>> {
+        let response = self.request(reqwest::Method::GET, peer, key).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+
+        let metadata = decode_metadata(response.headers());
+        Ok(Some(CachedObject {
+            data: response.bytes().await?,
+            metadata,
+        }))
+    }
+
+    async fn push(&self, peer: &str, key: &str, object: CachedObject) -> Result<()> {
+        self.request(reqwest::Method::PUT, peer, key)
+            .header(METADATA_HEADER, encode_metadata(&object.metadata))
+            .body(object.data)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+fn normalize(url: &str) -> String {
+    url.trim_end_matches('/').to_string()
+}
+
+fn encode_metadata(metadata: &ObjectMetadata) -> String {
+    serde_json::to_string(metadata).unwrap_or_default()
+}
+
+fn decode_metadata(headers: &HeaderMap) -> ObjectMetadata {
+    headers
+        .get(METADATA_HEADER)
+        .and_then(|value| serde_json::from_slice(value.as_bytes()).ok())
+        .unwrap_or_default()
+}
+
+/// Serve a variant from this replica's memory to a sibling
+pub async fn get_peer_object(
+    State(api_service): State<Arc<ApiService>>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let object = api_service
+        .resize_service
+        .peer_cache()
+        .filter(|cache| cache.authorize(&headers))
+        .and_then(|cache| cache.get_local(&key));
+
+    match object {
+        Some(object) => (
+            [(METADATA_HEADER, encode_metadata(&object.metadata))],
+            object.data,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Receive a variant produced by a sibling for a key this replica owns
+pub async fn put_peer_object(
+    State(api_service): State<Arc<ApiService>>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let Some(cache) = api_service.resize_service.peer_cache() else {
+        return StatusCode::NOT_FOUND;
+    };
+    if !cache.authorize(&headers) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    cache.put_local(
+        &key,
+        CachedObject {
+            data: body,
+            metadata: decode_metadata(&headers),
+        },
+    );
+    StatusCode::NO_CONTENT
+}
+
+/// Drop a variant purged on a sibling from this replica's memory
+pub async fn delete_peer_object(
+    State(api_service): State<Arc<ApiService>>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+) -> StatusCode {
+    let Some(cache) = api_service.resize_service.peer_cache() else {
+        return StatusCode::NOT_FOUND;
+    };
+    if !cache.authorize(&headers) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    cache.remove_local(&key);
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envconfig::Envconfig;
+
+    #[test]
+    fn test_memory_store_evicts_oldest() {
+        let mut store = MemoryStore {
+            max_size: 10,
+            ..Default::default()
+        };
+        let object = |size: usize| CachedObject {
+            data: vec![0; size].into(),
+            metadata: ObjectMetadata::default(),
+        };
+
+        store.insert("a".to_string(), object(4));
+        store.insert("b".to_string(), object(4));
+        store.insert("c".to_string(), object(4));
+        store.insert("huge".to_string(), object(11));
+
+        assert!(store.get("a").is_none());
+        assert!(store.get("b").is_some());
+        assert!(store.get("c").is_some());
+        assert!(store.get("huge").is_none());
+        assert_eq!(store.size, 8);
+
+        assert!(store.remove("b"));
+        assert!(!store.remove("b"));
+        assert_eq!(store.size, 4);
+        store.insert("d".to_string(), object(4));
+        store.insert("e".to_string(), object(4));
+        assert!(store.get("c").is_none());
+        assert!(store.get("e").is_some());
+        assert_eq!(store.size, 8);
+    }
+
+    #[test]
+    fn test_requires_a_token_along_with_peers() {
+        let config = |vars: &[(&str, &str)]| {
+            let vars = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            EnvConfig::init_from_hashmap(&vars).unwrap()
+        };
+        let peers = [
+            ("PEER_URLS", "http://a.test,http://b.test"),
+            ("PEER_SELF_URL", "http://a.test"),
+        ];
+
+        assert!(PeerCache::from_env(&config(&[])).unwrap().is_none());
+        assert!(PeerCache::from_env(&config(&peers)).is_err());
+        let with_token = [peers[0], peers[1], ("PEER_TOKEN", "secret")];
+        assert!(PeerCache::from_env(&config(&with_token)).unwrap().is_some());
+    }
+}
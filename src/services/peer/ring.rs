@@ -0,0 +1,75 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Points of every peer on the ring, to spread keys evenly
+const VIRTUAL_NODES: usize = 64;
+
+/// Consistent hash ring assigning each key to one peer
+///
+/// Adding or removing a peer only moves the keys of that peer, so the rest of the
+/// cluster keeps its warm caches.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    peers: Vec<String>,
+    ring: BTreeMap<u64, usize>,
+}
+
+impl HashRing {
+    pub fn new(peers: Vec<String>) -> Self {
+        let mut ring = BTreeMap::new();
+        for (index, peer) in peers.iter().enumerate() {
+            for node in 0..VIRTUAL_NODES {
+                ring.insert(hash(&format!("{}#{}", peer, node)), index);
+            }
+        }
+        Self { peers, ring }
+    }
+
+    /// Peer owning a key, or `None` for an empty ring
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        let point = hash(key);
+        let (_, index) = self
+            .ring
+            .range(point..)
+            .next()
+            .or_else(|| self.ring.iter().next())?;
+        Some(&self.peers[*index])
+    }
+
+    /// Every peer of the ring
+    pub fn peers(&self) -> impl Iterator<Item = &str> {
+        self.peers.iter().map(String::as_str)
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_only_moves_keys_of_removed_peer() {
+        let peers = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        let full = HashRing::new(peers(&["a", "b", "c"]));
+        let reduced = HashRing::new(peers(&["a", "b"]));
+
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        for key in &keys {
+            let owner = full.owner(key).unwrap();
+            if owner != "c" {
+                assert_eq!(reduced.owner(key), Some(owner));
+            }
+        }
+
+        // Every peer gets a share of the keys
+        for peer in ["a", "b", "c"] {
+            let owned = keys.iter().filter(|k| full.owner(k) == Some(peer)).count();
+            assert!(owned > 200, "{} owns {} keys", peer, owned);
+        }
+        assert_eq!(HashRing::new(Vec::new()).owner("key"), None);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::api::handler::ApiService;
+use crate::modules::env::env::EnvConfig;
+use crate::services::resize::errors::ErrorCode;
+use crate::services::resize::handler::ResizeResult;
+use crate::services::storage::core::content_type_from_key;
+use anyhow::{Context, Result, bail};
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::Host;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+/// Route of the art direction API
+pub const PICTURE_ROUTE: &str = "/api/pictures";
+
+/// Crop of a preset, one `<source>` of the `<picture>` element or its fallback `<img>`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Breakpoint {
+    /// Name the crops of pictures refer to, e.g. `wide`
+    pub name: String,
+    /// Media condition of the `<source>`, unset for the `<img>` of the last breakpoint
+    #[serde(default)]
+    pub media: Option<String>,
+    /// Resize query parameters without `url`, e.g. `"width": 1600, "ar": "21:9"`
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+/// Image published under an id, with its crops
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Picture {
+    pub url: String,
+    pub preset: String,
+    /// Parameters overriding those of the preset by breakpoint name, e.g. the `focus` of a crop
+    #[serde(default)]
+    pub crops: BTreeMap<String, Map<String, Value>>,
+}
+
+/// Art direction presets and the pictures cropped with them
+///
+/// A preset lists its breakpoints in the order of the `<source>` elements, the last one,
+/// without a media condition, being the fallback `<img>`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ArtDirectionManifest {
+    pub presets: BTreeMap<String, Vec<Breakpoint>>,
+    #[serde(default)]
+    pub pictures: BTreeMap<String, Picture>,
+}
+
+/// Resize of one breakpoint of a picture
+#[derive(Debug, Clone, PartialEq)]
+pub struct PictureVariant {
+    pub media: Option<String>,
+    pub query: ResizeQuery,
+}
+
+/// Pictures of the manifest, with the resize of each of their breakpoints
+///
+/// The manifest is checked as a whole when loaded, so a typo in a crop fails at startup
+/// rather than on the first request for the picture.
+#[derive(Debug, Clone, Default)]
+pub struct ArtDirection {
+    pictures: Arc<HashMap<String, Vec<PictureVariant>>>,
+}
+
+impl ArtDirection {
+    pub fn new(manifest: &ArtDirectionManifest) -> Result<Self> {
+        for (name, breakpoints) in &manifest.presets {
+            let Some((fallback, sources)) = breakpoints.split_last() else {
+                bail!("Preset {} has no breakpoints", name);
+            };
+            if fallback.media.is_some() {
+                bail!(
+                    "Breakpoint {} of preset {} is the fallback <img> and can't have a media condition",
+                    fallback.name,
+                    name
+                );
+            }
+            if let Some(source) = sources.iter().find(|source| source.media.is_none()) {
+                bail!(
+                    "Breakpoint {} of preset {} needs a media condition",
+                    source.name,
+                    name
+                );
+            }
+        }
+
+        let mut pictures = HashMap::with_capacity(manifest.pictures.len());
+        for (id, picture) in &manifest.pictures {
+            let breakpoints = manifest.presets.get(&picture.preset).with_context(|| {
+                format!("Picture {} uses unknown preset {}", id, picture.preset)
+            })?;
+            if let Some(crop) = picture.crops.keys().find(|crop| {
+                !breakpoints
+                    .iter()
+                    .any(|breakpoint| &&breakpoint.name == crop)
+            }) {
+                bail!(
+                    "Picture {} crops unknown breakpoint {} of preset {}",
+                    id,
+                    crop,
+                    picture.preset
+                );
+            }
+
+            let variants = breakpoints
+                .iter()
+                .map(|breakpoint| {
+                    let mut params = breakpoint.params.clone();
+                    if let Some(crop) = picture.crops.get(&breakpoint.name) {
+                        params.extend(crop.clone());
+                    }
+                    if params.contains_key("url") {
+                        bail!(
+                            "Breakpoint {} of picture {} must not set url",
+                            breakpoint.name,
+                            id
+                        );
+                    }
+                    params.insert("url".to_string(), Value::String(picture.url.clone()));
+                    let query: ResizeQuery = serde_json::from_value(Value::Object(params))
+                        .with_context(|| {
+                            format!("Invalid breakpoint {} of picture {}", breakpoint.name, id)
+                        })?;
+                    query.validate().with_context(|| {
+                        format!("Invalid breakpoint {} of picture {}", breakpoint.name, id)
+                    })?;
+                    Ok(PictureVariant {
+                        media: breakpoint.media.clone(),
+                        query,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            pictures.insert(id.clone(), variants);
+        }
+
+        Ok(Self {
+            pictures: Arc::new(pictures),
+        })
+    }
+
+    /// The art direction configured by the environment, if enabled
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let Some(path) = config
+            .art_direction_manifest
+            .as_deref()
+            .filter(|path| !path.is_empty())
+        else {
+            return Ok(None);
+        };
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        let manifest: ArtDirectionManifest = serde_json::from_slice(&data)
+            .with_context(|| format!("Invalid art direction manifest {}", path))?;
+        Ok(Some(Self::new(&manifest)?))
+    }
+
+    /// Resizes of the breakpoints of picture `id`, `None` for unknown pictures
+    pub fn variants(&self, id: &str) -> Option<&[PictureVariant]> {
+        self.pictures.get(id).map(Vec::as_slice)
+    }
+}
+
+/// Image of a breakpoint, resized or the source when the resize failed
+struct Rendition {
+    url: String,
+    content_type: Option<&'static str>,
+    width: Option<u32>,
+    height: Option<u32>,
+    error_code: Option<ErrorCode>,
+}
+
+impl From<ResizeResult> for Rendition {
+    fn from(result: ResizeResult) -> Self {
+        Self {
+            content_type: Some(content_type_from_key(&result.key)),
+            url: result.url,
+            width: result.width,
+            height: result.height,
+            error_code: None,
+        }
+    }
+}
+
+/// `<source>` of a picture
+#[derive(Debug, Clone, Serialize)]
+pub struct PictureSource {
+    pub media: String,
+    pub srcset: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Why the source image is shown, see [`ErrorCode`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Fallback `<img>` of a picture
+#[derive(Debug, Clone, Serialize)]
+pub struct PictureImg {
+    pub src: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// `<picture>` element of an art-directed picture
+#[derive(Debug, Clone, Serialize)]
+pub struct PictureElement {
+    pub id: String,
+    pub sources: Vec<PictureSource>,
+    pub img: PictureImg,
+}
+
+/// Resize every breakpoint of a picture and describe the `<picture>` element showing it
+///
+/// Breakpoints whose resize fails show the source, as resizes redirect to it, unless the
+/// failure is one a resize rejects, which then rejects the whole picture.
+pub async fn picture(
+    State(api_service): State<Arc<ApiService>>,
+    host: Host,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(variants) = api_service
+        .art_direction
+        .as_ref()
+        .and_then(|art_direction| art_direction.variants(&id))
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let resizes = variants
+        .iter()
+        .map(|variant| api_service.resize_image(variant.query.clone(), Some(&host.0)));
+    let results = futures::future::join_all(resizes).await;
+
+    let mut renditions = Vec::with_capacity(results.len());
+    for (variant, result) in variants.iter().zip(results) {
+        let rendition = match result {
+            Ok(result) => Rendition::from(result),
+            Err(e) => {
+                let code = ErrorCode::classify(&e);
+                let status = match code {
+                    ErrorCode::ContentBlocked => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                    ErrorCode::TooComplex => StatusCode::PAYLOAD_TOO_LARGE,
+                    ErrorCode::Overloaded
+                    | ErrorCode::DeadlineExceeded
+                    | ErrorCode::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => {
+                        error!("Failed to resize picture {} ({}): {}", id, code, e);
+                        renditions.push(Rendition {
+                            url: variant.query.url.clone(),
+                            content_type: None,
+                            width: None,
+                            height: None,
+                            error_code: Some(code),
+                        });
+                        continue;
+                    }
+                };
+                warn!("Rejecting picture {}: {}", id, e);
+                return (status, [("x-error-code", code.as_str())], e.to_string()).into_response();
+            }
+        };
+        renditions.push(rendition);
+    }
+
+    // Presets end with the fallback, so there is always one
+    let img = renditions
+        .pop()
+        .expect("Pictures have a fallback breakpoint");
+    let sources = variants
+        .iter()
+        .zip(renditions)
+        .map(|(variant, rendition)| PictureSource {
+            media: variant.media.clone().unwrap_or_default(),
+            srcset: rendition.url,
+            content_type: rendition.content_type,
+            width: rendition.width,
+            height: rendition.height,
+            error_code: rendition.error_code,
+        })
+        .collect();
+    let img = PictureImg {
+        src: img.url,
+        width: img.width,
+        height: img.height,
+        error_code: img.error_code,
+    };
+    Json(PictureElement { id, sources, img }).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(picture: Value) -> Result<ArtDirection> {
+        let manifest = serde_json::json!({
+            "presets": {
+                "story": [
+                    {"name": "wide", "media": "(min-width: 1200px)", "width": 1600, "ar": "21:9"},
+                    {"name": "mobile", "width": 640, "ar": "4:5"}
+                ]
+            },
+            "pictures": {"story-1": picture}
+        });
+        ArtDirection::new(&serde_json::from_value(manifest)?)
+    }
+
+    #[test]
+    fn test_applies_the_crops_of_pictures_to_their_preset() {
+        let art_direction = manifest(serde_json::json!({
+            "url": "https://a.test/1.jpg",
+            "preset": "story",
+            "crops": {"mobile": {"focus": "0.7,0.4"}}
+        }))
+        .unwrap();
+
+        let variants = art_direction.variants("story-1").unwrap();
+        assert_eq!(variants[0].media.as_deref(), Some("(min-width: 1200px)"));
+        assert_eq!(variants[0].query.width, Some(1600));
+        assert_eq!(variants[0].query.focus, None);
+        assert_eq!(variants[1].media, None);
+        assert_eq!(variants[1].query.ar.as_deref(), Some("4:5"));
+        assert_eq!(variants[1].query.focus.as_deref(), Some("0.7,0.4"));
+        assert_eq!(variants[1].query.url, "https://a.test/1.jpg");
+        assert!(art_direction.variants("story-2").is_none());
+    }
+
+    #[test]
+    fn test_rejects_invalid_crops() {
+        for picture in [
+            serde_json::json!({"url": "https://a.test/1.jpg", "preset": "gallery"}),
+            serde_json::json!({
+                "url": "https://a.test/1.jpg",
+                "preset": "story",
+                "crops": {"tablet": {"focus": "0.5,0.5"}}
+            }),
+            serde_json::json!({
+                "url": "https://a.test/1.jpg",
+                "preset": "story",
+                "crops": {"wide": {"focus": "2,0.5"}}
+            }),
+        ] {
+            assert!(manifest(picture).is_err());
+        }
+    }
+}
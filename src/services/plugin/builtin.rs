@@ -0,0 +1,50 @@
+use crate::models::params::ResizeQuery;
+use crate::services::plugin::core::TransformPlugin;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Invert the colors of the image
+pub struct Invert;
+
+impl TransformPlugin for Invert {
+    fn name(&self) -> &str {
+        "invert"
+    }
+
+    fn apply(&self, mut image: DynamicImage, _params: &ResizeQuery) -> Result<DynamicImage> {
+        image.invert();
+        Ok(image)
+    }
+}
+
+/// Give the image a warm brown sepia tone
+pub struct Sepia;
+
+impl TransformPlugin for Sepia {
+    fn name(&self) -> &str {
+        "sepia"
+    }
+
+    fn apply(&self, image: DynamicImage, _params: &ResizeQuery) -> Result<DynamicImage> {
+        let has_alpha = image.color().has_alpha();
+        let mut image = image.to_rgba8();
+        for pixel in image.pixels_mut() {
+            let [r, g, b, a] = pixel.0.map(f32::from);
+            let tone = |cr: f32, cg: f32, cb: f32| (r * cr + g * cg + b * cb).min(255.0) as u8;
+            pixel.0 = [
+                tone(0.393, 0.769, 0.189),
+                tone(0.349, 0.686, 0.168),
+                tone(0.272, 0.534, 0.131),
+                a as u8,
+            ];
+        }
+        let image = DynamicImage::ImageRgba8(image);
+
+        // Keep opaque images opaque so they can still be encoded as JPEG
+        if has_alpha {
+            Ok(image)
+        } else {
+            Ok(DynamicImage::ImageRgb8(image.to_rgb8()))
+        }
+    }
+}
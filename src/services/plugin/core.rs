@@ -0,0 +1,14 @@
+use crate::models::params::ResizeQuery;
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Custom transformation stage, run after resizing and before encoding
+///
+/// Plugins run on the CPU pool, so `apply` is synchronous and must not block on I/O.
+pub trait TransformPlugin: Send + Sync {
+    /// Name used to select the plugin with the `plugin` query parameter
+    fn name(&self) -> &str;
+
+    /// Transform the resized image
+    fn apply(&self, image: DynamicImage, params: &ResizeQuery) -> Result<DynamicImage>;
+}
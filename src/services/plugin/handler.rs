@@ -0,0 +1,140 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::env::env::EnvConfig;
+use crate::services::plugin::core::TransformPlugin;
+use anyhow::{Result, anyhow};
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Registry of the transform plugins available to requests
+#[derive(Clone, Default)]
+pub struct PluginRegistry {
+    plugins: HashMap<String, Arc<dyn TransformPlugin>>,
+}
+
+impl PluginRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry holding the built-in plugins enabled at compile time
+    pub fn with_builtins() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::new();
+
+        #[cfg(feature = "builtin_plugins")]
+        {
+            registry.register(crate::services::plugin::builtin::Invert);
+            registry.register(crate::services::plugin::builtin::Sepia);
+        }
+
+        registry
+    }
+
+    /// Create the registry configured by the environment: built-ins plus WASM plugins
+    #[allow(unused_variables)]
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        #[allow(unused_mut)]
+        let mut registry = Self::with_builtins();
+
+        #[cfg(feature = "wasm_plugins")]
+        if let Some(dir) = &config.wasm_plugin_dir {
+            use crate::services::plugin::wasm::{WasmLimits, load_dir};
+
+            let limits = WasmLimits {
+                fuel: config.wasm_plugin_fuel,
+                max_memory: config.wasm_plugin_max_memory_mb * 1024 * 1024,
+            };
+            load_dir(&mut registry, std::path::Path::new(dir), &limits)?;
+        }
+
+        Ok(registry)
+    }
+
+    /// Register a plugin, replacing any plugin with the same name
+    pub fn register(&mut self, plugin: impl TransformPlugin + 'static) {
+        self.plugins
+            .insert(plugin.name().to_string(), Arc::new(plugin));
+    }
+
+    /// Get a plugin by name
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn TransformPlugin>> {
+        self.plugins.get(name)
+    }
+
+    /// Names of the registered plugins, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.plugins.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Check that every plugin requested by `params` is registered
+    pub fn validate(&self, params: &ResizeQuery) -> Result<()> {
+        for name in params.plugins() {
+            if !self.plugins.contains_key(name) {
+                return Err(anyhow!(
+                    "Unknown plugin: {} (available: {})",
+                    name,
+                    self.names().join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the plugins requested by `params`, in order
+    pub fn apply(&self, image: DynamicImage, params: &ResizeQuery) -> Result<DynamicImage> {
+        params.plugins().try_fold(image, |image, name| {
+            let plugin = self
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown plugin: {}", name))?;
+            plugin
+                .apply(image, params)
+                .map_err(|e| e.context(format!("Plugin {} failed", name)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Flip;
+
+    impl TransformPlugin for Flip {
+        fn name(&self) -> &str {
+            "flip"
+        }
+
+        fn apply(&self, image: DynamicImage, _params: &ResizeQuery) -> Result<DynamicImage> {
+            Ok(image.fliph())
+        }
+    }
+
+    #[test]
+    fn test_apply_requested_plugins() {
+        let mut registry = PluginRegistry::new();
+        registry.register(Flip);
+
+        let mut image = image::RgbImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        let params = ResizeQuery {
+            plugin: Some("flip".to_string()),
+            ..Default::default()
+        };
+
+        let output = registry
+            .apply(DynamicImage::ImageRgb8(image), &params)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(output.get_pixel(1, 0), &image::Rgb([255, 0, 0]));
+
+        let unknown = ResizeQuery {
+            plugin: Some("flip,missing".to_string()),
+            ..Default::default()
+        };
+        assert!(registry.validate(&unknown).is_err());
+    }
+}
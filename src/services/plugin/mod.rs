@@ -0,0 +1,8 @@
+pub mod core;
+pub mod handler;
+
+#[cfg(feature = "builtin_plugins")]
+pub mod builtin;
+
+#[cfg(feature = "wasm_plugins")]
+pub mod wasm;
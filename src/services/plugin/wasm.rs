@@ -0,0 +1,250 @@
+use crate::models::params::ResizeQuery;
+use crate::services::plugin::core::TransformPlugin;
+use crate::services::plugin::handler::PluginRegistry;
+use anyhow::{Context, Result, anyhow, bail};
+use image::{DynamicImage, RgbaImage};
+use std::path::Path;
+use tracing::info;
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Resource limits applied to every WASM plugin call
+#[derive(Debug, Clone)]
+pub struct WasmLimits {
+    /// Fuel (roughly, WASM instructions) available to a single call
+    pub fuel: u64,
+    /// Maximum linear memory of an instance, in bytes
+    pub max_memory: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 1_000_000_000,
+            max_memory: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Untrusted transform plugin compiled from a WASM module
+///
+/// Modules cannot import anything, so they have no access to the host besides the
+/// pixel buffer. They must export:
+///
+/// - `memory`: the linear memory holding the pixel buffer
+/// - `alloc(len: i32) -> i32`: reserve `len` bytes and return their offset
+/// - `filter(ptr: i32, width: i32, height: i32) -> i32`: transform the RGBA8 buffer
+///   at `ptr` in place, returning 0 on success
+///
+/// Every call runs in a fresh instance with its own fuel and memory limits, so a
+/// misbehaving module fails its request without affecting the others.
+pub struct WasmPlugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    limits: WasmLimits,
+}
+
+impl WasmPlugin {
+    /// Compile and check a WASM module
+    pub fn new(
+        engine: &Engine,
+        name: impl Into<String>,
+        wasm: &[u8],
+        limits: WasmLimits,
+    ) -> Result<Self> {
+        let name = name.into();
+        let module = Module::new(engine, wasm)
+            .map_err(|e| anyhow!("Failed to compile WASM plugin {}: {}", name, e))?;
+
+        if let Some(import) = module.imports().next() {
+            bail!(
+                "WASM plugin {} must not import anything (imports {}::{})",
+                name,
+                import.module(),
+                import.name()
+            );
+        }
+        for export in ["memory", "alloc", "filter"] {
+            if module.get_export(export).is_none() {
+                bail!("WASM plugin {} does not export `{}`", name, export);
+            }
+        }
+
+        Ok(Self {
+            name,
+            engine: engine.clone(),
+            module,
+            limits,
+        })
+    }
+
+    /// Create the engine shared by WASM plugins, with fuel metering enabled
+    pub fn engine() -> Result<Engine> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config).map_err(|e| anyhow!("Failed to create WASM engine: {}", e))
+    }
+
+    fn run(&self, pixels: &[u8], width: u32, height: u32) -> wasmtime::Result<Vec<u8>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.max_memory)
+            .instances(1)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.limits.fuel)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("`memory` is not a memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let filter = instance.get_typed_func::<(i32, i32, i32), i32>(&mut store, "filter")?;
+
+        let len = i32::try_from(pixels.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as usize, pixels)?;
+
+        let code = filter.call(&mut store, (ptr, width as i32, height as i32))?;
+        if code != 0 {
+            return Err(wasmtime::Error::msg(format!(
+                "filter returned error code {}",
+                code
+            )));
+        }
+
+        let mut output = vec![0; pixels.len()];
+        memory.read(&store, ptr as usize, &mut output)?;
+        Ok(output)
+    }
+}
+
+impl TransformPlugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, image: DynamicImage, _params: &ResizeQuery) -> Result<DynamicImage> {
+        let has_alpha = image.color().has_alpha();
+        let image = image.to_rgba8();
+        let (width, height) = image.dimensions();
+
+        let output = self
+            .run(image.as_raw(), width, height)
+            .map_err(|e| anyhow!("WASM plugin {} failed: {}", self.name, e))?;
+        let image = DynamicImage::ImageRgba8(
+            RgbaImage::from_raw(width, height, output).context("Invalid WASM plugin output")?,
+        );
+
+        // Keep opaque images opaque so they can still be encoded as JPEG
+        if has_alpha {
+            Ok(image)
+        } else {
+            Ok(DynamicImage::ImageRgb8(image.to_rgb8()))
+        }
+    }
+}
+
+/// Register every `<name>.wasm` module of a directory as plugin `<name>`
+pub fn load_dir(registry: &mut PluginRegistry, dir: &Path, limits: &WasmLimits) -> Result<usize> {
+    let engine = WasmPlugin::engine()?;
+    let entries = std::fs::read_dir(dir).context(format!(
+        "Failed to read WASM plugin directory {}",
+        dir.display()
+    ))?;
+
+    let mut loaded = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("wasm") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let wasm = std::fs::read(&path).context(format!("Failed to read {}", path.display()))?;
+        registry.register(WasmPlugin::new(&engine, name, &wasm, limits.clone())?);
+        info!("Loaded WASM plugin {} from {}", name, path.display());
+        loaded += 1;
+    }
+
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inverts the RGB channels of every pixel
+    const INVERT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "filter") (param $ptr i32) (param $w i32) (param $h i32) (result i32)
+            (local $i i32) (local $end i32)
+            (local.set $end (i32.mul (i32.mul (local.get $w) (local.get $h)) (i32.const 4)))
+            (block $done
+              (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $end)))
+                (if (i32.ne (i32.rem_u (local.get $i) (i32.const 4)) (i32.const 3))
+                  (then
+                    (i32.store8 (local.get $i)
+                      (i32.sub (i32.const 255) (i32.load8_u (local.get $i))))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+            (i32.const 0)))
+    "#;
+
+    /// Never returns
+    const SPIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "filter") (param i32 i32 i32) (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0)))
+    "#;
+
+    fn plugin(wat: &str) -> Result<WasmPlugin> {
+        let engine = WasmPlugin::engine()?;
+        let limits = WasmLimits {
+            fuel: 1_000_000,
+            ..Default::default()
+        };
+        WasmPlugin::new(&engine, "test", &wat::parse_str(wat)?, limits)
+    }
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([10, 20, 30])))
+    }
+
+    #[test]
+    fn test_wasm_filter_transforms_pixels() {
+        let output = plugin(INVERT)
+            .unwrap()
+            .apply(image(), &ResizeQuery::default())
+            .unwrap();
+
+        assert!(!output.color().has_alpha());
+        assert_eq!(
+            output.to_rgb8().get_pixel(3, 3),
+            &image::Rgb([245, 235, 225])
+        );
+    }
+
+    #[test]
+    fn test_wasm_filter_is_sandboxed() {
+        // Runaway modules are stopped by the fuel limit
+        assert!(
+            plugin(SPIN)
+                .unwrap()
+                .apply(image(), &ResizeQuery::default())
+                .is_err()
+        );
+
+        // Modules cannot reach the host
+        let importing = r#"(module (import "env" "exit" (func)) (memory (export "memory") 1))"#;
+        assert!(plugin(importing).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::env::env::EnvConfig;
+use crate::services::resize::handler::ResizeService;
+use anyhow::{Context, Result, bail};
+use futures::stream::{self, StreamExt};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Time allowed to fetch the manifest or one of its sitemaps
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between two checks of whether live traffic left the pools idle
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Variants to keep generated ahead of requests
+///
+/// Every image URL is resized with every preset. Presets are resize query parameters
+/// without `url`, e.g. `{"width": 300, "format": "webp"}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Manifest {
+    pub presets: BTreeMap<String, Map<String, Value>>,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Sitemaps whose images are added to `urls`
+    #[serde(default)]
+    pub sitemaps: Vec<String>,
+}
+
+impl Manifest {
+    /// Query of every variant, combining each URL with each preset
+    pub fn queries(&self) -> Result<Vec<ResizeQuery>> {
+        let mut queries = Vec::with_capacity(self.urls.len() * self.presets.len());
+        for (name, preset) in &self.presets {
+            if preset.contains_key("url") {
+                bail!("Preset {} must not set url", name);
+            }
+            for url in &self.urls {
+                let mut params = preset.clone();
+                params.insert("url".to_string(), Value::String(url.clone()));
+                let query = serde_json::from_value(Value::Object(params))
+                    .with_context(|| format!("Invalid preset {}", name))?;
+                queries.push(query);
+            }
+        }
+        Ok(queries)
+    }
+}
+
+/// Image URLs of a sitemap, from its `<image:loc>` entries or else its `<loc>` entries
+pub fn sitemap_urls(sitemap: &str) -> Vec<String> {
+    let images = tag_values(sitemap, "image:loc");
+    if !images.is_empty() {
+        return images;
+    }
+    tag_values(sitemap, "loc")
+}
+
+/// Text of every `<tag>` element, with the XML entities allowed in URLs decoded
+fn tag_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    xml.split(&open)
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close))
+        .map(|(value, _)| {
+            value
+                .trim()
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&")
+        })
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Outcome of a pre-generation run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PregenReport {
+    pub variants: usize,
+    pub generated: usize,
+    /// Variants already in storage
+    pub cached: usize,
+    pub failed: usize,
+}
+
+/// Scheduled pre-generation of the variants listed by a manifest
+///
+/// The manifest is pulled every interval and the variants missing from storage are
+/// resized at low priority: a few at once, and only while live requests leave the
+/// download queue empty and CPU threads free.
+#[derive(Debug, Clone)]
+pub struct Pregenerator {
+    /// http(s) URL or file path of the manifest
+    manifest: String,
+    interval: Duration,
+    concurrency: usize,
+    client: Client,
+}
+
+impl Pregenerator {
+    pub fn new(manifest: &str, interval: Duration, concurrency: usize) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .context("Failed to create pre-generation HTTP client")?;
+
+        Ok(Self {
+            manifest: manifest.to_string(),
+            interval,
+            concurrency: concurrency.max(1),
+            client,
+        })
+    }
+
+    /// The pre-generation configured by the environment, if enabled
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let Some(manifest) = config.pregen_manifest.as_deref().filter(|m| !m.is_empty()) else {
+            return Ok(None);
+        };
+        Ok(Some(Self::new(
+            manifest,
+            Duration::from_secs(config.pregen_interval_secs.max(1)),
+            config.pregen_concurrency,
+        )?))
+    }
+
+    /// Fetch the manifest, with the images of its sitemaps
+    pub async fn load(&self) -> Result<Manifest> {
+        let manifest = self.fetch(&self.manifest).await?;
+        let mut manifest: Manifest = serde_json::from_str(&manifest)
+            .with_context(|| format!("Invalid pre-generation manifest {}", self.manifest))?;
+        for sitemap in std::mem::take(&mut manifest.sitemaps) {
+            let urls = sitemap_urls(&self.fetch(&sitemap).await?);
+            debug!("Sitemap {} lists {} images", sitemap, urls.len());
+            manifest.urls.extend(urls);
+        }
+        manifest.urls.sort();
+        manifest.urls.dedup();
+        Ok(manifest)
+    }
+
+    async fn fetch(&self, location: &str) -> Result<String> {
+        if !location.starts_with("http://") && !location.starts_with("https://") {
+            return tokio::fs::read_to_string(location)
+                .await
+                .with_context(|| format!("Failed to read {}", location));
+        }
+        self.client
+            .get(location)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to fetch {}", location))?
+            .text()
+            .await
+            .with_context(|| format!("Failed to read {}", location))
+    }
+
+    /// Resize the variants of the manifest missing from storage
+    pub async fn pregenerate(&self, resize_service: &ResizeService) -> Result<PregenReport> {
+        let queries = self.load().await?.queries()?;
+        let mut report = PregenReport {
+            variants: queries.len(),
+            ..PregenReport::default()
+        };
+
+        let mut outcomes = stream::iter(queries)
+            .map(|query| async move {
+                let plan = resize_service.plan(&query, None).await;
+                if plan.cache_hit {
+                    return Outcome::Cached;
+                }
+                if let Some(error) = plan.error {
+                    warn!("Skipped pre-generation of {}: {}", query.url, error);
+                    return Outcome::Failed;
+                }
+                wait_for_idle(resize_service).await;
+                match resize_service.resize(&query, None).await {
+                    Ok(_) => Outcome::Generated,
+                    Err(e) => {
+                        warn!("Failed to pre-generate {}: {:#}", query.url, e);
+                        Outcome::Failed
+                    }
+                }
+            })
+            .buffer_unordered(self.concurrency);
+        while let Some(outcome) = outcomes.next().await {
+            match outcome {
+                Outcome::Generated => report.generated += 1,
+                Outcome::Cached => report.cached += 1,
+                Outcome::Failed => report.failed += 1,
+            }
+        }
+        Ok(report)
+    }
+
+    /// Pre-generate every interval, forever
+    pub async fn run(self, resize_service: ResizeService) {
+        let mut ticks = tokio::time::interval(self.interval);
+        loop {
+            ticks.tick().await;
+            match resize_service.pregenerate(&self).await {
+                Ok(Some(report)) => info!(
+                    variants = report.variants,
+                    generated = report.generated,
+                    cached = report.cached,
+                    failed = report.failed,
+                    "Pre-generation done"
+                ),
+                Ok(None) => debug!("Pre-generation run by another replica"),
+                Err(e) => warn!("Pre-generation failed: {:?}", e),
+            }
+        }
+    }
+}
+
+enum Outcome {
+    Generated,
+    Cached,
+    Failed,
+}
+
+/// Wait until live requests leave the download queue empty and a CPU thread free
+async fn wait_for_idle(resize_service: &ResizeService) {
+    loop {
+        let stats = resize_service.image_stats();
+        if stats.download_queue.queued == 0 && stats.processing_in_flight < stats.cpu_threads {
+            return;
+        }
+        tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use super::*;
+    use crate::services::cache::handler::CacheServiceBuilder;
+    use crate::services::storage::handler::{StorageConfig, StorageService};
+    use image::{DynamicImage, ImageFormat};
+    use std::io::Cursor;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_reads_the_images_of_sitemaps() {
+        let sitemap = r#"<urlset>
+            <url><loc>https://shop.test/p/1</loc>
+              <image:image><image:loc>https://cdn.test/1.jpg?a=1&amp;b=2</image:loc></image:image>
+            </url>
+        </urlset>"#;
+        assert_eq!(sitemap_urls(sitemap), ["https://cdn.test/1.jpg?a=1&b=2"]);
+        assert_eq!(
+            sitemap_urls("<urlset><url><loc>https://cdn.test/2.png</loc></url></urlset>"),
+            ["https://cdn.test/2.png"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generates_missing_variants_once() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(40, 30)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let origin = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/source.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(png.into_inner()))
+            .expect(2)
+            .mount(&origin)
+            .await;
+        let manifest = serde_json::json!({
+            "presets": {
+                "thumb": {"width": 10, "format": "png"},
+                "small": {"width": 20, "format": "webp"},
+            },
+            "urls": [format!("{}/source.png", origin.uri())],
+        });
+        Mock::given(method("GET"))
+            .and(path("/manifest.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(manifest))
+            .mount(&origin)
+            .await;
+
+        let storage = StorageService::new(
+            StorageConfig::new("http://cdn.test".to_string()).with_storage_type("in_memory"),
+        )
+        .unwrap();
+        let cache = CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap();
+        let resize_service = ResizeService::new(storage, cache).unwrap();
+        let pregenerator = Pregenerator::new(
+            &format!("{}/manifest.json", origin.uri()),
+            Duration::from_secs(60),
+            2,
+        )
+        .unwrap();
+
+        let report = pregenerator.pregenerate(&resize_service).await.unwrap();
+        assert_eq!((report.variants, report.generated), (2, 2));
+        // Variants already in storage aren't downloaded again
+        let report = pregenerator.pregenerate(&resize_service).await.unwrap();
+        assert_eq!((report.cached, report.generated), (2, 0));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::services::deadline::handler as deadline;
+use crate::services::image::cancel::Cancelled;
+use crate::services::resize::handler::ResizeResult;
+use anyhow::{Result, anyhow};
+use futures::FutureExt;
+use futures::future::{BoxFuture, Shared};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+type Outcome = std::result::Result<ResizeResult, Arc<anyhow::Error>>;
+
+/// Failure of a resize shared by identical requests
+///
+/// The causes of the failure stay in its chain, so each request classifies it alike.
+#[derive(Debug)]
+pub struct Coalesced(pub Arc<anyhow::Error>);
+
+impl fmt::Display for Coalesced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Coalesced {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref().as_ref())
+    }
+}
+
+/// Resize in flight, with the number of requests waiting for it
+struct Flight {
+    id: u64,
+    outcome: Shared<BoxFuture<'static, Outcome>>,
+    waiters: usize,
+    cancel: CancellationToken,
+}
+
+/// Resizes in flight on this replica, shared by the identical requests arriving meanwhile
+///
+/// The work runs in its own task with its own token, cancelled once every request waiting
+/// for it gave up, so a client disconnecting doesn't fail the others.
+#[derive(Clone, Default)]
+pub struct Coalescer {
+    flights: Arc<Mutex<HashMap<String, Flight>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+/// Request waiting for a flight, leaving it when dropped
+struct Waiter<'a> {
+    coalescer: &'a Coalescer,
+    key: &'a str,
+    id: u64,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let mut flights = self.coalescer.flights.lock().unwrap();
+        if let Some(flight) = flights.get_mut(self.key)
+            && flight.id == self.id
+        {
+            flight.waiters -= 1;
+            if flight.waiters == 0 {
+                flight.cancel.cancel();
+                flights.remove(self.key);
+            }
+        }
+    }
+}
+
+impl Coalescer {
+    /// Run `work` for `key`, or wait for the result of the identical work in flight
+    ///
+    /// Also tells whether the result was shared with another request.
+    pub async fn run<F>(
+        &self,
+        key: &str,
+        cancel: &CancellationToken,
+        work: impl FnOnce(CancellationToken) -> F,
+    ) -> (Result<ResizeResult>, bool)
+    where
+        F: Future<Output = Result<ResizeResult>> + Send + 'static,
+    {
+        let (id, outcome, joined) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get_mut(key) {
+                Some(flight) => {
+                    flight.waiters += 1;
+                    (flight.id, flight.outcome.clone(), true)
+                }
+                None => {
+                    let token = CancellationToken::new();
+                    let task = tokio::spawn(
+                        deadline::scope(deadline::current(), work(token.clone())).in_current_span(),
+                    );
+                    let outcome = async move {
+                        match task.await {
+                            Ok(result) => result.map_err(Arc::new),
+                            Err(e) => Err(Arc::new(anyhow!("Resize task failed: {}", e))),
+                        }
+                    }
+                    .boxed()
+                    .shared();
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    flights.insert(
+                        key.to_string(),
+                        Flight {
+                            id,
+                            outcome: outcome.clone(),
+                            waiters: 1,
+                            cancel: token,
+                        },
+                    );
+                    (id, outcome, false)
+                }
+            }
+        };
+
+        let waiter = Waiter {
+            coalescer: self,
+            key,
+            id,
+        };
+        let outcome = tokio::select! {
+            outcome = outcome => outcome,
+            _ = cancel.cancelled() => return (Err(Cancelled.into()), joined),
+        };
+        // Later requests look the image up in storage rather than joining a finished flight
+        {
+            let mut flights = self.flights.lock().unwrap();
+            if flights.get(key).is_some_and(|flight| flight.id == id) {
+                flights.remove(key);
+            }
+        }
+        drop(waiter);
+
+        // The error is only shared when another request still holds it
+        let result =
+            outcome.map_err(|e| Arc::try_unwrap(e).unwrap_or_else(|e| Coalesced(e).into()));
+        (result, joined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::debug::handler::StageTimings;
+    use crate::services::resize::errors::{ErrorCode, InvalidParams};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    fn result() -> ResizeResult {
+        ResizeResult {
+            url: "https://cdn.test/a.jpg".to_string(),
+            key: "a.jpg".to_string(),
+            surrogate_keys: Vec::new(),
+            cache_hit: false,
+            width: None,
+            height: None,
+            size: None,
+            quality: None,
+            cache_key: "a.jpg".to_string(),
+            timings: StageTimings::default(),
+            filter: None,
+            encoder: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_share_the_work() {
+        let coalescer = Coalescer::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let run = |fail: bool| {
+            let runs = runs.clone();
+            let coalescer = coalescer.clone();
+            async move {
+                coalescer
+                    .run("a.jpg", &CancellationToken::new(), move |_| async move {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        if fail {
+                            Err(InvalidParams(anyhow!("Invalid width")).into())
+                        } else {
+                            Ok(result())
+                        }
+                    })
+                    .await
+            }
+        };
+
+        let results = futures::future::join_all((0..5).map(|_| run(false))).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(results.iter().filter(|(_, joined)| *joined).count(), 4);
+        assert!(results.iter().all(|(result, _)| result.is_ok()));
+
+        // Failures are shared too, still classified by their cause
+        let results = futures::future::join_all((0..2).map(|_| run(true))).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        for (result, _) in results {
+            let error = result.unwrap_err();
+            assert_eq!(ErrorCode::classify(&error), ErrorCode::InvalidRequest);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::services::deadline::handler::DeadlineTooShort;
+use crate::services::image::admission::AdmissionTimeout;
+use crate::services::image::complexity::ComplexityExceeded;
+use crate::services::image::handler::{OriginStatus, OriginTimeout};
+use crate::services::image::memory::MemoryExhausted;
+use crate::services::image::probe::SourceTooLarge;
+use crate::services::moderation::handler::ContentBlocked;
+use crate::services::resize::handler::{ImageNotFound, MaintenanceMode};
+use crate::services::storage::core::ChecksumMismatch;
+use image::ImageError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+/// Parameters of a resize rejected before any work
+#[derive(Debug, Error)]
+#[error("{0:#}")]
+pub struct InvalidParams(pub anyhow::Error);
+
+/// Storage failed to read or write an image
+#[derive(Debug, Error)]
+#[error("Storage error: {0:#}")]
+pub struct StorageFailure(pub anyhow::Error);
+
+/// Stable classification of failed resizes, sent to clients as the `X-Error-Code` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    /// The origin answered with a 4xx status
+    OriginClientError,
+    /// The origin answered with a 5xx status
+    OriginServerError,
+    OriginTimeout,
+    /// The origin could not be connected to, or broke the connection
+    OriginUnreachable,
+    /// The source isn't an image in a supported format, or is corrupt
+    DecodeError,
+    /// The source exceeds the size limits, in bytes or pixels
+    TooLarge,
+    /// The resize exceeds the complexity budget
+    TooComplex,
+    /// Work was shed as the memory budget is used up, or no download slot freed up in time
+    Overloaded,
+    /// Uncached images are refused in maintenance mode
+    Maintenance,
+    /// The deadline sent by the client leaves too little time for a cold miss
+    DeadlineExceeded,
+    StorageError,
+    /// An image read from storage doesn't match its checksum
+    StorageCorruption,
+    /// The processed image was blocked by the moderation policy of its tenant
+    ContentBlocked,
+    /// The URL isn't signed with the configured key
+    InvalidSignature,
+    /// No image is stored under the downloaded key
+    NotFound,
+    InternalError,
+}
+
+impl ErrorCode {
+    /// Classify `error` by the first typed cause in its chain
+    pub fn classify(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(Self::of_cause)
+            .unwrap_or(Self::InternalError)
+    }
+
+    fn of_cause(cause: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if cause.is::<InvalidParams>() {
+            return Some(Self::InvalidRequest);
+        }
+        if let Some(origin) = cause.downcast_ref::<OriginStatus>() {
+            return Some(if (400..500).contains(&origin.status) {
+                Self::OriginClientError
+            } else {
+                Self::OriginServerError
+            });
+        }
+        if cause.is::<OriginTimeout>() {
+            return Some(Self::OriginTimeout);
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return Some(if e.is_timeout() {
+                Self::OriginTimeout
+            } else {
+                Self::OriginUnreachable
+            });
+        }
+        if let Some(e) = cause.downcast_ref::<ImageError>() {
+            return Some(match e {
+                ImageError::Decoding(_) | ImageError::Unsupported(_) => Self::DecodeError,
+                ImageError::Limits(_) => Self::TooLarge,
+                _ => Self::InternalError,
+            });
+        }
+        if cause.is::<SourceTooLarge>() {
+            return Some(Self::TooLarge);
+        }
+        if cause.is::<ComplexityExceeded>() {
+            return Some(Self::TooComplex);
+        }
+        if cause.is::<MemoryExhausted>() || cause.is::<AdmissionTimeout>() {
+            return Some(Self::Overloaded);
+        }
+        if cause.is::<MaintenanceMode>() {
+            return Some(Self::Maintenance);
+        }
+        if cause.is::<DeadlineTooShort>() {
+            return Some(Self::DeadlineExceeded);
+        }
+        if let Some(StorageFailure(e)) = cause.downcast_ref::<StorageFailure>() {
+            return Some(if e.chain().any(|c| c.is::<ChecksumMismatch>()) {
+                Self::StorageCorruption
+            } else {
+                Self::StorageError
+            });
+        }
+        if cause.is::<ChecksumMismatch>() {
+            return Some(Self::StorageCorruption);
+        }
+        if cause.is::<ContentBlocked>() {
+            return Some(Self::ContentBlocked);
+        }
+        if cause.is::<ImageNotFound>() {
+            return Some(Self::NotFound);
+        }
+        None
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::OriginClientError => "origin_client_error",
+            Self::OriginServerError => "origin_server_error",
+            Self::OriginTimeout => "origin_timeout",
+            Self::OriginUnreachable => "origin_unreachable",
+            Self::DecodeError => "decode_error",
+            Self::TooLarge => "too_large",
+            Self::TooComplex => "too_complex",
+            Self::Overloaded => "overloaded",
+            Self::Maintenance => "maintenance",
+            Self::DeadlineExceeded => "deadline_exceeded",
+            Self::StorageError => "storage_error",
+            Self::StorageCorruption => "storage_corruption",
+            Self::ContentBlocked => "content_blocked",
+            Self::InvalidSignature => "invalid_signature",
+            Self::NotFound => "not_found",
+            Self::InternalError => "internal_error",
+        }
+    }
+
+    /// Whether the same request may succeed when retried later
+    ///
+    /// Failures caused by the request or its source are final until either changes.
+    pub fn is_retriable(self) -> bool {
+        match self {
+            Self::OriginServerError
+            | Self::OriginTimeout
+            | Self::OriginUnreachable
+            | Self::Overloaded
+            | Self::Maintenance
+            | Self::DeadlineExceeded
+            | Self::StorageError => true,
+            Self::InvalidRequest
+            | Self::OriginClientError
+            | Self::DecodeError
+            | Self::TooLarge
+            | Self::TooComplex
+            | Self::StorageCorruption
+            | Self::ContentBlocked
+            | Self::InvalidSignature
+            | Self::NotFound
+            | Self::InternalError => false,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classifies_wrapped_causes() {
+        let origin = anyhow::Error::from(OriginStatus {
+            url: "http://origin.test/a.jpg".to_string(),
+            status: 404,
+        })
+        .context("Failed to fetch source");
+        assert_eq!(ErrorCode::classify(&origin), ErrorCode::OriginClientError);
+
+        let decode = image::load_from_memory(b"not an image")
+            .context("Failed to decode image")
+            .unwrap_err();
+        assert_eq!(ErrorCode::classify(&decode), ErrorCode::DecodeError);
+
+        let storage = anyhow::Error::from(StorageFailure(anyhow::anyhow!("bucket missing")));
+        assert_eq!(ErrorCode::classify(&storage), ErrorCode::StorageError);
+        assert!(ErrorCode::StorageError.is_retriable());
+
+        let corrupt = anyhow::Error::from(StorageFailure(
+            anyhow::Error::from(ChecksumMismatch {
+                key: "originals/a".to_string(),
+                expected: "AAAA".to_string(),
+                actual: "BBBB".to_string(),
+            })
+            .context("Failed to read source"),
+        ));
+        assert_eq!(ErrorCode::classify(&corrupt), ErrorCode::StorageCorruption);
+
+        let unknown = anyhow::anyhow!("something else");
+        assert_eq!(ErrorCode::classify(&unknown), ErrorCode::InternalError);
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::OriginTimeout).unwrap(),
+            "\"origin_timeout\""
+        );
+    }
+}
@@ -1,20 +1,186 @@
-use crate::config::performance::PerformanceConfig;
-use crate::models::params::ResizeQuery;
+use crate::config::performance::{PerformanceConfig, PerformanceMetrics};
+use crate::models::params::{BackgroundRemoval, ResizeQuery};
+use crate::modules::utils::date::unix_now;
 use crate::services::cache::handler::CacheService;
-use crate::services::image::handler::ImageService;
+use crate::services::cache::source::SourceCache;
+use crate::services::cache::url::normalize_url;
+use crate::services::deadline::handler::{self as deadline, ColdMissEstimate};
+use crate::services::debug::handler::StageTimings;
+use crate::services::event::handler::EventPublisher;
+use crate::services::image::admission::DownloadAdmission;
+use crate::services::image::bandwidth::BandwidthLimiter;
+use crate::services::image::budget::ByteBudget;
+use crate::services::image::canary::CanaryPipeline;
+use crate::services::image::cancel::{self, or_cancelled};
+use crate::services::image::complexity::ComplexityBudget;
+#[cfg(feature = "dns_cache")]
+use crate::services::image::dns::DnsCache;
+use crate::services::image::encode::{ENCODER_VERSION, EncodingDefaults};
+use crate::services::image::handler::{ImageService, ImageStats, ProcessedImage};
+use crate::services::image::matting::BackgroundMatting;
+use crate::services::image::memory::MemoryGuard;
+use crate::services::image::probe::SourceProbe;
+use crate::services::image::quality::AutoQuality;
+use crate::services::image::tls::OriginTlsConfig;
+#[cfg(feature = "video")]
+use crate::services::image::video::Transcoder;
+use crate::services::lock::handler::{LockOutcome, ProcessingLock};
+use crate::services::moderation::handler::Moderation;
+use crate::services::peer::handler::{CachedObject, PeerCache};
+use crate::services::plugin::handler::PluginRegistry;
+use crate::services::pregen::handler::{PregenReport, Pregenerator};
+use crate::services::resize::coalesce::Coalescer;
+use crate::services::resize::errors::{ErrorCode, InvalidParams, StorageFailure};
+use crate::services::resize::metrics::PipelineMetrics;
+use crate::services::resize::observer::{Observed, PipelineLog, PipelineObserver, Stage};
+use crate::services::resize::report::{Accounting, UsageReport};
+use crate::services::storage::core::{ObjectMetadata, content_type_from_key};
 use crate::services::storage::handler::StorageService;
-use anyhow::Result;
+use crate::services::storage::retention::{ObjectLockPolicy, ObjectLocked};
+use crate::services::tenant::handler::TenantPolicies;
+use crate::services::tiering::handler::StorageTiering;
+use crate::services::watermark::handler::WatermarkLibrary;
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
 use derive_builder::Builder;
 use gen_server::models::DownloadPathParams;
+use image::DynamicImage;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, instrument};
 
+/// Processing lock key held by the replica sweeping storage tiers
+const TIERING_SWEEP_LOCK: &str = "tiering-sweep";
+/// Processing lock key held by the replica pre-generating variants
+const PREGEN_LOCK: &str = "pregen";
+
+/// Maintenance mode refused to download and process an image that isn't cached
+#[derive(Debug, Error)]
+#[error("Maintenance mode: {url} is not cached")]
+pub struct MaintenanceMode {
+    pub url: String,
+}
+
+/// No image is stored under the key of a download
+#[derive(Debug, Error)]
+#[error("Image not found in storage: {key}")]
+pub struct ImageNotFound {
+    pub key: String,
+}
+
+/// Outcome of a successful resize
+#[derive(Debug, Clone)]
+pub struct ResizeResult {
+    /// CDN URL of the resized image
+    pub url: String,
+    /// Storage key of the resized image
+    pub key: String,
+    /// Surrogate keys (cache tags) of the resized image
+    pub surrogate_keys: Vec<String>,
+    /// Whether the image was already in storage
+    pub cache_hit: bool,
+    /// Dimensions of the resized image, unless unknown for images stored before they were recorded
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Size of the resized image, in bytes
+    pub size: Option<u64>,
+    /// Encoder quality picked by `quality=auto` or `max_bytes`
+    pub quality: Option<u8>,
+    /// Cache key of the request, the storage key unless content-addressed
+    pub cache_key: String,
+    /// Time spent in each stage of the resize
+    pub timings: StageTimings,
+    /// Resampling filter and encoder settings, unset for images served from storage
+    pub filter: Option<&'static str>,
+    pub encoder: Option<String>,
+}
+
+/// What a resize would do, worked out without downloading or processing the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResizePlan {
+    /// Query with its defaults resolved, as used for the cache key
+    pub params: ResizeQuery,
+    /// Source URL as compared between requests
+    pub source_url: String,
+    pub cache_key: String,
+    /// CDN URL the resized image is, or would be, served from
+    pub url: String,
+    /// Whether the image is already in storage
+    pub cache_hit: bool,
+    /// Dimensions of the stored image, or those set by the query on a cache miss
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Why the resize would fail
+    pub error: Option<String>,
+    pub error_code: Option<ErrorCode>,
+}
+
 /// Main service for image resizing with performance optimizations
 #[derive(Clone, Builder)]
 pub struct ResizeService {
     storage_service: StorageService,
     cache_service: CacheService,
     image_service: ImageService,
+    #[builder(default)]
+    metrics: Arc<PerformanceMetrics>,
+    // OpenTelemetry instruments of the pipeline, by origin and tenant
+    #[builder(default)]
+    pipeline_metrics: PipelineMetrics,
+    // Cache efficiency and processing costs over a rolling window
+    #[builder(default)]
+    accounting: Accounting,
+    // Serve cached images only, without downloading or processing
+    #[builder(default)]
+    maintenance: Arc<AtomicBool>,
+    // Deduplicates processing of the same image across replicas
+    #[builder(default)]
+    lock: ProcessingLock,
+    // Shares freshly processed images with the other replicas
+    #[builder(default)]
+    peer_cache: Option<PeerCache>,
+    // Notifies downstream consumers of new images
+    #[builder(default)]
+    events: EventPublisher,
+    // Rejects resizes too costly for the shared pool
+    #[builder(default)]
+    complexity: ComplexityBudget,
+    // Moves idle images to a cheaper storage class
+    #[builder(default)]
+    tiering: Option<StorageTiering>,
+    // Alternative pipeline processing a share of the images
+    #[builder(default)]
+    canary: Option<CanaryPipeline>,
+    // Object Lock of the uploads of tenants with retention requirements
+    #[builder(default)]
+    object_lock: ObjectLockPolicy,
+    // Transformations merged into every request of their tenant
+    #[builder(default)]
+    tenant_defaults: TenantPolicies,
+    // Downloaded originals kept in storage for further variants
+    #[builder(default)]
+    source_cache: Option<SourceCache>,
+    // Classification of processed images against the policy of their tenant
+    #[builder(default)]
+    moderation: Option<Moderation>,
+    // Time cold misses take, checked against the deadlines of clients
+    #[builder(default)]
+    cold_miss: ColdMissEstimate,
+    // Shares the resizes in flight with identical requests
+    #[builder(default)]
+    coalescer: Coalescer,
+    // Watermarks of the tenants, referred to by name in queries
+    #[builder(default)]
+    watermarks: Option<WatermarkLibrary>,
+    // Matting model cutting out the subjects of remove_bg=matting
+    #[builder(default)]
+    matting: Option<BackgroundMatting>,
+    // Hooks of the pipeline stages, in addition to the built-in metrics and events
+    #[builder(default)]
+    observers: Vec<Arc<dyn PipelineObserver>>,
 }
 
 impl ResizeService {
@@ -25,6 +191,25 @@ impl ResizeService {
             storage_service,
             cache_service,
             image_service,
+            metrics: Arc::default(),
+            pipeline_metrics: PipelineMetrics::default(),
+            accounting: Accounting::default(),
+            maintenance: Arc::default(),
+            lock: ProcessingLock::default(),
+            peer_cache: None,
+            events: EventPublisher::default(),
+            complexity: ComplexityBudget::default(),
+            tiering: None,
+            canary: None,
+            object_lock: ObjectLockPolicy::default(),
+            tenant_defaults: TenantPolicies::default(),
+            source_cache: None,
+            moderation: None,
+            cold_miss: ColdMissEstimate::default(),
+            coalescer: Coalescer::default(),
+            watermarks: None,
+            matting: None,
+            observers: Vec::new(),
         })
     }
 
@@ -39,77 +224,1007 @@ impl ResizeService {
             storage_service,
             cache_service,
             image_service,
+            metrics: Arc::default(),
+            pipeline_metrics: PipelineMetrics::default(),
+            accounting: Accounting::default(),
+            maintenance: Arc::default(),
+            lock: ProcessingLock::default(),
+            peer_cache: None,
+            events: EventPublisher::default(),
+            complexity: ComplexityBudget::default(),
+            tiering: None,
+            canary: None,
+            object_lock: ObjectLockPolicy::default(),
+            tenant_defaults: TenantPolicies::default(),
+            source_cache: None,
+            moderation: None,
+            cold_miss: ColdMissEstimate::default(),
+            coalescer: Coalescer::default(),
+            watermarks: None,
+            matting: None,
+            observers: Vec::new(),
         })
     }
 
+    /// Replace the transform plugins available to requests
+    pub fn with_plugins(mut self, plugins: PluginRegistry) -> Self {
+        self.image_service = self.image_service.with_plugins(plugins);
+        self
+    }
+
+    /// Resolve origin host names through `dns_cache`
+    #[cfg(feature = "dns_cache")]
+    pub fn with_dns_cache(mut self, dns_cache: DnsCache) -> Result<Self> {
+        self.image_service = self.image_service.with_dns_cache(dns_cache)?;
+        Ok(self)
+    }
+
+    /// Use the TLS settings of `origin_tls` for the origins they match
+    pub fn with_origin_tls(mut self, origin_tls: OriginTlsConfig) -> Result<Self> {
+        self.image_service = self.image_service.with_origin_tls(origin_tls)?;
+        Ok(self)
+    }
+
+    /// Replace the budget for the memory of images being processed
+    pub fn with_memory_guard(mut self, memory: MemoryGuard) -> Self {
+        self.image_service = self.image_service.with_memory_guard(memory);
+        self
+    }
+
+    /// Replace the admission of origin downloads
+    pub fn with_download_admission(mut self, download_admission: DownloadAdmission) -> Self {
+        self.image_service = self
+            .image_service
+            .with_download_admission(download_admission);
+        self
+    }
+
+    /// Replace the header check of sources before their full download
+    pub fn with_probe(mut self, probe: SourceProbe) -> Self {
+        self.image_service = self.image_service.with_probe(probe);
+        self
+    }
+
+    /// Replace the caps on origin download throughput
+    pub fn with_bandwidth(mut self, bandwidth: BandwidthLimiter) -> Self {
+        self.image_service = self.image_service.with_bandwidth(bandwidth);
+        self
+    }
+
+    /// Replace the format and encoder settings of requests that leave them unset
+    pub fn with_encoding_defaults(mut self, encoding_defaults: EncodingDefaults) -> Self {
+        self.image_service = self.image_service.with_encoding_defaults(encoding_defaults);
+        self
+    }
+
+    /// Replace the bounds of the `quality=auto` search
+    pub fn with_auto_quality(mut self, auto_quality: AutoQuality) -> Self {
+        self.image_service = self.image_service.with_auto_quality(auto_quality);
+        self
+    }
+
+    /// Replace the limits of the `max_bytes` search
+    pub fn with_byte_budget(mut self, byte_budget: ByteBudget) -> Self {
+        self.image_service = self.image_service.with_byte_budget(byte_budget);
+        self
+    }
+
+    /// Replace the ffmpeg settings used for video outputs
+    #[cfg(feature = "video")]
+    pub fn with_transcoder(mut self, transcoder: Transcoder) -> Self {
+        self.image_service = self.image_service.with_transcoder(transcoder);
+        self
+    }
+
+    /// Coordinate processing with other replicas through a distributed lock
+    pub fn with_lock(mut self, lock: ProcessingLock) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Look processed images up in the memory of other replicas before storage
+    pub fn with_peer_cache(mut self, peer_cache: Option<PeerCache>) -> Self {
+        self.peer_cache = peer_cache;
+        self
+    }
+
+    pub fn peer_cache(&self) -> Option<&PeerCache> {
+        self.peer_cache.as_ref()
+    }
+
+    /// Publish an event after every image processed and stored
+    pub fn with_events(mut self, events: EventPublisher) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Reject resizes whose estimated cost exceeds `complexity`
+    pub fn with_complexity_budget(mut self, complexity: ComplexityBudget) -> Self {
+        self.complexity = complexity;
+        self
+    }
+
+    /// Track accesses to stored images to move the idle ones to a cheaper storage class
+    pub fn with_tiering(mut self, tiering: Option<StorageTiering>) -> Self {
+        self.tiering = tiering;
+        self
+    }
+
+    /// Process a share of the images with an alternative pipeline
+    pub fn with_canary(mut self, canary: Option<CanaryPipeline>) -> Self {
+        self.canary = canary;
+        self
+    }
+
+    /// Lock the uploads of the tenants of `object_lock` against deletion
+    pub fn with_object_lock(mut self, object_lock: ObjectLockPolicy) -> Self {
+        self.object_lock = object_lock;
+        self
+    }
+
+    /// Merge the defaults of the tenants of `tenant_defaults` into their requests
+    pub fn with_tenant_defaults(mut self, tenant_defaults: TenantPolicies) -> Self {
+        self.tenant_defaults = tenant_defaults;
+        self
+    }
+
+    /// `params` with the defaults of `tenant` and of the deployment filled in
+    ///
+    /// Both are part of the cache key. The defaults of the tenant come first, so that they
+    /// override those of the deployment.
+    fn resolve<'a>(
+        &self,
+        params: &'a ResizeQuery,
+        tenant: Option<&str>,
+    ) -> Result<Cow<'a, ResizeQuery>> {
+        let encoding_defaults = self.image_service.encoding_defaults();
+        Ok(
+            match self
+                .tenant_defaults
+                .apply(params, tenant, encoding_defaults)?
+            {
+                Cow::Borrowed(params) => self.image_service.resolve_defaults(params),
+                Cow::Owned(params) => {
+                    Cow::Owned(self.image_service.resolve_defaults(&params).into_owned())
+                }
+            },
+        )
+    }
+
+    /// Reuse downloaded originals from storage when generating further variants
+    pub fn with_source_cache(mut self, source_cache: Option<SourceCache>) -> Self {
+        self.source_cache = source_cache;
+        self
+    }
+
+    pub fn source_cache(&self) -> Option<&SourceCache> {
+        self.source_cache.as_ref()
+    }
+
+    /// Classify processed images, flagging or blocking them by tenant policy
+    pub fn with_moderation(mut self, moderation: Option<Moderation>) -> Self {
+        self.moderation = moderation;
+        self
+    }
+
+    pub fn moderation(&self) -> Option<&Moderation> {
+        self.moderation.as_ref()
+    }
+
+    /// Refuse cold misses the deadline of the client leaves too little time for
+    pub fn with_cold_miss_estimate(mut self, cold_miss: ColdMissEstimate) -> Self {
+        self.cold_miss = cold_miss;
+        self
+    }
+
+    /// Set the matting model of `remove_bg=matting`
+    pub fn with_matting(mut self, matting: Option<BackgroundMatting>) -> Self {
+        self.matting = matting;
+        self
+    }
+
+    /// Overlay watermarks of the library of the tenant, by name
+    pub fn with_watermarks(mut self, watermarks: Option<WatermarkLibrary>) -> Self {
+        self.watermarks = watermarks;
+        self
+    }
+
+    pub fn watermarks(&self) -> Option<&WatermarkLibrary> {
+        self.watermarks.as_ref()
+    }
+
+    pub fn tiering(&self) -> Option<&StorageTiering> {
+        self.tiering.as_ref()
+    }
+
+    /// Move idle processed images to the cold storage class, returning how many
+    ///
+    /// `None` when tiering is disabled or another replica is already sweeping.
+    pub async fn sweep_tiers(&self) -> Result<Option<usize>> {
+        let Some(tiering) = &self.tiering else {
+            return Ok(None);
+        };
+        let lease = match self.lock.acquire(TIERING_SWEEP_LOCK).await {
+            LockOutcome::Acquired(token) => Some(token),
+            LockOutcome::Contended => return Ok(None),
+            LockOutcome::Disabled => None,
+        };
+
+        let demoted = tiering
+            .sweep(&self.storage_service, &self.cache_service)
+            .await;
+        if let Some(token) = lease {
+            self.lock.release(TIERING_SWEEP_LOCK, &token).await;
+        }
+        demoted.map(Some)
+    }
+
+    /// Resize the variants of the pre-generation manifest missing from storage
+    ///
+    /// `None` when another replica is already pre-generating.
+    pub async fn pregenerate(&self, pregenerator: &Pregenerator) -> Result<Option<PregenReport>> {
+        let lease = match self.lock.acquire(PREGEN_LOCK).await {
+            LockOutcome::Acquired(token) => Some(token),
+            LockOutcome::Contended => return Ok(None),
+            LockOutcome::Disabled => None,
+        };
+
+        let report = pregenerator.pregenerate(self).await;
+        if let Some(token) = lease {
+            self.lock.release(PREGEN_LOCK, &token).await;
+        }
+        report.map(Some)
+    }
+
+    /// Call the hooks of `observer` as resizes go through the pipeline
+    pub fn with_observer(mut self, observer: Arc<dyn PipelineObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Built-in observers, then the added ones
+    fn observers(&self) -> impl Iterator<Item = &dyn PipelineObserver> {
+        let built_in: [&dyn PipelineObserver; 5] = [
+            &PipelineLog,
+            self.metrics.as_ref(),
+            &self.pipeline_metrics,
+            &self.accounting,
+            &self.events,
+        ];
+        built_in
+            .into_iter()
+            .chain(self.observers.iter().map(AsRef::as_ref))
+    }
+
+    /// Replace the OpenTelemetry instruments of the pipeline and their labels
+    pub fn with_pipeline_metrics(mut self, pipeline_metrics: PipelineMetrics) -> Self {
+        self.pipeline_metrics = pipeline_metrics;
+        self
+    }
+
+    /// Replace the rolling accounting reported by [`ResizeService::report`]
+    pub fn with_accounting(mut self, accounting: Accounting) -> Self {
+        self.accounting = accounting;
+        self
+    }
+
+    /// Cache efficiency and processing costs over the accounting window
+    pub fn report(&self) -> UsageReport {
+        self.accounting.report()
+    }
+
+    /// Counters of resize requests and cache hits
+    pub fn metrics(&self) -> &PerformanceMetrics {
+        &self.metrics
+    }
+
+    /// Current utilization of the download and processing pools
+    pub fn image_stats(&self) -> ImageStats {
+        self.image_service.stats()
+    }
+
+    pub fn is_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Toggle maintenance mode, in which only images already in storage are served and
+    /// others fail with [`MaintenanceMode`]
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::Relaxed);
+    }
+
     /// Main resize method with optimized processing
-    #[instrument(skip(self), fields(url = %params.url))]
-    pub async fn resize(&self, params: &ResizeQuery) -> Result<String> {
+    pub async fn resize(&self, params: &ResizeQuery, tenant: Option<&str>) -> Result<ResizeResult> {
+        self.resize_cancellable(params, tenant, &CancellationToken::new())
+            .await
+    }
+
+    /// Resize, giving up on the download and processing once `cancel` is triggered
+    ///
+    /// The processing lock is still released. An image already processed is still stored,
+    /// as the work is done and later requests can use it.
+    #[instrument(skip(self, cancel), fields(url = %params.url))]
+    pub async fn resize_cancellable(
+        &self,
+        params: &ResizeQuery,
+        tenant: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> Result<ResizeResult> {
+        // The default format is part of the cache key
+        let params = self.resolve(params, tenant)?;
+        let params = params.as_ref();
+
+        // Fail fast on invalid parameters instead of after the download
+        self.image_service
+            .validate_plugins(params)
+            .and_then(|()| params.validate())
+            .and_then(|()| self.validate_matting(params))
+            .map_err(InvalidParams)?;
+        let params = WatermarkLibrary::qualify(Cow::Borrowed(params), tenant);
+        let params = params.as_ref();
+
         // Generate cache key
-        let cache_key = self.cache_service.generate_key(params);
+        let cache_key = self.generate_key(params);
         debug!("Generated cache key: {}", cache_key);
+        self.produce(params, cache_key, tenant, cancel).await
+    }
+
+    /// Change the format of the source of `params`, without resizing nor transforming it
+    ///
+    /// Conversions are stored under their own keys, see [`CacheService::convert_key`], and
+    /// otherwise go through the same lookup, locking and storage as resizes.
+    #[instrument(skip(self, cancel), fields(url = %params.url))]
+    pub async fn convert_cancellable(
+        &self,
+        params: &ResizeQuery,
+        tenant: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> Result<ResizeResult> {
+        let params = self.resolve(params, tenant)?;
+        let params = params.as_ref();
+        params.validate().map_err(InvalidParams)?;
+
+        let cache_key = self.cache_service.convert_key(params);
+        debug!("Generated conversion key: {}", cache_key);
+        self.produce(params, cache_key, tenant, cancel).await
+    }
+
+    /// Serve the image stored under `cache_key`, or produce and store it
+    async fn produce(
+        &self,
+        params: &ResizeQuery,
+        cache_key: String,
+        tenant: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> Result<ResizeResult> {
+        let surrogate_keys = self.cache_service.generate_surrogate_keys(params, tenant);
 
         // Check cache
-        match self.storage_service.check_cache(&cache_key).await {
-            Ok(true) => {
+        let mut labels = self.pipeline_metrics.labels(&params.url, tenant);
+        if self.canary.is_some() {
+            let pipeline = self
+                .canary_for(&cache_key)
+                .map_or("stable", CanaryPipeline::name);
+            labels.push(("pipeline", pipeline.to_string()));
+        }
+        let mut timings = StageTimings::default();
+        let lookup_timer = Instant::now();
+        let cached = self.lookup_cache(&cache_key).await;
+        timings.record("lookup", lookup_timer.elapsed());
+        let resize = Observed {
+            params,
+            tenant,
+            cache_key: &cache_key,
+            labels: &labels,
+        };
+        for observer in self.observers() {
+            observer.on_lookup(&resize, cached.as_ref());
+        }
+        if let Some(metadata) = cached {
+            return Ok(self.cached_result(&cache_key, surrogate_keys, metadata, timings));
+        }
+
+        if self.is_maintenance() {
+            return Err(MaintenanceMode {
+                url: params.url.clone(),
+            }
+            .into());
+        }
+        // Don't start work the client will have abandoned before it is done
+        self.cold_miss.check(&params.url, deadline::current())?;
+        // Shed new work before downloading its source when memory is already used up
+        self.image_service.memory_guard().check()?;
+
+        // Identical requests in flight on this replica share a single download and processing
+        let service = self.clone();
+        let flight = {
+            let params = params.clone();
+            let cache_key = cache_key.clone();
+            let tenant = tenant.map(str::to_string);
+            move |cancel: CancellationToken| async move {
+                service
+                    .produce_miss(
+                        &params,
+                        cache_key,
+                        surrogate_keys,
+                        tenant.as_deref(),
+                        labels,
+                        &cancel,
+                    )
+                    .await
+            }
+        };
+        let (result, shared) = self.coalescer.run(&cache_key, cancel, flight).await;
+        if shared {
+            self.metrics.increment_coalesced();
+        }
+        result.map(|mut result| {
+            timings.append(result.timings);
+            result.timings = timings;
+            result
+        })
+    }
+
+    /// Produce and store an image missing from storage, unless another replica does
+    async fn produce_miss(
+        &self,
+        params: &ResizeQuery,
+        cache_key: String,
+        surrogate_keys: Vec<String>,
+        tenant: Option<&str>,
+        labels: Vec<(&'static str, String)>,
+        cancel: &CancellationToken,
+    ) -> Result<ResizeResult> {
+        let mut timings = StageTimings::default();
+
+        // Let a single replica process a cold image while the others wait for it
+        let lease = match self.lock.acquire(&cache_key).await {
+            LockOutcome::Acquired(token) => {
+                self.metrics.increment_lock_acquired();
+                Some(token)
+            }
+            LockOutcome::Contended => {
+                self.metrics.increment_lock_contended();
+                let wait_timer = Instant::now();
+                let waited = tokio::select! {
+                    metadata = self.wait_for_cache(&cache_key) => metadata,
+                    _ = cancel.cancelled() => None,
+                };
+                timings.record("lock_wait", wait_timer.elapsed());
+                if let Some(metadata) = waited {
+                    return Ok(self.cached_result(&cache_key, surrogate_keys, metadata, timings));
+                }
+                cancel::check(cancel)?;
+                self.metrics.increment_lock_timeouts();
+                None
+            }
+            LockOutcome::Disabled => None,
+        };
+
+        let miss_timer = Instant::now();
+        let result = self
+            .download_and_process(
+                params,
+                cache_key.clone(),
+                surrogate_keys,
+                tenant,
+                &labels,
+                cancel,
+            )
+            .await;
+        if result.is_ok() {
+            self.cold_miss.record(miss_timer.elapsed());
+        }
+
+        if let Some(token) = lease {
+            self.lock.release(&cache_key, &token).await;
+        }
+        result.map(|mut result| {
+            timings.append(result.timings);
+            result.timings = timings;
+            result
+        })
+    }
+
+    async fn download_and_process(
+        &self,
+        params: &ResizeQuery,
+        cache_key: String,
+        surrogate_keys: Vec<String>,
+        tenant: Option<&str>,
+        labels: &[(&'static str, String)],
+        cancel: &CancellationToken,
+    ) -> Result<ResizeResult> {
+        let resize = Observed {
+            params,
+            tenant,
+            cache_key: &cache_key,
+            labels,
+        };
+        // An unknown watermark fails the resize before the download
+        let watermark = self.load_watermark(params).await?;
+
+        // Download image
+        let download_timer = Instant::now();
+        let downloaded = or_cancelled(cancel, self.fetch_source(&params.url, tenant)).await;
+        let image_bytes = match downloaded {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                for observer in self.observers() {
+                    observer.on_error(&resize, Stage::Download, &e, download_timer.elapsed());
+                }
+                return Err(e);
+            }
+        };
+        let mut timings = StageTimings::default();
+        timings.record("download", download_timer.elapsed());
+        for observer in self.observers() {
+            observer.on_download(&resize, image_bytes.len() as u64, download_timer.elapsed());
+        }
+        self.complexity.check(&image_bytes, params, tenant)?;
+
+        let mut processed = self
+            .process(&resize, image_bytes, watermark, cancel)
+            .await?;
+        timings.append(processed.timings);
+        processed.timings = timings;
+        self.store(&resize, processed, surrogate_keys, download_timer)
+            .await
+    }
+
+    /// Wait for another replica to store the image, up to the lock wait timeout
+    async fn wait_for_cache(&self, cache_key: &str) -> Option<ObjectMetadata> {
+        let settings = self.lock.settings();
+        let deadline = tokio::time::Instant::now() + settings.wait_timeout;
+
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(settings.poll_interval).await;
+            if let Ok(Some(metadata)) = self.storage_service.get_metadata(cache_key).await {
+                info!("Image {} was processed by another replica", cache_key);
+                return Some(metadata);
+            }
+        }
+
+        info!(
+            "Timed out waiting for another replica to process {}",
+            cache_key
+        );
+        None
+    }
+
+    /// Result of a resize served from storage
+    fn cached_result(
+        &self,
+        cache_key: &str,
+        surrogate_keys: Vec<String>,
+        metadata: ObjectMetadata,
+        timings: StageTimings,
+    ) -> ResizeResult {
+        // Content-addressed keys point to the image stored under the hash of its content
+        let key = metadata.content_key.as_deref().unwrap_or(cache_key);
+        if let Some(tiering) = &self.tiering {
+            tiering.touch(&self.storage_service, key, &metadata);
+        }
+        ResizeResult {
+            url: self.storage_service.get_cdn_url(key),
+            key: key.to_string(),
+            surrogate_keys,
+            cache_hit: true,
+            width: metadata.width,
+            height: metadata.height,
+            size: metadata.size,
+            quality: metadata.quality,
+            cache_key: cache_key.to_string(),
+            timings,
+            filter: None,
+            encoder: None,
+        }
+    }
+
+    /// Work out what [`ResizeService::resize`] would do with `params`, only looking up storage
+    pub async fn plan(&self, params: &ResizeQuery, tenant: Option<&str>) -> ResizePlan {
+        let params = match self.resolve(params, tenant) {
+            Ok(resolved) => WatermarkLibrary::qualify(resolved, tenant).into_owned(),
+            Err(_) => self.image_service.resolve_defaults(params).into_owned(),
+        };
+        let cache_key = self.generate_key(&params);
+        let cached = self.lookup_cache(&cache_key).await;
+
+        let mut error: Option<anyhow::Error> = self
+            .image_service
+            .validate_plugins(&params)
+            .and_then(|()| params.validate())
+            .and_then(|()| self.validate_matting(&params))
+            .err()
+            .map(|e| InvalidParams(e).into());
+        if error.is_none() && cached.is_none() && self.is_maintenance() {
+            error = Some(
+                MaintenanceMode {
+                    url: params.url.clone(),
+                }
+                .into(),
+            );
+        }
+
+        let (width, height) = match &cached {
+            Some(metadata) => (metadata.width, metadata.height),
+            None => ImageService::predicted_size(&params).unwrap_or_default(),
+        };
+        let stored_key = cached
+            .as_ref()
+            .and_then(|metadata| metadata.content_key.as_deref())
+            .unwrap_or(&cache_key);
+        ResizePlan {
+            source_url: normalize_url(&params.url),
+            url: self.storage_service.get_cdn_url(stored_key),
+            cache_key,
+            cache_hit: cached.is_some(),
+            width,
+            height,
+            error_code: error.as_ref().map(ErrorCode::classify),
+            error: error.map(|e| e.to_string()),
+            params,
+        }
+    }
+
+    /// Resize an image whose source bytes were obtained by the caller (e.g. a local file)
+    ///
+    /// The result is stored under the same key as [`ResizeService::resize`] would use for
+    /// `params`, so later requests for `params.url` are served from the cache.
+    #[instrument(skip(self, source), fields(url = %params.url))]
+    pub async fn resize_source(
+        &self,
+        params: &ResizeQuery,
+        source: Bytes,
+        tenant: Option<&str>,
+    ) -> Result<ResizeResult> {
+        let started = Instant::now();
+        let params = self.resolve(params, tenant)?;
+        let params = WatermarkLibrary::qualify(params, tenant);
+        let params = params.as_ref();
+        let cache_key = self.generate_key(params);
+        let surrogate_keys = self.cache_service.generate_surrogate_keys(params, tenant);
+
+        if let Some(metadata) = self.lookup_cache(&cache_key).await {
+            return Ok(self.cached_result(
+                &cache_key,
+                surrogate_keys,
+                metadata,
+                StageTimings::default(),
+            ));
+        }
+        self.complexity.check(&source, params, tenant)?;
+
+        let labels = self.pipeline_metrics.labels(&params.url, tenant);
+        let resize = Observed {
+            params,
+            tenant,
+            cache_key: &cache_key,
+            labels: &labels,
+        };
+        let watermark = self.load_watermark(params).await?;
+        let processed = self
+            .process(&resize, source, watermark, &CancellationToken::new())
+            .await?;
+        self.store(&resize, processed, surrogate_keys, started)
+            .await
+    }
+
+    /// Check that the matting model `params` may ask for is configured
+    fn validate_matting(&self, params: &ResizeQuery) -> Result<()> {
+        if params.background_removal()? == Some(BackgroundRemoval::Matting)
+            && self.matting.is_none()
+        {
+            bail!("Background matting is not enabled");
+        }
+        Ok(())
+    }
+
+    /// Decoded watermark of `params`, failing with [`InvalidParams`] for unknown names
+    async fn load_watermark(&self, params: &ResizeQuery) -> Result<Option<Arc<DynamicImage>>> {
+        let Some(name) = &params.watermark else {
+            return Ok(None);
+        };
+        let Some(watermarks) = &self.watermarks else {
+            return Err(InvalidParams(anyhow!("Watermarks are not enabled")).into());
+        };
+        match watermarks.load(name).await? {
+            Some(image) => Ok(Some(image)),
+            None => Err(InvalidParams(anyhow!("Unknown watermark {}", name)).into()),
+        }
+    }
+
+    /// Download the source image, or read it from storage for `storage://` URLs and cached
+    /// sources
+    async fn fetch_source(&self, url: &str, tenant: Option<&str>) -> Result<Bytes> {
+        if let Some(key) = self.cache_service.storage_source_key(url)? {
+            return self
+                .storage_service
+                .get_image(&key)
+                .await
+                .map_err(|e| StorageFailure(e).into());
+        }
+        match &self.source_cache {
+            Some(source_cache) => {
+                source_cache
+                    .fetch(
+                        &self.storage_service,
+                        &self.image_service,
+                        &self.cache_service.source_cache_key(url),
+                        url,
+                        tenant,
+                    )
+                    .await
+            }
+            None => self.image_service.download_image_for(url, tenant).await,
+        }
+    }
+
+    /// Metadata of a processed image if it already exists, treating errors as a miss
+    async fn lookup_cache(&self, cache_key: &str) -> Option<ObjectMetadata> {
+        match self.storage_service.get_metadata(cache_key).await {
+            Ok(Some(metadata)) => {
                 info!("Cache hit for key: {}", cache_key);
-                return Ok(self.storage_service.get_cdn_url(&cache_key));
+                Some(metadata)
             }
-            Ok(false) => {
+            Ok(None) => {
                 info!(
                     "Cache miss for key: {}. Proceeding with processing.",
                     cache_key
                 );
+                None
             }
             Err(e) => {
                 error!("Error checking cache for key {}: {:?}", cache_key, e);
                 // Continue as if it's a cache miss
+                None
             }
         }
+    }
 
-        // Download image
-        let download_timer = Instant::now();
-        let image_bytes = match self.image_service.download_image(&params.url).await {
-            Ok(bytes) => bytes,
+    /// Cache key of `params`, labelled with the canary pipeline when it handles them
+    fn generate_key(&self, params: &ResizeQuery) -> String {
+        let cache_key = self.cache_service.generate_key(params);
+        match &self.canary {
+            Some(canary) if canary.handles(&cache_key) => {
+                self.cache_service.pipeline_key(&cache_key, canary.name())
+            }
+            _ => cache_key,
+        }
+    }
+
+    /// Canary pipeline producing the image stored under `cache_key`, if any
+    fn canary_for(&self, cache_key: &str) -> Option<&CanaryPipeline> {
+        self.canary.as_ref().filter(|canary| {
+            cache_key
+                .strip_prefix(self.cache_service.derivatives_prefix())
+                .and_then(|name| name.strip_prefix(canary.name()))
+                .is_some_and(|name| name.starts_with('-'))
+        })
+    }
+
+    /// Process source bytes for `resize`, giving up once `cancel` fires
+    async fn process(
+        &self,
+        resize: &Observed<'_>,
+        image_bytes: Bytes,
+        watermark: Option<Arc<DynamicImage>>,
+        cancel: &CancellationToken,
+    ) -> Result<ProcessedImage> {
+        let (params, cache_key) = (resize.params, resize.cache_key);
+        let process_timer = Instant::now();
+        let source_size = image_bytes.len() as u64;
+        // The cut-out of the matting model is processed in place of the source
+        let image_bytes = match (&self.matting, params.background_removal()) {
+            (Some(matting), Ok(Some(BackgroundRemoval::Matting))) => {
+                or_cancelled(cancel, matting.remove_background(image_bytes)).await?
+            }
+            _ => image_bytes,
+        };
+        let image_service = match self.canary_for(cache_key) {
+            Some(canary) => {
+                let defaults = canary.apply(*self.image_service.encoding_defaults());
+                Cow::Owned(self.image_service.clone().with_encoding_defaults(defaults))
+            }
+            None => Cow::Borrowed(&self.image_service),
+        };
+        let processed = if self.cache_service.is_conversion_key(cache_key) {
+            image_service
+                .convert_image_cancellable(image_bytes, params, cancel)
+                .await
+        } else {
+            image_service
+                .process_image_cancellable(image_bytes, params, watermark, cancel)
+                .await
+        };
+        let processed = match processed {
+            Ok(result) => result,
             Err(e) => {
-                error!("Failed to download image: {}", e);
+                for observer in self.observers() {
+                    observer.on_error(resize, Stage::Process, &e, process_timer.elapsed());
+                }
                 return Err(e);
             }
         };
-        debug!("Image download took {:?}", download_timer.elapsed());
-        info!("Image downloaded, {} bytes", image_bytes.len());
+        for observer in self.observers() {
+            observer.on_process(resize, source_size, &processed, process_timer.elapsed());
+        }
+        Ok(processed)
+    }
 
-        // Process image
-        let process_timer = Instant::now();
-        let (processed_image, content_type) =
-            match self.image_service.process_image(&image_bytes, params).await {
-                Ok(result) => result,
-                Err(e) => {
-                    error!("Failed to process image: {}", e);
-                    return Err(e);
-                }
-            };
-        debug!("Image processing took {:?}", process_timer.elapsed());
-        info!("Image processed, {} bytes", processed_image.len());
+    /// Upload a processed image under the cache key, or under its content hash with a pointer
+    /// to it under the cache key in content-addressed mode
+    async fn store(
+        &self,
+        resize: &Observed<'_>,
+        processed: ProcessedImage,
+        surrogate_keys: Vec<String>,
+        started: Instant,
+    ) -> Result<ResizeResult> {
+        let (params, tenant) = (resize.params, resize.tenant);
+        let cache_key = resize.cache_key.to_string();
+        let moderation = match &self.moderation {
+            Some(moderation) => {
+                moderation
+                    .moderate(
+                        &params.url,
+                        tenant,
+                        &processed.content_type,
+                        &processed.data,
+                    )
+                    .await?
+            }
+            None => None,
+        };
 
         // Upload to storage
         let upload_timer = Instant::now();
+        let output_size = processed.data.len();
+        let metadata = ObjectMetadata {
+            surrogate_keys: surrogate_keys.clone(),
+            width: Some(processed.width),
+            height: Some(processed.height),
+            size: Some(output_size as u64),
+            quality: processed.quality,
+            encoder_version: Some(ENCODER_VERSION),
+            content_type: Some(processed.content_type.clone()),
+            storage_class: None,
+            content_key: None,
+            object_lock: self.object_lock.lock_for(tenant, unix_now()),
+            checksum: None,
+            moderation,
+        };
+        let key = if self.cache_service.is_content_addressed() {
+            self.cache_service
+                .content_key(&processed.data, &params.output_format().to_string())
+        } else {
+            cache_key.clone()
+        };
+        let peer_object = self.peer_cache.as_ref().map(|_| CachedObject {
+            data: processed.data.clone(),
+            metadata: metadata.clone(),
+        });
         if let Err(e) = self
-            .storage_service
-            .upload_image(&cache_key, &content_type, processed_image)
+            .upload_unless_stored(&key, &processed.content_type, processed.data, &metadata)
             .await
         {
-            error!("Failed to upload image: {}", e);
-            return Err(e);
+            for observer in self.observers() {
+                observer.on_error(resize, Stage::Upload, &e, upload_timer.elapsed());
+            }
+            return Err(StorageFailure(e).into());
+        }
+        // The pointer is written last, so that replicas waiting for it find the image
+        if key != cache_key {
+            let pointer = ObjectMetadata {
+                content_key: Some(key.clone()),
+                ..metadata.clone()
+            };
+            if let Err(e) = self
+                .storage_service
+                .upload_image(&cache_key, &processed.content_type, Bytes::new(), &pointer)
+                .await
+            {
+                let e = e.context("Failed to upload content key pointer");
+                for observer in self.observers() {
+                    observer.on_error(resize, Stage::Upload, &e, upload_timer.elapsed());
+                }
+                return Err(StorageFailure(e).into());
+            }
+        }
+        let mut timings = processed.timings;
+        timings.record("upload", upload_timer.elapsed());
+
+        if let (Some(peer_cache), Some(object)) = (&self.peer_cache, peer_object) {
+            peer_cache.put(&key, object);
         }
-        debug!("Image upload took {:?}", upload_timer.elapsed());
-        info!("Upload successful");
 
         // Return CDN URL
-        let cdn_url = self.storage_service.get_cdn_url(&cache_key);
-        info!("Returning CDN URL: {}", cdn_url);
+        let cdn_url = self.storage_service.get_cdn_url(&key);
+        let result = ResizeResult {
+            url: cdn_url,
+            key,
+            surrogate_keys,
+            cache_hit: false,
+            width: metadata.width,
+            height: metadata.height,
+            size: metadata.size,
+            quality: metadata.quality,
+            cache_key,
+            timings,
+            filter: processed.filter,
+            encoder: processed.encoder,
+        };
+        for observer in self.observers() {
+            observer.on_upload(resize, &result, started.elapsed());
+        }
+        Ok(result)
+    }
 
-        Ok(cdn_url)
+    /// Upload an image, skipping content-addressed ones another query already stored
+    async fn upload_unless_stored(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
+        if self.cache_service.is_content_addressed()
+            && self.storage_service.check_cache(key).await?
+        {
+            info!("Identical image already stored as {}", key);
+            return Ok(());
+        }
+        self.storage_service
+            .upload_image(key, content_type, data, metadata)
+            .await
+    }
+
+    /// Delete a processed image from storage, returning whether it existed
+    ///
+    /// CDNs keep serving their copy until it is purged there too, e.g. by surrogate key.
+    #[instrument(skip(self))]
+    pub async fn purge(&self, key: &str) -> Result<bool> {
+        check_key(key)?;
+
+        // Locked images are refused on every backend, not only by S3 Object Lock
+        if let Some(lock) = self
+            .storage_service
+            .get_metadata(key)
+            .await?
+            .and_then(|metadata| metadata.object_lock)
+            .filter(|lock| lock.is_locked(unix_now()))
+        {
+            return Err(ObjectLocked::new(key, &lock).into());
+        }
+
+        let deleted = self.storage_service.delete_image(key).await?;
+        // Replicas would keep serving the variant from memory otherwise
+        if let Some(peer_cache) = &self.peer_cache {
+            peer_cache.evict(key).await;
+        }
+        info!("Purged {}: {}", key, deleted);
+        Ok(deleted)
+    }
+
+    /// Metadata of a stored image, following content-addressed pointers, `None` if missing
+    ///
+    /// Tells what the image was processed into and the verdict of the moderation.
+    #[instrument(skip(self))]
+    pub async fn info(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        check_key(key)?;
+
+        let Some(metadata) = self.storage_service.get_metadata(key).await? else {
+            return Ok(None);
+        };
+        match &metadata.content_key {
+            Some(content_key) => self.storage_service.get_metadata(content_key).await,
+            None => Ok(Some(metadata)),
+        }
+    }
+
+    /// Verify that the storage backend answers
+    pub async fn check_storage(&self) -> Result<()> {
+        self.storage_service.ping().await
     }
 
     /// Batch processing for multiple images with controlled concurrency
@@ -117,34 +1232,59 @@ impl ResizeService {
         &self,
         requests: Vec<ResizeQuery>,
         max_concurrent: usize,
-    ) -> Vec<Result<String>> {
+    ) -> Vec<Result<ResizeResult>> {
         use futures::stream::{self, StreamExt};
 
         stream::iter(requests)
-            .map(|params| async move { self.resize(&params).await })
+            .map(|params| async move { self.resize(&params, None).await })
             .buffer_unordered(max_concurrent)
             .collect()
             .await
     }
 
     #[instrument(skip(self), fields(url = %params.key))]
-    pub async fn download(&self, params: &DownloadPathParams) -> Result<Vec<u8>> {
+    pub async fn download(&self, params: &DownloadPathParams) -> Result<(Bytes, ObjectMetadata)> {
         let download_timer = Instant::now();
 
+        // A replica may still hold a freshly processed image in memory
+        if let Some(peer_cache) = &self.peer_cache
+            && let Some(object) = peer_cache.get(&params.key).await
+        {
+            info!("download served from peer cache");
+            debug!("Image download took {:?}", download_timer.elapsed());
+            return Ok((object.data, object.metadata));
+        }
+
         // First check if the image exists in the cache
-        if !self.storage_service.check_cache(&params.key).await? {
-            return Err(anyhow::anyhow!(
-                "Image not found in storage: {}",
-                params.key
-            ));
+        let Some(mut metadata) = self.storage_service.get_metadata(&params.key).await? else {
+            return Err(ImageNotFound {
+                key: params.key.clone(),
+            }
+            .into());
+        };
+        let mut key = params.key.clone();
+        if let Some(content_key) = metadata.content_key.take() {
+            let Some(content_metadata) = self.storage_service.get_metadata(&content_key).await?
+            else {
+                bail!("Image {} points to missing {}", params.key, content_key);
+            };
+            metadata = content_metadata;
+            key = content_key;
         }
+        // Images stored before content types were recorded are typed by their extension
+        metadata
+            .content_type
+            .get_or_insert_with(|| content_type_from_key(&key).to_string());
 
         // Get the image from storage
-        match self.storage_service.get_image(&params.key).await {
+        match self.storage_service.get_image(&key).await {
             Ok(data) => {
+                if let Some(tiering) = &self.tiering {
+                    tiering.touch(&self.storage_service, &key, &metadata);
+                }
                 info!("download successful");
                 debug!("Image download took {:?}", download_timer.elapsed());
-                Ok(data)
+                Ok((data, metadata))
             }
             Err(e) => {
                 error!("download failed: {}", e);
@@ -153,3 +1293,11 @@ impl ResizeService {
         }
     }
 }
+
+/// Refuse keys escaping the bucket or sub path of the images
+fn check_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.starts_with('/') || key.split('/').any(|part| part == "..") {
+        bail!("Invalid image key: {}", key);
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::image::handler::ProcessedImage;
+use crate::services::resize::observer::{Observed, PipelineObserver, Stage};
+use crate::services::storage::core::ObjectMetadata;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Label of the values outside of the top N
+pub const OTHER_LABEL: &str = "other";
+
+/// Values seen for every one reported under its own label, so that a newcomer can climb
+const CANDIDATES_PER_LABEL: usize = 4;
+
+/// Bounded top-N of the values of a label, the rest being bucketed as [`OTHER_LABEL`]
+///
+/// Counts are kept for a few times N candidates with the space-saving algorithm: a value
+/// seen when the candidates are full replaces the least seen one and inherits its count,
+/// so that memory stays bounded while frequent values still make it to the top.
+#[derive(Debug)]
+pub struct LabelBuckets {
+    top: usize,
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl LabelBuckets {
+    pub fn new(top: usize) -> Self {
+        Self {
+            top,
+            counts: Mutex::default(),
+        }
+    }
+
+    /// Count `value`, returning it if it is among the top N or [`OTHER_LABEL`] otherwise
+    pub fn observe(&self, value: &str) -> String {
+        let mut counts = self.counts.lock().unwrap();
+        if !counts.contains_key(value) && counts.len() >= self.top * CANDIDATES_PER_LABEL {
+            let evicted = counts
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(value, count)| (value.clone(), *count));
+            if let Some((evicted, count)) = evicted {
+                counts.remove(&evicted);
+                counts.insert(value.to_string(), count);
+            }
+        }
+        let count = counts.entry(value.to_string()).or_default();
+        *count += 1;
+        let count = *count;
+
+        let ahead = counts.values().filter(|other| **other > count).count();
+        if ahead < self.top {
+            value.to_string()
+        } else {
+            OTHER_LABEL.to_string()
+        }
+    }
+}
+
+/// Metrics of the resize pipeline, optionally labelled by origin host and tenant
+///
+/// Both labels are off by default. When enabled, only the N most frequent origins or
+/// tenants get their own label, to keep the number of series bounded.
+#[derive(Clone)]
+pub struct PipelineMetrics {
+    origins: Option<Arc<LabelBuckets>>,
+    tenants: Option<Arc<LabelBuckets>>,
+    #[cfg(feature = "otel")]
+    instruments: Instruments,
+}
+
+#[cfg(feature = "otel")]
+#[derive(Clone)]
+struct Instruments {
+    requests: opentelemetry::metrics::Counter<u64>,
+    download: opentelemetry::metrics::Histogram<f64>,
+    processing: opentelemetry::metrics::Histogram<f64>,
+}
+
+impl Default for PipelineMetrics {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl PipelineMetrics {
+    /// Metrics labelled by the `origins` most frequent origin hosts and `tenants` tenants,
+    /// 0 leaving the label out
+    pub fn new(origins: usize, tenants: usize) -> Self {
+        let buckets = |top: usize| (top > 0).then(|| Arc::new(LabelBuckets::new(top)));
+        Self {
+            origins: buckets(origins),
+            tenants: buckets(tenants),
+            #[cfg(feature = "otel")]
+            instruments: {
+                let meter = opentelemetry::global::meter("emgr");
+                Instruments {
+                    requests: meter
+                        .u64_counter("emgr.resize.requests")
+                        .with_description("Resize requests, by cache outcome")
+                        .build(),
+                    download: meter
+                        .f64_histogram("emgr.resize.download.duration")
+                        .with_unit("s")
+                        .with_description("Download time of sources")
+                        .build(),
+                    processing: meter
+                        .f64_histogram("emgr.resize.processing.duration")
+                        .with_unit("s")
+                        .with_description("Decoding, transformation and encoding time")
+                        .build(),
+                }
+            },
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self::new(config.metrics_origin_labels, config.metrics_tenant_labels)
+    }
+
+    /// `origin_host` and `tenant` labels of a request, for the enabled ones
+    pub fn labels(&self, url: &str, tenant: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut labels = Vec::new();
+        if let Some(origins) = &self.origins {
+            let host = Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase))
+                .unwrap_or_default();
+            labels.push(("origin_host", origins.observe(&host)));
+        }
+        if let Some(tenants) = &self.tenants {
+            // Tenants are hosts, labelled without their port
+            let tenant = tenant
+                .and_then(|tenant| tenant.split(':').next())
+                .unwrap_or_default()
+                .to_lowercase();
+            labels.push(("tenant", tenants.observe(&tenant)));
+        }
+        labels
+    }
+
+    #[allow(unused_variables)]
+    pub fn record_request(&self, labels: &[(&'static str, String)], cache_hit: bool) {
+        #[cfg(feature = "otel")]
+        self.instruments.requests.add(
+            1,
+            &attributes(labels, "cache", if cache_hit { "hit" } else { "miss" }),
+        );
+    }
+
+    #[allow(unused_variables)]
+    pub fn record_download(&self, labels: &[(&'static str, String)], elapsed: Duration, ok: bool) {
+        #[cfg(feature = "otel")]
+        self.instruments.download.record(
+            elapsed.as_secs_f64(),
+            &attributes(labels, "outcome", if ok { "ok" } else { "error" }),
+        );
+    }
+
+    #[allow(unused_variables)]
+    pub fn record_processing(
+        &self,
+        labels: &[(&'static str, String)],
+        elapsed: Duration,
+        ok: bool,
+    ) {
+        #[cfg(feature = "otel")]
+        self.instruments.processing.record(
+            elapsed.as_secs_f64(),
+            &attributes(labels, "outcome", if ok { "ok" } else { "error" }),
+        );
+    }
+}
+
+impl PipelineObserver for PipelineMetrics {
+    fn on_lookup(&self, resize: &Observed, cached: Option<&ObjectMetadata>) {
+        self.record_request(resize.labels, cached.is_some());
+    }
+
+    fn on_download(&self, resize: &Observed, _size: u64, elapsed: Duration) {
+        self.record_download(resize.labels, elapsed, true);
+    }
+
+    fn on_process(
+        &self,
+        resize: &Observed,
+        _source_size: u64,
+        _processed: &ProcessedImage,
+        elapsed: Duration,
+    ) {
+        self.record_processing(resize.labels, elapsed, true);
+    }
+
+    fn on_error(&self, resize: &Observed, stage: Stage, _error: &anyhow::Error, elapsed: Duration) {
+        match stage {
+            Stage::Download => self.record_download(resize.labels, elapsed, false),
+            Stage::Process => self.record_processing(resize.labels, elapsed, false),
+            Stage::Upload => {}
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+fn attributes(
+    labels: &[(&'static str, String)],
+    name: &'static str,
+    value: &'static str,
+) -> Vec<opentelemetry::KeyValue> {
+    labels
+        .iter()
+        .map(|(label, label_value)| opentelemetry::KeyValue::new(*label, label_value.clone()))
+        .chain([opentelemetry::KeyValue::new(name, value)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_most_frequent_values_get_their_own_label() {
+        let buckets = LabelBuckets::new(2);
+        for _ in 0..50 {
+            assert_eq!(buckets.observe("a.test"), "a.test");
+        }
+        for _ in 0..30 {
+            buckets.observe("b.test");
+        }
+        // One-off values don't displace the frequent ones
+        for i in 0..60 {
+            assert_eq!(buckets.observe(&format!("{}.test", i)), OTHER_LABEL);
+        }
+        assert_eq!(buckets.observe("a.test"), "a.test");
+        assert_eq!(buckets.observe("b.test"), "b.test");
+        assert!(buckets.counts.lock().unwrap().len() <= 2 * CANDIDATES_PER_LABEL);
+
+        // A value seen more often makes it to the top
+        for _ in 0..30 {
+            buckets.observe("c.test");
+        }
+        assert_eq!(buckets.observe("c.test"), "c.test");
+        assert_eq!(buckets.observe("b.test"), OTHER_LABEL);
+
+        let metrics = PipelineMetrics::new(1, 0);
+        assert_eq!(
+            metrics.labels("https://Images.A.test:8443/x.jpg", Some("t.test")),
+            vec![("origin_host", "images.a.test".to_string())]
+        );
+        assert!(
+            PipelineMetrics::default()
+                .labels("https://a.test/x", None)
+                .is_empty()
+        );
+    }
+}
@@ -1 +1,6 @@
+pub mod coalesce;
+pub mod errors;
 pub mod handler;
+pub mod metrics;
+pub mod observer;
+pub mod report;
@@ -0,0 +1,165 @@
+use crate::models::params::ResizeQuery;
+use crate::services::image::handler::ProcessedImage;
+use crate::services::resize::handler::ResizeResult;
+use crate::services::storage::core::ObjectMetadata;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+/// Stage of the pipeline that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Download,
+    Process,
+    Upload,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Download => "download",
+            Self::Process => "process",
+            Self::Upload => "upload",
+        }
+    }
+}
+
+/// Resize going through the pipeline, as told to observers
+#[derive(Debug, Clone, Copy)]
+pub struct Observed<'a> {
+    /// Query with its defaults resolved
+    pub params: &'a ResizeQuery,
+    pub tenant: Option<&'a str>,
+    pub cache_key: &'a str,
+    /// Metric labels of the request, see [`PipelineMetrics::labels`]
+    ///
+    /// [`PipelineMetrics::labels`]: crate::services::resize::metrics::PipelineMetrics::labels
+    pub labels: &'a [(&'static str, String)],
+}
+
+/// Hooks called as resizes go through the pipeline, for cross-cutting concerns such as
+/// metrics, accounting and events
+///
+/// Hooks run inline on the resize, so they must be cheap and hand slow work, like
+/// publishing to a broker, to a background task. Every hook does nothing by default.
+pub trait PipelineObserver: Send + Sync {
+    /// Storage was looked up for the image, `cached` being its metadata on a hit
+    fn on_lookup(&self, _resize: &Observed, _cached: Option<&ObjectMetadata>) {}
+
+    /// The source of `size` bytes was downloaded
+    fn on_download(&self, _resize: &Observed, _size: u64, _elapsed: Duration) {}
+
+    /// The source of `source_size` bytes was decoded, transformed and encoded
+    fn on_process(
+        &self,
+        _resize: &Observed,
+        _source_size: u64,
+        _processed: &ProcessedImage,
+        _elapsed: Duration,
+    ) {
+    }
+
+    /// The image was stored, `elapsed` since its download started
+    fn on_upload(&self, _resize: &Observed, _result: &ResizeResult, _elapsed: Duration) {}
+
+    /// `stage` failed after `elapsed`
+    fn on_error(
+        &self,
+        _resize: &Observed,
+        _stage: Stage,
+        _error: &anyhow::Error,
+        _elapsed: Duration,
+    ) {
+    }
+}
+
+/// Logs of the progress of resizes
+pub struct PipelineLog;
+
+impl PipelineObserver for PipelineLog {
+    fn on_download(&self, _resize: &Observed, size: u64, elapsed: Duration) {
+        debug!("Image download took {:?}", elapsed);
+        info!("Image downloaded, {} bytes", size);
+    }
+
+    fn on_process(
+        &self,
+        _resize: &Observed,
+        _source_size: u64,
+        processed: &ProcessedImage,
+        elapsed: Duration,
+    ) {
+        debug!("Image processing took {:?}", elapsed);
+        info!("Image processed, {} bytes", processed.data.len());
+    }
+
+    fn on_upload(&self, _resize: &Observed, result: &ResizeResult, _elapsed: Duration) {
+        info!("Upload successful, returning CDN URL: {}", result.url);
+    }
+
+    fn on_error(
+        &self,
+        _resize: &Observed,
+        stage: Stage,
+        error: &anyhow::Error,
+        _elapsed: Duration,
+    ) {
+        error!("Failed to {} image: {:#}", stage.name(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::performance::PerformanceMetrics;
+    use crate::services::debug::handler::StageTimings;
+    use crate::services::resize::report::Accounting;
+    use bytes::Bytes;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_built_in_observers_follow_the_pipeline() {
+        let params = ResizeQuery {
+            url: "https://origin.test/a.jpg".to_string(),
+            ..ResizeQuery::default()
+        };
+        let resize = Observed {
+            params: &params,
+            tenant: None,
+            cache_key: "a.jpg",
+            labels: &[],
+        };
+        let processed = ProcessedImage {
+            data: Bytes::from_static(&[0; 40]),
+            content_type: "image/jpeg".to_string(),
+            width: 10,
+            height: 10,
+            quality: None,
+            timings: StageTimings::default(),
+            filter: None,
+            encoder: None,
+        };
+        let metrics = PerformanceMetrics::new();
+        let accounting = Accounting::default();
+        let observers: [&dyn PipelineObserver; 3] = [&PipelineLog, &metrics, &accounting];
+
+        let cached = ObjectMetadata {
+            size: Some(25),
+            ..ObjectMetadata::default()
+        };
+        for observer in observers {
+            observer.on_lookup(&resize, Some(&cached));
+            observer.on_lookup(&resize, None);
+            observer.on_download(&resize, 100, Duration::from_millis(5));
+            observer.on_process(&resize, 100, &processed, Duration::from_millis(20));
+        }
+
+        assert_eq!(metrics.total_requests.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.cache_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.cache_misses.load(Ordering::Relaxed), 1);
+        let report = accounting.report();
+        assert_eq!((report.hits, report.misses), (1, 1));
+        assert_eq!(report.bytes_from_cache, 25);
+        assert_eq!(report.bytes_processed, 40);
+        assert_eq!(report.formats["jpg"].source_bytes, 100);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::image::handler::ProcessedImage;
+use crate::services::resize::observer::{Observed, PipelineObserver};
+use crate::services::storage::core::ObjectMetadata;
+use reqwest::Url;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Slices of the window, dropped one at a time as it rolls
+const BUCKETS: u64 = 60;
+
+/// Origins tracked per bucket, the others being accounted as [`OTHER_ORIGINS`]
+const MAX_ORIGINS_PER_BUCKET: usize = 1000;
+const OTHER_ORIGINS: &str = "other";
+
+/// Origins listed in the report
+const TOP_ORIGINS: usize = 10;
+
+/// Output of a format compared with its sources
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FormatReport {
+    pub images: u64,
+    pub source_bytes: u64,
+    pub output_bytes: u64,
+    /// Output bytes per source byte
+    pub compression_ratio: f64,
+}
+
+/// Time spent processing the sources of an origin
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OriginReport {
+    pub host: String,
+    pub images: u64,
+    pub processing_ms: u64,
+    pub avg_processing_ms: f64,
+}
+
+/// Cache efficiency and processing costs over the last window, for dashboards
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageReport {
+    pub window_secs: u64,
+    pub requests: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,
+    /// Size of the stored images resizes were served from
+    pub bytes_from_cache: u64,
+    /// Size of the images processed
+    pub bytes_processed: u64,
+    /// By output format
+    pub formats: BTreeMap<String, FormatReport>,
+    /// Origins by time spent processing their sources, highest first
+    pub top_origins: Vec<OriginReport>,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    hits: u64,
+    misses: u64,
+    bytes_from_cache: u64,
+    bytes_processed: u64,
+    /// Images, source and output bytes by format
+    formats: HashMap<String, (u64, u64, u64)>,
+    /// Images and processing milliseconds by origin host
+    origins: HashMap<String, (u64, u64)>,
+}
+
+/// Rolling accounting of resizes, reported by `GET /admin/report`
+#[derive(Debug, Clone)]
+pub struct Accounting {
+    bucket_secs: u64,
+    buckets: Arc<Mutex<VecDeque<(u64, Bucket)>>>,
+}
+
+impl Default for Accounting {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(3600))
+    }
+}
+
+impl Accounting {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            bucket_secs: (window.as_secs() / BUCKETS).max(1),
+            buckets: Arc::default(),
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Self {
+        Self::new(Duration::from_secs(config.report_window_secs))
+    }
+
+    /// Count a cache lookup, with the size of the stored image on a hit
+    pub fn record_lookup(&self, hit: bool, size: Option<u64>) {
+        self.update(now(), |bucket| {
+            if hit {
+                bucket.hits += 1;
+                bucket.bytes_from_cache += size.unwrap_or_default();
+            } else {
+                bucket.misses += 1;
+            }
+        });
+    }
+
+    /// Account a processed image, with the time spent processing it
+    pub fn record_processed(
+        &self,
+        url: &str,
+        format: &str,
+        source_size: u64,
+        output_size: u64,
+        elapsed: Duration,
+    ) {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+        self.update(now(), |bucket| {
+            bucket.bytes_processed += output_size;
+
+            let format = bucket.formats.entry(format.to_string()).or_default();
+            format.0 += 1;
+            format.1 += source_size;
+            format.2 += output_size;
+
+            let host = if bucket.origins.len() < MAX_ORIGINS_PER_BUCKET
+                || bucket.origins.contains_key(&host)
+            {
+                host
+            } else {
+                OTHER_ORIGINS.to_string()
+            };
+            let origin = bucket.origins.entry(host).or_default();
+            origin.0 += 1;
+            origin.1 += elapsed.as_millis() as u64;
+        });
+    }
+
+    /// Summary of the last window
+    pub fn report(&self) -> UsageReport {
+        self.report_at(now())
+    }
+
+    fn update(&self, at: u64, record: impl FnOnce(&mut Bucket)) {
+        let index = at / self.bucket_secs;
+        let mut buckets = self.buckets.lock().unwrap();
+        while buckets
+            .front()
+            .is_some_and(|(start, _)| *start + BUCKETS <= index)
+        {
+            buckets.pop_front();
+        }
+        if buckets.back().is_none_or(|(start, _)| *start != index) {
+            buckets.push_back((index, Bucket::default()));
+        }
+        if let Some((_, bucket)) = buckets.back_mut() {
+            record(bucket);
+        }
+    }
+
+    fn report_at(&self, at: u64) -> UsageReport {
+        let index = at / self.bucket_secs;
+        let buckets = self.buckets.lock().unwrap();
+        let mut report = UsageReport {
+            window_secs: self.bucket_secs * BUCKETS,
+            ..UsageReport::default()
+        };
+        let mut origins: HashMap<&str, (u64, u64)> = HashMap::new();
+
+        for (_, bucket) in buckets.iter().filter(|(start, _)| *start + BUCKETS > index) {
+            report.hits += bucket.hits;
+            report.misses += bucket.misses;
+            report.bytes_from_cache += bucket.bytes_from_cache;
+            report.bytes_processed += bucket.bytes_processed;
+            for (name, (images, source, output)) in &bucket.formats {
+                let format = report.formats.entry(name.clone()).or_default();
+                format.images += images;
+                format.source_bytes += source;
+                format.output_bytes += output;
+            }
+            for (host, (images, millis)) in &bucket.origins {
+                let origin = origins.entry(host).or_default();
+                origin.0 += images;
+                origin.1 += millis;
+            }
+        }
+
+        report.requests = report.hits + report.misses;
+        if report.requests > 0 {
+            report.hit_ratio = report.hits as f64 / report.requests as f64;
+        }
+        for format in report.formats.values_mut() {
+            if format.source_bytes > 0 {
+                format.compression_ratio = format.output_bytes as f64 / format.source_bytes as f64;
+            }
+        }
+
+        let mut origins: Vec<_> = origins.into_iter().collect();
+        origins.sort_by(|(a_host, a), (b_host, b)| b.1.cmp(&a.1).then(a_host.cmp(b_host)));
+        report.top_origins = origins
+            .into_iter()
+            .take(TOP_ORIGINS)
+            .map(|(host, (images, millis))| OriginReport {
+                host: host.to_string(),
+                images,
+                processing_ms: millis,
+                avg_processing_ms: millis as f64 / images.max(1) as f64,
+            })
+            .collect();
+        report
+    }
+}
+
+impl PipelineObserver for Accounting {
+    fn on_lookup(&self, _resize: &Observed, cached: Option<&ObjectMetadata>) {
+        self.record_lookup(cached.is_some(), cached.and_then(|metadata| metadata.size));
+    }
+
+    fn on_process(
+        &self,
+        resize: &Observed,
+        source_size: u64,
+        processed: &ProcessedImage,
+        elapsed: Duration,
+    ) {
+        self.record_processed(
+            &resize.params.url,
+            &resize.params.output_format().to_string(),
+            source_size,
+            processed.data.len() as u64,
+            elapsed,
+        );
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_the_last_window() {
+        let accounting = Accounting::new(Duration::from_secs(600));
+        let start = 1_000_000;
+        accounting.update(start, |bucket| {
+            bucket.hits += 5;
+        });
+        accounting.record_lookup(true, Some(100));
+        accounting.record_lookup(false, None);
+        accounting.record_processed(
+            "https://Slow.test/a.jpg",
+            "webp",
+            1000,
+            250,
+            Duration::from_millis(900),
+        );
+        accounting.record_processed(
+            "https://fast.test/a.jpg",
+            "webp",
+            1000,
+            150,
+            Duration::from_millis(100),
+        );
+
+        let report = accounting.report();
+        // The old lookups fell out of the window
+        assert_eq!(report.window_secs, 600);
+        assert_eq!((report.hits, report.misses), (1, 1));
+        assert_eq!(report.hit_ratio, 0.5);
+        assert_eq!(
+            (report.bytes_from_cache, report.bytes_processed),
+            (100, 400)
+        );
+        assert_eq!(
+            report.formats["webp"],
+            FormatReport {
+                images: 2,
+                source_bytes: 2000,
+                output_bytes: 400,
+                compression_ratio: 0.2,
+            }
+        );
+        let hosts: Vec<_> = report.top_origins.iter().map(|o| o.host.as_str()).collect();
+        assert_eq!(hosts, ["slow.test", "fast.test"]);
+        assert_eq!(report.top_origins[0].avg_processing_ms, 900.0);
+
+        // Everything rolls out once the window has passed
+        assert_eq!(accounting.report_at(now() + 600).requests, 0);
+    }
+}
@@ -0,0 +1,8 @@
+use crate::models::params::ResizeQuery;
+use anyhow::Result;
+
+/// Policy script run on every resize request before any work is done
+pub trait RequestScript: Send + Sync {
+    /// Inspect and rewrite a query; an error rejects the request
+    fn rewrite(&self, query: ResizeQuery, host: Option<&str>) -> Result<ResizeQuery>;
+}
@@ -0,0 +1,41 @@
+use crate::models::params::ResizeQuery;
+use crate::modules::env::env::EnvConfig;
+use crate::services::script::core::RequestScript;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Optional request rewriting hook; queries pass through unchanged without a script
+#[derive(Clone, Default)]
+pub struct ScriptHook {
+    script: Option<Arc<dyn RequestScript>>,
+}
+
+impl ScriptHook {
+    /// Create a hook running the given script
+    pub fn new(script: impl RequestScript + 'static) -> Self {
+        Self {
+            script: Some(Arc::new(script)),
+        }
+    }
+
+    /// Create the hook configured by the environment
+    #[allow(unused_variables)]
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        #[cfg(feature = "scripting")]
+        if let Some(path) = &config.rewrite_script_path {
+            let script =
+                crate::services::script::rhai_handler::RhaiScript::from_file(path.as_ref())?;
+            return Ok(Self::new(script));
+        }
+
+        Ok(Self::default())
+    }
+
+    /// Rewrite a query with the configured script, if any
+    pub fn rewrite(&self, query: ResizeQuery, host: Option<&str>) -> Result<ResizeQuery> {
+        match &self.script {
+            Some(script) => script.rewrite(query, host),
+            None => Ok(query),
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod core;
+pub mod handler;
+
+#[cfg(feature = "scripting")]
+pub mod rhai_handler;
@@ -0,0 +1,122 @@
+use crate::models::params::ResizeQuery;
+use crate::services::script::core::RequestScript;
+use anyhow::{Context, Result, anyhow};
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{AST, Dynamic, Engine, Scope};
+use std::path::Path;
+use tracing::info;
+
+/// Maximum number of operations a script may run per request
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Rewrite script written in Rhai
+///
+/// The script runs with two variables in scope: `query`, an object map holding the
+/// resize parameters (unset ones are `()`), and the constant `host`. Changes made to
+/// `query` are applied to the request, and `throw` rejects it.
+pub struct RhaiScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl RhaiScript {
+    /// Compile a script
+    pub fn new(source: &str) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_string_size(64 * 1024);
+
+        let ast = engine
+            .compile(source)
+            .map_err(|e| anyhow!("Failed to compile rewrite script: {}", e))?;
+
+        Ok(Self { engine, ast })
+    }
+
+    /// Compile a script from a file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .context(format!("Failed to read rewrite script {}", path.display()))?;
+        let script = Self::new(&source)?;
+        info!("Loaded rewrite script from {}", path.display());
+        Ok(script)
+    }
+}
+
+impl RequestScript for RhaiScript {
+    fn rewrite(&self, query: ResizeQuery, host: Option<&str>) -> Result<ResizeQuery> {
+        let mut scope = Scope::new();
+        scope.push(
+            "query",
+            to_dynamic(&query).map_err(|e| anyhow!("Failed to expose the query: {}", e))?,
+        );
+        scope.push_constant("host", host.unwrap_or_default().to_string());
+
+        self.engine
+            .run_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("Rewrite script rejected the request: {}", e))?;
+
+        let query = scope
+            .get_value::<Dynamic>("query")
+            .context("Rewrite script removed `query`")?;
+        from_dynamic(&query).map_err(|e| anyhow!("Rewrite script produced an invalid query: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gen_server::models::ImageFormat;
+
+    const SCRIPT: &str = r#"
+        if query.width != () && query.width > 1000 {
+            query.width = 1000;
+        }
+        if host == "legacy.example.com" {
+            query.format = "webp";
+            query.plugin = "sepia";
+        }
+        if query.url.contains("blocked") {
+            throw "source not allowed";
+        }
+    "#;
+
+    fn query(url: &str, width: u32) -> ResizeQuery {
+        ResizeQuery {
+            url: url.to_string(),
+            width: Some(width),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rhai_rewrite() {
+        let script = RhaiScript::new(SCRIPT).unwrap();
+
+        let unchanged = query("https://example.com/a.jpg", 300);
+        assert_eq!(
+            script
+                .rewrite(unchanged.clone(), Some("example.com"))
+                .unwrap(),
+            unchanged
+        );
+
+        let rewritten = script
+            .rewrite(
+                query("https://example.com/a.jpg", 4000),
+                Some("legacy.example.com"),
+            )
+            .unwrap();
+        assert_eq!(rewritten.width, Some(1000));
+        assert_eq!(rewritten.height, None);
+        assert_eq!(rewritten.format, Some(ImageFormat::Webp));
+        assert_eq!(rewritten.plugin.as_deref(), Some("sepia"));
+
+        assert!(
+            script
+                .rewrite(query("https://blocked.example.com/a.jpg", 300), None)
+                .is_err()
+        );
+    }
+}
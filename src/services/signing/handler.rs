@@ -0,0 +1,300 @@
+use crate::modules::env::env::EnvConfig;
+use crate::modules::utils::date::unix_now;
+use crate::services::resize::errors::ErrorCode;
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use gen_server::models::Rejection;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Query parameter holding the signature
+pub const SIGNATURE_PARAM: &str = "sig";
+
+/// Query parameter giving the Unix time after which a signed URL is refused
+pub const EXPIRES_PARAM: &str = "expires";
+
+/// Query parameter making a signed URL usable once
+pub const NONCE_PARAM: &str = "nonce";
+
+/// Paths of the endpoints fetching arbitrary sources, which need a signature
+const SIGNED_PATHS: [&str; 2] = ["/api/images/resize", "/api/images/convert"];
+
+/// Why a URL is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Not signed with the configured key, or with a malformed `expires`
+    Invalid,
+    /// Signed, but past its `expires`
+    Expired,
+    /// Signed with a `nonce` already used
+    Replayed,
+    /// Signed with a `nonce` but no `expires`, so it would have to be remembered forever
+    NonceWithoutExpiry,
+}
+
+impl SignatureError {
+    /// Machine-readable reason of the rejection
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::Invalid => "invalid_signature",
+            Self::Expired => "expired_signature",
+            Self::Replayed => "replayed_signature",
+            Self::NonceWithoutExpiry => "nonce_without_expiry",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::Invalid => "The URL is not signed, or its signature doesn't match",
+            Self::Expired => "The signed URL has expired",
+            Self::Replayed => "The signed URL was already used",
+            Self::NonceWithoutExpiry => "Signed URLs with a nonce must also expire",
+        }
+    }
+}
+
+/// HMAC-SHA256 signatures of resize and conversion URLs
+///
+/// Signatures cover the path and the query without `sig`, as sent, so that only URLs
+/// produced by holders of the key are served rather than any source anyone asks for.
+/// The optional `expires` and `nonce` parameters, being part of the query, are signed too.
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+    // Nonces already used, with the expiry of their URL, after which they are forgotten
+    nonces: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl UrlSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            nonces: Arc::default(),
+        }
+    }
+
+    /// Signer of the configured key, `None` when URLs are served unsigned
+    pub fn from_env(config: &EnvConfig) -> Option<Self> {
+        config
+            .signing_key
+            .as_deref()
+            .filter(|key| !key.is_empty())
+            .map(Self::new)
+    }
+
+    fn mac(&self, path: &str, query: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(path.as_bytes());
+        mac.update(b"?");
+        mac.update(unsigned_query(query).as_bytes());
+        mac
+    }
+
+    /// Base64url signature of `path` with `query`
+    pub fn sign(&self, path: &str, query: &str) -> String {
+        URL_SAFE_NO_PAD.encode(self.mac(path, query).finalize().into_bytes())
+    }
+
+    /// `path` with `query` and its signature appended
+    pub fn signed_url(&self, path: &str, query: &str) -> String {
+        let query = unsigned_query(query);
+        let separator = if query.is_empty() { "" } else { "&" };
+        format!(
+            "{}?{}{}{}={}",
+            path,
+            query,
+            separator,
+            SIGNATURE_PARAM,
+            self.sign(path, &query)
+        )
+    }
+
+    /// Check that `query` carries the signature of `path` with the rest of `query`, and
+    /// that it is neither expired nor replayed
+    ///
+    /// The nonce of an accepted URL is used up.
+    pub fn verify(&self, path: &str, query: &str) -> Result<(), SignatureError> {
+        self.verify_at(path, query, unix_now())
+    }
+
+    fn verify_at(&self, path: &str, query: &str, now: u64) -> Result<(), SignatureError> {
+        let signature = param(query, SIGNATURE_PARAM)
+            .and_then(|s| URL_SAFE_NO_PAD.decode(s).ok())
+            .ok_or(SignatureError::Invalid)?;
+        // Compared in constant time
+        self.mac(path, query)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+
+        let expires = param(query, EXPIRES_PARAM)
+            .map(|expires| expires.parse::<u64>())
+            .transpose()
+            .map_err(|_| SignatureError::Invalid)?;
+        if expires.is_some_and(|expires| now >= expires) {
+            return Err(SignatureError::Expired);
+        }
+
+        let Some(nonce) = param(query, NONCE_PARAM) else {
+            return Ok(());
+        };
+        let expires = expires.ok_or(SignatureError::NonceWithoutExpiry)?;
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, until| *until > now);
+        if nonces.contains_key(nonce) {
+            return Err(SignatureError::Replayed);
+        }
+        nonces.insert(nonce.to_string(), expires);
+        Ok(())
+    }
+}
+
+/// Raw value of the first `name` parameter of `query`
+fn param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        pair.strip_prefix(name)
+            .and_then(|value| value.strip_prefix('='))
+    })
+}
+
+/// `query` without its signature, the other parameters kept in their order
+fn unsigned_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(SIGNATURE_PARAM))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Reject resizes and conversions whose URL isn't signed with the configured key
+pub async fn verify_signatures(
+    State(signer): State<UrlSigner>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let uri = request.uri();
+    if !SIGNED_PATHS.contains(&uri.path()) {
+        return next.run(request).await;
+    }
+    match signer.verify(uri.path(), uri.query().unwrap_or("")) {
+        Ok(()) => next.run(request).await,
+        Err(e) => {
+            warn!("Rejected request to {}: {}", uri.path(), e.message());
+            rejection(e.reason(), e.message())
+        }
+    }
+}
+
+/// `403 Forbidden` answer to a request lacking a valid signature
+pub fn rejection(reason: &str, message: &str) -> Response {
+    let code = ErrorCode::InvalidSignature;
+    let rejection = Rejection {
+        reason: reason.to_string(),
+        message: message.to_string(),
+        cost: None,
+        budget: None,
+        code: code.to_string(),
+        retriable: code.is_retriable(),
+    };
+    (
+        StatusCode::FORBIDDEN,
+        [("x-error-code", code.as_str())],
+        Json(rejection),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verifies_signed_urls() {
+        let signer = UrlSigner::new("secret");
+        let path = "/api/images/resize";
+        let url = signer.signed_url(path, "url=https%3A%2F%2Fexample.com%2Fa.jpg&width=200");
+        let query = url.split_once('?').unwrap().1;
+        assert!(query.starts_with("url=https%3A%2F%2Fexample.com%2Fa.jpg&width=200&sig="));
+        assert_eq!(signer.verify(path, query), Ok(()));
+
+        // The signature may come anywhere in the query
+        let sig = query.rsplit_once('&').unwrap().1;
+        assert_eq!(
+            signer.verify(
+                path,
+                &format!("{}&url=https%3A%2F%2Fexample.com%2Fa.jpg&width=200", sig)
+            ),
+            Ok(())
+        );
+
+        // Other parameters, paths or keys don't match
+        let invalid = Err(SignatureError::Invalid);
+        assert_eq!(
+            signer.verify(path, &query.replace("width=200", "width=2000")),
+            invalid
+        );
+        assert_eq!(signer.verify("/api/images/convert", query), invalid);
+        assert_eq!(UrlSigner::new("other").verify(path, query), invalid);
+        assert_eq!(
+            signer.verify(path, "url=https%3A%2F%2Fexample.com%2Fa.jpg&width=200"),
+            invalid
+        );
+        assert_eq!(signer.verify(path, &format!("{}x", query)), invalid);
+    }
+
+    #[test]
+    fn test_refuses_expired_and_replayed_urls() {
+        let signer = UrlSigner::new("secret");
+        let path = "/api/images/resize";
+        let signed = |query: &str| {
+            signer
+                .signed_url(path, query)
+                .split_once('?')
+                .unwrap()
+                .1
+                .to_string()
+        };
+
+        let expiring = signed("url=a.jpg&expires=1000");
+        assert_eq!(signer.verify_at(path, &expiring, 999), Ok(()));
+        assert_eq!(signer.verify_at(path, &expiring, 999), Ok(()));
+        assert_eq!(
+            signer.verify_at(path, &expiring, 1000),
+            Err(SignatureError::Expired)
+        );
+        // The expiry is signed like the other parameters
+        assert_eq!(
+            signer.verify_at(path, &expiring.replace("1000", "2000"), 1500),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            signer.verify_at(path, &signed("url=a.jpg&expires=soon"), 0),
+            Err(SignatureError::Invalid)
+        );
+
+        let single_use = signed("url=a.jpg&expires=1000&nonce=n1");
+        assert_eq!(signer.verify_at(path, &single_use, 900), Ok(()));
+        assert_eq!(
+            signer.verify_at(path, &single_use, 901),
+            Err(SignatureError::Replayed)
+        );
+        // Forgotten once expired, when the URL is refused anyway
+        assert_eq!(
+            signer.verify_at(path, &signed("url=b.jpg&expires=2000&nonce=n2"), 1000),
+            Ok(())
+        );
+        assert!(!signer.nonces.lock().unwrap().contains_key("n1"));
+        assert_eq!(
+            signer.verify_at(path, &signed("url=a.jpg&nonce=n3"), 0),
+            Err(SignatureError::NonceWithoutExpiry)
+        );
+    }
+}
@@ -1,4 +1,144 @@
 use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Metadata stored alongside an image in the storage backend
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectMetadata {
+    /// Cache tags used by CDNs to purge the object (origin hash, tenant)
+    #[serde(default)]
+    pub surrogate_keys: Vec<String>,
+    /// Dimensions of the image, in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Size of the image, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Encoder quality picked by `quality=auto` or `max_bytes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Encoder version the image was produced with, unset for images stored before versioning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder_version: Option<u32>,
+    /// MIME type the image was stored with, unset for images stored before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Storage class of the object, unset for backends without storage classes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    /// Key of the image this object points to, for parameter keys in content-addressed mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_key: Option<String>,
+    /// Retention and legal hold the object is protected by, for tenants in compliance buckets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_lock: Option<ObjectLock>,
+    /// Base64 SHA-256 of the object, unset for images stored before checksums were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Verdict of the moderation classifier, unset for images that weren't classified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moderation: Option<ModerationVerdict>,
+}
+
+/// Verdict of the moderation classifier on a processed image
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModerationVerdict {
+    /// Likelihood of the image being unsafe, from 0 to 1
+    pub score: f32,
+    /// Category given by the classifier, e.g. `explicit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Whether the score reached the threshold of the tenant
+    #[serde(default)]
+    pub flagged: bool,
+}
+
+/// S3 Object Lock settings of an object, preventing its deletion
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectLock {
+    /// `GOVERNANCE` or `COMPLIANCE` retention, until `retain_until`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// End of the retention, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain_until: Option<u64>,
+    #[serde(default)]
+    pub legal_hold: bool,
+}
+
+impl ObjectLock {
+    /// Whether the object can't be deleted at `now`, in seconds since the Unix epoch
+    pub fn is_locked(&self, now: u64) -> bool {
+        self.legal_hold || self.retain_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Stored data no longer matches the checksum it was uploaded with
+#[derive(Debug, Error)]
+#[error("{key} is corrupt: its SHA-256 is {actual} instead of {expected}")]
+pub struct ChecksumMismatch {
+    pub key: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Base64 SHA-256 of `data`, the format of S3 `x-amz-checksum-sha256` headers
+pub fn checksum(data: &[u8]) -> String {
+    STANDARD.encode(Sha256::digest(data))
+}
+
+/// Check `data` read for `key` against the checksum it was stored with, if any
+pub fn verify_checksum(
+    key: &str,
+    data: &[u8],
+    expected: Option<&str>,
+) -> Result<(), ChecksumMismatch> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = checksum(data);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ChecksumMismatch {
+            key: key.to_string(),
+            expected: expected.to_string(),
+            actual,
+        })
+    }
+}
+
+/// Object listed in the storage backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredObject {
+    pub key: String,
+    /// Last modification time, in seconds since the Unix epoch
+    pub last_modified: Option<u64>,
+    pub storage_class: Option<String>,
+    /// Size of the object, in bytes
+    pub size: Option<u64>,
+}
+
+/// Content type of a stored image, from the extension of its key
+pub fn content_type_from_key(key: &str) -> &'static str {
+    match key.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("avif") => "image/avif",
+        Some("gif") => "image/gif",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
 
 /// Storage backend trait defining operations for image storage
 #[async_trait]
@@ -8,12 +148,39 @@ pub trait StorageBackend: Send + Sync + 'static {
         &self,
         key: &str,
         content_type: &str,
-        data: Vec<u8>,
+        data: Bytes,
+        metadata: &ObjectMetadata,
     ) -> anyhow::Result<()>;
 
     /// Checks if an object with the given key exists in the storage backend.
     async fn check_cache(&self, key: &str) -> anyhow::Result<bool>;
 
-    /// Retrieves image data from the storage backend with a given key.
-    async fn get_image(&self, key: &str) -> anyhow::Result<Vec<u8>>;
+    /// Retrieves image data from the storage backend with a given key, failing with
+    /// [`ChecksumMismatch`] if it doesn't match the checksum it was uploaded with.
+    async fn get_image(&self, key: &str) -> anyhow::Result<Bytes>;
+
+    /// Retrieves the metadata stored with the given key, or `None` if the object doesn't exist.
+    async fn get_metadata(&self, key: &str) -> anyhow::Result<Option<ObjectMetadata>>;
+
+    /// Deletes the object and its metadata, returning whether it existed.
+    async fn delete_image(&self, key: &str) -> anyhow::Result<bool>;
+
+    /// Returns a URL clients can `PUT` the object to directly, if the backend supports it.
+    async fn presign_upload(
+        &self,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Lists the objects whose key starts with `prefix`, if the backend supports it.
+    async fn list_objects(&self, _prefix: &str) -> anyhow::Result<Vec<StoredObject>> {
+        Ok(Vec::new())
+    }
+
+    /// Moves the object to another storage class, returning whether the backend supports it.
+    async fn set_storage_class(&self, _key: &str, _storage_class: &str) -> anyhow::Result<bool> {
+        Ok(false)
+    }
 }
@@ -1,8 +1,17 @@
-use crate::services::storage::core::StorageBackend;
+use crate::modules::env::env::EnvConfig;
+use crate::services::storage::core::{
+    ChecksumMismatch, ObjectMetadata, StorageBackend, StoredObject, checksum,
+};
 use anyhow::{Result, anyhow};
+use bytes::Bytes;
 use derive_builder::Builder;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::error;
+
+/// Key looked up by the readiness probe
+const HEALTH_PROBE_KEY: &str = "health-probe";
 
 /// Factory for creating storage backends based on configuration
 #[derive(Clone, Builder)]
@@ -17,6 +26,7 @@ pub enum StorageType {
     S3,
     LocalFs,
     InMemory,
+    Redis,
 }
 
 impl StorageType {
@@ -26,6 +36,7 @@ impl StorageType {
             "S3" | "MINIO" => Ok(StorageType::S3),
             "LOCAL_FS" | "LOCALFS" | "LOCAL" => Ok(StorageType::LocalFs),
             "IN_MEMORY" | "INMEMORY" | "MEMORY" => Ok(StorageType::InMemory),
+            "REDIS" => Ok(StorageType::Redis),
             _ => Err(anyhow!("Invalid storage type: {}", s)),
         }
     }
@@ -62,6 +73,14 @@ impl StorageService {
             #[cfg(feature = "in_memory")]
             StorageType::InMemory => Self::create_in_memory_storage(config.cdn_base_url),
 
+            #[cfg(feature = "redis_storage")]
+            StorageType::Redis => Self::create_redis_storage(
+                config
+                    .redis_config
+                    .ok_or_else(|| anyhow!("Redis configuration is required"))?,
+                config.cdn_base_url,
+            ),
+
             #[allow(unreachable_patterns)]
             _ => Err(anyhow!(
                 "No storage backend available for the selected type"
@@ -89,6 +108,11 @@ impl StorageService {
             enabled_features += 1;
         }
 
+        #[cfg(feature = "redis_storage")]
+        {
+            enabled_features += 1;
+        }
+
         // If no features are enabled, return an error
         if enabled_features == 0 {
             return Err(anyhow!("No storage features are enabled"));
@@ -104,6 +128,9 @@ impl StorageService {
 
             #[cfg(feature = "in_memory")]
             return Ok(StorageType::InMemory);
+
+            #[cfg(feature = "redis_storage")]
+            return Ok(StorageType::Redis);
         }
 
         // If multiple features are enabled, use the storage_type parameter or environment variable
@@ -126,6 +153,9 @@ impl StorageService {
         #[cfg(feature = "in_memory")]
         return Ok(StorageType::InMemory);
 
+        #[cfg(feature = "redis_storage")]
+        return Ok(StorageType::Redis);
+
         // This code is unreachable due to the checks above, but kept for completeness
         #[allow(unreachable_code)]
         Err(anyhow!("No storage features are enabled"))
@@ -176,9 +206,33 @@ impl StorageService {
         })
     }
 
-    /// Upload an image to storage
-    pub async fn upload_image(&self, key: &str, content_type: &str, data: Vec<u8>) -> Result<()> {
-        self.storage.upload_image(key, content_type, data).await
+    /// Create a new Redis storage backend
+    #[cfg(feature = "redis_storage")]
+    fn create_redis_storage(config: RedisStorageConfig, cdn_base_url: String) -> Result<Self> {
+        let redis_storage_adapter =
+            crate::services::storage::redis_handler::RedisStorage::new(&config.url, config.ttl)?;
+
+        Ok(Self {
+            storage: Arc::new(redis_storage_adapter),
+            cdn_base_url,
+        })
+    }
+
+    /// Upload an image to storage, along with its checksum
+    pub async fn upload_image(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
+        let metadata = ObjectMetadata {
+            checksum: Some(checksum(&data)),
+            ..metadata.clone()
+        };
+        self.storage
+            .upload_image(key, content_type, data, &metadata)
+            .await
     }
 
     /// Check if an image exists in the cache
@@ -186,14 +240,68 @@ impl StorageService {
         self.storage.check_cache(key).await
     }
 
+    /// Verify that the backend is reachable by looking up a key that never exists
+    pub async fn ping(&self) -> Result<()> {
+        self.storage.check_cache(HEALTH_PROBE_KEY).await.map(|_| ())
+    }
+
     /// Get the CDN URL for a cached image
     pub fn get_cdn_url(&self, key: &str) -> String {
         format!("{}/{}", self.cdn_base_url.trim_end_matches('/'), key)
     }
 
-    /// Get an image from storage
-    pub async fn get_image(&self, key: &str) -> Result<Vec<u8>> {
-        self.storage.get_image(key).await
+    /// Get an image from storage, verified against its checksum
+    pub async fn get_image(&self, key: &str) -> Result<Bytes> {
+        let result = self.storage.get_image(key).await;
+        if let Err(e) = &result
+            && let Some(mismatch) = e.downcast_ref::<ChecksumMismatch>()
+        {
+            error!("Corrupt object in storage: {}", mismatch);
+            #[cfg(feature = "otel")]
+            opentelemetry::global::meter("emgr")
+                .u64_counter("emgr.storage.corruptions")
+                .with_description("Objects read from storage not matching their checksum")
+                .build()
+                .add(1, &[]);
+        }
+        result
+    }
+
+    /// Get the metadata of an image, or `None` if it doesn't exist
+    pub async fn get_metadata(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        self.storage.get_metadata(key).await
+    }
+
+    /// Delete an image and its metadata, returning whether it existed
+    pub async fn delete_image(&self, key: &str) -> Result<bool> {
+        self.storage.delete_image(key).await
+    }
+
+    /// Presigned URL to upload an object directly, if the backend supports it
+    pub async fn presign_upload(&self, key: &str, expires_in: Duration) -> Result<Option<String>> {
+        self.storage.presign_upload(key, expires_in).await
+    }
+
+    /// Objects whose key starts with `prefix`, empty if the backend can't list them
+    pub async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        self.storage.list_objects(prefix).await
+    }
+
+    /// Move an object to another storage class, returning whether the backend supports it
+    pub async fn set_storage_class(&self, key: &str, storage_class: &str) -> Result<bool> {
+        self.storage.set_storage_class(key, storage_class).await
+    }
+
+    /// Fail a share of the storage calls, as set on `chaos`
+    #[cfg(feature = "chaos")]
+    pub fn with_chaos(self, chaos: crate::services::chaos::handler::ChaosController) -> Self {
+        Self {
+            storage: Arc::new(crate::services::chaos::handler::ChaosStorage::new(
+                self.storage,
+                chaos,
+            )),
+            ..self
+        }
     }
 }
 
@@ -214,6 +322,14 @@ pub struct LocalFsConfig {
     pub base_path: std::path::PathBuf,
 }
 
+/// Configuration for Redis storage
+#[derive(Debug, Clone)]
+pub struct RedisStorageConfig {
+    pub url: String,
+    /// Lifetime of stored images, `None` to keep them until evicted
+    pub ttl: Option<Duration>,
+}
+
 /// Configuration for storage service
 #[derive(Debug, Clone, Default)]
 #[cfg(feature = "s3")]
@@ -222,6 +338,8 @@ pub struct StorageConfig {
     pub cdn_base_url: String,
     pub s3_config: Option<S3Config>,
     pub local_fs_config: Option<LocalFsConfig>,
+    #[cfg(feature = "redis_storage")]
+    pub redis_config: Option<RedisStorageConfig>,
 }
 
 #[cfg(not(feature = "s3"))]
@@ -229,6 +347,8 @@ pub struct StorageConfig {
     pub storage_type: Option<String>,
     pub cdn_base_url: String,
     pub local_fs_config: Option<LocalFsConfig>,
+    #[cfg(feature = "redis_storage")]
+    pub redis_config: Option<RedisStorageConfig>,
 }
 
 #[cfg(feature = "s3")]
@@ -240,6 +360,8 @@ impl StorageConfig {
             cdn_base_url,
             s3_config: None,
             local_fs_config: None,
+            #[cfg(feature = "redis_storage")]
+            redis_config: None,
         }
     }
 }
@@ -252,6 +374,8 @@ impl StorageConfig {
             storage_type: None,
             cdn_base_url,
             local_fs_config: None,
+            #[cfg(feature = "redis_storage")]
+            redis_config: None,
         }
     }
 }
@@ -292,4 +416,53 @@ impl StorageConfig {
         });
         self
     }
+
+    /// Set the Redis configuration, a `ttl` of zero keeping images until evicted
+    #[cfg(feature = "redis_storage")]
+    pub fn with_redis_config(mut self, url: impl Into<String>, ttl: Duration) -> Self {
+        self.redis_config = Some(RedisStorageConfig {
+            url: url.into(),
+            ttl: (!ttl.is_zero()).then_some(ttl),
+        });
+        self
+    }
+}
+
+impl From<&EnvConfig> for StorageConfig {
+    /// Build the storage configuration from the environment
+    fn from(config: &EnvConfig) -> Self {
+        let mut storage_config = StorageConfig::new(config.cdn_base_url.clone());
+
+        // Add storage type if specified
+        if let Some(storage_type) = &config.storage_type {
+            storage_config = storage_config.with_storage_type(storage_type);
+        }
+
+        // Configure S3 storage
+        #[cfg(feature = "s3")]
+        {
+            storage_config = storage_config.with_s3_config(
+                config.minio_endpoint_url.clone(),
+                config.minio_access_key_id.clone(),
+                config.minio_secret_access_key.clone(),
+                config.minio_bucket.clone(),
+                config.minio_region.clone(),
+            );
+        }
+
+        // Configure local FS storage
+        #[cfg(feature = "local_fs")]
+        {
+            storage_config = storage_config.with_local_fs_config(&config.local_fs_storage_path);
+        }
+
+        // Configure Redis storage
+        #[cfg(feature = "redis_storage")]
+        if let Some(url) = &config.redis_url {
+            storage_config = storage_config
+                .with_redis_config(url, Duration::from_secs(config.redis_storage_ttl_secs));
+        }
+
+        storage_config
+    }
 }
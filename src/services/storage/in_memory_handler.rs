@@ -1,9 +1,13 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use bytes::Bytes;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::services::storage::core::StorageBackend;
+use crate::services::storage::core::{
+    ObjectMetadata, StorageBackend, StoredObject, verify_checksum,
+};
 
 /// In-memory storage implementation
 ///
@@ -14,7 +18,11 @@ use crate::services::storage::core::StorageBackend;
 /// - Not suitable for production environments or distributed systems
 pub struct InMemoryStorage {
     /// Internal storage using a thread-safe hash map
-    storage: Arc<RwLock<HashMap<String, (String, Vec<u8>)>>>,
+    storage: Arc<RwLock<HashMap<String, (String, Bytes)>>>,
+    /// Metadata stored alongside each image
+    metadata: Arc<RwLock<HashMap<String, ObjectMetadata>>>,
+    /// Upload time of each image, in seconds since the Unix epoch
+    modified: Arc<RwLock<HashMap<String, u64>>>,
 }
 
 impl InMemoryStorage {
@@ -22,16 +30,33 @@ impl InMemoryStorage {
     pub fn new() -> Self {
         Self {
             storage: Arc::new(RwLock::new(HashMap::new())),
+            metadata: Arc::new(RwLock::new(HashMap::new())),
+            modified: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 }
 
 #[async_trait]
 impl StorageBackend for InMemoryStorage {
-    async fn upload_image(&self, key: &str, content_type: &str, data: Vec<u8>) -> Result<()> {
+    async fn upload_image(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
         // Store the image data with its content type in memory
         let mut storage = self.storage.write().unwrap();
         storage.insert(key.to_string(), (content_type.to_string(), data));
+
+        let mut stored_metadata = self.metadata.write().unwrap();
+        stored_metadata.insert(key.to_string(), metadata.clone());
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        self.modified.write().unwrap().insert(key.to_string(), now);
         Ok(())
     }
 
@@ -41,22 +66,74 @@ impl StorageBackend for InMemoryStorage {
         Ok(storage.contains_key(key))
     }
 
-    async fn get_image(&self, key: &str) -> Result<Vec<u8>> {
+    async fn get_image(&self, key: &str) -> Result<Bytes> {
         // Retrieve the image data from in-memory storage
         let storage = self.storage.read().unwrap();
         match storage.get(key) {
-            Some((_, data)) => Ok(data.clone()),
+            Some((_, data)) => {
+                let metadata = self.metadata.read().unwrap();
+                let checksum = metadata.get(key).and_then(|m| m.checksum.as_deref());
+                verify_checksum(key, data, checksum)?;
+                Ok(data.clone())
+            }
             None => Err(anyhow::anyhow!(
                 "Image not found in memory storage: {}",
                 key
             )),
         }
     }
+
+    async fn get_metadata(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        let content_type = match self.storage.read().unwrap().get(key) {
+            Some((content_type, _)) => content_type.clone(),
+            None => return Ok(None),
+        };
+
+        let metadata = self.metadata.read().unwrap();
+        Ok(Some(ObjectMetadata {
+            content_type: Some(content_type),
+            ..metadata.get(key).cloned().unwrap_or_default()
+        }))
+    }
+
+    async fn delete_image(&self, key: &str) -> Result<bool> {
+        self.metadata.write().unwrap().remove(key);
+        self.modified.write().unwrap().remove(key);
+        Ok(self.storage.write().unwrap().remove(key).is_some())
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        let metadata = self.metadata.read().unwrap();
+        let modified = self.modified.read().unwrap();
+        Ok(self
+            .storage
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, (_, data))| StoredObject {
+                key: key.clone(),
+                last_modified: modified.get(key).copied(),
+                storage_class: metadata
+                    .get(key)
+                    .and_then(|metadata| metadata.storage_class.clone()),
+                size: Some(data.len() as u64),
+            })
+            .collect())
+    }
+
+    async fn set_storage_class(&self, key: &str, storage_class: &str) -> Result<bool> {
+        if let Some(metadata) = self.metadata.write().unwrap().get_mut(key) {
+            metadata.storage_class = Some(storage_class.to_string());
+        }
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::storage::core::{ChecksumMismatch, checksum};
 
     #[tokio::test]
     async fn test_in_memory_storage() {
@@ -65,11 +142,25 @@ mod tests {
         // Test uploading an image
         let key = "test-image.jpg";
         let content_type = "image/jpeg";
-        let data = vec![1, 2, 3, 4, 5]; // Dummy image data
+        let data = Bytes::from_static(&[1, 2, 3, 4, 5]); // Dummy image data
+        let metadata = ObjectMetadata {
+            surrogate_keys: vec!["origin-0123456789abcdef".to_string()],
+            width: Some(1),
+            height: Some(1),
+            size: Some(data.len() as u64),
+            quality: None,
+            encoder_version: Some(1),
+            content_type: Some(content_type.to_string()),
+            storage_class: None,
+            content_key: None,
+            object_lock: None,
+            checksum: None,
+            moderation: None,
+        };
 
         assert!(
             storage
-                .upload_image(key, content_type, data.clone())
+                .upload_image(key, content_type, data.clone(), &metadata)
                 .await
                 .is_ok()
         );
@@ -79,9 +170,41 @@ mod tests {
         assert!(!storage.check_cache("nonexistent-key").await.unwrap());
 
         // Verify the stored data
-        let stored_data = storage.storage.read().unwrap();
-        let (stored_content_type, stored_bytes) = stored_data.get(key).unwrap();
-        assert_eq!(stored_content_type, content_type);
-        assert_eq!(stored_bytes, &data);
+        {
+            let stored_data = storage.storage.read().unwrap();
+            let (stored_content_type, stored_bytes) = stored_data.get(key).unwrap();
+            assert_eq!(stored_content_type, content_type);
+            assert_eq!(stored_bytes, &data);
+        }
+
+        // Verify the stored metadata
+        assert_eq!(storage.get_metadata(key).await.unwrap(), Some(metadata));
+        assert_eq!(storage.get_metadata("nonexistent-key").await.unwrap(), None);
+
+        // Test deleting the image
+        assert!(storage.delete_image(key).await.unwrap());
+        assert!(!storage.check_cache(key).await.unwrap());
+        assert!(!storage.delete_image(key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_detects_corrupt_images() {
+        let storage = InMemoryStorage::new();
+        let data = Bytes::from_static(&[1, 2, 3]);
+        let metadata = ObjectMetadata {
+            checksum: Some(checksum(&data)),
+            ..ObjectMetadata::default()
+        };
+        storage
+            .upload_image("a.png", "image/png", data.clone(), &metadata)
+            .await
+            .unwrap();
+        assert_eq!(storage.get_image("a.png").await.unwrap(), data);
+
+        // Flip a bit behind the backend's back
+        storage.storage.write().unwrap().get_mut("a.png").unwrap().1 =
+            Bytes::from_static(&[1, 2, 2]);
+        let error = storage.get_image("a.png").await.unwrap_err();
+        assert!(error.is::<ChecksumMismatch>());
     }
 }
@@ -1,8 +1,12 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use bytes::Bytes;
 use std::path::PathBuf;
 
-use crate::services::storage::core::StorageBackend;
+use crate::services::storage::core::{ObjectMetadata, StorageBackend, verify_checksum};
+
+/// Suffix of the sidecar file holding an image's metadata
+const METADATA_SUFFIX: &str = ".meta.json";
 
 /// Local file system storage implementation
 pub struct LocalFSStorage {
@@ -15,11 +19,22 @@ impl LocalFSStorage {
             base_path: base_path.into(),
         })
     }
+
+    /// Path of the metadata sidecar for a given key
+    fn metadata_path(&self, key: &str) -> PathBuf {
+        self.base_path.join(format!("{}{}", key, METADATA_SUFFIX))
+    }
 }
 
 #[async_trait]
 impl StorageBackend for LocalFSStorage {
-    async fn upload_image(&self, key: &str, _content_type: &str, data: Vec<u8>) -> Result<()> {
+    async fn upload_image(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
         let file_path = self.base_path.join(key);
         // Ensure directory exists
         if let Some(parent) = file_path.parent() {
@@ -30,6 +45,15 @@ impl StorageBackend for LocalFSStorage {
         tokio::fs::write(&file_path, data)
             .await
             .context("Failed to write image to a local file system")?;
+
+        let metadata = ObjectMetadata {
+            content_type: Some(content_type.to_string()),
+            ..metadata.clone()
+        };
+        let metadata = serde_json::to_vec(&metadata).context("Failed to serialize metadata")?;
+        tokio::fs::write(self.metadata_path(key), metadata)
+            .await
+            .context("Failed to write metadata to a local file system")?;
         Ok(())
     }
 
@@ -38,11 +62,50 @@ impl StorageBackend for LocalFSStorage {
         Ok(tokio::fs::metadata(&file_path).await.is_ok())
     }
 
-    async fn get_image(&self, key: &str) -> Result<Vec<u8>> {
+    async fn get_image(&self, key: &str) -> Result<Bytes> {
         let file_path = self.base_path.join(key);
-        tokio::fs::read(&file_path).await.context(format!(
+        let data = tokio::fs::read(&file_path).await.context(format!(
             "Failed to read image from local file system: {}",
             file_path.display()
-        ))
+        ))?;
+
+        // A flaky disk may have flipped bits since the image was written
+        let checksum = self
+            .get_metadata(key)
+            .await?
+            .and_then(|metadata| metadata.checksum);
+        verify_checksum(key, &data, checksum.as_deref())?;
+        Ok(data.into())
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        if !self.check_cache(key).await? {
+            return Ok(None);
+        }
+
+        // Images stored before metadata existed have no sidecar
+        match tokio::fs::read(self.metadata_path(key)).await {
+            Ok(data) => Ok(Some(
+                serde_json::from_slice(&data).context("Failed to parse image metadata")?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Some(ObjectMetadata::default()))
+            }
+            Err(e) => Err(e).context("Failed to read metadata from local file system"),
+        }
+    }
+
+    async fn delete_image(&self, key: &str) -> Result<bool> {
+        match tokio::fs::remove_file(self.metadata_path(key)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("Failed to delete metadata from local file system"),
+        }
+
+        match tokio::fs::remove_file(self.base_path.join(key)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).context("Failed to delete image from local file system"),
+        }
     }
 }
@@ -9,4 +9,8 @@ pub mod local_fs_handler;
 #[cfg(feature = "in_memory")]
 pub mod in_memory_handler;
 
+#[cfg(feature = "redis_storage")]
+pub mod redis_handler;
+
 pub mod core;
+pub mod retention;
@@ -0,0 +1,246 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use redis::aio::ConnectionManager;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+
+use crate::services::storage::core::{
+    ObjectMetadata, StorageBackend, StoredObject, verify_checksum,
+};
+
+/// Prefix of the image keys in Redis
+const KEY_PREFIX: &str = "emgr:image:";
+
+/// Hash fields of each image
+const DATA_FIELD: &str = "data";
+const METADATA_FIELD: &str = "metadata";
+const MODIFIED_FIELD: &str = "modified";
+
+/// Keys requested from Redis by each `SCAN` call
+const SCAN_COUNT: usize = 500;
+
+/// Redis storage implementation
+///
+/// Every image is a hash of its bytes, its metadata as JSON and its upload time, expiring
+/// after the configured TTL. Meant for small variants such as thumbnails, served much
+/// faster than from S3 while surviving restarts, unlike the in-memory backend. Images are
+/// subject to the eviction policy of the Redis server.
+pub struct RedisStorage {
+    client: redis::Client,
+    /// Connected on first use, as the service is created outside of the runtime
+    connection: OnceCell<ConnectionManager>,
+    /// Lifetime of stored images, `None` to keep them until evicted
+    ttl: Option<Duration>,
+}
+
+impl RedisStorage {
+    pub(crate) fn new(url: &str, ttl: Option<Duration>) -> Result<Self> {
+        let client = redis::Client::open(url).context("Invalid Redis URL")?;
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+            ttl,
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(connection.clone())
+    }
+}
+
+/// Redis key of the image stored under `key`
+fn redis_key(key: &str) -> String {
+    format!("{}{}", KEY_PREFIX, key)
+}
+
+/// `SCAN` pattern of the images whose key starts with `prefix`
+fn scan_pattern(prefix: &str) -> String {
+    let mut pattern = KEY_PREFIX.to_string();
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('*');
+    pattern
+}
+
+fn parse_metadata(key: &str, metadata: Option<String>) -> Result<ObjectMetadata> {
+    metadata
+        .map(|metadata| {
+            serde_json::from_str(&metadata).context(format!("Invalid metadata of {}", key))
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+#[async_trait]
+impl StorageBackend for RedisStorage {
+    async fn upload_image(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
+        let metadata = ObjectMetadata {
+            content_type: Some(content_type.to_string()),
+            ..metadata.clone()
+        };
+        let metadata = serde_json::to_string(&metadata).context("Failed to serialize metadata")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+
+        // Replaced as a whole, so a previous TTL doesn't outlive the new image
+        let redis_key = redis_key(key);
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("DEL")
+            .arg(&redis_key)
+            .ignore()
+            .cmd("HSET")
+            .arg(&redis_key)
+            .arg(DATA_FIELD)
+            .arg(data.as_ref())
+            .arg(METADATA_FIELD)
+            .arg(metadata)
+            .arg(MODIFIED_FIELD)
+            .arg(now)
+            .ignore();
+        if let Some(ttl) = self.ttl {
+            pipe.cmd("PEXPIRE")
+                .arg(&redis_key)
+                .arg(ttl.as_millis() as u64)
+                .ignore();
+        }
+        let () = pipe
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to write image to Redis")?;
+        Ok(())
+    }
+
+    async fn check_cache(&self, key: &str) -> Result<bool> {
+        let exists: bool = redis::cmd("EXISTS")
+            .arg(redis_key(key))
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to look up image in Redis")?;
+        Ok(exists)
+    }
+
+    async fn get_image(&self, key: &str) -> Result<Bytes> {
+        let (data, metadata): (Option<Vec<u8>>, Option<String>) = redis::cmd("HMGET")
+            .arg(redis_key(key))
+            .arg(DATA_FIELD)
+            .arg(METADATA_FIELD)
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to read image from Redis")?;
+
+        let data = data.ok_or_else(|| anyhow::anyhow!("Image not found in Redis: {}", key))?;
+        let metadata = parse_metadata(key, metadata)?;
+        verify_checksum(key, &data, metadata.checksum.as_deref())?;
+        Ok(Bytes::from(data))
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        let (exists, metadata): (bool, Option<String>) = redis::pipe()
+            .cmd("EXISTS")
+            .arg(redis_key(key))
+            .cmd("HGET")
+            .arg(redis_key(key))
+            .arg(METADATA_FIELD)
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to read metadata from Redis")?;
+
+        if !exists {
+            return Ok(None);
+        }
+        parse_metadata(key, metadata).map(Some)
+    }
+
+    async fn delete_image(&self, key: &str) -> Result<bool> {
+        let deleted: u64 = redis::cmd("DEL")
+            .arg(redis_key(key))
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to delete image from Redis")?;
+        Ok(deleted > 0)
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        let mut connection = self.connection().await?;
+        let pattern = scan_pattern(prefix);
+        let mut redis_keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SCAN_COUNT)
+                .query_async(&mut connection)
+                .await
+                .context("Failed to list images in Redis")?;
+            redis_keys.extend(keys);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        let mut objects = Vec::with_capacity(redis_keys.len());
+        for redis_key in redis_keys {
+            let ((modified, metadata), size): ((Option<u64>, Option<String>), u64) = redis::pipe()
+                .cmd("HMGET")
+                .arg(&redis_key)
+                .arg(MODIFIED_FIELD)
+                .arg(METADATA_FIELD)
+                .cmd("HSTRLEN")
+                .arg(&redis_key)
+                .arg(DATA_FIELD)
+                .query_async(&mut connection)
+                .await
+                .context("Failed to read image from Redis")?;
+            // Expired or deleted since the scan
+            if modified.is_none() {
+                continue;
+            }
+
+            let key = redis_key[KEY_PREFIX.len()..].to_string();
+            let metadata = parse_metadata(&key, metadata)?;
+            objects.push(StoredObject {
+                key,
+                last_modified: modified,
+                storage_class: metadata.storage_class,
+                size: Some(size),
+            });
+        }
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lists_prefixes_literally() {
+        assert_eq!(scan_pattern(""), "emgr:image:*");
+        assert_eq!(scan_pattern("audit/"), "emgr:image:audit/*");
+        assert_eq!(scan_pattern("a*b?[c]\\"), "emgr:image:a\\*b\\?\\[c\\]\\\\*");
+        assert_eq!(redis_key("a/b.jpg"), "emgr:image:a/b.jpg");
+    }
+}
@@ -0,0 +1,146 @@
+use crate::modules::env::env::EnvConfig;
+use crate::services::storage::core::ObjectLock;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// Seconds in a day of retention
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// A purge targets an object under retention or legal hold
+#[derive(Debug, Error)]
+#[error("{key} is locked by {reason} and can't be purged")]
+pub struct ObjectLocked {
+    pub key: String,
+    /// Legal hold or the end of the retention
+    pub reason: String,
+}
+
+impl ObjectLocked {
+    pub fn new(key: &str, lock: &ObjectLock) -> Self {
+        let reason = match (lock.legal_hold, lock.retain_until) {
+            (true, _) => "a legal hold".to_string(),
+            (false, Some(until)) => format!(
+                "{} retention until {}",
+                lock.mode.as_deref().unwrap_or("GOVERNANCE"),
+                until
+            ),
+            (false, None) => "an object lock".to_string(),
+        };
+        Self {
+            key: key.to_string(),
+            reason,
+        }
+    }
+}
+
+/// Object Lock applied to the uploads of one tenant
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TenantObjectLock {
+    /// `GOVERNANCE` or `COMPLIANCE`, retaining uploads for `retain_days`
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub retain_days: u64,
+    #[serde(default)]
+    pub legal_hold: bool,
+}
+
+/// Per-tenant Object Lock of processed images, for tenants with retention requirements
+///
+/// The bucket must have Object Lock enabled for S3 to enforce it. The lock is also kept in
+/// the image metadata, so that purges of locked images are refused on every backend.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectLockPolicy {
+    /// Lock of the uploads of each tenant host
+    pub tenants: HashMap<String, TenantObjectLock>,
+}
+
+impl ObjectLockPolicy {
+    /// Read the JSON object of `TenantObjectLock` entries by tenant host at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+        let tenants: HashMap<String, TenantObjectLock> = serde_json::from_slice(&data)
+            .context(format!("Invalid object lock config {}", path.display()))?;
+
+        for (tenant, lock) in &tenants {
+            match lock.mode.as_deref() {
+                None if lock.retain_days > 0 => {
+                    bail!("Object lock of {} sets retain_days without a mode", tenant)
+                }
+                Some("GOVERNANCE" | "COMPLIANCE") if lock.retain_days == 0 => {
+                    bail!("Object lock of {} sets a mode without retain_days", tenant)
+                }
+                None | Some("GOVERNANCE" | "COMPLIANCE") => {}
+                Some(mode) => bail!(
+                    "Invalid object lock mode {:?} of {}, expected GOVERNANCE or COMPLIANCE",
+                    mode,
+                    tenant
+                ),
+            }
+        }
+
+        Ok(Self {
+            tenants: tenants
+                .into_iter()
+                .map(|(tenant, lock)| (tenant.to_lowercase(), lock))
+                .collect(),
+        })
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        match config.object_lock_config.as_deref() {
+            Some(path) if !path.is_empty() => Self::load(Path::new(path)),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Lock of an image uploaded at `now` for `tenant`, if the tenant has one
+    pub fn lock_for(&self, tenant: Option<&str>, now: u64) -> Option<ObjectLock> {
+        // Tenants are hosts, compared without their port
+        let host = tenant?.split(':').next().unwrap_or_default().to_lowercase();
+        let lock = self.tenants.get(&host)?;
+        Some(ObjectLock {
+            mode: lock.mode.clone(),
+            retain_until: lock
+                .mode
+                .as_ref()
+                .map(|_| now + lock.retain_days * DAY_SECS),
+            legal_hold: lock.legal_hold,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locks_the_uploads_of_configured_tenants() {
+        let policy = ObjectLockPolicy {
+            tenants: HashMap::from([(
+                "news.example.com".to_string(),
+                TenantObjectLock {
+                    mode: Some("COMPLIANCE".to_string()),
+                    retain_days: 2,
+                    legal_hold: false,
+                },
+            )]),
+        };
+
+        let lock = policy
+            .lock_for(Some("News.example.com:8080"), 1_000)
+            .unwrap();
+        assert_eq!(lock.retain_until, Some(1_000 + 2 * DAY_SECS));
+        assert!(lock.is_locked(1_000 + DAY_SECS));
+        assert!(!lock.is_locked(1_000 + 2 * DAY_SECS));
+        assert_eq!(policy.lock_for(Some("other.example.com"), 1_000), None);
+        assert_eq!(policy.lock_for(None, 1_000), None);
+        assert_eq!(
+            ObjectLocked::new("a.jpg", &lock).to_string(),
+            "a.jpg is locked by COMPLIANCE retention until 173800 and can't be purged"
+        );
+    }
+}
@@ -1,11 +1,34 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use bytes::Bytes;
 
 use aws_sdk_s3 as s3;
 use aws_sdk_s3::operation::head_object::HeadObjectError;
-use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::{ByteStream, DateTime};
+use aws_sdk_s3::types::{
+    MetadataDirective, ObjectLockLegalHoldStatus, ObjectLockMode, StorageClass,
+};
+use std::time::Duration;
 
-use crate::services::storage::core::StorageBackend;
+use crate::services::storage::core::{
+    ObjectLock, ObjectMetadata, StorageBackend, StoredObject, verify_checksum,
+};
+
+/// User metadata entry holding the space separated surrogate keys
+const SURROGATE_KEY_METADATA: &str = "surrogate-key";
+
+/// User metadata entries holding the image dimensions and encoder settings
+const WIDTH_METADATA: &str = "image-width";
+const HEIGHT_METADATA: &str = "image-height";
+const QUALITY_METADATA: &str = "image-quality";
+const ENCODER_VERSION_METADATA: &str = "encoder-version";
+/// User metadata entry holding the key a content-addressed pointer points to
+const CONTENT_KEY_METADATA: &str = "content-key";
+/// User metadata entry holding the checksum, returned with the object unlike S3 checksums
+const CHECKSUM_METADATA: &str = "checksum-sha256";
+/// User metadata entry holding the moderation verdict, as JSON
+const MODERATION_METADATA: &str = "moderation";
 
 /// MinIO storage implementation
 pub struct MinIOStorage {
@@ -43,13 +66,61 @@ impl MinIOStorage {
 
 #[async_trait]
 impl StorageBackend for MinIOStorage {
-    async fn upload_image(&self, key: &str, content_type: &str, data: Vec<u8>) -> Result<()> {
-        self.client
+    async fn upload_image(
+        &self,
+        key: &str,
+        content_type: &str,
+        data: Bytes,
+        metadata: &ObjectMetadata,
+    ) -> Result<()> {
+        let mut request = self
+            .client
             .put_object()
             .bucket(&self.bucket)
             .key(key)
             .body(ByteStream::from(data))
-            .content_type(content_type)
+            .content_type(content_type);
+
+        if !metadata.surrogate_keys.is_empty() {
+            request = request.metadata(SURROGATE_KEY_METADATA, metadata.surrogate_keys.join(" "));
+        }
+        if let Some(width) = metadata.width {
+            request = request.metadata(WIDTH_METADATA, width.to_string());
+        }
+        if let Some(height) = metadata.height {
+            request = request.metadata(HEIGHT_METADATA, height.to_string());
+        }
+        if let Some(quality) = metadata.quality {
+            request = request.metadata(QUALITY_METADATA, quality.to_string());
+        }
+        if let Some(version) = metadata.encoder_version {
+            request = request.metadata(ENCODER_VERSION_METADATA, version.to_string());
+        }
+        if let Some(content_key) = &metadata.content_key {
+            request = request.metadata(CONTENT_KEY_METADATA, content_key);
+        }
+        // S3 also verifies the upload against it
+        if let Some(checksum) = &metadata.checksum {
+            request = request
+                .metadata(CHECKSUM_METADATA, checksum)
+                .checksum_sha256(checksum);
+        }
+        // The bucket must have Object Lock enabled
+        if let Some(verdict) = &metadata.moderation {
+            request = request.metadata(MODERATION_METADATA, serde_json::to_string(verdict)?);
+        }
+        if let Some(lock) = &metadata.object_lock {
+            if let (Some(mode), Some(until)) = (&lock.mode, lock.retain_until) {
+                request = request
+                    .object_lock_mode(ObjectLockMode::from(mode.as_str()))
+                    .object_lock_retain_until_date(DateTime::from_secs(until as i64));
+            }
+            if lock.legal_hold {
+                request = request.object_lock_legal_hold_status(ObjectLockLegalHoldStatus::On);
+            }
+        }
+
+        request
             .send()
             .await
             .map_err(|e| anyhow::anyhow!("S3 error: {}", e))
@@ -74,7 +145,7 @@ impl StorageBackend for MinIOStorage {
         }
     }
 
-    async fn get_image(&self, key: &str) -> Result<Vec<u8>> {
+    async fn get_image(&self, key: &str) -> Result<Bytes> {
         let response = self
             .client
             .get_object()
@@ -85,12 +156,167 @@ impl StorageBackend for MinIOStorage {
             .map_err(|e| anyhow::anyhow!("S3 error: {}", e))
             .context(format!("Failed to get image from S3: {}", key))?;
 
+        let expected = response
+            .metadata()
+            .and_then(|metadata| metadata.get(CHECKSUM_METADATA))
+            .cloned();
         let data = response
             .body
             .collect()
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to read S3 response body: {}", e))?;
+            .map_err(|e| anyhow::anyhow!("Failed to read S3 response body: {}", e))?
+            .into_bytes();
+
+        verify_checksum(key, &data, expected.as_deref())?;
+        Ok(data)
+    }
+
+    async fn get_metadata(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => {
+                let user_metadata =
+                    |name: &str| output.metadata().and_then(|metadata| metadata.get(name));
+                let surrogate_keys = user_metadata(SURROGATE_KEY_METADATA)
+                    .map(|keys| keys.split_whitespace().map(str::to_string).collect())
+                    .unwrap_or_default();
+                let legal_hold =
+                    output.object_lock_legal_hold_status() == Some(&ObjectLockLegalHoldStatus::On);
+                let object_lock =
+                    (output.object_lock_mode().is_some() || legal_hold).then(|| ObjectLock {
+                        mode: output
+                            .object_lock_mode()
+                            .map(|mode| mode.as_str().to_string()),
+                        retain_until: output
+                            .object_lock_retain_until_date()
+                            .and_then(|until| u64::try_from(until.secs()).ok()),
+                        legal_hold,
+                    });
+
+                Ok(Some(ObjectMetadata {
+                    surrogate_keys,
+                    width: user_metadata(WIDTH_METADATA).and_then(|w| w.parse().ok()),
+                    height: user_metadata(HEIGHT_METADATA).and_then(|h| h.parse().ok()),
+                    quality: user_metadata(QUALITY_METADATA).and_then(|q| q.parse().ok()),
+                    encoder_version: user_metadata(ENCODER_VERSION_METADATA)
+                        .and_then(|v| v.parse().ok()),
+                    size: output
+                        .content_length()
+                        .and_then(|len| u64::try_from(len).ok()),
+                    content_type: output.content_type().map(str::to_string),
+                    storage_class: output
+                        .storage_class()
+                        .map(|class| class.as_str().to_string()),
+                    content_key: user_metadata(CONTENT_KEY_METADATA).cloned(),
+                    object_lock,
+                    checksum: user_metadata(CHECKSUM_METADATA).cloned(),
+                    moderation: user_metadata(MODERATION_METADATA)
+                        .and_then(|verdict| serde_json::from_str(verdict).ok()),
+                }))
+            }
+            Err(sdk_err) => match sdk_err.into_service_error() {
+                HeadObjectError::NotFound(_) => Ok(None),
+                err => Err(anyhow::anyhow!("S3 error: {}", err)),
+            },
+        }
+    }
 
-        Ok(data.into_bytes().to_vec())
+    async fn delete_image(&self, key: &str) -> Result<bool> {
+        // S3 deletes succeed whether or not the object exists
+        if !self.check_cache(key).await? {
+            return Ok(false);
+        }
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("S3 error: {}", e))
+            .context(format!("Failed to delete image from S3: {}", key))?;
+        Ok(true)
+    }
+
+    async fn presign_upload(&self, key: &str, expires_in: Duration) -> Result<Option<String>> {
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(PresigningConfig::expires_in(expires_in)?)
+            .await
+            .map_err(|e| anyhow::anyhow!("S3 error: {}", e))
+            .context(format!("Failed to presign upload to S3: {}", key))?;
+        Ok(Some(request.uri().to_string()))
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<StoredObject>> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page
+                .map_err(|e| anyhow::anyhow!("S3 error: {}", e))
+                .context(format!("Failed to list objects in S3: {}", prefix))?;
+            objects.extend(page.contents().iter().filter_map(|object| {
+                Some(StoredObject {
+                    key: object.key()?.to_string(),
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|modified| u64::try_from(modified.secs()).ok()),
+                    storage_class: object
+                        .storage_class()
+                        .map(|class| class.as_str().to_string()),
+                    size: object.size().and_then(|size| u64::try_from(size).ok()),
+                })
+            }));
+        }
+        Ok(objects)
+    }
+
+    async fn set_storage_class(&self, key: &str, storage_class: &str) -> Result<bool> {
+        // Copying an object onto itself with another storage class keeps its metadata,
+        // but not its Object Lock, which the copy must set again
+        let object_lock = self
+            .get_metadata(key)
+            .await?
+            .and_then(|metadata| metadata.object_lock);
+        let mut request = self
+            .client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .copy_source(format!("{}/{}", self.bucket, key))
+            .storage_class(StorageClass::from(storage_class))
+            .metadata_directive(MetadataDirective::Copy);
+        if let Some(lock) = object_lock {
+            if let (Some(mode), Some(until)) = (&lock.mode, lock.retain_until) {
+                request = request
+                    .object_lock_mode(ObjectLockMode::from(mode.as_str()))
+                    .object_lock_retain_until_date(DateTime::from_secs(until as i64));
+            }
+            if lock.legal_hold {
+                request = request.object_lock_legal_hold_status(ObjectLockLegalHoldStatus::On);
+            }
+        }
+        request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("S3 error: {}", e))
+            .context(format!("Failed to change the storage class in S3: {}", key))?;
+        Ok(true)
     }
 }
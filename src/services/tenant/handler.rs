@@ -0,0 +1,229 @@
+use crate::models::params::{ResizeQuery, supports_quality};
+use crate::modules::env::env::EnvConfig;
+use crate::services::image::encode::EncodingDefaults;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Transformations applied to every request of one tenant
+///
+/// Parameters are given as in the body of `POST /api/jobs`, e.g. `"quality": "80"`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantDefaults {
+    /// Parameters of requests that leave them unset
+    #[serde(default)]
+    pub defaults: Map<String, Value>,
+    /// Parameters replacing those of every request, e.g. a mandatory watermark
+    #[serde(default)]
+    pub force: Map<String, Value>,
+    /// Highest encoder quality, lowering requested and default qualities above it
+    #[serde(default)]
+    pub max_quality: Option<u8>,
+}
+
+impl TenantDefaults {
+    /// `params` with the defaults and forced parameters merged in
+    fn merge(&self, params: &ResizeQuery) -> Result<ResizeQuery> {
+        let Value::Object(mut query) = serde_json::to_value(params)? else {
+            bail!("Resize queries serialize to objects");
+        };
+        for (name, value) in &self.defaults {
+            if query.get(name).is_none_or(Value::is_null) {
+                query.insert(name.clone(), value.clone());
+            }
+        }
+        for (name, value) in &self.force {
+            query.insert(name.clone(), value.clone());
+        }
+        Ok(serde_json::from_value(Value::Object(query))?)
+    }
+
+    /// Check that the parameters name fields of a query, and form a valid query
+    fn validate(&self) -> Result<()> {
+        let Value::Object(fields) = serde_json::to_value(ResizeQuery::default())? else {
+            bail!("Resize queries serialize to objects");
+        };
+        for name in self.defaults.keys().chain(self.force.keys()) {
+            if name == "url" || !fields.contains_key(name) {
+                bail!("Unknown parameter {:?}", name);
+            }
+        }
+        if self
+            .max_quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+        {
+            bail!("max_quality must be from 1 to 100");
+        }
+
+        let example = ResizeQuery {
+            url: "https://example.com/image.jpg".to_string(),
+            ..ResizeQuery::default()
+        };
+        self.merge(&example)?.validate()
+    }
+}
+
+/// Per-tenant defaults merged into every request before processing, e.g. a preferred
+/// format or a mandatory watermark
+///
+/// They are merged before the cache key is computed, so changing them produces new variants
+/// rather than serving stale ones.
+#[derive(Debug, Clone, Default)]
+pub struct TenantPolicies {
+    /// Defaults of each tenant host
+    pub tenants: HashMap<String, TenantDefaults>,
+}
+
+impl TenantPolicies {
+    /// Read the JSON object of `TenantDefaults` entries by tenant host at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+        let tenants: HashMap<String, TenantDefaults> = serde_json::from_slice(&data)
+            .context(format!("Invalid tenant defaults config {}", path.display()))?;
+
+        for (tenant, defaults) in &tenants {
+            defaults
+                .validate()
+                .context(format!("Invalid defaults of {}", tenant))?;
+        }
+
+        Ok(Self {
+            tenants: tenants
+                .into_iter()
+                .map(|(tenant, defaults)| (tenant.to_lowercase(), defaults))
+                .collect(),
+        })
+    }
+
+    pub fn from_env(config: &EnvConfig) -> Result<Self> {
+        match config.tenant_defaults_config.as_deref() {
+            Some(path) if !path.is_empty() => Self::load(Path::new(path)),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// `params` with the defaults of `tenant` applied, borrowed when it has none
+    ///
+    /// The quality of requests without one, from `encoding_defaults`, is capped too, but
+    /// only for output formats honoring it, so lossless variants keep a single cache key.
+    pub fn apply<'a>(
+        &self,
+        params: &'a ResizeQuery,
+        tenant: Option<&str>,
+        encoding_defaults: &EncodingDefaults,
+    ) -> Result<Cow<'a, ResizeQuery>> {
+        // Tenants are hosts, compared without their port
+        let Some(defaults) = tenant
+            .and_then(|tenant| tenant.split(':').next())
+            .and_then(|host| self.tenants.get(&host.to_lowercase()))
+        else {
+            return Ok(Cow::Borrowed(params));
+        };
+
+        let mut merged = defaults.merge(params)?;
+        let output_format = encoding_defaults.resolve(&merged).output_format();
+        if let Some(max_quality) = defaults.max_quality
+            && supports_quality(&output_format)
+        {
+            let quality = match merged.fixed_quality() {
+                Ok(Some(quality)) => quality,
+                // quality=auto searches below its own bound, invalid ones are rejected later
+                Ok(None) if merged.quality.is_none() => encoding_defaults.jpeg_quality,
+                _ => 0,
+            };
+            if quality > max_quality {
+                merged.quality = Some(max_quality.to_string());
+            }
+        }
+        Ok(Cow::Owned(merged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gen_server::models::ImageFormat;
+    use serde_json::json;
+
+    #[test]
+    fn test_merges_the_defaults_of_the_tenant() {
+        let defaults: TenantDefaults = serde_json::from_value(json!({
+            "defaults": {"format": "webp", "auto_sharpen": true},
+            "force": {"watermark": "logo"},
+            "max_quality": 85
+        }))
+        .unwrap();
+        defaults.validate().unwrap();
+        let policies = TenantPolicies {
+            tenants: HashMap::from([("shop.example.com".to_string(), defaults)]),
+        };
+
+        let encoding = |jpeg_quality| EncodingDefaults {
+            jpeg_quality,
+            ..EncodingDefaults::default()
+        };
+        let params = ResizeQuery {
+            url: "https://example.com/a.jpg".to_string(),
+            format: Some(ImageFormat::Jpg),
+            watermark: Some("other".to_string()),
+            quality: Some("95".to_string()),
+            ..ResizeQuery::default()
+        };
+        let merged = policies
+            .apply(&params, Some("Shop.example.com:8080"), &encoding(75))
+            .unwrap();
+        // Requested parameters win over defaults, forced ones over requested ones
+        assert_eq!(merged.format, Some(ImageFormat::Jpg));
+        assert_eq!(merged.auto_sharpen, Some(true));
+        assert_eq!(merged.watermark.as_deref(), Some("logo"));
+        assert_eq!(merged.quality.as_deref(), Some("85"));
+
+        // The deployment default is capped too
+        let unset = ResizeQuery {
+            quality: None,
+            ..params.clone()
+        };
+        let merged = policies
+            .apply(&unset, Some("shop.example.com"), &encoding(90))
+            .unwrap();
+        assert_eq!(merged.quality.as_deref(), Some("85"));
+
+        // Lossless formats ignore the quality, which is left as requested
+        let lossless = ResizeQuery {
+            format: Some(ImageFormat::Png),
+            ..params.clone()
+        };
+        let merged = policies
+            .apply(&lossless, Some("shop.example.com"), &encoding(75))
+            .unwrap();
+        assert_eq!(merged.quality.as_deref(), Some("95"));
+        // As is the default format of the tenant
+        let tenant_format = ResizeQuery {
+            format: None,
+            ..params.clone()
+        };
+        let merged = policies
+            .apply(&tenant_format, Some("shop.example.com"), &encoding(75))
+            .unwrap();
+        assert_eq!(merged.format, Some(ImageFormat::Webp));
+        assert_eq!(merged.quality.as_deref(), Some("95"));
+
+        assert!(matches!(
+            policies
+                .apply(&params, Some("other.example.com"), &encoding(75))
+                .unwrap(),
+            Cow::Borrowed(_)
+        ));
+
+        let unknown: TenantDefaults =
+            serde_json::from_value(json!({"defaults": {"strip": true}})).unwrap();
+        assert!(unknown.validate().is_err());
+        let invalid: TenantDefaults =
+            serde_json::from_value(json!({"force": {"colors": 1}})).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Accesses to a stored object
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Access {
+    /// Number of accesses since tracking started
+    pub count: u64,
+    /// Time of the last access, in seconds since the Unix epoch
+    pub last: u64,
+}
+
+/// Record of the accesses to stored objects, shared by all replicas when it can be
+#[async_trait]
+pub trait AccessLog: Send + Sync {
+    /// Count an access to `key` at `at`, in seconds since the Unix epoch
+    async fn record(&self, key: &str, at: u64) -> Result<()>;
+
+    /// Accesses to each of `keys`, `None` for keys never accessed
+    async fn accesses(&self, keys: &[String]) -> Result<Vec<Option<Access>>>;
+}
@@ -0,0 +1,240 @@
+use crate::modules::env::env::EnvConfig;
+use crate::modules::utils::date::unix_now;
+use crate::services::cache::handler::CacheService;
+use crate::services::resize::handler::ResizeService;
+use crate::services::storage::core::ObjectMetadata;
+use crate::services::storage::handler::StorageService;
+use crate::services::tiering::core::AccessLog;
+use crate::services::tiering::memory_handler::MemoryAccessLog;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Keys whose accesses are looked up at once during a sweep
+const ACCESS_BATCH: usize = 500;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Storage classes and timing of the tiering
+#[derive(Debug, Clone)]
+pub struct TieringSettings {
+    /// Idle time after which a processed image moves to the cold storage class
+    pub cold_after: Duration,
+    pub cold_storage_class: String,
+    /// Storage class cold images move back to once requested
+    pub hot_storage_class: String,
+    /// Time between two sweeps
+    pub interval: Duration,
+}
+
+/// Hot/cold tiering of processed images by access
+///
+/// Every request served from storage is recorded. A periodic sweep moves the processed
+/// images neither requested nor written for `cold_after` to the cold storage class, and
+/// cold images move back to the hot class on their next request. Uploaded originals are
+/// left alone.
+#[derive(Clone)]
+pub struct StorageTiering {
+    access: Arc<dyn AccessLog>,
+    settings: TieringSettings,
+}
+
+impl StorageTiering {
+    pub fn new(access: impl AccessLog + 'static, settings: TieringSettings) -> Self {
+        Self {
+            access: Arc::new(access),
+            settings,
+        }
+    }
+
+    /// The tiering configured by the environment, if enabled
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        if config.tiering_cold_after_days == 0 {
+            return Ok(None);
+        }
+        let settings = TieringSettings {
+            cold_after: Duration::from_secs(config.tiering_cold_after_days * SECS_PER_DAY),
+            cold_storage_class: config.tiering_cold_storage_class.clone(),
+            hot_storage_class: config.tiering_hot_storage_class.clone(),
+            interval: Duration::from_secs(config.tiering_interval_secs.max(1)),
+        };
+
+        #[cfg(feature = "redis_tiering")]
+        if let Some(url) = &config.redis_url {
+            let access = crate::services::tiering::redis_handler::RedisAccessLog::new(url)?;
+            return Ok(Some(Self::new(access, settings)));
+        }
+
+        warn!(
+            "Storage tiering tracks accesses in memory, requests served by other replicas \
+             or before a restart are not accounted for"
+        );
+        Ok(Some(Self::new(MemoryAccessLog::new(), settings)))
+    }
+
+    pub fn settings(&self) -> &TieringSettings {
+        &self.settings
+    }
+
+    /// Record a request for `key` in the background, moving it back to the hot class if cold
+    pub fn touch(&self, storage: &StorageService, key: &str, metadata: &ObjectMetadata) {
+        let tiering = self.clone();
+        let storage = storage.clone();
+        let key = key.to_string();
+        let cold = metadata.storage_class.as_deref() == Some(&self.settings.cold_storage_class);
+        tokio::spawn(async move {
+            if let Err(e) = tiering.access.record(&key, unix_now()).await {
+                warn!("Failed to record an access to {}: {:?}", key, e);
+            }
+            if !cold {
+                return;
+            }
+            match storage
+                .set_storage_class(&key, &tiering.settings.hot_storage_class)
+                .await
+            {
+                Ok(_) => info!("Moved {} back to the hot storage class", key),
+                Err(e) => warn!("Failed to move {} to the hot storage class: {:?}", key, e),
+            }
+        });
+    }
+
+    /// Move the processed images idle for `cold_after` to the cold class, returning how many
+    pub async fn sweep(&self, storage: &StorageService, cache: &CacheService) -> Result<usize> {
+        self.sweep_at(storage, cache, unix_now()).await
+    }
+
+    async fn sweep_at(
+        &self,
+        storage: &StorageService,
+        cache: &CacheService,
+        now: u64,
+    ) -> Result<usize> {
+        let cold_class = &self.settings.cold_storage_class;
+        let cutoff = now.saturating_sub(self.settings.cold_after.as_secs());
+        let candidates: Vec<_> = storage
+            .list_objects(cache.derivatives_prefix())
+            .await?
+            .into_iter()
+            .filter(|object| cache.is_derivative_key(&object.key))
+            .filter(|object| object.storage_class.as_ref() != Some(cold_class))
+            // Empty objects, such as content-addressed pointers, cost next to nothing
+            .filter(|object| object.size != Some(0))
+            .collect();
+
+        let mut demoted = 0;
+        for batch in candidates.chunks(ACCESS_BATCH) {
+            let keys: Vec<String> = batch.iter().map(|object| object.key.clone()).collect();
+            let accesses = self.access.accesses(&keys).await?;
+
+            for (object, access) in batch.iter().zip(accesses) {
+                // Images never requested count from their creation
+                let last_used = access.map(|access| access.last).max(object.last_modified);
+                if last_used.is_none_or(|last_used| last_used >= cutoff) {
+                    continue;
+                }
+
+                match storage.set_storage_class(&object.key, cold_class).await {
+                    Ok(true) => {
+                        debug!(
+                            accesses = access.map(|access| access.count).unwrap_or_default(),
+                            "Moved {} to the cold storage class", object.key
+                        );
+                        demoted += 1;
+                    }
+                    Ok(false) => {}
+                    Err(e) => warn!(
+                        "Failed to move {} to the cold storage class: {:?}",
+                        object.key, e
+                    ),
+                }
+            }
+        }
+        Ok(demoted)
+    }
+
+    /// Sweep every interval, forever
+    pub async fn run(self, resize_service: ResizeService) {
+        let mut ticks = tokio::time::interval(self.settings.interval);
+        loop {
+            ticks.tick().await;
+            match resize_service.sweep_tiers().await {
+                Ok(Some(demoted)) => info!(demoted, "Storage tiering sweep done"),
+                Ok(None) => debug!("Storage tiering sweep run by another replica"),
+                Err(e) => warn!("Storage tiering sweep failed: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use super::*;
+    use crate::services::cache::handler::CacheServiceBuilder;
+    use crate::services::storage::handler::StorageConfig;
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn test_idle_images_move_to_the_cold_class_and_back() {
+        let config =
+            StorageConfig::new("http://cdn.test".to_string()).with_storage_type("in_memory");
+        let storage = StorageService::new(config).unwrap();
+        let cache = CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap();
+        for key in ["idle.png", "requested.png", "originals/idle"] {
+            storage
+                .upload_image(
+                    key,
+                    "image/png",
+                    Bytes::from_static(&[0]),
+                    &ObjectMetadata::default(),
+                )
+                .await
+                .unwrap();
+        }
+        storage
+            .upload_image(
+                "pointer.png",
+                "image/png",
+                Bytes::new(),
+                &ObjectMetadata::default(),
+            )
+            .await
+            .unwrap();
+        let tiering = StorageTiering::new(
+            MemoryAccessLog::new(),
+            TieringSettings {
+                cold_after: Duration::from_secs(SECS_PER_DAY),
+                cold_storage_class: "STANDARD_IA".to_string(),
+                hot_storage_class: "STANDARD".to_string(),
+                interval: Duration::from_secs(60),
+            },
+        );
+        let later = unix_now() + 2 * SECS_PER_DAY;
+        tiering.access.record("requested.png", later).await.unwrap();
+
+        let class = async |key: &str| {
+            storage
+                .get_metadata(key)
+                .await
+                .unwrap()
+                .unwrap()
+                .storage_class
+        };
+        assert_eq!(tiering.sweep_at(&storage, &cache, later).await.unwrap(), 1);
+        assert_eq!(class("idle.png").await.as_deref(), Some("STANDARD_IA"));
+        assert_eq!(class("requested.png").await, None);
+        assert_eq!(class("originals/idle").await, None);
+        assert_eq!(class("pointer.png").await, None);
+        // Cold images aren't moved again
+        assert_eq!(tiering.sweep_at(&storage, &cache, later).await.unwrap(), 0);
+
+        let metadata = storage.get_metadata("idle.png").await.unwrap().unwrap();
+        tiering.touch(&storage, "idle.png", &metadata);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(class("idle.png").await.as_deref(), Some("STANDARD"));
+    }
+}
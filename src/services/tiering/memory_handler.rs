@@ -0,0 +1,33 @@
+use crate::services::tiering::core::{Access, AccessLog};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Process-local access log, only aware of the accesses served by this replica
+#[derive(Default)]
+pub struct MemoryAccessLog {
+    accesses: Mutex<HashMap<String, Access>>,
+}
+
+impl MemoryAccessLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AccessLog for MemoryAccessLog {
+    async fn record(&self, key: &str, at: u64) -> Result<()> {
+        let mut accesses = self.accesses.lock().unwrap();
+        let access = accesses.entry(key.to_string()).or_default();
+        access.count += 1;
+        access.last = access.last.max(at);
+        Ok(())
+    }
+
+    async fn accesses(&self, keys: &[String]) -> Result<Vec<Option<Access>>> {
+        let accesses = self.accesses.lock().unwrap();
+        Ok(keys.iter().map(|key| accesses.get(key).copied()).collect())
+    }
+}
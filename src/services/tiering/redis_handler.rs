@@ -0,0 +1,87 @@
+use crate::services::tiering::core::{Access, AccessLog};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use tokio::sync::OnceCell;
+
+/// Hash of the number of accesses of every key
+const COUNTS_KEY: &str = "emgr:access:count";
+/// Sorted set of the keys by time of their last access
+const LAST_ACCESS_KEY: &str = "emgr:access:last";
+
+/// Access log shared by all replicas through Redis
+pub struct RedisAccessLog {
+    client: redis::Client,
+    /// Connected on first use, as the service is created outside of the runtime
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl RedisAccessLog {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).context("Invalid Redis URL")?;
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(connection.clone())
+    }
+}
+
+#[async_trait]
+impl AccessLog for RedisAccessLog {
+    async fn record(&self, key: &str, at: u64) -> Result<()> {
+        let () = redis::pipe()
+            .cmd("HINCRBY")
+            .arg(COUNTS_KEY)
+            .arg(key)
+            .arg(1)
+            .ignore()
+            // Replicas may record out of order, keep the latest time
+            .cmd("ZADD")
+            .arg(LAST_ACCESS_KEY)
+            .arg("GT")
+            .arg(at)
+            .arg(key)
+            .ignore()
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to record an access in Redis")?;
+        Ok(())
+    }
+
+    async fn accesses(&self, keys: &[String]) -> Result<Vec<Option<Access>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (counts, lasts): (Vec<Option<u64>>, Vec<Option<f64>>) = redis::pipe()
+            .cmd("HMGET")
+            .arg(COUNTS_KEY)
+            .arg(keys)
+            .cmd("ZMSCORE")
+            .arg(LAST_ACCESS_KEY)
+            .arg(keys)
+            .query_async(&mut self.connection().await?)
+            .await
+            .context("Failed to read accesses from Redis")?;
+
+        Ok(counts
+            .into_iter()
+            .zip(lasts)
+            .map(|(count, last)| {
+                Some(Access {
+                    count: count.unwrap_or_default(),
+                    last: last? as u64,
+                })
+            })
+            .collect())
+    }
+}
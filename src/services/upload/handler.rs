@@ -0,0 +1,333 @@
+use crate::modules::api::handler::ApiService;
+use crate::services::cache::handler::{CacheService, ORIGINALS_PREFIX, STORAGE_SCHEME};
+use crate::services::storage::core::ObjectMetadata;
+use crate::services::storage::handler::StorageService;
+use crate::services::upload::scan::{ScanFailed, UploadInfected, UploadScanner, Verdict};
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Route accepting uploads authorized by a one-time token
+pub const UPLOAD_ROUTE: &str = "/api/uploads";
+
+/// Where and how to upload an original, and how to reference it afterwards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadTicket {
+    /// Source URL to pass to the resize endpoint once uploaded
+    pub source: String,
+    pub method: String,
+    pub upload_url: String,
+    pub expires_in_secs: u64,
+}
+
+/// Upload authorized by a one-time token
+#[derive(Debug, Clone)]
+struct PendingUpload {
+    id: String,
+    /// Tenant the upload was issued for, whose scanner checks it
+    tenant: Option<String>,
+    expires_at: Instant,
+}
+
+/// Issues direct-upload URLs for originals, so they don't go through the resize API
+///
+/// Storage backends that support presigning (S3) get a presigned `PUT` URL. Others get
+/// a one-time token for [`UPLOAD_ROUTE`], valid on the replica that issued it. So do tenants
+/// whose uploads are scanned, as presigned uploads wouldn't go through the scanner.
+#[derive(Clone)]
+pub struct UploadService {
+    storage_service: StorageService,
+    cache_service: CacheService,
+    /// Public URL of this service, to build absolute upload URLs
+    public_base_url: Option<String>,
+    ttl: Duration,
+    /// Largest original accepted on the upload route, in bytes
+    max_size: usize,
+    pending: Arc<Mutex<HashMap<String, PendingUpload>>>,
+    scanner: Option<UploadScanner>,
+}
+
+impl UploadService {
+    pub fn new(
+        storage_service: StorageService,
+        cache_service: CacheService,
+        public_base_url: Option<String>,
+        ttl: Duration,
+        max_size: usize,
+    ) -> Self {
+        Self {
+            storage_service,
+            cache_service,
+            public_base_url,
+            ttl,
+            max_size,
+            pending: Arc::default(),
+            scanner: None,
+        }
+    }
+
+    /// Scan uploads with `scanner` before storing them
+    pub fn with_scanner(mut self, scanner: Option<UploadScanner>) -> Self {
+        self.scanner = scanner;
+        self
+    }
+
+    pub fn scanner(&self) -> Option<&UploadScanner> {
+        self.scanner.as_ref()
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn is_scanned(&self, tenant: Option<&str>) -> bool {
+        self.scanner
+            .as_ref()
+            .is_some_and(|scanner| scanner.scanner_for(tenant).is_some())
+    }
+
+    /// Reserve a key for a new original of `tenant` and return where to upload it
+    pub async fn issue(&self, tenant: Option<&str>) -> Result<UploadTicket> {
+        let id = Uuid::new_v4().simple().to_string();
+        let key = self.cache_service.original_key(&id);
+
+        let presigned = if self.is_scanned(tenant) {
+            None
+        } else {
+            self.storage_service.presign_upload(&key, self.ttl).await?
+        };
+        let upload_url = match presigned {
+            Some(url) => url,
+            None => {
+                let token = Uuid::new_v4().simple().to_string();
+                let mut pending = self.pending.lock().unwrap();
+                let now = Instant::now();
+                pending.retain(|_, upload| upload.expires_at > now);
+                pending.insert(
+                    token.clone(),
+                    PendingUpload {
+                        id: id.clone(),
+                        tenant: tenant.map(str::to_string),
+                        expires_at: now + self.ttl,
+                    },
+                );
+
+                format!(
+                    "{}{}/{}",
+                    self.public_base_url
+                        .as_deref()
+                        .unwrap_or_default()
+                        .trim_end_matches('/'),
+                    UPLOAD_ROUTE,
+                    token
+                )
+            }
+        };
+        info!("Issued upload URL for {}", key);
+
+        Ok(UploadTicket {
+            source: format!("{}{}{}", STORAGE_SCHEME, ORIGINALS_PREFIX, id),
+            method: "PUT".to_string(),
+            upload_url,
+            expires_in_secs: self.ttl.as_secs(),
+        })
+    }
+
+    /// Store an original uploaded with a one-time token, which is consumed
+    ///
+    /// Returns the storage key, or `None` if the token is unknown or expired. Uploads the
+    /// scanner finds infected are stored in quarantine instead, failing with
+    /// [`UploadInfected`], and those it fails to check with [`ScanFailed`].
+    pub async fn accept(
+        &self,
+        token: &str,
+        content_type: &str,
+        data: Bytes,
+    ) -> Result<Option<String>> {
+        let upload = self.pending.lock().unwrap().remove(token);
+        let Some(upload) = upload.filter(|upload| upload.expires_at > Instant::now()) else {
+            return Ok(None);
+        };
+
+        if let Some(scanner) = &self.scanner {
+            let verdict = scanner
+                .scan(upload.tenant.as_deref(), content_type, &data)
+                .await?;
+            if let Verdict::Infected { threat } = verdict {
+                let key = self.cache_service.quarantine_key(&upload.id);
+                self.storage_service
+                    .upload_image(&key, content_type, data, &ObjectMetadata::default())
+                    .await?;
+                return Err(UploadInfected { threat, key }.into());
+            }
+        }
+
+        let key = self.cache_service.original_key(&upload.id);
+        self.storage_service
+            .upload_image(&key, content_type, data, &ObjectMetadata::default())
+            .await?;
+        info!("Stored uploaded original {}", key);
+        Ok(Some(key))
+    }
+}
+
+/// Receive an original uploaded with a one-time token
+pub async fn accept_upload(
+    State(api_service): State<Arc<ApiService>>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream");
+
+    match api_service
+        .upload_service
+        .accept(&token, content_type, body)
+        .await
+    {
+        Ok(Some(_)) => StatusCode::CREATED,
+        Ok(None) => StatusCode::FORBIDDEN,
+        Err(e) if e.is::<UploadInfected>() => {
+            warn!("Refused upload: {}", e);
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        Err(e) if e.is::<ScanFailed>() => {
+            error!("{}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        Err(e) => {
+            error!("Failed to store upload: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[cfg(all(test, feature = "in_memory"))]
+mod tests {
+    use super::*;
+    use crate::services::cache::handler::{CacheServiceBuilder, QUARANTINE_PREFIX};
+    use crate::services::storage::handler::StorageConfig;
+
+    #[tokio::test]
+    async fn test_upload_token_is_single_use() {
+        let config =
+            StorageConfig::new("http://cdn.test".to_string()).with_storage_type("in_memory");
+        let storage_service = StorageService::new(config).unwrap();
+        let cache_service = CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap();
+        let uploads = UploadService::new(
+            storage_service.clone(),
+            cache_service.clone(),
+            None,
+            Duration::from_secs(60),
+            1024,
+        );
+
+        let ticket = uploads.issue(None).await.unwrap();
+        let token = ticket.upload_url.rsplit('/').next().unwrap();
+        assert!(ticket.upload_url.starts_with(UPLOAD_ROUTE));
+
+        let key = uploads
+            .accept(token, "image/png", Bytes::from_static(&[1, 2, 3]))
+            .await
+            .unwrap();
+        assert_eq!(
+            key,
+            cache_service.storage_source_key(&ticket.source).unwrap()
+        );
+        assert!(storage_service.check_cache(&key.unwrap()).await.unwrap());
+        assert_eq!(
+            uploads
+                .accept(token, "image/png", Bytes::from_static(&[1]))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quarantines_infected_uploads() {
+        use crate::services::upload::scan::Scanner;
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let scanner_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"clean": false, "threat": "Eicar"})),
+            )
+            .expect(1)
+            .mount(&scanner_server)
+            .await;
+
+        let config =
+            StorageConfig::new("http://cdn.test".to_string()).with_storage_type("in_memory");
+        let storage_service = StorageService::new(config).unwrap();
+        let cache_service = CacheServiceBuilder::default()
+            .minio_sub_path(String::new())
+            .build()
+            .unwrap();
+        let scanner = UploadScanner::new(
+            None,
+            HashMap::from([(
+                "secure.example.com".to_string(),
+                Some(Scanner::parse(&scanner_server.uri()).unwrap()),
+            )]),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let uploads = UploadService::new(
+            storage_service.clone(),
+            cache_service.clone(),
+            None,
+            Duration::from_secs(60),
+            1024,
+        )
+        .with_scanner(Some(scanner));
+
+        // Other tenants aren't scanned
+        let ticket = uploads.issue(Some("other.example.com")).await.unwrap();
+        let token = ticket.upload_url.rsplit('/').next().unwrap();
+        assert!(
+            uploads
+                .accept(token, "image/png", Bytes::from_static(&[1]))
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let ticket = uploads.issue(Some("secure.example.com")).await.unwrap();
+        let token = ticket.upload_url.rsplit('/').next().unwrap();
+        let error = uploads
+            .accept(token, "image/png", Bytes::from_static(&[1, 2, 3]))
+            .await
+            .unwrap_err();
+        let infected = error.downcast_ref::<UploadInfected>().unwrap();
+        assert_eq!(infected.threat, "Eicar");
+        assert!(infected.key.starts_with(QUARANTINE_PREFIX));
+        assert!(storage_service.check_cache(&infected.key).await.unwrap());
+
+        let original = cache_service.storage_source_key(&ticket.source).unwrap();
+        assert!(
+            !storage_service
+                .check_cache(&original.unwrap())
+                .await
+                .unwrap()
+        );
+        assert_eq!(uploads.scanner().unwrap().stats().infected, 1);
+    }
+}
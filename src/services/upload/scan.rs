@@ -0,0 +1,316 @@
+use crate::modules::env::env::EnvConfig;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::warn;
+
+/// Scheme of clamd scanners, reached over TCP
+const CLAMD_SCHEME: &str = "clamd://";
+/// Size of the chunks streamed to clamd
+const CLAMD_CHUNK: usize = 64 * 1024;
+
+/// An upload was found infected and quarantined instead of stored
+#[derive(Debug, Error)]
+#[error("Upload infected with {threat}, quarantined as {key}")]
+pub struct UploadInfected {
+    pub threat: String,
+    pub key: String,
+}
+
+/// The scanner couldn't give a verdict, so the upload was refused
+#[derive(Debug, Error)]
+#[error("Failed to scan upload: {0:#}")]
+pub struct ScanFailed(pub anyhow::Error);
+
+/// Outcome of a scan
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Clean,
+    Infected { threat: String },
+}
+
+/// Verdict of an HTTP scanner
+#[derive(Debug, Deserialize)]
+struct CalloutVerdict {
+    clean: bool,
+    #[serde(default)]
+    threat: Option<String>,
+}
+
+/// Where uploads are sent to be scanned
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scanner {
+    /// ClamAV daemon, at `host:port`, scanning with `INSTREAM`
+    Clamd(String),
+    /// HTTP callout receiving the upload as a `POST` body and answering with
+    /// `{"clean": bool, "threat": "…"}`
+    Http(String),
+}
+
+impl Scanner {
+    pub fn parse(url: &str) -> Result<Self> {
+        if let Some(addr) = url.strip_prefix(CLAMD_SCHEME) {
+            if addr.is_empty() {
+                bail!("Missing clamd address in {}", url);
+            }
+            return Ok(Self::Clamd(addr.trim_end_matches('/').to_string()));
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Self::Http(url.to_string()));
+        }
+        bail!(
+            "Invalid upload scanner {:?}, expected {}host:port or an http(s) URL",
+            url,
+            CLAMD_SCHEME
+        )
+    }
+
+    async fn scan(
+        &self,
+        client: &reqwest::Client,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<Verdict> {
+        match self {
+            Self::Clamd(addr) => Self::scan_clamd(addr, data).await,
+            Self::Http(url) => {
+                let verdict: CalloutVerdict = client
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, content_type)
+                    .body(data.to_vec())
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .context("Invalid verdict of the upload scanner")?;
+                Ok(if verdict.clean {
+                    Verdict::Clean
+                } else {
+                    Verdict::Infected {
+                        threat: verdict.threat.unwrap_or_else(|| "unknown".to_string()),
+                    }
+                })
+            }
+        }
+    }
+
+    /// Stream `data` to clamd, then read its `stream: OK` or `stream: <threat> FOUND` reply
+    async fn scan_clamd(addr: &str, data: &[u8]) -> Result<Verdict> {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .context(format!("Failed to connect to clamd at {}", addr))?;
+        stream.write_all(b"zINSTREAM\0").await?;
+        for chunk in data.chunks(CLAMD_CHUNK) {
+            stream
+                .write_all(&(chunk.len() as u32).to_be_bytes())
+                .await?;
+            stream.write_all(chunk).await?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        parse_clamd_reply(&String::from_utf8_lossy(&reply))
+    }
+}
+
+fn parse_clamd_reply(reply: &str) -> Result<Verdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        return Ok(Verdict::Clean);
+    }
+    match result.strip_suffix(" FOUND") {
+        Some(threat) => Ok(Verdict::Infected {
+            threat: threat.to_string(),
+        }),
+        None => bail!("clamd failed: {}", reply),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    clean: AtomicU64,
+    infected: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Upload scans since startup
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadScanStats {
+    pub clean: u64,
+    /// Uploads quarantined
+    pub infected: u64,
+    /// Uploads refused as the scanner failed
+    pub failed: u64,
+}
+
+/// Scanning of uploaded originals for malware before they are stored
+///
+/// Tenants listed in the config use their own scanner, or none for `null`, the others the
+/// default one. Scans fail closed: an upload the scanner couldn't check is refused.
+#[derive(Clone)]
+pub struct UploadScanner {
+    default: Option<Scanner>,
+    /// Scanner of each tenant host, `None` to leave its uploads unscanned
+    tenants: HashMap<String, Option<Scanner>>,
+    client: reqwest::Client,
+    counters: Arc<Counters>,
+}
+
+impl UploadScanner {
+    pub fn new(
+        default: Option<Scanner>,
+        tenants: HashMap<String, Option<Scanner>>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            default,
+            tenants: tenants
+                .into_iter()
+                .map(|(tenant, scanner)| (tenant.to_lowercase(), scanner))
+                .collect(),
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            counters: Arc::default(),
+        })
+    }
+
+    /// The upload scanner configured by the environment, if any scanner is set
+    pub fn from_env(config: &EnvConfig) -> Result<Option<Self>> {
+        let default = match config.upload_scanner.as_deref() {
+            Some(url) if !url.is_empty() => Some(Scanner::parse(url)?),
+            _ => None,
+        };
+        let tenants = match config.upload_scan_config.as_deref() {
+            Some(path) if !path.is_empty() => Self::load_tenants(Path::new(path))?,
+            _ => HashMap::new(),
+        };
+        if default.is_none() && tenants.is_empty() {
+            return Ok(None);
+        }
+
+        let timeout = Duration::from_secs(config.upload_scan_timeout_secs);
+        Self::new(default, tenants, timeout).map(Some)
+    }
+
+    /// Read the JSON object of scanner URLs, or `null`, by tenant host at `path`
+    fn load_tenants(path: &Path) -> Result<HashMap<String, Option<Scanner>>> {
+        let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+        let tenants: HashMap<String, Option<String>> = serde_json::from_slice(&data)
+            .context(format!("Invalid upload scan config {}", path.display()))?;
+        tenants
+            .into_iter()
+            .map(|(tenant, url)| Ok((tenant, url.as_deref().map(Scanner::parse).transpose()?)))
+            .collect()
+    }
+
+    /// Scanner of the uploads of `tenant`, if they are scanned
+    pub fn scanner_for(&self, tenant: Option<&str>) -> Option<&Scanner> {
+        // Tenants are hosts, compared without their port
+        let host = tenant.map(|tenant| tenant.split(':').next().unwrap_or_default().to_lowercase());
+        match host.and_then(|host| self.tenants.get(&host)) {
+            Some(scanner) => scanner.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+
+    /// Scan an upload of `tenant`, `Clean` when its uploads aren't scanned
+    pub async fn scan(
+        &self,
+        tenant: Option<&str>,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<Verdict> {
+        let Some(scanner) = self.scanner_for(tenant) else {
+            return Ok(Verdict::Clean);
+        };
+
+        let result = scanner.scan(&self.client, content_type, data).await;
+        let outcome = match &result {
+            Ok(Verdict::Clean) => {
+                self.counters.clean.fetch_add(1, Ordering::Relaxed);
+                "clean"
+            }
+            Ok(Verdict::Infected { threat }) => {
+                warn!("Upload infected with {}", threat);
+                self.counters.infected.fetch_add(1, Ordering::Relaxed);
+                "infected"
+            }
+            Err(e) => {
+                warn!("Failed to scan upload: {:#}", e);
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                "failed"
+            }
+        };
+        #[cfg(feature = "otel")]
+        opentelemetry::global::meter("emgr")
+            .u64_counter("emgr.uploads.scanned")
+            .with_description("Uploads scanned for malware, by outcome")
+            .build()
+            .add(1, &[opentelemetry::KeyValue::new("outcome", outcome)]);
+        #[cfg(not(feature = "otel"))]
+        let _ = outcome;
+
+        result.map_err(|e| ScanFailed(e).into())
+    }
+
+    pub fn stats(&self) -> UploadScanStats {
+        let counters = &self.counters;
+        UploadScanStats {
+            clean: counters.clean.load(Ordering::Relaxed),
+            infected: counters.infected.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_clamd_replies() {
+        assert_eq!(parse_clamd_reply("stream: OK\0").unwrap(), Verdict::Clean);
+        assert_eq!(
+            parse_clamd_reply("stream: Eicar-Signature FOUND\0").unwrap(),
+            Verdict::Infected {
+                threat: "Eicar-Signature".to_string()
+            }
+        );
+        assert!(parse_clamd_reply("INSTREAM size limit exceeded. ERROR\0").is_err());
+    }
+
+    #[test]
+    fn test_picks_the_scanner_of_the_tenant() {
+        let clamd = Scanner::parse("clamd://clamav:3310").unwrap();
+        let scanner = UploadScanner::new(
+            Some(clamd.clone()),
+            HashMap::from([
+                (
+                    "Secure.example.com".to_string(),
+                    Some(Scanner::parse("https://scan.test/v1").unwrap()),
+                ),
+                ("internal.example.com".to_string(), None),
+            ]),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+
+        assert_eq!(
+            scanner.scanner_for(Some("secure.example.com:443")),
+            Some(&Scanner::Http("https://scan.test/v1".to_string()))
+        );
+        assert_eq!(scanner.scanner_for(Some("internal.example.com")), None);
+        assert_eq!(scanner.scanner_for(Some("other.example.com")), Some(&clamd));
+        assert_eq!(scanner.scanner_for(None), Some(&clamd));
+        assert!(Scanner::parse("ftp://scan.test").is_err());
+    }
+}
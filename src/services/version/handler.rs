@@ -0,0 +1,53 @@
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+/// Optional cargo features and whether this binary was compiled with them
+const FEATURES: &[(&str, bool)] = &[
+    ("local_fs", cfg!(feature = "local_fs")),
+    ("s3", cfg!(feature = "s3")),
+    ("in_memory", cfg!(feature = "in_memory")),
+    ("otel", cfg!(feature = "otel")),
+    ("builtin_plugins", cfg!(feature = "builtin_plugins")),
+    ("wasm_plugins", cfg!(feature = "wasm_plugins")),
+    ("scripting", cfg!(feature = "scripting")),
+    ("grpc", cfg!(feature = "grpc")),
+    ("swagger_ui", cfg!(feature = "swagger_ui")),
+    ("redis_lock", cfg!(feature = "redis_lock")),
+    ("redis_queue", cfg!(feature = "redis_queue")),
+    ("redis_tiering", cfg!(feature = "redis_tiering")),
+    ("nats_events", cfg!(feature = "nats_events")),
+    ("video", cfg!(feature = "video")),
+    ("dns_cache", cfg!(feature = "dns_cache")),
+    ("mozjpeg", cfg!(feature = "mozjpeg")),
+];
+
+/// Build information of the running binary, embedded at compile time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_sha: String,
+    /// Cargo features the binary was compiled with
+    pub features: Vec<String>,
+    pub build_timestamp: String,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let features = FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: env!("GIT_SHA").to_string(),
+            features,
+            build_timestamp: env!("VERGEN_BUILD_TIMESTAMP").to_string(),
+        }
+    }
+}
+
+pub async fn version() -> Json<BuildInfo> {
+    Json(BuildInfo::current())
+}
@@ -0,0 +1,106 @@
+use crate::config::allocator;
+use crate::modules::env::env::EnvConfig;
+use crate::services::resize::handler::ResizeService;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+/// Resident memory of the process in bytes, from `/proc/self/status`
+///
+/// `None` on systems without procfs.
+pub fn current_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Periodic check of the resident memory of the process
+///
+/// Above the threshold, the memory accounted to images and caches is logged to tell
+/// live data from memory the allocator holds on to, and the in-memory caches can be
+/// dropped and the allocator collected to give memory back to the OS.
+#[derive(Clone)]
+pub struct RssWatchdog {
+    threshold: u64,
+    interval: Duration,
+    drop_caches: bool,
+    resize_service: ResizeService,
+}
+
+impl RssWatchdog {
+    /// The watchdog configured by the environment, if enabled
+    pub fn from_env(config: &EnvConfig, resize_service: ResizeService) -> Option<Self> {
+        if config.rss_watchdog_threshold_mb == 0 {
+            return None;
+        }
+        if current_rss().is_none() {
+            warn!("RSS watchdog disabled: resident memory is not available on this system");
+            return None;
+        }
+
+        Some(Self {
+            threshold: config.rss_watchdog_threshold_mb * BYTES_PER_MB,
+            interval: Duration::from_secs(config.rss_watchdog_interval_secs.max(1)),
+            drop_caches: config.rss_watchdog_drop_caches,
+            resize_service,
+        })
+    }
+
+    /// Check the resident memory every interval, forever
+    pub async fn run(self) {
+        let mut ticks = tokio::time::interval(self.interval);
+        loop {
+            ticks.tick().await;
+            self.check();
+        }
+    }
+
+    fn check(&self) {
+        let Some(rss) = current_rss().filter(|rss| *rss > self.threshold) else {
+            return;
+        };
+
+        let image_stats = self.resize_service.image_stats();
+        warn!(
+            rss_mb = rss / BYTES_PER_MB,
+            threshold_mb = self.threshold / BYTES_PER_MB,
+            images_reserved_mb = image_stats.memory.reserved_bytes / BYTES_PER_MB,
+            processing_in_flight = image_stats.processing_in_flight,
+            downloads_in_flight = image_stats.downloads_in_flight,
+            "Resident memory above the watchdog threshold"
+        );
+        if !self.drop_caches {
+            return;
+        }
+
+        let released = self
+            .resize_service
+            .peer_cache()
+            .map_or(0, |peer_cache| peer_cache.clear_local());
+        allocator::collect();
+        info!(
+            peer_cache_mb = released as u64 / BYTES_PER_MB,
+            rss_mb = current_rss().unwrap_or_default() / BYTES_PER_MB,
+            "Dropped in-memory caches and collected the allocator"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn reads_resident_memory() {
+        assert!(current_rss().unwrap() > 0);
+    }
+}
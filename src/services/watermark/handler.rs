@@ -0,0 +1,347 @@
+use crate::models::params::{ResizeQuery, is_watermark_name};
+use crate::modules::api::handler::ApiService;
+use crate::modules::env::env::EnvConfig;
+use crate::services::admin::handler::AdminActor;
+use crate::services::audit::handler::AuditEvent;
+use crate::services::storage::core::ObjectMetadata;
+use crate::services::storage::handler::StorageService;
+use anyhow::{Context, Result, bail};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use image::DynamicImage;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{error, info};
+
+/// Storage prefix of the watermark library
+pub const WATERMARK_PREFIX: &str = "watermarks/";
+
+/// Library of requests without a tenant, e.g. batch resizes
+const DEFAULT_TENANT: &str = "default";
+
+/// Uploaded watermark that isn't an image, or that is named invalidly
+#[derive(Debug, Error)]
+#[error("Invalid watermark {name}: {reason}")]
+pub struct InvalidWatermark {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Decoded watermark, with the time it was read from storage
+type Loaded = (Instant, Arc<DynamicImage>);
+
+/// Watermark assets named per tenant, which the `watermark` parameter refers to
+///
+/// Assets are stored under `watermarks/<tenant>/<name>`. Decoded watermarks are kept in
+/// memory for `ttl`, so changes made through another replica show up within it.
+#[derive(Clone)]
+pub struct WatermarkLibrary {
+    storage: StorageService,
+    sub_path: String,
+    /// Decoded watermarks by qualified name, with the time they were loaded
+    cache: Arc<Mutex<HashMap<String, Loaded>>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl WatermarkLibrary {
+    pub fn new(
+        storage: StorageService,
+        sub_path: impl Into<String>,
+        capacity: usize,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            storage,
+            sub_path: sub_path.into(),
+            cache: Arc::default(),
+            capacity,
+            ttl,
+        }
+    }
+
+    pub fn from_env(config: &EnvConfig, storage: &StorageService) -> Self {
+        Self::new(
+            storage.clone(),
+            config.sub_path.clone(),
+            config.watermark_cache_size,
+            Duration::from_secs(config.watermark_cache_ttl_secs),
+        )
+    }
+
+    /// Library of `tenant`: its host, without the port
+    pub fn tenant_id(tenant: Option<&str>) -> String {
+        tenant
+            .and_then(|tenant| tenant.split(':').next())
+            .filter(|host| !host.is_empty())
+            .unwrap_or(DEFAULT_TENANT)
+            .to_lowercase()
+    }
+
+    /// `params` with its watermark name qualified with the library of `tenant`
+    ///
+    /// Tenants can use the same names for different assets, so the qualified name is the
+    /// one hashed into the cache key.
+    pub fn qualify<'a>(params: Cow<'a, ResizeQuery>, tenant: Option<&str>) -> Cow<'a, ResizeQuery> {
+        let Some(name) = &params.watermark else {
+            return params;
+        };
+        let watermark = Some(format!("{}/{}", Self::tenant_id(tenant), name));
+        Cow::Owned(ResizeQuery {
+            watermark,
+            ..params.into_owned()
+        })
+    }
+
+    /// Storage prefix of the library of `tenant`, `None` for hosts that can't name one
+    fn prefix(&self, tenant: &str) -> Option<String> {
+        let valid = !tenant.contains("..")
+            && tenant
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+        valid.then(|| format!("{}{}{}/", self.sub_path, WATERMARK_PREFIX, tenant))
+    }
+
+    /// Storage key of watermark `name` of `tenant`, `None` when either is invalid
+    fn key(&self, tenant: &str, name: &str) -> Option<String> {
+        let prefix = self.prefix(tenant)?;
+        is_watermark_name(name).then(|| format!("{}{}", prefix, name))
+    }
+
+    /// Decoded watermark of a qualified name, `None` when it isn't in the library
+    pub async fn load(&self, qualified: &str) -> Result<Option<Arc<DynamicImage>>> {
+        if let Some((loaded, image)) = self.cache.lock().unwrap().get(qualified)
+            && loaded.elapsed() < self.ttl
+        {
+            return Ok(Some(Arc::clone(image)));
+        }
+
+        let Some((tenant, name)) = qualified.split_once('/') else {
+            bail!("Watermark {} isn't qualified with its tenant", qualified);
+        };
+        let Some(key) = self.key(tenant, name) else {
+            return Ok(None);
+        };
+        if !self.storage.check_cache(&key).await? {
+            return Ok(None);
+        }
+        let data = self.storage.get_image(&key).await?;
+        let image = Arc::new(
+            image::load_from_memory(&data)
+                .with_context(|| format!("Failed to decode watermark {}", qualified))?,
+        );
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.capacity {
+            // Make room by dropping the watermark loaded the longest ago
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, (loaded, _))| *loaded)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        if self.capacity > 0 {
+            cache.insert(qualified.to_string(), (Instant::now(), Arc::clone(&image)));
+        }
+        Ok(Some(image))
+    }
+
+    /// Store `data` as watermark `name` of `tenant`, replacing any previous one
+    ///
+    /// Images already stored with the previous watermark keep it until purged.
+    pub async fn put(&self, tenant: &str, name: &str, data: Bytes) -> Result<()> {
+        let invalid = |reason: String| InvalidWatermark {
+            name: name.to_string(),
+            reason,
+        };
+        let tenant = Self::tenant_id(Some(tenant));
+        let Some(key) = self.key(&tenant, name) else {
+            return Err(invalid("names are letters, digits, - and _".to_string()).into());
+        };
+        let format = image::guess_format(&data).map_err(|e| invalid(e.to_string()))?;
+        image::load_from_memory_with_format(&data, format).map_err(|e| invalid(e.to_string()))?;
+
+        self.storage
+            .upload_image(
+                &key,
+                format.to_mime_type(),
+                data,
+                &ObjectMetadata::default(),
+            )
+            .await?;
+        self.forget(&tenant, name);
+        info!("Stored watermark {}/{}", tenant, name);
+        Ok(())
+    }
+
+    /// Data and content type of watermark `name` of `tenant`
+    pub async fn get(&self, tenant: &str, name: &str) -> Result<Option<(Bytes, String)>> {
+        let Some(key) = self.key(&Self::tenant_id(Some(tenant)), name) else {
+            return Ok(None);
+        };
+        let Some(metadata) = self.storage.get_metadata(&key).await? else {
+            return Ok(None);
+        };
+        let data = self.storage.get_image(&key).await?;
+        let content_type = metadata
+            .content_type
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        Ok(Some((data, content_type)))
+    }
+
+    /// Remove watermark `name` of `tenant`, returning whether it existed
+    pub async fn delete(&self, tenant: &str, name: &str) -> Result<bool> {
+        let tenant = Self::tenant_id(Some(tenant));
+        let Some(key) = self.key(&tenant, name) else {
+            return Ok(false);
+        };
+        let deleted = self.storage.delete_image(&key).await?;
+        self.forget(&tenant, name);
+        Ok(deleted)
+    }
+
+    /// Names of the watermarks of `tenant`, if the storage backend can list objects
+    pub async fn list(&self, tenant: &str) -> Result<Vec<String>> {
+        let Some(prefix) = self.prefix(&Self::tenant_id(Some(tenant))) else {
+            return Ok(Vec::new());
+        };
+        let mut names: Vec<String> = self
+            .storage
+            .list_objects(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|object| object.key.strip_prefix(&prefix).map(str::to_string))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn forget(&self, tenant: &str, name: &str) {
+        self.cache
+            .lock()
+            .unwrap()
+            .remove(&format!("{}/{}", tenant, name));
+    }
+}
+
+/// Names of the watermarks of a tenant
+pub async fn list_watermarks(
+    State(api_service): State<Arc<ApiService>>,
+    Path(tenant): Path<String>,
+) -> Response {
+    let Some(watermarks) = api_service.resize_service.watermarks() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match watermarks.list(&tenant).await {
+        Ok(names) => Json(names).into_response(),
+        Err(e) => {
+            error!("Failed to list the watermarks of {}: {}", tenant, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Download a watermark as uploaded
+pub async fn get_watermark(
+    State(api_service): State<Arc<ApiService>>,
+    Path((tenant, name)): Path<(String, String)>,
+) -> Response {
+    let Some(watermarks) = api_service.resize_service.watermarks() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match watermarks.get(&tenant, &name).await {
+        Ok(Some((data, content_type))) => ([(CONTENT_TYPE, content_type)], data).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to read watermark {}/{}: {}", tenant, name, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Upload or replace a watermark, sent as the request body
+pub async fn put_watermark(
+    State(api_service): State<Arc<ApiService>>,
+    Extension(actor): Extension<AdminActor>,
+    Path((tenant, name)): Path<(String, String)>,
+    data: Bytes,
+) -> Response {
+    let Some(watermarks) = api_service.resize_service.watermarks() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let result = watermarks.put(&tenant, &name, data).await;
+
+    let event = AuditEvent::new(actor.0, "watermark")
+        .target(format!("{}/{}", tenant, name))
+        .success(result.is_ok());
+    api_service.audit_log.record(event).await;
+
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) if e.is::<InvalidWatermark>() => {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+        Err(e) => {
+            error!("Failed to store watermark {}/{}: {}", tenant, name, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Remove a watermark from the library
+pub async fn delete_watermark(
+    State(api_service): State<Arc<ApiService>>,
+    Extension(actor): Extension<AdminActor>,
+    Path((tenant, name)): Path<(String, String)>,
+) -> Response {
+    let Some(watermarks) = api_service.resize_service.watermarks() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let result = watermarks.delete(&tenant, &name).await;
+
+    let event = AuditEvent::new(actor.0, "delete_watermark")
+        .target(format!("{}/{}", tenant, name))
+        .success(matches!(result, Ok(true)));
+    api_service.audit_log.record(event).await;
+
+    match result {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to delete watermark {}/{}: {}", tenant, name, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qualifies_watermarks_with_the_tenant() {
+        let params = ResizeQuery {
+            watermark: Some("logo".to_string()),
+            ..ResizeQuery::default()
+        };
+        let qualified = WatermarkLibrary::qualify(Cow::Borrowed(&params), Some("Img.Example:8080"));
+        assert_eq!(qualified.watermark.as_deref(), Some("img.example/logo"));
+        let qualified = WatermarkLibrary::qualify(Cow::Borrowed(&params), None);
+        assert_eq!(qualified.watermark.as_deref(), Some("default/logo"));
+
+        let plain = ResizeQuery::default();
+        assert!(matches!(
+            WatermarkLibrary::qualify(Cow::Borrowed(&plain), Some("img.example")),
+            Cow::Borrowed(_)
+        ));
+    }
+}
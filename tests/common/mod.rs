@@ -0,0 +1,93 @@
+//! Harness of the end-to-end tests: the full router served on a local port
+
+use emgr::modules::api::handler::ApiService;
+use emgr::modules::env::env::EnvConfig;
+use emgr::modules::router::router::router;
+use envconfig::Envconfig;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use reqwest::redirect::Policy;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// The server under test and a client that doesn't follow its redirects
+pub struct App {
+    pub base: String,
+    pub client: reqwest::Client,
+}
+
+impl App {
+    /// Serve the router configured by `vars` on top of the test defaults
+    ///
+    /// `CDN_BASE_URL` points to the app's own download route, so resize redirects
+    /// can be followed without a CDN.
+    pub async fn spawn(vars: &[(&str, &str)]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let mut env = HashMap::from([
+            ("STORAGE_TYPE".to_string(), "in_memory".to_string()),
+            (
+                "CDN_BASE_URL".to_string(),
+                format!("{}/api/images/files", base),
+            ),
+            ("HTTP_TIMEOUT_SECS".to_string(), "5".to_string()),
+        ]);
+        env.extend(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        let config = EnvConfig::init_from_hashmap(&env).unwrap();
+        let api_service = Arc::new(ApiService::create(config).unwrap());
+
+        #[cfg(feature = "otel")]
+        let app = router(
+            axum_otel_metrics::HttpMetricsLayerBuilder::default()
+                .with_provider(opentelemetry_sdk::metrics::SdkMeterProvider::default())
+                .build(),
+            api_service,
+        )
+        .await
+        .unwrap();
+        #[cfg(not(feature = "otel"))]
+        let app = router(api_service).await.unwrap();
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Self {
+            base,
+            client: reqwest::Client::builder()
+                .redirect(Policy::none())
+                .build()
+                .unwrap(),
+        }
+    }
+
+    /// Request a resize of `url`, returning the response without following it
+    pub async fn resize(&self, url: &str, query: &[(&str, &str)]) -> reqwest::Response {
+        let mut params = vec![("url", url)];
+        params.extend_from_slice(query);
+        self.client
+            .get(format!("{}/api/images/resize", self.base))
+            .query(&params)
+            .send()
+            .await
+            .unwrap()
+    }
+}
+
+/// Location a resize redirected to
+pub fn location(response: &reqwest::Response) -> String {
+    assert_eq!(response.status(), reqwest::StatusCode::MOVED_PERMANENTLY);
+    response.headers()["location"].to_str().unwrap().to_string()
+}
+
+/// A `width`x`height` PNG with a gradient
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let img = RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+    });
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(img)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    png
+}
@@ -0,0 +1,44 @@
+//! Replays the decode fuzzing corpus, which holds inputs that crashed decoders before
+
+use bytes::Bytes;
+use emgr::{ImageFormat, ImageService, ResizeQuery};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[tokio::test]
+async fn malformed_images_fail_without_panicking() {
+    // Panics on the CPU pool are turned into errors, so count them as they are raised
+    let panics = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&panics);
+    std::panic::set_hook(Box::new(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }));
+
+    let service = ImageService::new().unwrap();
+    let query = ResizeQuery {
+        url: "https://corpus.test/source".to_string(),
+        width: Some(32),
+        format: Some(ImageFormat::Png),
+        ..Default::default()
+    };
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/decode");
+    let mut inputs: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    for input in inputs {
+        let name = input.file_name().unwrap().to_string_lossy().into_owned();
+        let data = Bytes::from(std::fs::read(&input).unwrap());
+        let result = service.process_image(data, &query).await;
+
+        assert_eq!(panics.load(Ordering::Relaxed), 0, "{} panicked", name);
+        if name.starts_with("seed") {
+            assert!(result.is_ok(), "{}: {:?}", name, result.err());
+        }
+    }
+}
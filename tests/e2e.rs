@@ -0,0 +1,1494 @@
+//! Resize, store and download flows through the full router, against mock origins
+#![cfg(feature = "in_memory")]
+
+mod common;
+
+use base64::Engine;
+use common::{App, location, png};
+use emgr::services::signing::handler::UrlSigner;
+use std::time::{Duration, Instant};
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn origin(response: ResponseTemplate, expected_requests: u64) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/source.png"))
+        .respond_with(response)
+        .expect(expected_requests)
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn resizes_stores_and_serves_images() {
+    let source = ResponseTemplate::new(200)
+        .set_body_bytes(png(400, 300))
+        .insert_header("content-type", "image/png");
+    // The second resize is served from storage
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = app
+        .resize(&url, &[("width", "100"), ("format", "png")])
+        .await;
+    let cdn_url = location(&resized);
+    assert!(cdn_url.starts_with(&format!("{}/api/images/files/", app.base)));
+    assert_eq!(resized.headers()["x-image-width"], "100");
+
+    // Spelled differently, but the same source and variant
+    let respelled = format!("{}/./%73ource.png#top", origin.uri());
+    let cached = app
+        .resize(&respelled, &[("width", "100"), ("format", "png")])
+        .await;
+    assert_eq!(location(&cached), cdn_url);
+
+    // Brotli isn't decoded by the client, so the encoding stays visible
+    let downloaded = app
+        .client
+        .get(&cdn_url)
+        .header("accept-encoding", "br")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(downloaded.status(), reqwest::StatusCode::OK);
+    assert_eq!(downloaded.headers()["content-type"], "image/png");
+    assert!(!downloaded.headers().contains_key("content-encoding"));
+    let img = image::load_from_memory(&downloaded.bytes().await.unwrap()).unwrap();
+    assert_eq!((img.width(), img.height()), (100, 75));
+
+    let missing = app
+        .client
+        .get(format!("{}/api/images/files/missing.png", app.base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(missing.headers()["x-error-code"], "not_found");
+    let rejection: serde_json::Value = missing.json().await.unwrap();
+    assert_eq!(rejection["reason"], "image_not_found");
+}
+
+#[tokio::test]
+async fn deduplicates_identical_outputs_with_content_addressed_keys() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 2).await;
+    let app = App::spawn(&[("CONTENT_ADDRESSED_KEYS", "true")]).await;
+
+    // Different sources, and cache keys, with the same bytes
+    let first = app
+        .resize(&format!("{}/source.png", origin.uri()), &[("width", "100")])
+        .await;
+    let second = app
+        .resize(
+            &format!("{}/source.png?v=2", origin.uri()),
+            &[("width", "100")],
+        )
+        .await;
+    let cdn_url = location(&first);
+    assert!(cdn_url.contains("/content-"));
+    assert_eq!(location(&second), cdn_url);
+
+    // Cache hits follow the pointer stored under the cache key
+    let cached = app
+        .resize(&format!("{}/source.png", origin.uri()), &[("width", "100")])
+        .await;
+    assert_eq!(location(&cached), cdn_url);
+
+    let downloaded = app.client.get(&cdn_url).send().await.unwrap();
+    assert_eq!(downloaded.status(), reqwest::StatusCode::OK);
+    let img = image::load_from_memory(&downloaded.bytes().await.unwrap()).unwrap();
+    assert_eq!((img.width(), img.height()), (100, 75));
+}
+
+#[tokio::test]
+async fn generates_further_variants_from_the_cached_source() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    // Only the first variant downloads the source
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("SOURCE_CACHE_TTL_SECS", "3600")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    for width in ["100", "200", "300"] {
+        let resized = app.resize(&url, &[("width", width)]).await;
+        location(&resized);
+        assert_eq!(resized.headers()["x-image-width"], width);
+    }
+}
+
+#[tokio::test]
+async fn converts_images_without_resizing_them() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    // The resize of the same source and format doesn't share the conversion
+    let origin = origin(source, 2).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let converted = app
+        .client
+        .get(format!("{}/api/images/convert", app.base))
+        .query(&[("url", url.as_str()), ("format", "webp")])
+        .send()
+        .await
+        .unwrap();
+    let cdn_url = location(&converted);
+    assert!(cdn_url.starts_with(&format!("{}/api/images/files/convert-", app.base)));
+    assert!(cdn_url.ends_with(".webp"));
+    assert_eq!(converted.headers()["x-image-width"], "400");
+    assert_eq!(converted.headers()["x-image-height"], "300");
+
+    let resized = app.resize(&url, &[("format", "webp")]).await;
+    assert_ne!(location(&resized), cdn_url);
+
+    let downloaded = app.client.get(&cdn_url).send().await.unwrap();
+    assert_eq!(downloaded.headers()["content-type"], "image/webp");
+}
+
+#[tokio::test]
+async fn stores_canary_outputs_under_labelled_keys() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    // The second resize is served from the canary output
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[
+        ("CANARY_PIPELINE", "resize_filter=nearest"),
+        ("CANARY_PERCENT", "100"),
+        ("CANARY_NAME", "fast"),
+    ])
+    .await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let cdn_url = location(&app.resize(&url, &[("width", "100")]).await);
+    assert!(cdn_url.starts_with(&format!("{}/api/images/files/fast-", app.base)));
+    assert_eq!(
+        location(&app.resize(&url, &[("width", "100")]).await),
+        cdn_url
+    );
+}
+
+#[tokio::test]
+async fn plans_resizes_without_fetching_the_source() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    // Only the actual resize downloads the source
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let query = [("width", "100"), ("height", "50"), ("pad", "10")];
+    let plan = |dry_run: &[(&'static str, &'static str)]| {
+        let query = [&query[..], dry_run, &[("dry_run", "true")]].concat();
+        let app = &app;
+        let url = &url;
+        async move {
+            let planned = app.resize(url, &query).await;
+            assert_eq!(planned.status(), reqwest::StatusCode::OK);
+            planned.json::<serde_json::Value>().await.unwrap()
+        }
+    };
+
+    let planned = plan(&[]).await;
+    assert_eq!(planned["cache_hit"], false);
+    // Margins are included in the dimensions
+    assert_eq!(planned["width"], 120);
+    assert_eq!(planned["height"], 70);
+    assert_eq!(planned["params"]["format"], "jpg");
+    assert!(planned.get("error").is_none());
+
+    let resized = app.resize(&url, &query).await;
+    assert_eq!(location(&resized), planned["url"]);
+    assert_eq!(plan(&[]).await["cache_hit"], true);
+
+    let invalid = plan(&[("pixelate_region", "0,0,10,10")]).await;
+    assert!(invalid["error"].as_str().unwrap().contains("pixelate"));
+    assert_eq!(invalid["error_code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn answers_with_a_json_envelope() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let query = [("width", "100"), ("format", "png"), ("response", "json")];
+
+    let answered = app.resize(&url, &query).await;
+    assert_eq!(answered.status(), reqwest::StatusCode::OK);
+    let envelope = answered.json::<serde_json::Value>().await.unwrap();
+    assert!(
+        envelope["url"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("{}/api/images/files/", app.base))
+    );
+    assert_eq!(
+        (envelope["width"].as_u64(), envelope["height"].as_u64()),
+        (Some(100), Some(75))
+    );
+    assert_eq!(envelope["content_type"], "image/png");
+    assert!(envelope.get("data_base64").is_none());
+
+    let inlined = app
+        .resize(&url, &[&query[..], &[("inline", "true")]].concat())
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(inlined["data_base64"].as_str().unwrap())
+        .unwrap();
+    assert_eq!(Some(data.len() as u64), inlined["bytes"].as_u64());
+    let img = image::load_from_memory(&data).unwrap();
+    assert_eq!((img.width(), img.height()), (100, 75));
+}
+
+#[tokio::test]
+async fn sends_debug_headers_to_admin_callers() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let resize = |query: &'static [(&'static str, &'static str)], token: &'static str| {
+        let app = &app;
+        let url = format!("{}/source.png", origin.uri());
+        async move {
+            app.client
+                .get(format!("{}/api/images/resize", app.base))
+                .query(&[("url", url.as_str())])
+                .query(query)
+                .header("x-debug", "1")
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+
+    let debugged = resize(&[("width", "100"), ("format", "jpg")], "secret").await;
+    location(&debugged);
+    let headers = debugged.headers();
+    let timing = headers["server-timing"].to_str().unwrap();
+    for stage in [
+        "lookup",
+        "download",
+        "decode",
+        "transform",
+        "encode",
+        "upload",
+    ] {
+        assert!(timing.contains(&format!("{};dur=", stage)), "{}", timing);
+    }
+    assert!(headers.contains_key("x-debug-cache-key"));
+    assert_eq!(headers["x-debug-filter"], "lanczos3");
+    assert_eq!(
+        headers["x-debug-encoder"],
+        "jpeg library=image quality=75 subsampling=444"
+    );
+
+    // Served from storage, to a caller without the admin token
+    let anonymous = resize(&[("width", "100"), ("format", "jpg")], "guess").await;
+    location(&anonymous);
+    assert!(!anonymous.headers().contains_key("server-timing"));
+    assert!(!anonymous.headers().contains_key("x-debug-cache-key"));
+}
+
+#[tokio::test]
+async fn rejects_resizes_over_the_complexity_budget() {
+    // 0.12 megapixels
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 2).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let app = App::spawn(&[("COMPLEXITY_BUDGET", "0.1")]).await;
+    let rejected = app.resize(&url, &[("width", "20")]).await;
+    assert_eq!(rejected.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(rejected.headers()["x-error-code"], "too_complex");
+    let rejection = rejected.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(rejection["reason"], "complexity_budget_exceeded");
+    assert_eq!(rejection["budget"], 0.1);
+    assert_eq!(rejection["code"], "too_complex");
+    assert_eq!(rejection["retriable"], false);
+
+    // The test client reaches the app through its loopback address
+    let app = App::spawn(&[
+        ("COMPLEXITY_BUDGET", "0.1"),
+        ("COMPLEXITY_ALLOWLIST", "127.0.0.1"),
+    ])
+    .await;
+    location(&app.resize(&url, &[("width", "20")]).await);
+}
+
+#[tokio::test]
+async fn compresses_json_responses() {
+    let app = App::spawn(&[]).await;
+    let spec = app
+        .client
+        .get(format!("{}/openapi.json", app.base))
+        .header("accept-encoding", "br")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(spec.headers()["content-encoding"], "br");
+
+    let identity = app
+        .client
+        .get(format!("{}/openapi.json", app.base))
+        .header("accept-encoding", "identity")
+        .send()
+        .await
+        .unwrap();
+    assert!(!identity.headers().contains_key("content-encoding"));
+}
+
+#[tokio::test]
+async fn sends_the_configured_cache_headers() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[
+        ("REDIRECT_CACHE_CONTROL", "public, max-age=60"),
+        ("CDN_CACHE_CONTROL", "public, max-age=86400"),
+        ("STALE_IF_ERROR_SECS", "600"),
+        ("VARY", "Accept, DPR"),
+    ])
+    .await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = app.resize(&url, &[("width", "20")]).await;
+    let headers = resized.headers();
+    assert_eq!(
+        headers["cache-control"],
+        "public, max-age=60, stale-if-error=600"
+    );
+    assert_eq!(
+        headers["cdn-cache-control"],
+        "public, max-age=86400, stale-if-error=600"
+    );
+    assert_eq!(headers["vary"], "Accept, DPR");
+
+    let downloaded = app.client.get(location(&resized)).send().await.unwrap();
+    let headers = downloaded.headers();
+    assert_eq!(
+        headers["cache-control"],
+        "public, max-age=31536000, immutable, stale-if-error=600"
+    );
+    assert_eq!(
+        headers["cdn-cache-control"],
+        "public, max-age=86400, stale-if-error=600"
+    );
+}
+
+#[tokio::test]
+async fn answers_revalidated_redirects_with_not_modified() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 2).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = app.resize(&url, &[("width", "20")]).await;
+    assert_eq!(resized.headers()["cache-control"], "no-cache");
+    let etag = resized.headers()["etag"].to_str().unwrap().to_string();
+
+    let revalidated = app
+        .client
+        .get(format!("{}/api/images/resize", app.base))
+        .query(&[("url", url.as_str()), ("width", "20")])
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(revalidated.status(), 304);
+    assert_eq!(revalidated.headers()["etag"], etag.as_str());
+
+    // Another size has another key
+    let other = app
+        .client
+        .get(format!("{}/api/images/resize", app.base))
+        .query(&[("url", url.as_str()), ("width", "10")])
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(other.status(), 301);
+}
+
+#[tokio::test]
+async fn describes_art_directed_pictures() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 2).await;
+    let manifest = serde_json::json!({
+        "presets": {
+            "story": [
+                {"name": "wide", "media": "(min-width: 600px)", "width": 40, "height": 10},
+                {"name": "mobile", "width": 10, "height": 20}
+            ]
+        },
+        "pictures": {
+            "story-1": {
+                "url": format!("{}/source.png", origin.uri()),
+                "preset": "story",
+                "crops": {"mobile": {"focus": "0.2,0.5"}}
+            }
+        }
+    });
+    let path = std::env::temp_dir().join(format!("art-direction-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, manifest.to_string()).unwrap();
+    let app = App::spawn(&[("ART_DIRECTION_MANIFEST", path.to_str().unwrap())]).await;
+
+    let picture: serde_json::Value = app
+        .client
+        .get(format!("{}/api/pictures/story-1", app.base))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let source = &picture["sources"][0];
+    assert_eq!(source["media"], "(min-width: 600px)");
+    assert_eq!(source["type"], "image/jpeg");
+    assert_eq!(
+        (&source["width"], &source["height"]),
+        (&40.into(), &10.into())
+    );
+    let img = &picture["img"];
+    assert_ne!(img["src"], source["srcset"]);
+    assert_eq!((&img["width"], &img["height"]), (&10.into(), &20.into()));
+
+    let unknown = app
+        .client
+        .get(format!("{}/api/pictures/story-2", app.base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), 404);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn answers_bad_gateway_when_the_origin_fails() {
+    let origin = origin(ResponseTemplate::new(500), 1).await;
+    let app = App::spawn(&[("CDN_CACHE_CONTROL", "public, max-age=86400")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    // The failure must not be cached past the outage
+    let resized = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(resized.status(), reqwest::StatusCode::BAD_GATEWAY);
+    assert!(!resized.headers().contains_key("cdn-cache-control"));
+    assert_eq!(resized.headers()["x-error-code"], "origin_server_error");
+    let rejection: serde_json::Value = resized.json().await.unwrap();
+    assert_eq!(rejection["reason"], "source_download_failed");
+    assert_eq!(rejection["code"], "origin_server_error");
+    assert_eq!(rejection["retriable"], true);
+}
+
+#[tokio::test]
+async fn answers_bad_gateway_when_the_origin_is_slow() {
+    let source = ResponseTemplate::new(200)
+        .set_body_bytes(png(40, 30))
+        .set_delay(Duration::from_secs(10));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("FIRST_BYTE_TIMEOUT_MS", "1000")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let started = Instant::now();
+    let resized = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(resized.status(), reqwest::StatusCode::BAD_GATEWAY);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(resized.headers()["x-error-code"], "origin_timeout");
+}
+
+#[tokio::test]
+async fn gives_up_on_origins_stalling_mid_body() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Sends the headers and the start of the body, then stalls
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/source.png", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).await;
+        let head = "HTTP/1.1 200 OK\r\ncontent-type: image/png\r\ncontent-length: 4096\r\n\r\n";
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(&png(40, 30)[..64]).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+    let app = App::spawn(&[("IDLE_TIMEOUT_MS", "500")]).await;
+
+    let started = Instant::now();
+    let resized = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(resized.status(), reqwest::StatusCode::BAD_GATEWAY);
+    assert!(started.elapsed() < Duration::from_secs(4));
+    assert_eq!(resized.headers()["x-error-code"], "origin_timeout");
+}
+
+#[tokio::test]
+async fn stops_reading_bodies_over_the_maximum_size() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Streams a body without content-length, twice the size allowed
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/source.png", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).await;
+        let head = "HTTP/1.1 200 OK\r\ncontent-type: image/png\r\nconnection: close\r\n\r\n";
+        stream.write_all(head.as_bytes()).await.unwrap();
+        let chunk = vec![0; 64 * 1024];
+        for _ in 0..32 {
+            if stream.write_all(&chunk).await.is_err() {
+                return;
+            }
+        }
+    });
+    let app = App::spawn(&[("MAX_IMAGE_SIZE_MB", "1")]).await;
+
+    let resized = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(location(&resized), url);
+    assert_eq!(resized.headers()["x-error-code"], "too_large");
+}
+
+#[tokio::test]
+async fn falls_back_to_the_source_when_it_isnt_an_image() {
+    let page = ResponseTemplate::new(200)
+        .set_body_string("<html>not an image</html>")
+        .insert_header("content-type", "text/html");
+    let origin = origin(page, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(location(&resized), url);
+    assert_eq!(resized.headers()["x-error-code"], "decode_error");
+}
+
+#[tokio::test]
+async fn falls_back_to_the_source_over_the_decode_limits() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("MAX_DECODE_WIDTH", "200")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(location(&resized), url);
+    assert_eq!(resized.headers()["x-error-code"], "too_large");
+}
+
+#[cfg(feature = "s3")]
+#[tokio::test]
+async fn reports_s3_outages() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 1).await;
+    // Nothing listens on the discard port
+    let app = App::spawn(&[
+        ("STORAGE_TYPE", "s3"),
+        ("MINIO_ENDPOINT_URL", "http://127.0.0.1:9"),
+    ])
+    .await;
+    let url = format!("{}/source.png", origin.uri());
+
+    assert_eq!(location(&app.resize(&url, &[("width", "20")]).await), url);
+
+    let ready = app
+        .client
+        .get(format!("{}/health/ready?deep=true", app.base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ready.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn reports_cache_efficiency_through_the_admin_api() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    location(
+        &app.resize(&url, &[("width", "100"), ("format", "webp")])
+            .await,
+    );
+    location(
+        &app.resize(&url, &[("width", "100"), ("format", "webp")])
+            .await,
+    );
+
+    let report = app
+        .client
+        .get(format!("{}/admin/report", app.base))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(
+        (report["hits"].as_u64(), report["misses"].as_u64()),
+        (Some(1), Some(1))
+    );
+    assert_eq!(report["hit_ratio"], 0.5);
+    assert_eq!(report["bytes_from_cache"], report["bytes_processed"]);
+    assert_eq!(report["formats"]["webp"]["images"], 1);
+    assert_eq!(report["top_origins"][0]["host"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn serves_only_cached_images_in_maintenance_mode() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    // Only the resize before maintenance reaches the origin
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let cdn_url = location(&app.resize(&url, &[("width", "100")]).await);
+
+    let toggled = app
+        .client
+        .put(format!("{}/admin/maintenance", app.base))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({"enabled": true}))
+        .send()
+        .await
+        .unwrap();
+    assert!(toggled.status().is_success());
+
+    assert_eq!(
+        location(&app.resize(&url, &[("width", "100")]).await),
+        cdn_url
+    );
+    let refused = app.resize(&url, &[("width", "50")]).await;
+    assert_eq!(refused.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(refused.headers()["retry-after"], "30");
+    assert_eq!(refused.headers()["x-error-code"], "maintenance");
+    let rejection = refused.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(rejection["retriable"], true);
+
+    let ready = app
+        .client
+        .get(format!("{}/health/ready", app.base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ready.status(), reqwest::StatusCode::OK);
+    let ready = ready.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(ready["maintenance"], true);
+}
+
+#[tokio::test]
+async fn refuses_cold_misses_past_the_deadline_of_the_client() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    // Only the resize with enough time left reaches the origin
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("DEADLINE_MIN_COLD_MISS_MS", "1000")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let resize = |timeout: &'static str| {
+        app.client
+            .get(format!("{}/api/images/resize", app.base))
+            .query(&[("url", url.as_str()), ("width", "100")])
+            .header("request-timeout", timeout)
+            .send()
+    };
+
+    let refused = resize("0.2").await.unwrap();
+    assert_eq!(refused.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(refused.headers()["retry-after"], "1");
+    assert_eq!(refused.headers()["x-error-code"], "deadline_exceeded");
+    let rejection = refused.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(rejection["reason"], "deadline_too_short");
+
+    let cdn_url = location(&resize("30").await.unwrap());
+    // Cached images are served whatever the deadline
+    assert_eq!(location(&resize("0.2").await.unwrap()), cdn_url);
+}
+
+#[tokio::test]
+async fn mirrors_resizes_to_a_secondary_deployment() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 1).await;
+    let canary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/images/resize"))
+        .and(query_param("width", "20"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&canary)
+        .await;
+    let app = App::spawn(&[
+        ("ADMIN_TOKEN", "secret"),
+        ("MIRROR_BASE_URL", &canary.uri()),
+        ("MIRROR_PERCENT", "100"),
+    ])
+    .await;
+    let url = format!("{}/source.png", origin.uri());
+    location(&app.resize(&url, &[("width", "20")]).await);
+
+    // Mirrored in the background, once answered
+    let mut stats = serde_json::Value::Null;
+    for _ in 0..50 {
+        stats = app
+            .client
+            .get(format!("{}/admin/stats", app.base))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        if stats["mirror"]["mirrored"] == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(stats["mirror"]["mirrored"], 1);
+    assert_eq!(stats["mirror"]["status_mismatches"], 1);
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn injects_faults_set_through_the_admin_api() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let set_chaos = |settings: serde_json::Value| {
+        app.client
+            .put(format!("{}/admin/chaos", app.base))
+            .bearer_auth("secret")
+            .json(&settings)
+            .send()
+    };
+
+    let rejected = set_chaos(serde_json::json!({ "error_rate": 2.0 }))
+        .await
+        .unwrap();
+    assert_eq!(rejected.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    set_chaos(serde_json::json!({ "error_rate": 1.0, "error_status": 502 }))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let failed = app.resize(&url, &[("width", "20")]).await;
+    assert_eq!(failed.status(), reqwest::StatusCode::BAD_GATEWAY);
+
+    // The processed image can't be stored, so the source is served instead
+    set_chaos(serde_json::json!({ "storage_failure_rate": 1.0 }))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(location(&app.resize(&url, &[("width", "20")]).await), url);
+}
+
+#[tokio::test]
+async fn overlays_watermarks_of_the_library() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    // Requests are sent to 127.0.0.1, the tenant of the library
+    let watermark = format!("{}/admin/watermarks/127.0.0.1/logo", app.base);
+
+    let invalid = app
+        .client
+        .put(&watermark)
+        .bearer_auth("secret")
+        .body("not an image")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+    let stored = app
+        .client
+        .put(&watermark)
+        .bearer_auth("secret")
+        .body(png(40, 20))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(stored.status(), reqwest::StatusCode::NO_CONTENT);
+
+    let resized = location(
+        &app.resize(&url, &[("width", "100"), ("watermark", "logo")])
+            .await,
+    );
+    assert_ne!(resized, url);
+
+    // Unknown watermarks fail before the download
+    let unknown = app
+        .resize(&url, &[("width", "100"), ("watermark", "badge")])
+        .await;
+    assert_eq!(unknown.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(unknown.headers()["x-error-code"], "invalid_request");
+
+    let deleted = app
+        .client
+        .delete(&watermark)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(deleted.status(), reqwest::StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn removes_backgrounds() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 2).await;
+    // Matting service cutting out nothing but the background
+    let matting = MockServer::start().await;
+    let mut cutout = Vec::new();
+    image::DynamicImage::ImageRgba8(image::RgbaImage::new(40, 30))
+        .write_to(
+            &mut std::io::Cursor::new(&mut cutout),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(cutout))
+        .expect(1)
+        .mount(&matting)
+        .await;
+    let app = App::spawn(&[("MATTING_URL", &matting.uri())]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let corner_alpha = |cdn_url: String| {
+        let client = app.client.clone();
+        async move {
+            let data = client
+                .get(cdn_url)
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            assert_eq!(image::guess_format(&data).unwrap(), image::ImageFormat::Png);
+            image::load_from_memory(&data)
+                .unwrap()
+                .to_rgba8()
+                .get_pixel(0, 0)
+                .0[3]
+        }
+    };
+
+    // Removed backgrounds default to PNG
+    let keyed = location(&app.resize(&url, &[("remove_bg", "border:40")]).await);
+    assert_eq!(corner_alpha(keyed).await, 0);
+    let matted = location(&app.resize(&url, &[("remove_bg", "matting")]).await);
+    assert_eq!(corner_alpha(matted).await, 0);
+
+    let jpeg = app
+        .resize(&url, &[("remove_bg", "border"), ("format", "jpg")])
+        .await;
+    assert_eq!(jpeg.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(jpeg.headers()["x-error-code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn reduces_the_palette_of_icons() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(64, 64));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let icon = location(
+        &app.resize(
+            &url,
+            &[
+                ("width", "32"),
+                ("format", "png"),
+                ("colors", "4"),
+                ("dither", "ordered"),
+            ],
+        )
+        .await,
+    );
+    let data = app
+        .client
+        .get(icon)
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let colors: std::collections::HashSet<[u8; 3]> = image::load_from_memory(&data)
+        .unwrap()
+        .to_rgb8()
+        .pixels()
+        .map(|pixel| pixel.0)
+        .collect();
+    assert!(colors.len() <= 4);
+
+    let invalid = app.resize(&url, &[("dither", "ordered")]).await;
+    assert_eq!(invalid.headers()["x-error-code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn encodes_at_the_requested_quality() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(200, 150));
+    let origin = origin(source, 2).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let size = |quality: &'static str| {
+        let app = &app;
+        let url = url.clone();
+        async move {
+            let resized = location(
+                &app.resize(&url, &[("format", "jpg"), ("quality", quality)])
+                    .await,
+            );
+            app.client
+                .get(resized)
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap()
+                .len()
+        }
+    };
+
+    assert!(size("20").await < size("95").await);
+
+    let invalid = app.resize(&url, &[("quality", "0")]).await;
+    assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.headers()["x-error-code"], "invalid_request");
+    let rejection: serde_json::Value = invalid.json().await.unwrap();
+    assert_eq!(rejection["reason"], "invalid_parameters");
+    assert_eq!(rejection["retriable"], false);
+}
+
+#[tokio::test]
+async fn answers_with_the_image_inline() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(80, 60));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let inline = app
+        .resize(
+            &url,
+            &[("width", "40"), ("format", "webp"), ("response", "inline")],
+        )
+        .await;
+    assert_eq!(inline.status(), 200);
+    assert_eq!(inline.headers()["content-type"], "image/webp");
+    assert_eq!(inline.headers()["x-image-width"], "40");
+    let etag = inline.headers()["etag"].to_str().unwrap().to_string();
+    let data = inline.bytes().await.unwrap();
+    assert_eq!(image::load_from_memory(&data).unwrap().width(), 40);
+
+    let revalidated = app
+        .client
+        .get(format!("{}/api/images/resize", app.base))
+        .query(&[
+            ("url", url.as_str()),
+            ("width", "40"),
+            ("format", "webp"),
+            ("response", "inline"),
+        ])
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(revalidated.status(), 304);
+    assert_eq!(revalidated.headers()["etag"], etag.as_str());
+}
+
+#[tokio::test]
+async fn stretches_the_levels_of_dull_images() {
+    // The red and green channels only span 0 to 63
+    let source = ResponseTemplate::new(200).set_body_bytes(png(64, 64));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let stretched = location(
+        &app.resize(&url, &[("format", "png"), ("auto_level", "channels")])
+            .await,
+    );
+    let data = app
+        .client
+        .get(stretched)
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let image = image::load_from_memory(&data).unwrap().to_rgb8();
+    let brightest = image.pixels().map(|pixel| pixel.0[0]).max().unwrap();
+    assert!(brightest > 250, "{}", brightest);
+
+    let invalid = app.resize(&url, &[("auto_level", "yes")]).await;
+    assert_eq!(invalid.headers()["x-error-code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn picks_the_interpolation_by_direction() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 3).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let filter = |query: &'static [(&'static str, &'static str)]| {
+        let app = &app;
+        let url = url.clone();
+        async move {
+            let resized = app
+                .client
+                .get(format!("{}/api/images/resize", app.base))
+                .query(&[("url", url.as_str())])
+                .query(query)
+                .header("x-debug", "1")
+                .bearer_auth("secret")
+                .send()
+                .await
+                .unwrap();
+            location(&resized);
+            resized.headers()["x-debug-filter"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    // Slight upscales default to the Mitchell cubic
+    assert_eq!(filter(&[("width", "50")]).await, "mitchell");
+    assert_eq!(
+        filter(&[("width", "80"), ("filter_up", "nearest")]).await,
+        "nearest"
+    );
+    assert_eq!(
+        filter(&[("width", "20"), ("filter_down", "catmull_rom")]).await,
+        "catmull_rom"
+    );
+
+    let invalid = app
+        .resize(&url, &[("width", "80"), ("filter_up", "bicubic")])
+        .await;
+    assert_eq!(invalid.headers()["x-error-code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn encodes_jpeg_with_the_requested_subsampling() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(40, 30));
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = app
+        .client
+        .get(format!("{}/api/images/resize", app.base))
+        .query(&[
+            ("url", url.as_str()),
+            ("width", "20"),
+            ("format", "jpg"),
+            ("subsampling", "444"),
+        ])
+        .header("x-debug", "1")
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    let cdn_url = location(&resized);
+    assert_eq!(
+        resized.headers()["x-debug-encoder"],
+        "jpeg library=image quality=75 subsampling=444"
+    );
+    // The image encoder ignores the subsampling, so every value shares the variant
+    let subsampled = app
+        .resize(
+            &url,
+            &[("width", "20"), ("format", "jpg"), ("subsampling", "420")],
+        )
+        .await;
+    assert_eq!(location(&subsampled), cdn_url);
+
+    let invalid = app
+        .resize(&url, &[("width", "20"), ("subsampling", "411")])
+        .await;
+    assert_eq!(invalid.headers()["x-error-code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn sharpens_downscales_by_default_when_configured() {
+    // The red channel drops from 255 to 0 halfway, an edge the downscale softens
+    let source = ResponseTemplate::new(200).set_body_bytes(png(512, 64));
+    let origin = origin(source, 2).await;
+    let app = App::spawn(&[("AUTO_SHARPEN", "true")]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let pixels = |query: &'static [(&'static str, &'static str)]| {
+        let app = &app;
+        let url = url.clone();
+        async move {
+            let resized = location(&app.resize(&url, query).await);
+            let data = app
+                .client
+                .get(resized)
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            image::load_from_memory(&data).unwrap().to_rgb8()
+        }
+    };
+
+    let sharpened = pixels(&[("width", "128"), ("format", "png")]).await;
+    let soft = pixels(&[
+        ("width", "128"),
+        ("format", "png"),
+        ("auto_sharpen", "false"),
+    ])
+    .await;
+    assert_eq!(sharpened.dimensions(), soft.dimensions());
+    assert_ne!(sharpened.as_raw(), soft.as_raw());
+}
+
+#[tokio::test]
+async fn carves_banners_with_fit_liquid() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, if cfg!(feature = "seam_carving") { 1 } else { 0 }).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let banner = app
+        .resize(
+            &url,
+            &[
+                ("width", "420"),
+                ("height", "180"),
+                ("fit", "liquid"),
+                ("format", "png"),
+            ],
+        )
+        .await;
+    if cfg!(feature = "seam_carving") {
+        let data = app
+            .client
+            .get(location(&banner))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let image = image::load_from_memory(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (420, 180));
+    } else {
+        assert_eq!(banner.headers()["x-error-code"], "invalid_request");
+    }
+
+    let oversized = app
+        .resize(
+            &url,
+            &[("width", "2100"), ("height", "900"), ("fit", "liquid")],
+        )
+        .await;
+    assert_eq!(oversized.headers()["x-error-code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn merges_the_defaults_of_the_tenant() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(400, 300));
+    let origin = origin(source, 1).await;
+    let config = serde_json::json!({
+        "127.0.0.1": {
+            "defaults": {"format": "avif", "auto_sharpen": true},
+            "max_quality": 60
+        }
+    });
+    let path = std::env::temp_dir().join(format!("tenant-defaults-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, config.to_string()).unwrap();
+    let app = App::spawn(&[("TENANT_DEFAULTS_CONFIG", path.to_str().unwrap())]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let query = [("width", "100"), ("quality", "90")];
+
+    let planned = app
+        .resize(&url, &[&query[..], &[("dry_run", "true")]].concat())
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(planned["params"]["format"], "avif");
+    assert_eq!(planned["params"]["auto_sharpen"], true);
+    assert_eq!(planned["params"]["quality"], "60");
+
+    // The defaults are part of the cache key the resize is stored under
+    let resized = app.resize(&url, &query).await;
+    assert_eq!(location(&resized), planned["url"]);
+    assert!(planned["url"].as_str().unwrap().ends_with(".avif"));
+
+    // Requested parameters win over the defaults
+    let planned = app
+        .resize(
+            &url,
+            &[&query[..], &[("format", "png"), ("dry_run", "true")]].concat(),
+        )
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(planned["params"]["format"], "png");
+    // Lossless formats ignore the quality, so it isn't capped
+    assert_eq!(planned["params"]["quality"], "90");
+}
+
+#[tokio::test]
+async fn coalesces_identical_resizes() {
+    let source = ResponseTemplate::new(200)
+        .set_body_bytes(png(200, 150))
+        .set_delay(Duration::from_millis(300));
+    // A single download for all the requests
+    let origin = origin(source, 1).await;
+    let app = App::spawn(&[("ADMIN_TOKEN", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resizes = (0..8).map(|_| app.resize(&url, &[("width", "50")]));
+    let locations: Vec<_> = futures::future::join_all(resizes)
+        .await
+        .iter()
+        .map(location)
+        .collect();
+    assert!(locations.iter().all(|location| *location == locations[0]));
+
+    let stats: serde_json::Value = app
+        .client
+        .get(format!("{}/admin/stats", app.base))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["lock"]["coalesced"], 7);
+}
+
+#[tokio::test]
+async fn rejects_unsigned_urls_when_a_signing_key_is_set() {
+    // Only the signed resize downloads the source
+    let origin = origin(ResponseTemplate::new(200).set_body_bytes(png(200, 100)), 1).await;
+    let app = App::spawn(&[("SIGNING_KEY", "secret")]).await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let unsigned = app.resize(&url, &[("width", "100")]).await;
+    assert_eq!(unsigned.status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(unsigned.headers()["x-error-code"], "invalid_signature");
+    let rejection: serde_json::Value = unsigned.json().await.unwrap();
+    assert_eq!(rejection["retriable"], false);
+
+    let request = app
+        .client
+        .get(format!("{}/api/images/resize", app.base))
+        .query(&[("url", url.as_str()), ("width", "100")])
+        .build()
+        .unwrap();
+    let signed =
+        UrlSigner::new("secret").signed_url(request.url().path(), request.url().query().unwrap());
+    let get = |path: String| app.client.get(format!("{}{}", app.base, path)).send();
+    location(&get(signed.clone()).await.unwrap());
+
+    // Changing a parameter invalidates the signature
+    let tampered = get(signed.replace("width=100", "width=1000"))
+        .await
+        .unwrap();
+    assert_eq!(tampered.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Expired and replayed URLs are refused, the variant being served from storage otherwise
+    let sign = |extra: &str| {
+        UrlSigner::new("secret").signed_url(
+            request.url().path(),
+            &format!("{}&{}", request.url().query().unwrap(), extra),
+        )
+    };
+    let expired = get(sign("expires=1")).await.unwrap();
+    assert_eq!(expired.status(), reqwest::StatusCode::FORBIDDEN);
+    let rejection: serde_json::Value = expired.json().await.unwrap();
+    assert_eq!(rejection["reason"], "expired_signature");
+    let single_use = sign("expires=4102444800&nonce=campaign-1");
+    location(&get(single_use.clone()).await.unwrap());
+    let replayed = get(single_use).await.unwrap();
+    assert_eq!(replayed.status(), reqwest::StatusCode::FORBIDDEN);
+    let rejection: serde_json::Value = replayed.json().await.unwrap();
+    assert_eq!(rejection["reason"], "replayed_signature");
+
+    // Other routes are served unsigned
+    let health = get("/health".to_string()).await.unwrap();
+    assert_eq!(health.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn resizes_every_frame_of_animated_images() {
+    let mut gif = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+        for shade in [0u8, 255] {
+            encoder
+                .encode_frame(image::Frame::from_parts(
+                    image::RgbaImage::from_pixel(60, 40, image::Rgba([shade, 0, 0, 255])),
+                    0,
+                    0,
+                    image::Delay::from_numer_denom_ms(100, 1),
+                ))
+                .unwrap();
+        }
+    }
+    let source = ResponseTemplate::new(200).set_body_bytes(gif);
+    let origin = origin(source, 4).await;
+    let app = App::spawn(&[]).await;
+    let url = format!("{}/source.png", origin.uri());
+    let frames = |extra: &'static [(&'static str, &'static str)]| {
+        let app = &app;
+        let url = url.clone();
+        async move {
+            let mut query = vec![("width", "30"), ("format", "gif"), ("response", "inline")];
+            query.extend_from_slice(extra);
+            let inline = app.resize(&url, &query).await;
+            assert_eq!(inline.status(), 200);
+            assert_eq!(inline.headers()["content-type"], "image/gif");
+            assert_eq!(inline.headers()["x-image-width"], "30");
+            let data = inline.bytes().await.unwrap();
+            let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(data)).unwrap();
+            image::AnimationDecoder::into_frames(decoder)
+                .map(|frame| frame.unwrap().into_buffer().get_pixel(0, 0)[0])
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let animated = frames(&[]).await;
+    assert_eq!(animated.len(), 2);
+    assert!(animated[0] < 128 && animated[1] > 128);
+    assert_eq!(frames(&[("frames", "first")]).await.len(), 1);
+
+    // WebP output is animated too, the other formats keeping the first frame
+    let webp = app
+        .resize(
+            &url,
+            &[("width", "30"), ("format", "webp"), ("response", "inline")],
+        )
+        .await;
+    assert_eq!(webp.status(), 200);
+    assert_eq!(webp.headers()["content-type"], "image/webp");
+    let data = webp.bytes().await.unwrap();
+    let decoder = image::codecs::webp::WebPDecoder::new(std::io::Cursor::new(data)).unwrap();
+    let animated = image::AnimationDecoder::into_frames(decoder)
+        .map(|frame| frame.unwrap().into_buffer().get_pixel(0, 0)[0])
+        .collect::<Vec<_>>();
+    assert_eq!(animated.len(), 2);
+    assert!(animated[0] < 128 && animated[1] > 128);
+    let png = app
+        .resize(
+            &url,
+            &[("width", "30"), ("format", "png"), ("response", "inline")],
+        )
+        .await;
+    assert_eq!(png.status(), 200);
+    let png = image::load_from_memory(&png.bytes().await.unwrap()).unwrap();
+    assert!(png.to_rgba8().get_pixel(0, 0)[0] < 128);
+
+    let invalid = app
+        .resize(
+            &url,
+            &[("format", "gif"), ("frames", "all"), ("frame", "1")],
+        )
+        .await;
+    assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn requires_the_peer_token_on_the_internal_routes() {
+    let app = App::spawn(&[
+        ("PEER_URLS", "http://replica.test"),
+        ("PEER_SELF_URL", "http://replica.test"),
+        ("PEER_TOKEN", "secret"),
+    ])
+    .await;
+    let object = format!("{}/internal/peer-cache/poisoned.jpg", app.base);
+    let put = |token: Option<&'static str>| {
+        let request = app.client.put(&object).body("not an image");
+        match token {
+            Some(token) => request.header("x-peer-token", token),
+            None => request,
+        }
+        .send()
+    };
+
+    assert_eq!(put(None).await.unwrap().status(), 403);
+    assert_eq!(put(Some("guess")).await.unwrap().status(), 403);
+    let served = app
+        .client
+        .get(format!("{}/api/images/files/poisoned.jpg", app.base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(served.status(), 404);
+
+    assert_eq!(put(Some("secret")).await.unwrap().status(), 204);
+}
+
+#[tokio::test]
+async fn purges_variants_from_the_peer_cache() {
+    let source = ResponseTemplate::new(200).set_body_bytes(png(80, 60));
+    let origin = origin(source, 1).await;
+    // Two siblings, whichever owns the key, the second failing to evict
+    let sibling = |eviction: u16| async move {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(header("x-peer-token", "secret"))
+            .respond_with(ResponseTemplate::new(eviction))
+            .expect(1)
+            .mount(&server)
+            .await;
+        server
+    };
+    let (healthy, failing) = (sibling(204).await, sibling(500).await);
+    let peers = format!("http://replica.test,{},{}", healthy.uri(), failing.uri());
+    let app = App::spawn(&[
+        ("ADMIN_TOKEN", "admin"),
+        ("PEER_URLS", &peers),
+        ("PEER_SELF_URL", "http://replica.test"),
+        ("PEER_TOKEN", "secret"),
+    ])
+    .await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let resized = location(&app.resize(&url, &[("width", "40")]).await);
+    let key = resized.rsplit('/').next().unwrap().to_string();
+    let purged = app
+        .client
+        .delete(format!("{}/admin/images/{}", app.base, key))
+        .bearer_auth("admin")
+        .send()
+        .await
+        .unwrap();
+    // Removed from storage, so the failed eviction doesn't fail the purge
+    assert_eq!(purged.status(), 204);
+
+    let served = app.client.get(&resized).send().await.unwrap();
+    assert_eq!(served.status(), 404);
+}
+
+#[tokio::test]
+async fn refuses_jobs_when_urls_must_be_signed() {
+    let app = App::spawn(&[("SIGNING_KEY", "secret")]).await;
+    let job = app
+        .client
+        .post(format!("{}/api/jobs", app.base))
+        .json(&serde_json::json!({ "url": "https://origin.test/a.png", "width": 40 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(job.status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(job.headers()["x-error-code"], "invalid_signature");
+}
@@ -0,0 +1,69 @@
+//! Resize flows against a MinIO container, including an outage mid-run
+//!
+//! Requires Docker: `cargo test --test e2e_minio -- --ignored`
+#![cfg(feature = "s3")]
+
+mod common;
+
+use common::{App, location, png};
+use testcontainers_modules::minio::MinIO;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const BUCKET: &str = "image-cache";
+
+async fn create_bucket(endpoint: &str) {
+    let config = aws_sdk_s3::config::Builder::new()
+        .endpoint_url(endpoint)
+        .credentials_provider(aws_sdk_s3::config::Credentials::new(
+            "minioadmin",
+            "minioadmin",
+            None,
+            None,
+            "Static",
+        ))
+        .region(aws_sdk_s3::config::Region::new("us-east-1"))
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+        .create_bucket()
+        .bucket(BUCKET)
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn stores_in_minio_and_survives_outages() {
+    let minio = MinIO::default().start().await.unwrap();
+    let endpoint = format!(
+        "http://127.0.0.1:{}",
+        minio.get_host_port_ipv4(9000).await.unwrap()
+    );
+    create_bucket(&endpoint).await;
+
+    let origin = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(png(400, 300)))
+        .mount(&origin)
+        .await;
+    let url = format!("{}/source.png", origin.uri());
+
+    let app = App::spawn(&[
+        ("STORAGE_TYPE", "s3"),
+        ("MINIO_ENDPOINT_URL", &endpoint),
+        ("MINIO_BUCKET", BUCKET),
+    ])
+    .await;
+
+    let cdn_url = location(&app.resize(&url, &[("width", "100")]).await);
+    assert_ne!(cdn_url, url);
+    let downloaded = app.client.get(&cdn_url).send().await.unwrap();
+    let img = image::load_from_memory(&downloaded.bytes().await.unwrap()).unwrap();
+    assert_eq!(img.width(), 100);
+
+    minio.stop().await.unwrap();
+    assert_eq!(location(&app.resize(&url, &[("width", "50")]).await), url);
+}
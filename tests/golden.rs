@@ -0,0 +1,202 @@
+//! Golden-image regression tests of the encoder output
+//!
+//! Each case processes the same synthetic source and compares the decoded output with a
+//! PNG under `tests/golden/`: lossless formats pixel for pixel, JPEG by SSIM. Outputs are
+//! regenerated with `UPDATE_GOLDEN=1 cargo test --test golden`, which refuses to overwrite
+//! a changed golden until `ENCODER_VERSION` is bumped.
+
+use bytes::Bytes;
+use emgr::services::image::encode::ENCODER_VERSION;
+use emgr::{ImageFormat, ImageService, ResizeQuery};
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Lowest SSIM accepted for lossy outputs
+const MIN_SSIM: f64 = 0.99;
+
+struct Case {
+    name: &'static str,
+    query: ResizeQuery,
+    lossless: bool,
+}
+
+fn cases() -> Vec<Case> {
+    let query = |width, height, format| ResizeQuery {
+        url: "https://golden.test/source.png".to_string(),
+        width,
+        height,
+        format: Some(format),
+        ..Default::default()
+    };
+
+    vec![
+        Case {
+            name: "jpeg_resize",
+            query: query(Some(160), None, ImageFormat::Jpg),
+            lossless: false,
+        },
+        Case {
+            name: "png_crop",
+            query: query(Some(120), Some(120), ImageFormat::Png),
+            lossless: true,
+        },
+        Case {
+            name: "webp_resize",
+            query: query(Some(200), None, ImageFormat::Webp),
+            lossless: true,
+        },
+        // Enlargements are interpolated apart from reductions
+        Case {
+            name: "png_upscale",
+            query: query(Some(480), None, ImageFormat::Png),
+            lossless: true,
+        },
+        Case {
+            name: "png_blur_grayscale",
+            query: ResizeQuery {
+                blur_sigma: Some(2.0),
+                grayscale: Some(true),
+                ..query(Some(100), None, ImageFormat::Png)
+            },
+            lossless: true,
+        },
+    ]
+}
+
+/// Gradient with a checkerboard and a disc, exercising both smooth areas and edges
+fn source() -> Bytes {
+    let img = RgbImage::from_fn(320, 240, |x, y| {
+        let (dx, dy) = (x as i32 - 220, y as i32 - 120);
+        if dx * dx + dy * dy < 60 * 60 {
+            Rgb([230, 40, 60])
+        } else if x < 120 && ((x / 12) + (y / 12)) % 2 == 0 {
+            Rgb([20, 20, 20])
+        } else {
+            Rgb([(x * 255 / 319) as u8, (y * 255 / 239) as u8, 128])
+        }
+    });
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(img)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    png.into()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Mean SSIM of the luma of two images of the same size, over 8x8 windows
+fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |img: &RgbaImage, x, y| {
+        let [r, g, b, _] = img.get_pixel(x, y).0;
+        0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+    };
+
+    let (width, height) = a.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..height.saturating_sub(7)).step_by(8) {
+        for wx in (0..width.saturating_sub(7)).step_by(8) {
+            let pixels: Vec<(f64, f64)> = (wy..wy + 8)
+                .flat_map(|y| (wx..wx + 8).map(move |x| (x, y)))
+                .map(|(x, y)| (luma(a, x, y), luma(b, x, y)))
+                .collect();
+            let n = pixels.len() as f64;
+            let mean_a = pixels.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_b = pixels.iter().map(|p| p.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+            for (pa, pb) in &pixels {
+                var_a += (pa - mean_a).powi(2) / n;
+                var_b += (pb - mean_b).powi(2) / n;
+                cov += (pa - mean_a) * (pb - mean_b) / n;
+            }
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows.max(1) as f64
+}
+
+/// Why `output` doesn't match `golden`, if it doesn't
+fn mismatch(output: &RgbaImage, golden: &RgbaImage, lossless: bool) -> Option<String> {
+    if output.dimensions() != golden.dimensions() {
+        return Some(format!(
+            "dimensions {:?} instead of {:?}",
+            output.dimensions(),
+            golden.dimensions()
+        ));
+    }
+    if lossless {
+        let differing = output
+            .pixels()
+            .zip(golden.pixels())
+            .filter(|(a, b)| a != b)
+            .count();
+        return (differing > 0).then(|| format!("{} pixels differ", differing));
+    }
+    let ssim = ssim(output, golden);
+    (ssim < MIN_SSIM).then(|| format!("SSIM {:.4} below {}", ssim, MIN_SSIM))
+}
+
+#[tokio::test]
+async fn outputs_match_golden_images() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let dir = golden_dir();
+    let version_path = dir.join("ENCODER_VERSION");
+    let recorded_version = std::fs::read_to_string(&version_path)
+        .ok()
+        .and_then(|version| version.trim().parse::<u32>().ok());
+
+    let service = ImageService::new().unwrap();
+    let source = source();
+    let mut failures = Vec::new();
+    for case in cases() {
+        let processed = service
+            .process_image(source.clone(), &case.query)
+            .await
+            .unwrap();
+        let output = image::load_from_memory(&processed.data).unwrap().to_rgba8();
+
+        let path = dir.join(format!("{}.png", case.name));
+        let golden = image::open(&path).ok().map(|golden| golden.to_rgba8());
+        let problem = match &golden {
+            Some(golden) => mismatch(&output, golden, case.lossless),
+            None => Some("no golden image".to_string()),
+        };
+        let Some(problem) = problem else {
+            continue;
+        };
+
+        if !update {
+            failures.push(format!("{}: {}", case.name, problem));
+        } else if golden.is_some() && recorded_version == Some(ENCODER_VERSION) {
+            failures.push(format!(
+                "{}: {}, bump ENCODER_VERSION before updating it",
+                case.name, problem
+            ));
+        } else {
+            std::fs::create_dir_all(&dir).unwrap();
+            output.save(&path).unwrap();
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "Outputs differ from tests/golden (UPDATE_GOLDEN=1 regenerates them):\n{}",
+        failures.join("\n")
+    );
+    if update {
+        std::fs::write(&version_path, format!("{}\n", ENCODER_VERSION)).unwrap();
+    } else {
+        assert_eq!(
+            recorded_version,
+            Some(ENCODER_VERSION),
+            "Golden images were recorded with another encoder version, regenerate them with UPDATE_GOLDEN=1"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Downloads from local HTTP/1.1-only and HTTP/2-capable origins
+//!
+//! Each origin answers with the protocol version of the request, so the tests can
+//! check what the client actually spoke.
+
+use bytes::Bytes;
+use emgr::{ImageService, PerformanceConfig};
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+async fn version(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    Ok(Response::new(Full::new(Bytes::from(format!(
+        "{:?}",
+        request.version()
+    )))))
+}
+
+/// Origin speaking HTTP/1.1 only
+async fn http1_origin() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(
+                http1::Builder::new().serve_connection(TokioIo::new(stream), service_fn(version)),
+            );
+        }
+    });
+    addr
+}
+
+/// Origin accepting HTTP/1.1 and cleartext HTTP/2 with prior knowledge
+async fn http2_origin() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service_fn(version))
+                    .await;
+            });
+        }
+    });
+    addr
+}
+
+fn service(enable_http2: bool, force_http2_prior_knowledge: bool) -> ImageService {
+    ImageService::with_config(PerformanceConfig {
+        enable_http2,
+        force_http2_prior_knowledge,
+        ..PerformanceConfig::default()
+    })
+    .unwrap()
+}
+
+async fn download(service: &ImageService, addr: SocketAddr) -> anyhow::Result<String> {
+    let body = service
+        .download_image(&format!("http://{}/image.jpg", addr))
+        .await?;
+    Ok(String::from_utf8(body.to_vec())?)
+}
+
+#[tokio::test]
+async fn http2_enabled_falls_back_to_http1() {
+    let service = service(true, false);
+
+    assert_eq!(
+        download(&service, http1_origin().await).await.unwrap(),
+        "HTTP/1.1"
+    );
+    // Without TLS there is no ALPN to negotiate HTTP/2 with
+    assert_eq!(
+        download(&service, http2_origin().await).await.unwrap(),
+        "HTTP/1.1"
+    );
+}
+
+#[tokio::test]
+async fn http2_disabled_speaks_http1() {
+    let service = service(false, false);
+
+    assert_eq!(
+        download(&service, http2_origin().await).await.unwrap(),
+        "HTTP/1.1"
+    );
+}
+
+#[tokio::test]
+async fn prior_knowledge_speaks_http2() {
+    let service = service(true, true);
+
+    assert_eq!(
+        download(&service, http2_origin().await).await.unwrap(),
+        "HTTP/2.0"
+    );
+    assert!(download(&service, http1_origin().await).await.is_err());
+}
@@ -0,0 +1,122 @@
+//! Header probing of sources against a local origin honoring `Range`
+
+use bytes::Bytes;
+use emgr::ImageService;
+use emgr::services::image::probe::SourceProbe;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{CONTENT_RANGE, RANGE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use std::convert::Infallible;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// Chunk of a PNG stream, with its length and checksum
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(data);
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(&chunk);
+    png.extend_from_slice(&crc32fast::hash(&chunk).to_be_bytes());
+}
+
+/// Start of a 100000x100000 PNG, truncated after its header
+fn huge_png_header() -> Vec<u8> {
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&100_000u32.to_be_bytes());
+    ihdr.extend_from_slice(&100_000u32.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &ihdr);
+    png_chunk(&mut png, b"IDAT", &[0x78, 0x9c]);
+    png
+}
+
+fn small_png() -> Vec<u8> {
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::new(64, 48))
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+async fn source(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (body, total) = match request.uri().path() {
+        "/huge.png" => (huge_png_header(), None),
+        "/small.png" => (small_png(), None),
+        "/heavy.png" => (small_png(), Some(u64::MAX / 2)),
+        _ => (b"<html>not an image</html>".to_vec(), None),
+    };
+    let total = total.unwrap_or(body.len() as u64);
+
+    let end = request.headers().get(RANGE).and_then(|v| {
+        v.to_str()
+            .ok()?
+            .strip_prefix("bytes=0-")?
+            .parse::<usize>()
+            .ok()
+    });
+    let Some(end) = end else {
+        return Ok(Response::new(Full::new(body.into())));
+    };
+
+    let head = body[..body.len().min(end + 1)].to_vec();
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    response.headers_mut().insert(
+        CONTENT_RANGE,
+        format!("bytes 0-{}/{}", head.len() - 1, total)
+            .parse()
+            .unwrap(),
+    );
+    *response.body_mut() = Full::new(head.into());
+    Ok(response)
+}
+
+async fn origin() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(
+                http1::Builder::new().serve_connection(TokioIo::new(stream), service_fn(source)),
+            );
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn rejects_sources_from_their_header() {
+    let service = ImageService::new().unwrap().with_probe(SourceProbe {
+        bytes: 1024,
+        max_pixels: 50_000_000,
+    });
+    let base = format!("http://{}", origin().await);
+
+    let probed = service
+        .probe_source(&format!("{}/small.png", base))
+        .await
+        .unwrap();
+    assert_eq!(probed.format, ImageFormat::Png);
+    assert_eq!(probed.dimensions, Some((64, 48)));
+    assert_eq!(probed.size, Some(small_png().len() as u64));
+    assert!(
+        service
+            .download_image(&format!("{}/small.png", base))
+            .await
+            .is_ok()
+    );
+
+    for path in ["huge.png", "heavy.png", "page.html"] {
+        let url = format!("{}/{}", base, path);
+        assert!(service.probe_source(&url).await.is_err(), "{}", path);
+        assert!(service.download_image(&url).await.is_err(), "{}", path);
+    }
+}
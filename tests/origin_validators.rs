@@ -0,0 +1,89 @@
+//! Conditional downloads against a local origin honoring `If-None-Match`
+
+use bytes::Bytes;
+use emgr::ImageService;
+use emgr::services::image::validators::{SourceDownload, SourceValidators};
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{ETAG, IF_NONE_MATCH, LAST_MODIFIED};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+const ETAG_VALUE: &str = "\"v1\"";
+const LAST_MODIFIED_VALUE: &str = "Wed, 21 Oct 2026 07:28:00 GMT";
+
+async fn source(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let mut response = if request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .is_some_and(|v| v == ETAG_VALUE)
+    {
+        let mut response = Response::new(Full::default());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        response
+    } else {
+        Response::new(Full::new(Bytes::from_static(b"image")))
+    };
+    response
+        .headers_mut()
+        .insert(ETAG, ETAG_VALUE.parse().unwrap());
+    response
+        .headers_mut()
+        .insert(LAST_MODIFIED, LAST_MODIFIED_VALUE.parse().unwrap());
+    Ok(response)
+}
+
+async fn origin() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(
+                http1::Builder::new().serve_connection(TokioIo::new(stream), service_fn(source)),
+            );
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn revalidates_with_stored_validators() {
+    let service = ImageService::new().unwrap();
+    let url = format!("http://{}/image.jpg", origin().await);
+
+    let SourceDownload::Modified { data, validators } = service
+        .download_if_modified(&url, &SourceValidators::default())
+        .await
+        .unwrap()
+    else {
+        panic!("unconditional download was not modified");
+    };
+    assert_eq!(data, &b"image"[..]);
+    assert_eq!(validators.etag.as_deref(), Some(ETAG_VALUE));
+    assert_eq!(
+        validators.last_modified.as_deref(),
+        Some(LAST_MODIFIED_VALUE)
+    );
+
+    assert!(matches!(
+        service
+            .download_if_modified(&url, &validators)
+            .await
+            .unwrap(),
+        SourceDownload::NotModified
+    ));
+
+    let stale = SourceValidators {
+        etag: Some("\"v0\"".to_string()),
+        last_modified: None,
+    };
+    assert!(matches!(
+        service.download_if_modified(&url, &stale).await.unwrap(),
+        SourceDownload::Modified { .. }
+    ));
+}